@@ -5,36 +5,39 @@ use k2::{
 
 use clap::{App, Arg};
 
+use std::sync::Arc;
+
 fn main() {
     // Note: `find_executable` relies on $PATH. For a real experiment, you will
     // probably want to use absolute paths instead.
-    let python_bin = find_executable("python");
-    let pypy_bin = find_executable("pypy");
-    let luajit_bin = find_executable("luajit");
+    let python_bin = find_executable("python").expect("Could not find python");
+    let pypy_bin = find_executable("pypy").expect("Could not find pypy");
+    let luajit_bin = find_executable("luajit").expect("Could not find luajit");
     let expb = setup();
-    let cpython = GenericScriptingVm::new(&python_bin);
-    let pypy = GenericScriptingVm::new(&pypy_bin);
-    let luajit = GenericScriptingVm::new(&luajit_bin);
-    let cpython_bench = Benchmark::new("./benchmarks/binarytrees/binarytrees.py", &cpython)
+    let cpython = Arc::new(GenericScriptingVm::new(&python_bin));
+    let pypy = Arc::new(GenericScriptingVm::new(&pypy_bin));
+    let luajit = Arc::new(GenericScriptingVm::new(&luajit_bin));
+    let cpython_bench = Benchmark::new("./benchmarks/binarytrees/binarytrees.py", cpython)
         .tag("benchmark_name", "binarytrees")
         .arg("10".into());
-    let pypy_bench = Benchmark::new("./benchmarks/binarytrees/binarytrees.py", &pypy)
+    let pypy_bench = Benchmark::new("./benchmarks/binarytrees/binarytrees.py", pypy)
         .tag("benchmark_name", "binarytrees")
         .arg("10".into());
-    let lua_bench = Benchmark::new("./benchmarks/binarytrees/binarytrees.lua", &luajit)
+    let lua_bench = Benchmark::new("./benchmarks/binarytrees/binarytrees.lua", luajit)
         .tag("benchmark_name", "binarytrees")
         .arg("12".into())
         .stack_lim(Limit::KiB(8.192))
         .heap_lim(Limit::GiB(2.097152));
     let exp = expb
-        .benchmark(&cpython_bench)
-        .benchmark(&pypy_bench)
-        .benchmark(&lua_bench)
-        .build();
+        .benchmark(cpython_bench)
+        .benchmark(pypy_bench)
+        .benchmark(lua_bench)
+        .build()
+        .expect("Failed to set up the experiment");
     let _ = exp.run().expect("Failed to run the experiment");
 }
 
-fn setup<'a>() -> ExperimentBuilder<'a> {
+fn setup() -> ExperimentBuilder {
     let expb = parse_args(ExperimentBuilder::new("simple_experiment"));
     // These could've been command-line arguments too.
     expb.pexecs(2).in_proc_iters(40)
@@ -0,0 +1,144 @@
+//! Stopping the benchmarks whose results are already precise enough.
+//!
+//! Some benchmarks are so stable that a handful of process executions estimate
+//! their wall-clock time as precisely as `Config::pexecs` of them would. In an
+//! adaptive experiment (see `ExperimentBuilder::adaptive`), once a benchmark has
+//! run `AdaptiveConfig::min_pexecs` process executions successfully, the confidence
+//! interval of its mean wall-clock time is computed after each of its jobs (in the
+//! same way as by `Results::statistics`). When the interval is narrower than
+//! `AdaptiveConfig::max_ci_width` (relative to the mean), the outstanding jobs of
+//! the benchmark aren't run: they are marked as `JobStatus::Unneeded`.
+//!
+//! `Config::pexecs` is the most process executions a benchmark runs. Benchmarks
+//! which don't print their wall-clock times (see `measure`) never stop early.
+
+use crate::{
+    analysis::{self, BootstrapOpts},
+    error::K2Error,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// When to stop running the process executions of a benchmark.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdaptiveConfig {
+    /// The number of process executions a benchmark runs before it can stop.
+    #[serde(default = "default_min_pexecs")]
+    pub min_pexecs: usize,
+    /// The width of the confidence interval of the mean below which a benchmark
+    /// stops, as a fraction of the mean (`0.01` stops once the interval is within
+    /// 1% of the mean).
+    #[serde(default = "default_max_ci_width")]
+    pub max_ci_width: f64,
+    /// The confidence level of the interval.
+    #[serde(default = "default_confidence")]
+    pub confidence: f64,
+    /// The number of bootstrap resamples the interval is computed with. This is
+    /// lower than for `Results::statistics`, since the interval is computed after
+    /// each job.
+    #[serde(default = "default_resamples")]
+    pub resamples: usize,
+}
+
+fn default_min_pexecs() -> usize {
+    5
+}
+
+fn default_max_ci_width() -> f64 {
+    0.01
+}
+
+fn default_confidence() -> f64 {
+    0.99
+}
+
+fn default_resamples() -> usize {
+    1000
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> AdaptiveConfig {
+        AdaptiveConfig::new()
+    }
+}
+
+impl AdaptiveConfig {
+    /// Stop a benchmark after at least 5 process executions, once the 99% confidence
+    /// interval of its mean is within 1% of the mean.
+    pub fn new() -> AdaptiveConfig {
+        AdaptiveConfig {
+            min_pexecs: default_min_pexecs(),
+            max_ci_width: default_max_ci_width(),
+            confidence: default_confidence(),
+            resamples: default_resamples(),
+        }
+    }
+
+    /// Run at least `min_pexecs` process executions of each benchmark, instead of 5.
+    pub fn min_pexecs(mut self, min_pexecs: usize) -> Self {
+        self.min_pexecs = min_pexecs;
+        self
+    }
+
+    /// Stop once the confidence interval is narrower than `max_ci_width` (a fraction
+    /// of the mean), instead of 1%.
+    pub fn max_ci_width(mut self, max_ci_width: f64) -> Self {
+        self.max_ci_width = max_ci_width;
+        self
+    }
+
+    /// Use a `confidence` level (between 0 and 1) instead of 99%.
+    pub fn confidence(mut self, confidence: f64) -> Self {
+        self.confidence = confidence;
+        self
+    }
+
+    /// Compute the interval with `resamples` bootstrap resamples, instead of 1000.
+    pub fn resamples(mut self, resamples: usize) -> Self {
+        self.resamples = resamples;
+        self
+    }
+
+    /// Check that a confidence interval can be computed, and that the width is
+    /// positive.
+    pub(crate) fn check(&self) -> Result<(), K2Error> {
+        let invalid = |setting: &str, value: &dyn std::fmt::Display, expected: &str| {
+            Err(K2Error::InvalidConfig(format!(
+                "Invalid adaptive {}: {} (expected {})",
+                setting, value, expected
+            )))
+        };
+        if self.min_pexecs < 2 {
+            return invalid("min_pexecs", &self.min_pexecs, "at least 2");
+        }
+        if !(self.max_ci_width.is_finite() && self.max_ci_width > 0.0) {
+            return invalid("max_ci_width", &self.max_ci_width, "a positive number");
+        }
+        if !(self.confidence > 0.0 && self.confidence < 1.0) {
+            return invalid("confidence", &self.confidence, "a number between 0 and 1");
+        }
+        if self.resamples == 0 {
+            return invalid("resamples", &self.resamples, "at least 1");
+        }
+        Ok(())
+    }
+
+    /// The width of the confidence interval of the mean of the wall-clock times of
+    /// each process execution in `pexecs`, relative to the mean, if the benchmark
+    /// can stop: it ran at least `min_pexecs` process executions, and the interval
+    /// is narrower than `max_ci_width`.
+    pub(crate) fn stops(&self, pexecs: &[Vec<f64>]) -> Option<f64> {
+        let opts = BootstrapOpts {
+            resamples: self.resamples,
+            confidence: self.confidence,
+            ..Default::default()
+        };
+        let statistics = analysis::statistics(pexecs, &opts)?;
+        if statistics.pexecs < self.min_pexecs || statistics.mean.value <= 0.0 {
+            return None;
+        }
+        let width = (statistics.mean.upper - statistics.mean.lower) / statistics.mean.value;
+        Some(width).filter(|width| *width < self.max_ci_width)
+    }
+}
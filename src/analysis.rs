@@ -0,0 +1,545 @@
+//! The analysis of the wall-clock times of the benchmarks.
+//!
+//! # Warmup
+//!
+//! The wall-clock times of the in-process iterations are segmented with PELT
+//! (Killick et al., 2012), which finds the changepoints that minimise the cost of
+//! modelling each segment as normally distributed with its own mean and variance,
+//! plus a penalty per changepoint. The last segment is considered the steady
+//! state, and the iterations before it the warmup.
+//!
+//! This is the analysis of "Virtual Machine Warmup Blows Hot and Cold" (Barrett et
+//! al., 2017), which is usually done with the `changepoint` R package
+//! (`cpt.meanvar`, with the PELT method and a penalty of `15 * log(n)`).
+//!
+//! # Outliers
+//!
+//! An in-process iteration is an outlier if its wall-clock time is more than 3
+//! times the 10th-90th interpercentile range away from the median of the
+//! iterations around it. This is the outlier analysis of Krun's `warmup_stats`,
+//! except that the window is centred on each iteration, so the first iterations of
+//! a process execution are checked too.
+//!
+//! # Summary statistics
+//!
+//! The mean and median wall-clock times of a benchmark are estimated with
+//! confidence intervals computed by a hierarchical bootstrap (Kalibera and Jones,
+//! 2013): each resample draws process executions with replacement, and then draws
+//! the in-process iterations of each of them with replacement. This accounts for the
+//! variation between process executions, as well as the variation between the
+//! iterations of each of them.
+
+use crate::{error::K2Error, measure::Measurements, results::Results};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use std::{cmp::Ordering, collections::BTreeMap, fmt};
+
+/// The number of iterations in the shortest segment. A segment needs at least two
+/// iterations to have a variance.
+const MIN_SEGMENT_LEN: usize = 2;
+/// The smallest variance of a segment, in seconds squared. This stops segments of
+/// identical iterations (e.g. because of the resolution of the timer) from having
+/// an infinitely low cost.
+const MIN_VARIANCE: f64 = 1e-18;
+/// The multiplier of `log(n)` in the default penalty.
+const PENALTY_FACTOR: f64 = 15.0;
+/// The number of iterations in the window an iteration is compared with to decide
+/// whether it's an outlier.
+const OUTLIER_WINDOW: usize = 200;
+/// How many 10th-90th interpercentile ranges away from the median of its window an
+/// outlier is.
+const OUTLIER_THRESHOLD: f64 = 3.0;
+/// The default number of bootstrap resamples.
+const DEFAULT_RESAMPLES: usize = 10_000;
+/// The default confidence level of the confidence intervals.
+const DEFAULT_CONFIDENCE: f64 = 0.99;
+
+/// A segment of consecutive in-process iterations.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Segment {
+    /// The index of the first iteration of the segment.
+    pub start: usize,
+    /// The index after the last iteration of the segment.
+    pub end: usize,
+    /// The mean wall-clock time of the iterations, in seconds.
+    pub mean: f64,
+    /// The sample standard deviation of the wall-clock times, in seconds.
+    pub stddev: f64,
+}
+
+impl Segment {
+    fn new(times: &[f64], start: usize, end: usize) -> Segment {
+        let samples = &times[start..end];
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let stddev = if samples.len() > 1 {
+            let sq_diffs = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>();
+            (sq_diffs / (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+        Segment {
+            start,
+            end,
+            mean,
+            stddev,
+        }
+    }
+
+    /// The number of iterations in the segment.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Whether the segment has no iterations.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// The penalty of a changepoint in a process execution of `num_iters` iterations,
+/// if none is specified: `15 * ln(num_iters)`.
+pub fn default_penalty(num_iters: usize) -> f64 {
+    PENALTY_FACTOR * (num_iters.max(1) as f64).ln()
+}
+
+/// Split the wall-clock `times` (in seconds) of the in-process iterations of a
+/// process execution into segments, adding `penalty` to the cost of each
+/// changepoint. The segments are in iteration order, and cover all the
+/// iterations. Returns an empty list if there are no iterations.
+pub fn segments(times: &[f64], penalty: f64) -> Vec<Segment> {
+    let n = times.len();
+    if n < 2 * MIN_SEGMENT_LEN {
+        return if n == 0 {
+            Vec::new()
+        } else {
+            vec![Segment::new(times, 0, n)]
+        };
+    }
+    // The sums and sums of squares of the first `i` times, so that the cost of any
+    // segment can be computed in constant time.
+    let mut sums = vec![0.0; n + 1];
+    let mut sq_sums = vec![0.0; n + 1];
+    for (i, time) in times.iter().enumerate() {
+        sums[i + 1] = sums[i] + time;
+        sq_sums[i + 1] = sq_sums[i] + time * time;
+    }
+    // The negative log-likelihood of the times in `start..end` (without the terms
+    // which are the same for every segmentation).
+    let cost = |start: usize, end: usize| {
+        let len = (end - start) as f64;
+        let sum = sums[end] - sums[start];
+        let variance = (sq_sums[end] - sq_sums[start] - sum * sum / len) / len;
+        len * variance.max(MIN_VARIANCE).ln()
+    };
+    // `best[t]` is the lowest cost of segmenting the first `t` times, and
+    // `last_change[t]` the last changepoint of that segmentation.
+    let mut best = vec![f64::INFINITY; n + 1];
+    let mut last_change = vec![0; n + 1];
+    best[0] = -penalty;
+    let mut candidates = vec![0];
+    for end in MIN_SEGMENT_LEN..=n {
+        if end >= 2 * MIN_SEGMENT_LEN {
+            candidates.push(end - MIN_SEGMENT_LEN);
+        }
+        let costs = candidates
+            .iter()
+            .map(|&start| best[start] + cost(start, end) + penalty)
+            .collect::<Vec<_>>();
+        let (i, &min_cost) = costs
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .expect("There is always a candidate changepoint");
+        best[end] = min_cost;
+        last_change[end] = candidates[i];
+        // A candidate which can't beat the best segmentation now never will.
+        let mut costs = costs.into_iter();
+        candidates.retain(|_| costs.next().is_some_and(|cost| cost - penalty <= min_cost));
+    }
+    let mut boundaries = vec![n];
+    while let Some(&end) = boundaries.last() {
+        if end == 0 {
+            break;
+        }
+        boundaries.push(last_change[end]);
+    }
+    boundaries.reverse();
+    boundaries
+        .windows(2)
+        .map(|bounds| Segment::new(times, bounds[0], bounds[1]))
+        .collect()
+}
+
+/// Record the warmup analysis of the wall-clock times in `measurements`, as
+/// per-pexec measurements:
+/// * `analysis:segments`, the number of segments;
+/// * `analysis:warmup_iters`, the number of iterations before the last segment;
+/// * `analysis:steady_mean` and `analysis:steady_stddev`, the mean and sample
+///   standard deviation (in seconds) of the iterations of the last segment.
+///
+/// Nothing is recorded if there are no iterations.
+pub fn record(measurements: &mut Measurements) {
+    let times = measurements
+        .wallclock
+        .iter()
+        .map(|wallclock| wallclock.as_secs_f64())
+        .collect::<Vec<_>>();
+    let segments = segments(&times, default_penalty(times.len()));
+    let steady = match segments.last() {
+        Some(steady) => steady,
+        None => return,
+    };
+    for (name, value) in &[
+        ("segments", segments.len() as f64),
+        ("warmup_iters", steady.start as f64),
+        ("steady_mean", steady.mean),
+        ("steady_stddev", steady.stddev),
+    ] {
+        measurements
+            .per_pexec
+            .insert(format!("analysis:{}", name), *value);
+    }
+}
+
+/// The indices of the outliers among the wall-clock `times` of the in-process
+/// iterations of a process execution, in increasing order.
+///
+/// Each iteration is compared with the `OUTLIER_WINDOW` iterations around it (or
+/// all the iterations, if there are fewer).
+pub fn outliers(times: &[f64]) -> Vec<usize> {
+    let n = times.len();
+    let mut window = Vec::with_capacity(OUTLIER_WINDOW.min(n));
+    (0..n)
+        .filter(|&i| {
+            let start = i
+                .saturating_sub(OUTLIER_WINDOW / 2)
+                .min(n.saturating_sub(OUTLIER_WINDOW));
+            let end = (start + OUTLIER_WINDOW).min(n);
+            window.clear();
+            window.extend_from_slice(&times[start..end]);
+            window.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+            let range = percentile(&window, 0.9) - percentile(&window, 0.1);
+            (times[i] - percentile(&window, 0.5)).abs() > OUTLIER_THRESHOLD * range
+        })
+        .collect()
+}
+
+/// Flag the outliers among the wall-clock times in `measurements` (see
+/// `outliers`).
+pub fn mark_outliers(measurements: &mut Measurements) {
+    let times = measurements
+        .wallclock
+        .iter()
+        .map(|wallclock| wallclock.as_secs_f64())
+        .collect::<Vec<_>>();
+    measurements.outliers = outliers(&times);
+}
+
+/// The `quantile` of the `sorted` sample, which isn't empty, interpolating between
+/// the closest values.
+fn percentile(sorted: &[f64], quantile: f64) -> f64 {
+    let pos = quantile * (sorted.len() - 1) as f64;
+    let (lower, upper) = (pos.floor() as usize, pos.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (pos - lower as f64)
+}
+
+/// The options of the bootstrap which computes confidence intervals.
+#[derive(Debug, Clone)]
+pub struct BootstrapOpts {
+    /// The number of resamples. 10000 by default.
+    pub resamples: usize,
+    /// The confidence level of the intervals, between 0 and 1. 0.99 by default.
+    pub confidence: f64,
+    /// The seed of the random number generator. The same results, options and seed
+    /// always produce the same intervals. 0 by default.
+    pub seed: u64,
+}
+
+impl Default for BootstrapOpts {
+    fn default() -> BootstrapOpts {
+        BootstrapOpts {
+            resamples: DEFAULT_RESAMPLES,
+            confidence: DEFAULT_CONFIDENCE,
+            seed: 0,
+        }
+    }
+}
+
+/// An estimate of a statistic, with its confidence interval.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Estimate {
+    /// The value of the statistic, computed from the measurements themselves.
+    pub value: f64,
+    /// The lower bound of the confidence interval.
+    pub lower: f64,
+    /// The upper bound of the confidence interval.
+    pub upper: f64,
+}
+
+/// Summary statistics of the wall-clock times (in seconds) of a benchmark on a
+/// language implementation.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Statistics {
+    /// The number of process executions.
+    pub pexecs: usize,
+    /// The total number of in-process iterations.
+    pub iters: usize,
+    /// The mean wall-clock time of an in-process iteration.
+    pub mean: Estimate,
+    /// The median wall-clock time of an in-process iteration.
+    pub median: Estimate,
+}
+
+/// Compute the summary statistics of the wall-clock times (in seconds) of the
+/// in-process iterations of each process execution in `pexecs`. Returns `None` if
+/// there are no iterations. Process executions without any iterations are ignored.
+pub fn statistics(pexecs: &[Vec<f64>], opts: &BootstrapOpts) -> Option<Statistics> {
+    let pexecs = non_empty(pexecs);
+    let mut sample = pexecs
+        .iter()
+        .flat_map(|iters| iters.iter().copied())
+        .collect::<Vec<_>>();
+    if sample.is_empty() {
+        return None;
+    }
+    let (sample_mean, sample_median) = (mean(&sample), median(&mut sample));
+    let mut rng = StdRng::seed_from_u64(opts.seed);
+    let mut means = Vec::with_capacity(opts.resamples);
+    let mut medians = Vec::with_capacity(opts.resamples);
+    for _ in 0..opts.resamples {
+        resample(&pexecs, &mut rng, &mut sample);
+        means.push(mean(&sample));
+        medians.push(median(&mut sample));
+    }
+    Some(Statistics {
+        pexecs: pexecs.len(),
+        iters: pexecs.iter().map(|iters| iters.len()).sum(),
+        mean: estimate(sample_mean, means, opts.confidence),
+        median: estimate(sample_median, medians, opts.confidence),
+    })
+}
+
+/// The comparison of the wall-clock times of a benchmark on two language
+/// implementations.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BenchmarkComparison {
+    /// The results key of the benchmark, without the language implementation
+    /// (`<path>[<params>]{<wrapper>}`).
+    pub benchmark: String,
+    /// The speedup of the second language implementation over the first: the
+    /// ratio of their mean wall-clock times (first / second).
+    pub speedup: Estimate,
+}
+
+impl BenchmarkComparison {
+    /// Whether the difference is statistically significant: the confidence
+    /// interval of the speedup doesn't include 1.
+    pub fn significant(&self) -> bool {
+        self.speedup.lower > 1.0 || self.speedup.upper < 1.0
+    }
+}
+
+/// The comparison of two language implementations on the benchmarks they both ran.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Comparison {
+    /// The results key of the first language implementation (the baseline).
+    pub vm_a: String,
+    /// The results key of the second language implementation.
+    pub vm_b: String,
+    /// The comparison of each benchmark, in results key order.
+    pub benchmarks: Vec<BenchmarkComparison>,
+    /// The geometric mean of the speedups, or `None` if there are no benchmarks.
+    pub geomean: Option<Estimate>,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Speedup of {} over {}:", self.vm_b, self.vm_a)?;
+        let describe = |speedup: &Estimate| {
+            format!(
+                "{:.3}x ({:.3}x - {:.3}x)",
+                speedup.value, speedup.lower, speedup.upper
+            )
+        };
+        for bench in &self.benchmarks {
+            let significance = if bench.significant() {
+                ""
+            } else {
+                " (not significant)"
+            };
+            writeln!(
+                f,
+                "  {}: {}{}",
+                bench.benchmark,
+                describe(&bench.speedup),
+                significance
+            )?;
+        }
+        match &self.geomean {
+            Some(geomean) => write!(f, "Geometric mean: {}", describe(geomean)),
+            None => write!(f, "No benchmarks ran on both"),
+        }
+    }
+}
+
+/// Compare the wall-clock times of language implementation `vm_a` (the baseline)
+/// with those of `vm_b` (both identified by their results key), on each benchmark
+/// which completed successfully on both. The confidence intervals of the speedups,
+/// and of their geometric mean, are computed by a hierarchical bootstrap of the
+/// results of each language implementation.
+pub fn compare(
+    results: &mut Results,
+    vm_a: &str,
+    vm_b: &str,
+    opts: &BootstrapOpts,
+) -> Result<Comparison, K2Error> {
+    let times = results.wallclock_times()?;
+    // The results of `vm` on each benchmark, indexed by the rest of the key.
+    let benchmarks = |vm: &str| {
+        let prefix = format!("{}:", vm);
+        let benchmarks = times
+            .iter()
+            .filter_map(|(key, pexecs)| {
+                let pexecs = non_empty(pexecs);
+                let bench = key.strip_prefix(&prefix)?;
+                (!pexecs.is_empty()).then_some((bench, pexecs))
+            })
+            .collect::<BTreeMap<_, _>>();
+        if benchmarks.is_empty() {
+            Err(K2Error::InvalidConfig(format!(
+                "No successful jobs on {}",
+                vm
+            )))
+        } else {
+            Ok(benchmarks)
+        }
+    };
+    let benchmarks_a = benchmarks(vm_a)?;
+    let benchmarks_b = benchmarks(vm_b)?;
+    let common = benchmarks_a
+        .iter()
+        .filter_map(|(bench, pexecs_a)| Some((*bench, pexecs_a, benchmarks_b.get(bench)?)))
+        .collect::<Vec<_>>();
+    let pooled_mean = |pexecs: &[&Vec<f64>]| {
+        mean(
+            &pexecs
+                .iter()
+                .flat_map(|iters| iters.iter().copied())
+                .collect::<Vec<_>>(),
+        )
+    };
+    let speedups = common
+        .iter()
+        .map(|(_, pexecs_a, pexecs_b)| pooled_mean(pexecs_a) / pooled_mean(pexecs_b))
+        .collect::<Vec<_>>();
+    // The speedup of each benchmark, and their geometric mean, in each resample.
+    let mut resampled = vec![Vec::with_capacity(opts.resamples); common.len()];
+    let mut geomeans = Vec::with_capacity(opts.resamples);
+    let mut rng = StdRng::seed_from_u64(opts.seed);
+    let (mut sample_a, mut sample_b) = (Vec::new(), Vec::new());
+    for _ in 0..opts.resamples {
+        let resample_speedups = common
+            .iter()
+            .map(|(_, pexecs_a, pexecs_b)| {
+                resample(pexecs_a, &mut rng, &mut sample_a);
+                resample(pexecs_b, &mut rng, &mut sample_b);
+                mean(&sample_a) / mean(&sample_b)
+            })
+            .collect::<Vec<_>>();
+        for (resampled, speedup) in resampled.iter_mut().zip(&resample_speedups) {
+            resampled.push(*speedup);
+        }
+        if !common.is_empty() {
+            geomeans.push(geomean(&resample_speedups));
+        }
+    }
+    let geomean = if common.is_empty() {
+        None
+    } else {
+        Some(estimate(geomean(&speedups), geomeans, opts.confidence))
+    };
+    let benchmarks = common
+        .iter()
+        .zip(speedups)
+        .zip(resampled)
+        .map(
+            |(((bench, _, _), speedup), resampled)| BenchmarkComparison {
+                benchmark: bench.to_string(),
+                speedup: estimate(speedup, resampled, opts.confidence),
+            },
+        )
+        .collect();
+    Ok(Comparison {
+        vm_a: vm_a.to_string(),
+        vm_b: vm_b.to_string(),
+        benchmarks,
+        geomean,
+    })
+}
+
+/// The process executions in `pexecs` which have at least one iteration.
+fn non_empty(pexecs: &[Vec<f64>]) -> Vec<&Vec<f64>> {
+    pexecs.iter().filter(|iters| !iters.is_empty()).collect()
+}
+
+/// Replace `sample` with a hierarchical resample of `pexecs`, which must not be
+/// empty: the process executions are drawn with replacement, and then the
+/// iterations of each of them.
+fn resample<R: Rng>(pexecs: &[&Vec<f64>], rng: &mut R, sample: &mut Vec<f64>) {
+    sample.clear();
+    for _ in 0..pexecs.len() {
+        let iters = pexecs[rng.gen_range(0, pexecs.len())];
+        for _ in 0..iters.len() {
+            sample.push(iters[rng.gen_range(0, iters.len())]);
+        }
+    }
+}
+
+/// The geometric mean of `values`, which must not be empty.
+fn geomean(values: &[f64]) -> f64 {
+    (values.iter().map(|value| value.ln()).sum::<f64>() / values.len() as f64).exp()
+}
+
+/// The estimate of a statistic of value `value`, with a percentile confidence
+/// interval computed from the values of the statistic in each resample.
+fn estimate(value: f64, mut resampled: Vec<f64>, confidence: f64) -> Estimate {
+    if resampled.is_empty() {
+        return Estimate {
+            value,
+            lower: value,
+            upper: value,
+        };
+    }
+    resampled.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let alpha = (1.0 - confidence.clamp(0.0, 1.0)) / 2.0;
+    let last = resampled.len() - 1;
+    let idx = |quantile: f64| ((quantile * last as f64).round() as usize).min(last);
+    Estimate {
+        value,
+        lower: resampled[idx(alpha)],
+        upper: resampled[idx(1.0 - alpha)],
+    }
+}
+
+/// The mean of `sample`, which isn't empty.
+fn mean(sample: &[f64]) -> f64 {
+    sample.iter().sum::<f64>() / sample.len() as f64
+}
+
+/// The median of `sample`, which isn't empty. The sample is reordered.
+fn median(sample: &mut [f64]) -> f64 {
+    let n = sample.len();
+    let cmp = |a: &f64, b: &f64| a.partial_cmp(b).unwrap_or(Ordering::Equal);
+    let (lower, upper, _) = sample.select_nth_unstable_by(n / 2, cmp);
+    let upper = *upper;
+    if n % 2 == 1 {
+        upper
+    } else {
+        // The largest value below the middle one.
+        let lower = lower.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        (lower + upper) / 2.0
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{config::Config, error::K2Error, isolation, numa, util};
+
+use std::{collections::BTreeMap, ffi::CStr, fs, mem, path::Path};
+
+/// The value recorded for a property which couldn't be read.
+const UNKNOWN: &str = "unknown";
+
+/// A description of the environment an experiment runs in, indexed by property
+/// name.
+pub type Audit = BTreeMap<String, String>;
+
+/// Describe the current environment. `config` is included in the audit, so that
+/// an experiment can't be resumed with a different configuration by mistake.
+pub(crate) fn collect(config: &Config) -> Audit {
+    let mut audit = Audit::new();
+    audit.insert("hostname".into(), util::hostname());
+    audit.insert("uname".into(), uname());
+    audit.insert("cpu_model".into(), cpu_model());
+    audit.insert("num_cpus".into(), num_cpus());
+    audit.insert("mem_total".into(), meminfo("MemTotal"));
+    audit.insert("kernel_cmdline".into(), read_trimmed("/proc/cmdline"));
+    audit.insert("governors".into(), governors());
+    audit.insert(
+        "aslr".into(),
+        read_trimmed("/proc/sys/kernel/randomize_va_space"),
+    );
+    let (isolcpus, nohz_full) = isolation::kernel_isolation();
+    audit.insert("isolcpus".into(), isolcpus);
+    audit.insert("nohz_full".into(), nohz_full);
+    audit.insert("numa_topology".into(), numa::topology());
+    audit.insert(
+        "sched_policy".into(),
+        config
+            .sched_policy
+            .map_or_else(|| "inherit".into(), |sched_policy| sched_policy.name()),
+    );
+    audit.insert("clock".into(), config.clock.name().into());
+    audit.insert(
+        "clock_resolution_ns".into(),
+        config
+            .clock
+            .resolution()
+            .map_or_else(|_| UNKNOWN.into(), |res| res.as_nanos().to_string()),
+    );
+    audit.insert("config".into(), format!("{:?}", config));
+    audit
+}
+
+/// Check that the `current` environment is the same as the `recorded` one.
+///
+/// Returns `K2Error::AuditMismatch` listing the properties which changed.
+pub(crate) fn check(recorded: &Audit, current: &Audit) -> Result<(), K2Error> {
+    let changed = recorded
+        .iter()
+        .filter(|(key, value)| current.get(*key) != Some(value))
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    if changed.is_empty() {
+        Ok(())
+    } else {
+        Err(K2Error::AuditMismatch(changed))
+    }
+}
+
+/// The equivalent of `uname -srvm`.
+fn uname() -> String {
+    let mut uts = unsafe { mem::zeroed::<libc::utsname>() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return UNKNOWN.into();
+    }
+    let field = |field: &[libc::c_char]| {
+        unsafe { CStr::from_ptr(field.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    format!(
+        "{} {} {} {}",
+        field(&uts.sysname),
+        field(&uts.release),
+        field(&uts.version),
+        field(&uts.machine)
+    )
+}
+
+/// The model name of the first CPU listed in `/proc/cpuinfo`.
+fn cpu_model() -> String {
+    fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|cpuinfo| {
+            cpuinfo
+                .lines()
+                .find(|line| line.starts_with("model name"))
+                .and_then(|line| line.split_once(':'))
+                .map(|(_, model)| model.trim().to_string())
+        })
+        .unwrap_or_else(|| UNKNOWN.into())
+}
+
+fn num_cpus() -> String {
+    match unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) } {
+        n if n > 0 => n.to_string(),
+        _ => UNKNOWN.into(),
+    }
+}
+
+/// The value of `field` in `/proc/meminfo`.
+fn meminfo(field: &str) -> String {
+    fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|meminfo| {
+            meminfo
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(key, _)| *key == field)
+                .map(|(_, value)| value.trim().to_string())
+        })
+        .unwrap_or_else(|| UNKNOWN.into())
+}
+
+/// The scaling governor of each CPU, separated by commas.
+fn governors() -> String {
+    let governors = util::scaling_governors();
+    if governors.is_empty() {
+        return UNKNOWN.into();
+    }
+    governors
+        .iter()
+        .map(read_trimmed)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Read the file at `path`, without any leading or trailing whitespace.
+fn read_trimmed<P: AsRef<Path>>(path: P) -> String {
+    fs::read_to_string(path)
+        .map(|contents| contents.trim().to_string())
+        .unwrap_or_else(|_| UNKNOWN.into())
+}
@@ -1,9 +1,61 @@
-use crate::{config::Config, error::K2Error, lang_impl::LangImpl, limit::Limit};
+use crate::{
+    config::{Config, QUICK_IN_PROC_ITERS},
+    error::K2Error,
+    lang_impl::LangImpl,
+    limit::{CgroupLimits, Limit, ResourceLimits},
+    measure::{self, MeasurementCollector, Measurements},
+    output,
+    process::{InvocationOutcome, MemoryUsage, ProcessOpts},
+    profile::Profile,
+    util,
+};
 
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{self, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Returns `K2Error::File` if `path` doesn't exist.
+fn check_exists(path: &Path) -> Result<(), K2Error> {
+    if path.exists() {
+        Ok(())
+    } else {
+        Err(K2Error::not_found(path))
+    }
+}
+
+/// Returns `K2Error::ExecutionFailed` if the benchmark process didn't succeed.
+fn check_success(outcome: &InvocationOutcome) -> Result<(), K2Error> {
+    if outcome.success() {
+        Ok(())
+    } else {
+        Err(K2Error::ExecutionFailed {
+            exit_code: outcome.exit_code,
+            signal: outcome.signal,
+            stderr: String::from_utf8_lossy(&outcome.stderr).into_owned(),
+        })
+    }
+}
 
 /// The key of the path tag.
 pub const TAG_PATH: &str = "path";
+/// The key of the tag which records the entry point of a project benchmark (see
+/// `Benchmark::entry`).
+pub const TAG_ENTRY: &str = "entry";
+/// The key of the tag which records the file fed to the standard input of a
+/// benchmark.
+pub const TAG_STDIN_FILE: &str = "stdin_file";
+/// The key of the tag which records the command a benchmark is wrapped in.
+pub const TAG_WRAPPER: &str = "wrapper";
+/// The key of the tag which records the version of the language implementation a
+/// job runs on (see `LangImpl::version`).
+pub const TAG_LANG_IMPL_VERSION: &str = "lang_impl_version";
 
 /// A collection of tags associated with a benchmark.
 ///
@@ -11,41 +63,326 @@ pub const TAG_PATH: &str = "path";
 /// and the results of a benchmark.
 pub type TagStore = HashMap<String, String>;
 
+/// The output a benchmark is expected to print on its standard output. See
+/// `Benchmark::expect_stdout`.
+#[derive(Debug, Clone)]
+enum ExpectedStdout {
+    /// The expected output itself.
+    Text(String),
+    /// The file which contains the expected output.
+    File(PathBuf),
+}
+
 /// A benchmark, which consists of a set of tags, and a list of language
 /// implementations the benchmark will be run on.
-pub struct Benchmark<'a> {
+#[derive(Clone)]
+pub struct Benchmark {
     tags: TagStore,
     /// The command-line arguments passed to this benchmark.
     args: Vec<String>,
-    lang_impl: &'a dyn LangImpl,
+    /// The environment variables set for this benchmark, which override those of
+    /// its language implementation.
+    env: HashMap<String, String>,
+    /// The parameters to sweep, with the values of each. See `param`.
+    params: Vec<(String, Vec<String>)>,
+    /// The value of each parameter, if this benchmark was created by `expand`.
+    param_values: Vec<(String, String)>,
+    /// What the language implementation runs, if the benchmark is a project. See
+    /// `entry`.
+    entry: Option<String>,
+    /// The working directory of the benchmark. See `cwd`.
+    cwd: Option<PathBuf>,
+    /// The files copied to the working directory before each process execution.
+    /// See `input`.
+    inputs: Vec<PathBuf>,
+    /// The file fed to the standard input of the benchmark. See `stdin_file`.
+    stdin_file: Option<PathBuf>,
+    /// The output the benchmark is expected to print. See `expect_stdout`.
+    expected_stdout: Option<ExpectedStdout>,
+    /// The program (followed by its arguments) the benchmark is run with, or an
+    /// empty list if it's run directly. See `wrapper`.
+    wrapper: Vec<String>,
+    lang_impl: Arc<dyn LangImpl>,
     /// The stack size limit. `None` by default.
     pub stack_lim: Option<Limit>,
     /// The heap size limit. `None` by default.
     pub heap_lim: Option<Limit>,
+    /// The CPU time limit. `None` by default.
+    pub cpu_lim: Option<Limit>,
+    /// The limits of the cgroup each process of this benchmark is run in.
+    /// Overrides `Config::cgroup` if set.
+    pub cgroup: Option<CgroupLimits>,
+    /// The timeout of this benchmark. Overrides `Config::timeout` if set.
+    pub timeout: Option<Duration>,
+    /// The CPUs to pin this benchmark to. Overrides `Config::cpu_affinity` if set.
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// The NUMA node to bind this benchmark to. Overrides `Config::numa_node` if set.
+    pub numa_node: Option<usize>,
+    /// The number of in-process iterations of this benchmark. Overrides
+    /// `Config::in_proc_iters` if set.
+    pub in_proc_iters: Option<usize>,
+    /// The priority of this benchmark. The jobs of the benchmarks with a higher
+    /// priority run first (see `OrderingStrategy`). 0 by default.
+    pub priority: i32,
 }
 
-impl<'a> Benchmark<'a> {
+impl Benchmark {
     /// Create a new benchmark with the specified path.
-    pub fn new(path: &str, lang_impl: &'a dyn LangImpl) -> Benchmark<'a> {
+    pub fn new(path: &str, lang_impl: Arc<dyn LangImpl>) -> Benchmark {
         let b = Benchmark {
-            tags: Default::default(),
+            tags: lang_impl.tags(),
             args: Default::default(),
+            env: Default::default(),
+            params: Default::default(),
+            param_values: Default::default(),
+            entry: None,
+            cwd: None,
+            inputs: Default::default(),
+            stdin_file: None,
+            expected_stdout: None,
+            wrapper: Default::default(),
             lang_impl,
             stack_lim: None,
             heap_lim: None,
+            cpu_lim: None,
+            cgroup: None,
+            timeout: None,
+            cpu_affinity: None,
+            numa_node: None,
+            in_proc_iters: None,
+            priority: 0,
         };
         // The path tag is mandatory (k2 can't run the benchmark without knowing
         // the path).
         b.tag("path", path)
     }
 
-    pub(crate) fn run(&self, _config: &Config) -> Result<(), K2Error> {
-        self.lang_impl.invoke(self);
-        Ok(())
+    /// Run the benchmark as the job with identifier `job_id` (profiling it if
+    /// `profile` is set), and return the measurements gathered by `collectors`.
+    /// If `cpus` is set, the benchmark is pinned to them instead of its own CPUs
+    /// (see `Config::slots`).
+    ///
+    /// The output of the benchmark is stored in the results directory.
+    pub(crate) fn run(
+        &self,
+        config: &Config,
+        job_id: usize,
+        cpus: Option<&[usize]>,
+        profile: Option<Profile>,
+        collectors: &mut [Box<dyn MeasurementCollector>],
+    ) -> Result<Measurements, K2Error> {
+        if config.dry_run {
+            return self.dry_run(config);
+        }
+        let mut opts = self.process_opts(config);
+        if let Some(cpus) = cpus {
+            opts.cpu_affinity = Some(cpus.to_vec());
+        }
+        opts.profile = profile;
+        // The files are copied before the measurements start, so that the time it
+        // takes isn't measured.
+        self.lang_impl.stage(self)?;
+        for collector in collectors.iter_mut() {
+            collector.start()?;
+        }
+        let outcome = self.lang_impl.invoke(self, &opts);
+        for collector in collectors.iter_mut().rev() {
+            collector.stop()?;
+        }
+        let outcome = outcome?;
+        output::save(&config.results_dir, job_id, &outcome)?;
+        if let Some(reason) = measure::rerun_request(&outcome.stderr) {
+            info!("job {} asked to be run again: {}", job_id, reason);
+            return Err(K2Error::RerunError);
+        }
+        // A process which exceeds its CPU time limit is sent `SIGXCPU`.
+        if let (Some(Limit::Secs(secs)), Some(libc::SIGXCPU)) = (self.cpu_lim, outcome.signal) {
+            return Err(K2Error::TimedOut(Duration::from_secs_f32(secs)));
+        }
+        check_success(&outcome)?;
+        self.check_stdout(&outcome.stdout)?;
+        let mut measurements = Measurements::default();
+        for collector in collectors.iter_mut() {
+            collector.collect(&outcome, &mut measurements)?;
+        }
+        Ok(measurements)
+    }
+
+    /// Run the benchmark once without any resource limits (other than those k2
+    /// itself runs under), and return the memory usage of its process (see
+    /// `calibrate`). Its output isn't stored.
+    pub(crate) fn calibrate(&self, config: &Config) -> Result<MemoryUsage, K2Error> {
+        let mut opts = self.process_opts(config);
+        opts.limits = ResourceLimits::default();
+        opts.sample_memory = true;
+        self.lang_impl.stage(self)?;
+        let outcome = self.lang_impl.invoke(self, &opts)?;
+        check_success(&outcome)?;
+        Ok(outcome.memory.unwrap_or_default())
+    }
+
+    /// Run the benchmark once, to measure the overhead of its language
+    /// implementation (see `overhead`). Returns how long the process took, and the
+    /// wall-clock times of the in-process iterations it reported.
+    pub(crate) fn run_empty(&self, config: &Config) -> Result<(Duration, Measurements), K2Error> {
+        let opts = self.process_opts(config);
+        self.lang_impl.stage(self)?;
+        let start = config.clock.now()?;
+        let outcome = self.lang_impl.invoke(self, &opts)?;
+        let elapsed = config.clock.now()?.saturating_sub(start);
+        check_success(&outcome)?;
+        let stdout = String::from_utf8_lossy(&outcome.stdout);
+        Ok((elapsed, Measurements::from_stdout(&stdout)?))
+    }
+
+    /// The options to run the benchmark process with.
+    fn process_opts(&self, config: &Config) -> ProcessOpts {
+        ProcessOpts {
+            in_proc_iters: self.effective_in_proc_iters(config),
+            timeout: self.timeout.or(config.timeout),
+            kill_behaviour: config.kill_behaviour,
+            interrupt_grace: config.interrupt_grace,
+            limits: self.limits(config),
+            cpu_affinity: self.effective_cpu_affinity(config).cloned(),
+            numa_node: self.effective_numa_node(config),
+            isolate_network: config.isolate_network,
+            sched_policy: config.sched_policy,
+            perf_events: config.perf_events.clone(),
+            stdin: self.stdin_file.clone(),
+            profile: None,
+            sample_memory: false,
+            clock: config.clock,
+            cpu_time: config.cpu_time,
+        }
+    }
+
+    /// Pretend to run the benchmark. The benchmark isn't executed, but its path, its
+    /// working directory and its input files (including its standard input) are
+    /// checked, so that a broken experiment definition is caught early.
+    fn dry_run(&self, config: &Config) -> Result<Measurements, K2Error> {
+        self.check_path()?;
+        self.check_files()?;
+        Ok(Measurements::synthetic(
+            self.effective_in_proc_iters(config),
+        ))
+    }
+
+    /// Check that the path of the benchmark is a file which exists (or, if the
+    /// benchmark is a project, that its entry point is).
+    pub fn check_path(&self) -> Result<(), K2Error> {
+        check_exists(&self.entry_path()?)
+    }
+
+    /// Check that the other files the benchmark needs exist: its working directory,
+    /// its input files, its standard input, and its expected output.
+    pub fn check_files(&self) -> Result<(), K2Error> {
+        let mut paths = Vec::new();
+        paths.extend(self.cwd.clone());
+        paths.extend(self.inputs.iter().cloned());
+        paths.extend(self.stdin_file.clone());
+        if let Some(ExpectedStdout::File(path)) = &self.expected_stdout {
+            paths.push(path.clone());
+        }
+        paths.iter().try_for_each(|path| check_exists(path))
+    }
+
+    /// Check that the benchmark printed the output it was expected to, ignoring the
+    /// lines which report its measurements.
+    fn check_stdout(&self, stdout: &[u8]) -> Result<(), K2Error> {
+        let expected = match &self.expected_stdout {
+            Some(ExpectedStdout::Text(text)) => text.clone(),
+            Some(ExpectedStdout::File(path)) => fs::read_to_string(path)?,
+            None => return Ok(()),
+        };
+        let expected = expected.lines().collect::<Vec<_>>();
+        let stdout = String::from_utf8_lossy(stdout);
+        let actual = stdout
+            .lines()
+            .filter(|line| !measure::is_measurement(line))
+            .collect::<Vec<_>>();
+        if actual == expected {
+            return Ok(());
+        }
+        let line = actual
+            .iter()
+            .zip(&expected)
+            .take_while(|(actual, expected)| actual == expected)
+            .count();
+        let describe = |lines: &[&str]| match lines.get(line) {
+            Some(line) => format!("{:?}", line),
+            None => "the end of the output".to_string(),
+        };
+        Err(K2Error::WrongAnswer(format!(
+            "line {}: expected {}, found {}",
+            line + 1,
+            describe(&expected),
+            describe(&actual)
+        )))
+    }
+
+    /// The resource limits of the benchmark.
+    pub(crate) fn limits(&self, config: &Config) -> ResourceLimits {
+        ResourceLimits {
+            stack: self.stack_lim,
+            heap: self.heap_lim,
+            cpu: self.cpu_lim,
+            cgroup: self.cgroup.clone().or_else(|| config.cgroup.clone()),
+        }
+    }
+
+    /// The CPUs this benchmark is pinned to, or `None` if it can run on any CPU.
+    pub(crate) fn effective_cpu_affinity<'c>(
+        &'c self,
+        config: &'c Config,
+    ) -> Option<&'c Vec<usize>> {
+        self.cpu_affinity
+            .as_ref()
+            .or(config.cpu_affinity.as_ref())
+            .or(config.isolated_cpus.as_ref())
     }
 
-    pub fn results_key(&self) -> String {
-        format!("{}:{}", self.lang_impl.results_key(), self.path())
+    /// The NUMA node this benchmark is bound to, or `None` if it isn't bound to one.
+    pub(crate) fn effective_numa_node(&self, config: &Config) -> Option<usize> {
+        self.numa_node.or(config.numa_node)
+    }
+
+    /// The number of in-process iterations of this benchmark. In quick mode, a
+    /// benchmark which sets its own runs no more iterations than the other ones.
+    pub(crate) fn effective_in_proc_iters(&self, config: &Config) -> usize {
+        match self.in_proc_iters {
+            Some(in_proc_iters) if config.quick => in_proc_iters.min(QUICK_IN_PROC_ITERS),
+            Some(in_proc_iters) => in_proc_iters,
+            None => config.in_proc_iters,
+        }
+    }
+
+    /// The language implementation this benchmark runs on.
+    pub(crate) fn lang_impl(&self) -> &Arc<dyn LangImpl> {
+        &self.lang_impl
+    }
+
+    /// The key which identifies the results of this benchmark:
+    /// `<lang_impl>:<path>`, followed by the value of each parameter
+    /// (`[<name>=<value>,...]`), if any, and by the wrapper (`{<wrapper>}`), if
+    /// any.
+    pub fn results_key(&self) -> Result<String, K2Error> {
+        let mut key = format!(
+            "{}:{}",
+            self.lang_impl.results_key(),
+            self.entry_path()?.display()
+        );
+        if !self.param_values.is_empty() {
+            let params = self
+                .param_values
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>();
+            key = format!("{}[{}]", key, params.join(","));
+        }
+        if !self.wrapper.is_empty() {
+            key = format!("{}{{{}}}", key, self.wrapper.join(" "));
+        }
+        Ok(key)
     }
 
     /// Get all the arguments passed to this benchmark.
@@ -59,9 +396,228 @@ impl<'a> Benchmark<'a> {
         self
     }
 
+    /// Run the benchmark through `cmd`, which is passed `args` followed by the
+    /// command which would otherwise run the benchmark (e.g. `numactl
+    /// --membind=0`, or `perf stat -o perf.txt`). The wrapper is recorded as tag
+    /// `wrapper`, and in the results key.
+    ///
+    /// The wrapper runs with the resource limits, CPU affinity and performance
+    /// counters of the benchmark, so anything it does is measured too.
+    pub fn wrapper(mut self, cmd: &str, args: &[&str]) -> Self {
+        self.wrapper = std::iter::once(cmd)
+            .chain(args.iter().copied())
+            .map(str::to_string)
+            .collect();
+        let wrapper = self.wrapper.join(" ");
+        self.tag(TAG_WRAPPER, &wrapper)
+    }
+
+    /// The program (followed by its arguments) the benchmark is run with, or an
+    /// empty list if it's run directly. See `wrapper`.
+    pub fn wrapper_cmd(&self) -> &[String] {
+        &self.wrapper
+    }
+
+    /// Get the environment variables set for this benchmark.
+    pub fn env_vars(&self) -> &HashMap<String, String> {
+        &self.env
+    }
+
+    /// Set environment variable `k` to `v` when running the benchmark. This
+    /// overrides the value the language implementation sets `k` to, if any.
+    pub fn env(mut self, k: &str, v: &str) -> Self {
+        self.env.insert(k.to_string(), v.to_string());
+        self
+    }
+
+    /// Run the benchmark once with each of the `values` of parameter `name`. The
+    /// value is appended to the arguments of the benchmark, and recorded as tag
+    /// `name`.
+    ///
+    /// If several parameters are declared, the benchmark is run with every
+    /// combination of their values, and the values are appended to the arguments in
+    /// the order the parameters were declared.
+    pub fn param(mut self, name: &str, values: &[&str]) -> Self {
+        let values = values.iter().map(|value| value.to_string()).collect();
+        self.params.push((name.to_string(), values));
+        self
+    }
+
+    /// Create one benchmark for each combination of the values of the parameters of
+    /// this benchmark (see `param`). A benchmark without parameters expands to
+    /// itself.
+    pub fn expand(&self) -> Vec<Benchmark> {
+        let mut expanded = vec![Benchmark {
+            params: Default::default(),
+            ..self.clone()
+        }];
+        for (name, values) in &self.params {
+            expanded = expanded
+                .iter()
+                .flat_map(|bench| {
+                    values.iter().map(move |value| {
+                        let mut bench = bench.clone().tag(name, value).arg(value.clone());
+                        bench.param_values.push((name.clone(), value.clone()));
+                        bench
+                    })
+                })
+                .collect();
+        }
+        expanded
+    }
+
     /// The path of the benchmark.
-    pub fn path(&self) -> &str {
-        self.tags.get(TAG_PATH).expect("Benchmark path not set.")
+    pub fn path(&self) -> Result<&str, K2Error> {
+        self.tag_value(TAG_PATH)
+            .ok_or(K2Error::MissingTag(TAG_PATH))
+    }
+
+    /// The path of the file the benchmark is run from: the path of the benchmark, or
+    /// the entry point of a project, in its directory (see `entry`).
+    pub(crate) fn entry_path(&self) -> Result<PathBuf, K2Error> {
+        let path = Path::new(self.path()?);
+        Ok(match &self.entry {
+            Some(entry) => path.join(entry),
+            None => path.to_path_buf(),
+        })
+    }
+
+    /// The absolute path of the file the benchmark is run from (see `entry_path`),
+    /// which remains valid when the benchmark is run in its working directory.
+    pub(crate) fn absolute_path(&self) -> Result<PathBuf, K2Error> {
+        Ok(path::absolute(self.entry_path()?)?)
+    }
+
+    /// Make the benchmark a project, rather than a single file: its path is a
+    /// directory (e.g. a Maven project, or a Python package made of several files),
+    /// and `entry` is what its language implementation runs: a file in the
+    /// directory (e.g. `main.py`, or `target/bench.jar`), or, on a `Jvm`, the name
+    /// of the main class. The entry point is recorded as tag `entry`, and is part
+    /// of the results key (`<path>/<entry>`), while the path tag is the directory.
+    ///
+    /// A project runs in its directory, unless `cwd` is set.
+    pub fn entry(mut self, entry: &str) -> Self {
+        self.entry = Some(entry.to_string());
+        self.tag(TAG_ENTRY, entry)
+    }
+
+    /// The entry point of the benchmark, if it is a project (see `entry`).
+    pub fn entry_point(&self) -> Option<&str> {
+        self.entry.as_deref()
+    }
+
+    /// Run the benchmark in directory `path`, rather than in the directory which
+    /// contains the benchmark (or the directory of a project).
+    pub fn cwd<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.cwd = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// The working directory of the benchmark: the directory set with `cwd`, the
+    /// directory of a project, or the directory which contains the benchmark. A
+    /// relative path is relative to the working directory of k2.
+    pub fn working_dir(&self) -> Result<PathBuf, K2Error> {
+        match &self.cwd {
+            Some(cwd) => Ok(cwd.clone()),
+            None if self.entry.is_some() => Ok(PathBuf::from(self.path()?)),
+            None => Ok(Path::new(self.path()?)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."))
+                .to_path_buf()),
+        }
+    }
+
+    /// Copy the file at `path` to the working directory of the benchmark before each
+    /// process execution, so that the benchmark can open it using its file name.
+    pub fn input<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.inputs.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Feed the file at `path` to the standard input of the benchmark, rather than
+    /// `/dev/null`. The path is recorded as tag `stdin_file`.
+    pub fn stdin_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        let path = path.as_ref();
+        self.stdin_file = Some(path.to_path_buf());
+        self.tag(TAG_STDIN_FILE, &path.to_string_lossy())
+    }
+
+    /// Check that each process execution of the benchmark prints `text` on its
+    /// standard output. The lines which report measurements (see
+    /// `measure::WALLCLOCK_PREFIX` and `measure::CPU_TIME_PREFIX`) are ignored, and
+    /// so is a missing newline at the end of the output. A process execution which
+    /// prints anything else is recorded as a wrong answer, and its measurements are
+    /// discarded.
+    pub fn expect_stdout(mut self, text: &str) -> Self {
+        self.expected_stdout = Some(ExpectedStdout::Text(text.to_string()));
+        self
+    }
+
+    /// Like `expect_stdout`, but the expected output is read from the file at
+    /// `path`.
+    pub fn expect_stdout_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.expected_stdout = Some(ExpectedStdout::File(path.as_ref().to_path_buf()));
+        self
+    }
+
+    /// The input files of the benchmark. See `input`.
+    pub fn inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    /// The files the benchmark reads when it runs: the benchmark itself (or the
+    /// files of a project, see `project_files`), its input files, its standard
+    /// input, and the executable of its language implementation (see `cache`).
+    pub(crate) fn files(&self) -> Result<Vec<PathBuf>, K2Error> {
+        let mut files = match &self.entry {
+            Some(_) => self.project_files()?,
+            None => vec![self.absolute_path()?],
+        };
+        files.extend(self.inputs.iter().cloned());
+        files.extend(self.stdin_file.clone());
+        files.extend(self.lang_impl.executable().map(util::program_path));
+        Ok(files)
+    }
+
+    /// The files of a project (see `entry`), found recursively, in a stable order, or
+    /// nothing if the benchmark isn't a project (or its directory doesn't exist yet).
+    ///
+    /// The files the benchmark runs with, but which aren't part of the project,
+    /// are left out: the hidden files and directories (such as `.git`), the
+    /// bytecode caches Python writes while the benchmark runs (`__pycache__`), and
+    /// the input files copied to the directory (see `input`).
+    pub(crate) fn project_files(&self) -> Result<Vec<PathBuf>, K2Error> {
+        let dir = match &self.entry {
+            Some(_) => PathBuf::from(self.path()?),
+            None => return Ok(Vec::new()),
+        };
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let working_dir = path::absolute(self.working_dir()?)?;
+        let staged = self
+            .inputs
+            .iter()
+            .map(|input| Ok(working_dir.join(input_file_name(input)?)))
+            .collect::<Result<Vec<_>, K2Error>>()?;
+        let mut files = Vec::new();
+        walk_project(&dir, &staged, &mut files)?;
+        Ok(files)
+    }
+
+    /// Copy the input files of the benchmark to its working directory. Inputs which
+    /// are already in the working directory are left alone.
+    pub(crate) fn stage_inputs(&self) -> Result<(), K2Error> {
+        let working_dir = path::absolute(self.working_dir()?)?;
+        for input in &self.inputs {
+            let dest = working_dir.join(input_file_name(input)?);
+            if dest.exists() && fs::canonicalize(&dest)? == fs::canonicalize(input)? {
+                continue;
+            }
+            fs::copy(input, &dest)?;
+        }
+        Ok(())
     }
 
     /// Retrieve the tags recorded for this benchmark.
@@ -75,19 +631,16 @@ impl<'a> Benchmark<'a> {
         self
     }
 
-    /// Get the value of the tag with key `t`.
-    fn tag_value(&self, t: &str) -> &str {
-        &self
-            .tags
-            .get(t)
-            .unwrap_or_else(|| panic!("Tag key {} doesn't have an associated value!", t))
+    /// Get the value of the tag with key `t`, or `None` if the tag isn't set.
+    fn tag_value(&self, t: &str) -> Option<&str> {
+        self.tags.get(t).map(String::as_str)
     }
 
     /// Check if the value of the tag identified by `t` matches `val`.
-    fn matches_tag(&self, t: &str, val: &str) -> bool {
+    pub fn matches_tag(&self, t: &str, val: &str) -> bool {
         // This function could implement a more sophisticated check to decide whether
         // `val` is a match.
-        self.tag_value(t) == val
+        self.tag_value(t) == Some(val)
     }
 
     pub fn stack_lim(mut self, stack_lim: Limit) -> Self {
@@ -99,4 +652,265 @@ impl<'a> Benchmark<'a> {
         self.heap_lim = Some(heap_lim);
         self
     }
+
+    /// Limit the CPU time of each process of the benchmark to `cpu_lim` (which must
+    /// be a `Limit::Secs`). A process which exceeds it is recorded as timed out.
+    pub fn cpu_lim(mut self, cpu_lim: Limit) -> Self {
+        self.cpu_lim = Some(cpu_lim);
+        self
+    }
+
+    /// Run each process of the benchmark in its own transient cgroup, with the
+    /// specified limits.
+    pub fn cgroup(mut self, cgroup: CgroupLimits) -> Self {
+        self.cgroup = Some(cgroup);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn cpu_affinity(mut self, cpus: Vec<usize>) -> Self {
+        self.cpu_affinity = Some(cpus);
+        self
+    }
+
+    pub fn numa_node(mut self, node: usize) -> Self {
+        self.numa_node = Some(node);
+        self
+    }
+
+    /// Run `in_proc_iters` in-process iterations of this benchmark, instead of
+    /// `Config::in_proc_iters` (e.g. because it takes many iterations to warm up, or
+    /// because each iteration takes minutes). The number of iterations each job ran
+    /// is recorded in its `JobRecord::in_proc_iters`.
+    pub fn in_proc_iters(mut self, in_proc_iters: usize) -> Self {
+        self.in_proc_iters = Some(in_proc_iters);
+        self
+    }
+
+    /// Run the jobs of this benchmark before those of the benchmarks with a lower
+    /// `priority` (0 by default), so that, if the experiment has to be cut short,
+    /// the benchmarks which matter most (e.g. those which were just added) have
+    /// finished. The jobs of the benchmarks with the same priority are ordered by
+    /// `Config::ordering`, as usual.
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// An owned description of this benchmark, which can be serialized, and turned
+    /// back into the same benchmark with `BenchmarkSpec::build`.
+    ///
+    /// The tags k2 sets itself (those of the language implementation, the path,
+    /// `entry`, `stdin_file`, `wrapper`, and the parameters) aren't part of `tags`. A
+    /// benchmark created by `expand` is described as a benchmark with a single
+    /// value for each of its parameters.
+    pub fn spec(&self) -> BenchmarkSpec {
+        let lang_impl_tags = self.lang_impl.tags();
+        let is_param = |tag: &str| self.param_values.iter().any(|(name, _)| name == tag);
+        let tags = self
+            .tags
+            .iter()
+            .filter(|(tag, value)| {
+                ![TAG_PATH, TAG_ENTRY, TAG_STDIN_FILE, TAG_WRAPPER].contains(&tag.as_str())
+                    && !is_param(tag)
+                    && lang_impl_tags.get(*tag) != Some(*value)
+            })
+            .map(|(tag, value)| (tag.clone(), value.clone()))
+            .collect();
+        // `expand` appended the values of the parameters to the arguments.
+        let num_args = self.args.len().saturating_sub(self.param_values.len());
+        let params = self
+            .params
+            .iter()
+            .cloned()
+            .chain(
+                self.param_values
+                    .iter()
+                    .map(|(name, value)| (name.clone(), vec![value.clone()])),
+            )
+            .collect();
+        let (expect_stdout, expect_stdout_file) = match &self.expected_stdout {
+            Some(ExpectedStdout::Text(text)) => (Some(text.clone()), None),
+            Some(ExpectedStdout::File(path)) => (None, Some(path.clone())),
+            None => (None, None),
+        };
+        BenchmarkSpec {
+            path: self.path().unwrap_or_default().to_string(),
+            entry: self.entry.clone(),
+            tags,
+            args: self.args[..num_args].to_vec(),
+            env: self.env.clone().into_iter().collect(),
+            params,
+            cwd: self.cwd.clone(),
+            inputs: self.inputs.clone(),
+            stdin_file: self.stdin_file.clone(),
+            expect_stdout,
+            expect_stdout_file,
+            wrapper: self.wrapper.clone(),
+            stack_lim: self.stack_lim,
+            heap_lim: self.heap_lim,
+            cpu_lim: self.cpu_lim,
+            cgroup: self.cgroup.clone(),
+            timeout: self.timeout,
+            cpu_affinity: self.cpu_affinity.clone(),
+            numa_node: self.numa_node,
+            in_proc_iters: self.in_proc_iters,
+            priority: self.priority,
+        }
+    }
+}
+
+/// An owned description of a benchmark, independent of the language
+/// implementation it runs on, which can be (de)serialized with serde. See
+/// `Benchmark::spec`.
+///
+/// Each field corresponds to the `Benchmark` method of the same name. Only `path`
+/// is required when deserializing; the limits are strings such as `"8MiB"`, and
+/// the timeout is in seconds.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BenchmarkSpec {
+    pub path: String,
+    pub entry: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// The parameters to sweep, with the values of each, in the order they are
+    /// declared.
+    #[serde(default)]
+    pub params: Vec<(String, Vec<String>)>,
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub inputs: Vec<PathBuf>,
+    pub stdin_file: Option<PathBuf>,
+    pub expect_stdout: Option<String>,
+    pub expect_stdout_file: Option<PathBuf>,
+    /// The program (followed by its arguments) to run the benchmark with, or an
+    /// empty list to run it directly.
+    #[serde(default)]
+    pub wrapper: Vec<String>,
+    pub stack_lim: Option<Limit>,
+    pub heap_lim: Option<Limit>,
+    pub cpu_lim: Option<Limit>,
+    #[serde(default, with = "crate::util::opt_secs")]
+    pub timeout: Option<Duration>,
+    pub cpu_affinity: Option<Vec<usize>>,
+    pub numa_node: Option<usize>,
+    pub in_proc_iters: Option<usize>,
+    #[serde(default)]
+    pub priority: i32,
+    // The tables come last, so that the spec can be serialized as TOML.
+    /// The tags set by the user.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub cgroup: Option<CgroupLimits>,
+}
+
+impl BenchmarkSpec {
+    /// Describe the benchmark at `path`, which doesn't set anything else.
+    pub fn new(path: &str) -> BenchmarkSpec {
+        BenchmarkSpec {
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Create the benchmark described by this spec, which runs on `lang_impl`.
+    pub fn build(&self, lang_impl: Arc<dyn LangImpl>) -> Result<Benchmark, K2Error> {
+        let mut bench = Benchmark::new(&self.path, lang_impl);
+        if let Some(entry) = &self.entry {
+            bench = bench.entry(entry);
+        }
+        if let [cmd, args @ ..] = self.wrapper.as_slice() {
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            bench = bench.wrapper(cmd, &args);
+        }
+        for arg in &self.args {
+            bench = bench.arg(arg.clone());
+        }
+        for (k, v) in &self.env {
+            bench = bench.env(k, v);
+        }
+        for (k, v) in &self.tags {
+            bench = bench.tag(k, v);
+        }
+        for (name, values) in &self.params {
+            let values = values.iter().map(String::as_str).collect::<Vec<_>>();
+            bench = bench.param(name, &values);
+        }
+        if let Some(cwd) = &self.cwd {
+            bench = bench.cwd(cwd);
+        }
+        for input in &self.inputs {
+            bench = bench.input(input);
+        }
+        if let Some(stdin_file) = &self.stdin_file {
+            bench = bench.stdin_file(stdin_file);
+        }
+        match (&self.expect_stdout, &self.expect_stdout_file) {
+            (Some(_), Some(_)) => {
+                return Err(K2Error::InvalidConfig(format!(
+                    "Benchmark {} can't set both expect_stdout and expect_stdout_file",
+                    self.path
+                )))
+            }
+            (Some(text), None) => bench = bench.expect_stdout(text),
+            (None, Some(path)) => bench = bench.expect_stdout_file(path),
+            (None, None) => {}
+        }
+        bench.stack_lim = self.stack_lim;
+        bench.heap_lim = self.heap_lim;
+        bench.cpu_lim = self.cpu_lim;
+        bench.cgroup = self.cgroup.clone();
+        bench.timeout = self.timeout;
+        bench.cpu_affinity = self.cpu_affinity.clone();
+        bench.numa_node = self.numa_node;
+        bench.in_proc_iters = self.in_proc_iters;
+        bench.priority = self.priority;
+        Ok(bench)
+    }
+}
+
+/// Add the files of the project directory `dir` (see `Benchmark::project_files`) to
+/// `files`, except for the `staged` input files. Only the symbolic links to files
+/// are followed, so that a link can't make the walk go round in circles.
+fn walk_project(dir: &Path, staged: &[PathBuf], files: &mut Vec<PathBuf>) -> Result<(), K2Error> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|err| K2Error::file(dir, err))?;
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with('.') || name == "__pycache__" {
+            continue;
+        }
+        let metadata = fs::symlink_metadata(&path).map_err(|err| K2Error::file(&path, err))?;
+        if metadata.is_dir() {
+            walk_project(&path, staged, files)?;
+        } else if path.is_file() && !staged.contains(&path::absolute(&path)?) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The name the input file at `path` is staged under.
+pub(crate) fn input_file_name(path: &Path) -> Result<&str, K2Error> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| {
+            K2Error::InvalidConfig(format!(
+                "Input file {} should have a valid unicode file name",
+                path.display()
+            ))
+        })
 }
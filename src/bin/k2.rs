@@ -0,0 +1,287 @@
+//! A command-line tool for running and managing k2 experiments.
+
+use k2::{
+    analysis::{self, BootstrapOpts},
+    error::K2Error,
+    experiment::ExperimentBuilder,
+    export, migrations, overrides, registry, report,
+    results::{self, Results},
+};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use std::process;
+
+fn main() {
+    let matches = App::new("k2")
+        .about("Run and manage k2 experiments")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("status")
+                .about("Show the progress of an experiment")
+                .arg(Arg::with_name("results_dir")
+                        .required(true)
+                        .help("The results directory of the experiment (or of several named experiments)")))
+        .subcommand(SubCommand::with_name("resume")
+                .about("Run (or resume) the experiment described by a configuration file")
+                .arg(Arg::with_name("config")
+                        .required(true)
+                        .help("The TOML configuration file of the experiment"))
+                .arg(Arg::with_name("only_tag")
+                        .long("only-tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("KEY=VALUE")
+                        .help("Only run the benchmarks with this tag (may be repeated)"))
+                .arg(Arg::with_name("skip_tag")
+                        .long("skip-tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("KEY=VALUE")
+                        .help("Don't run the benchmarks with this tag (may be repeated)"))
+                .args(&overrides::args()))
+        .subcommand(SubCommand::with_name("export")
+                .about("Export the results of an experiment")
+                .arg(Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .possible_values(&["csv", "krun", "stats"])
+                        .default_value("csv")
+                        .help("The format to export the results in"))
+                .arg(Arg::with_name("results_dir")
+                        .required(true)
+                        .help("The results directory of the experiment"))
+                .arg(Arg::with_name("out")
+                        .required(true)
+                        .help("The directory (csv) or file (krun, stats) to export the results to")))
+        .subcommand(SubCommand::with_name("stats")
+                .about("Show the mean and median wall-clock time of each benchmark, with bootstrapped confidence intervals")
+                .arg(Arg::with_name("resamples")
+                        .long("resamples")
+                        .takes_value(true)
+                        .help("The number of bootstrap resamples [default: 10000]"))
+                .arg(Arg::with_name("confidence")
+                        .long("confidence")
+                        .takes_value(true)
+                        .help("The confidence level of the intervals [default: 0.99]"))
+                .arg(Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("The seed of the random number generator [default: 0]"))
+                .arg(Arg::with_name("results_dir")
+                        .required(true)
+                        .help("The results directory of the experiment")))
+        .subcommand(SubCommand::with_name("compare")
+                .about("Show the speedup of a language implementation over another on each benchmark")
+                .arg(Arg::with_name("results_dir")
+                        .required(true)
+                        .help("The results directory of the experiment"))
+                .arg(Arg::with_name("vm_a")
+                        .required(true)
+                        .help("The results key of the baseline language implementation"))
+                .arg(Arg::with_name("vm_b")
+                        .required(true)
+                        .help("The results key of the language implementation to compare")))
+        .subcommand(SubCommand::with_name("report")
+                .about("Render an HTML report of the results of an experiment")
+                .arg(Arg::with_name("results_dir")
+                        .required(true)
+                        .help("The results directory of the experiment"))
+                .arg(Arg::with_name("out")
+                        .required(true)
+                        .help("The HTML file to write the report to")))
+        .subcommand(SubCommand::with_name("reset-errors")
+                .about("Re-queue the jobs which failed, so they are run when the experiment is resumed")
+                .arg(Arg::with_name("max_retries")
+                        .long("max-retries")
+                        .takes_value(true)
+                        .help("Only re-queue the jobs retried fewer than this many times"))
+                .arg(Arg::with_name("results_dir")
+                        .required(true)
+                        .help("The results directory of the experiment")))
+        .subcommand(SubCommand::with_name("merge")
+                .about("Combine the results of several experiments (e.g. from different machines) into a new results directory")
+                .arg(Arg::with_name("out")
+                        .short("o")
+                        .long("out")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The results directory to create"))
+                .arg(Arg::with_name("results_dir")
+                        .required(true)
+                        .multiple(true)
+                        .help("The results directories of the experiments")))
+        .subcommand(SubCommand::with_name("migrate")
+                .about("Upgrade the results of an experiment created by an older version of k2")
+                .arg(Arg::with_name("results_dir")
+                        .required(true)
+                        .help("The results directory of the experiment")))
+        .get_matches();
+    let result = match matches.subcommand() {
+        ("status", Some(matches)) => status(matches),
+        ("resume", Some(matches)) => resume(matches),
+        ("export", Some(matches)) => export(matches),
+        ("stats", Some(matches)) => stats(matches),
+        ("compare", Some(matches)) => compare(matches),
+        ("report", Some(matches)) => report(matches),
+        ("reset-errors", Some(matches)) => reset_errors(matches),
+        ("merge", Some(matches)) => merge(matches),
+        ("migrate", Some(matches)) => migrate(matches),
+        _ => unreachable!("clap requires a subcommand"),
+    };
+    match result {
+        Ok(()) => (),
+        Err(K2Error::Interrupted(signal)) => {
+            eprintln!("k2: interrupted: run `k2 resume` to carry on with the experiment");
+            // Exit the way a shell reports a process killed by `signal`.
+            process::exit(128 + signal);
+        }
+        Err(err) => {
+            eprintln!("k2: {}", err.chain());
+            process::exit(1);
+        }
+    }
+}
+
+fn status(matches: &ArgMatches) -> Result<(), K2Error> {
+    let results_dir = matches.value_of("results_dir").unwrap();
+    // A results directory shared by named experiments doesn't necessarily hold an
+    // experiment of its own.
+    let experiments = registry::experiments(results_dir)?;
+    if experiments.is_empty() {
+        println!("{}", k2::status(results_dir)?);
+    }
+    for experiment in experiments {
+        println!("Experiment {} ({}):", experiment.name, experiment.uuid);
+        println!("{}", k2::status(&experiment.dir)?);
+    }
+    Ok(())
+}
+
+fn resume(matches: &ArgMatches) -> Result<(), K2Error> {
+    let mut expb = ExperimentBuilder::from_config_file(matches.value_of("config").unwrap())?
+        .apply_env()?
+        .apply_cli(matches);
+    for tag in matches.values_of("only_tag").into_iter().flatten() {
+        let (t, val) = parse_tag(tag)?;
+        expb = expb.only_tag(t, val);
+    }
+    for tag in matches.values_of("skip_tag").into_iter().flatten() {
+        let (t, val) = parse_tag(tag)?;
+        expb = expb.skip_tag(t, val);
+    }
+    let exp = expb.build()?;
+    // The process is restarted (with the same arguments) after each job, so this
+    // only returns once all the jobs have run.
+    let db_path = exp.run()?;
+    println!("The results are in {}", db_path.display());
+    Ok(())
+}
+
+/// Parse a `<key>=<value>` tag.
+fn parse_tag(tag: &str) -> Result<(&str, &str), K2Error> {
+    tag.split_once('=')
+        .ok_or_else(|| K2Error::InvalidConfig(format!("Invalid tag {}: expected KEY=VALUE", tag)))
+}
+
+fn export(matches: &ArgMatches) -> Result<(), K2Error> {
+    let results_dir = matches.value_of("results_dir").unwrap();
+    let out = matches.value_of("out").unwrap();
+    match matches.value_of("format") {
+        Some("krun") => export::export_krun_json(results_dir, out),
+        Some("stats") => export::export_statistics_csv(results_dir, out, &Default::default()),
+        _ => export::export_csv(results_dir, out),
+    }
+}
+
+fn stats(matches: &ArgMatches) -> Result<(), K2Error> {
+    let mut opts = BootstrapOpts::default();
+    if let Some(resamples) = matches.value_of("resamples") {
+        opts.resamples = parse_arg("--resamples", resamples)?;
+    }
+    if let Some(confidence) = matches.value_of("confidence") {
+        opts.confidence = parse_arg("--confidence", confidence)?;
+    }
+    if let Some(seed) = matches.value_of("seed") {
+        opts.seed = parse_arg("--seed", seed)?;
+    }
+    let statistics = Results::open(matches.value_of("results_dir").unwrap())?.statistics(&opts)?;
+    let ci = format!("{}% CI", opts.confidence * 100.0);
+    for (key, stats) in &statistics {
+        println!(
+            "{} ({} pexecs, {} iterations)",
+            key, stats.pexecs, stats.iters
+        );
+        for (name, estimate) in &[("mean", &stats.mean), ("median", &stats.median)] {
+            println!(
+                "  {:<6} {:.6}s ({}: {:.6}s - {:.6}s)",
+                name, estimate.value, ci, estimate.lower, estimate.upper
+            );
+        }
+    }
+    Ok(())
+}
+
+fn compare(matches: &ArgMatches) -> Result<(), K2Error> {
+    let mut results = Results::open(matches.value_of("results_dir").unwrap())?;
+    let comparison = analysis::compare(
+        &mut results,
+        matches.value_of("vm_a").unwrap(),
+        matches.value_of("vm_b").unwrap(),
+        &Default::default(),
+    )?;
+    println!("{}", comparison);
+    Ok(())
+}
+
+fn report(matches: &ArgMatches) -> Result<(), K2Error> {
+    let out = matches.value_of("out").unwrap();
+    report::write_report(matches.value_of("results_dir").unwrap(), out)?;
+    println!("The report is in {}", out);
+    Ok(())
+}
+
+/// Parse the value of option `name`.
+fn parse_arg<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, K2Error> {
+    value
+        .parse()
+        .map_err(|_| K2Error::InvalidConfig(format!("Invalid {}: {}", name, value)))
+}
+
+fn reset_errors(matches: &ArgMatches) -> Result<(), K2Error> {
+    let max_retries = match matches.value_of("max_retries") {
+        Some(max_retries) => max_retries.parse::<usize>().map_err(|_| {
+            K2Error::InvalidConfig(format!("Invalid --max-retries: {}", max_retries))
+        })?,
+        None => usize::MAX,
+    };
+    let num_jobs = results::reset_errored(matches.value_of("results_dir").unwrap(), max_retries)?;
+    println!("Re-queued {} jobs", num_jobs);
+    Ok(())
+}
+
+fn merge(matches: &ArgMatches) -> Result<(), K2Error> {
+    let results_dirs = matches
+        .values_of("results_dir")
+        .unwrap()
+        .collect::<Vec<_>>();
+    let out = matches.value_of("out").unwrap();
+    let num_jobs = results::merge(&results_dirs, out)?;
+    println!("Merged {} jobs into {}", num_jobs, out);
+    Ok(())
+}
+
+fn migrate(matches: &ArgMatches) -> Result<(), K2Error> {
+    let migrated = migrations::migrate(matches.value_of("results_dir").unwrap())?;
+    if migrated.manifest {
+        println!("Upgraded the manifest header");
+    }
+    if let Some(version) = migrated.db_from {
+        println!("Upgraded the database from schema version {}", version);
+    }
+    if !migrated.manifest && migrated.db_from.is_none() {
+        println!("The results are up to date");
+    }
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use crate::error::K2Error;
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// The directory where the systemd units are installed.
+const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+
+/// How to restart the experiment after a reboot.
+#[derive(Debug, Copy, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootMethod {
+    /// Install a systemd unit which runs the experiment on boot.
+    Systemd,
+    /// Add an `@reboot` entry to the crontab of the current user.
+    Cron,
+}
+
+/// The command which resumes the experiment.
+struct ResumeCommand {
+    /// The directory to run the command in.
+    cwd: PathBuf,
+    /// The path of the current executable, followed by its arguments.
+    argv: Vec<String>,
+}
+
+impl ResumeCommand {
+    fn current() -> Result<ResumeCommand, K2Error> {
+        let exe = env::current_exe()?.to_string_lossy().into_owned();
+        Ok(ResumeCommand {
+            cwd: env::current_dir()?,
+            argv: Some(exe).into_iter().chain(env::args().skip(1)).collect(),
+        })
+    }
+}
+
+/// Arrange for the current executable to be rerun (with the same arguments and
+/// working directory) when the machine boots.
+///
+/// `results_dir` is used to identify the experiment, so that multiple experiments
+/// can be installed at the same time.
+pub fn install<P: AsRef<Path>>(method: BootMethod, results_dir: P) -> Result<(), K2Error> {
+    let cmd = ResumeCommand::current()?;
+    let name = experiment_name(results_dir.as_ref())?;
+    match method {
+        BootMethod::Systemd => {
+            let unit = format!(
+                "[Unit]\n\
+                 Description=k2 experiment {name}\n\
+                 After=multi-user.target\n\
+                 \n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 WorkingDirectory={cwd}\n\
+                 ExecStart={exec}\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=multi-user.target\n",
+                name = name,
+                cwd = cmd.cwd.display(),
+                exec = cmd
+                    .argv
+                    .iter()
+                    .map(|arg| systemd_quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            );
+            fs::write(unit_path(&name), unit)?;
+            run_cmd("systemctl", &["enable", &unit_name(&name)])
+        }
+        BootMethod::Cron => {
+            let mut crontab = crontab_without(&name)?;
+            crontab.push(format!(
+                "@reboot cd {} && {} {}",
+                cron_quote(&cmd.cwd.to_string_lossy()),
+                cmd.argv
+                    .iter()
+                    .map(|arg| cron_quote(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                cron_marker(&name)
+            ));
+            write_crontab(&crontab)
+        }
+    }
+}
+
+/// Undo the changes made by `install`.
+pub fn uninstall<P: AsRef<Path>>(method: BootMethod, results_dir: P) -> Result<(), K2Error> {
+    let name = experiment_name(results_dir.as_ref())?;
+    match method {
+        BootMethod::Systemd => {
+            let unit_path = unit_path(&name);
+            if unit_path.exists() {
+                run_cmd("systemctl", &["disable", &unit_name(&name)])?;
+                fs::remove_file(unit_path)?;
+            }
+            Ok(())
+        }
+        BootMethod::Cron => write_crontab(&crontab_without(&name)?),
+    }
+}
+
+/// Derive a name for the experiment from the name of its results directory.
+fn experiment_name(results_dir: &Path) -> Result<String, K2Error> {
+    let results_dir = results_dir.canonicalize()?;
+    let name = results_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect())
+}
+
+fn unit_name(name: &str) -> String {
+    format!("k2-{}.service", name)
+}
+
+fn unit_path(name: &str) -> PathBuf {
+    Path::new(SYSTEMD_UNIT_DIR).join(unit_name(name))
+}
+
+/// The comment used to identify the crontab entry of an experiment.
+fn cron_marker(name: &str) -> String {
+    format!("# k2:{}", name)
+}
+
+/// Return the lines of the current crontab, except for the entry of experiment
+/// `name`.
+fn crontab_without(name: &str) -> Result<Vec<String>, K2Error> {
+    let output = Command::new("crontab").arg("-l").output()?;
+    // `crontab -l` fails if the user doesn't have a crontab yet.
+    if !output.status.success() {
+        return Ok(Default::default());
+    }
+    let marker = cron_marker(name);
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.ends_with(&marker))
+        .map(String::from)
+        .collect())
+}
+
+fn write_crontab(lines: &[String]) -> Result<(), K2Error> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        for line in lines {
+            writeln!(stdin, "{}", line)?;
+        }
+    }
+    if child.wait()?.success() {
+        Ok(())
+    } else {
+        Err(K2Error::BootSetup("Failed to update the crontab".into()))
+    }
+}
+
+fn run_cmd(cmd: &str, args: &[&str]) -> Result<(), K2Error> {
+    let status = Command::new(cmd).args(args).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(K2Error::BootSetup(format!(
+            "{} {} failed",
+            cmd,
+            args.join(" ")
+        )))
+    }
+}
+
+/// Quote `arg` so that it can be used as a single word in a systemd `ExecStart`.
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{}\"", escaped)
+}
+
+/// Quote `arg` so that it can be used as a single word in a crontab command.
+fn cron_quote(arg: &str) -> String {
+    // `%` has a special meaning in crontab commands.
+    format!("'{}'", arg.replace('\'', "'\\''").replace('%', "\\%"))
+}
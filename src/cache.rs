@@ -0,0 +1,65 @@
+//! Controlling the file-system cache before each job.
+//!
+//! An I/O-bound benchmark runs very differently depending on whether the files it
+//! reads are in the page cache, so by default, whether a job starts warm or cold
+//! depends on what ran before it. Before each job (once the machine has cooled
+//! down), k2 can:
+//!
+//! * drop the page cache, and the cached directory entries and inodes (see
+//!   `Config::drop_caches`), after writing back the dirty pages, so that the job
+//!   starts cold. This requires root;
+//! * read the files of the benchmarks the job runs (see `Config::warm_cache`): the
+//!   benchmark itself (or all the files of a project), its input files, its
+//!   standard input and the executable of its language implementation, so that
+//!   the job starts warm.
+//!
+//! When both are set, the caches are dropped first, so only the files of the
+//! benchmarks are cached. The input files are copied to the working directory of
+//! the benchmark before each process execution, so their copies are always cached.
+
+use crate::error::K2Error;
+
+use tracing::debug;
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    path::PathBuf,
+};
+
+/// The file which drops the caches of the kernel when written to.
+pub const DROP_CACHES: &str = "/proc/sys/vm/drop_caches";
+
+/// Write back the dirty pages, and drop the page cache, and the cached directory
+/// entries and inodes.
+pub(crate) fn drop_caches() -> Result<(), K2Error> {
+    unsafe { libc::sync() };
+    fs::write(DROP_CACHES, "3\n").map_err(|err| K2Error::file(DROP_CACHES, err))?;
+    debug!("dropped the caches");
+    Ok(())
+}
+
+/// Check that k2 is allowed to drop the caches.
+pub(crate) fn check() -> Result<(), K2Error> {
+    OpenOptions::new()
+        .write(true)
+        .open(DROP_CACHES)
+        .map(|_| ())
+        .map_err(|err| {
+            K2Error::InvalidConfig(format!(
+                "Can't drop the caches ({}: {}); it usually requires root",
+                DROP_CACHES, err
+            ))
+        })
+}
+
+/// Read each of the `files`, so that they are in the page cache. The paths which
+/// aren't files (such as the name of a JVM class) are skipped.
+pub(crate) fn warm(files: &[PathBuf]) -> Result<(), K2Error> {
+    for path in files.iter().filter(|path| path.is_file()) {
+        let mut file = File::open(path).map_err(|err| K2Error::file(path, err))?;
+        io::copy(&mut file, &mut io::sink()).map_err(|err| K2Error::file(path, err))?;
+    }
+    debug!("warmed the cache with {} file(s)", files.len());
+    Ok(())
+}
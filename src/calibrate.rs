@@ -0,0 +1,120 @@
+//! Deriving the stack and heap limits of the benchmarks from a calibration run.
+//!
+//! Limits which are too tight make the benchmarks fail, and limits which are too
+//! loose don't catch the runaway ones. When an experiment is calibrated (see
+//! `ExperimentBuilder::calibrate`), each benchmark is run once, without any limits,
+//! before the first job, while the memory usage of its process is sampled from
+//! `/proc/<pid>/status`: the peak size of its address space (`VmPeak`), its peak
+//! resident set size (`VmHWM`), and the size of its stack (`VmStk`). The limits
+//! suggested for the benchmark are the peak size of the address space (which is
+//! what the heap limit restricts) and the size of the stack, plus a margin.
+//!
+//! The measurements and the suggested limits are recorded in the `calibration`
+//! table of the results database (see `Results::calibrations`), so each benchmark
+//! is only calibrated once, and logged. If `CalibrationConfig::apply` is set, the
+//! suggested limits become the limits of the benchmarks which don't have their own.
+//!
+//! Only the benchmark process itself is measured, not its descendants, and a peak
+//! which falls between two samples is missed (the margin allows for this). The
+//! benchmarks of an `SshLangImpl` run on another machine: only the ssh client is
+//! measured.
+
+use crate::{
+    error::K2Error,
+    limit::{Limit, MIN_MEMORY, MIN_STACK},
+    process::MemoryUsage,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// How to calibrate the limits of the benchmarks.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CalibrationConfig {
+    /// The margin added to the memory usage of a benchmark to get its suggested
+    /// limits, as a fraction of the usage (`0.5` adds 50%).
+    #[serde(default = "default_margin")]
+    pub margin: f64,
+    /// Whether to apply the suggested limits to the benchmarks which don't have
+    /// their own.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+fn default_margin() -> f64 {
+    0.5
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> CalibrationConfig {
+        CalibrationConfig::new()
+    }
+}
+
+impl CalibrationConfig {
+    /// Suggest limits 50% larger than the memory usage of each benchmark, without
+    /// applying them.
+    pub fn new() -> CalibrationConfig {
+        CalibrationConfig {
+            margin: default_margin(),
+            apply: false,
+        }
+    }
+
+    /// Add `margin` (a fraction of the memory usage) instead of 50%.
+    pub fn margin(mut self, margin: f64) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    /// Apply the suggested limits to the benchmarks which don't have their own.
+    pub fn apply(mut self, apply: bool) -> Self {
+        self.apply = apply;
+        self
+    }
+
+    /// Check that the margin isn't negative.
+    pub(crate) fn check(&self) -> Result<(), K2Error> {
+        if self.margin.is_finite() && self.margin >= 0.0 {
+            Ok(())
+        } else {
+            Err(K2Error::InvalidConfig(format!(
+                "Invalid calibration margin: {} (expected a non-negative number)",
+                self.margin
+            )))
+        }
+    }
+}
+
+/// The memory usage of a benchmark in its calibration run, in bytes, and the limits
+/// suggested for it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Calibration {
+    /// The peak size of the address space of the benchmark process.
+    pub peak_vm: u64,
+    /// The peak resident set size of the benchmark process.
+    pub peak_rss: u64,
+    /// The size of the stack of the benchmark process.
+    pub stack: u64,
+    /// The suggested heap limit, in whole MiB.
+    pub heap_lim: Limit,
+    /// The suggested stack limit, in whole KiB.
+    pub stack_lim: Limit,
+}
+
+impl Calibration {
+    /// The calibration of a benchmark which used `usage`, with limits `margin`
+    /// larger than it (but no smaller than the minimum limits).
+    pub(crate) fn new(usage: &MemoryUsage, margin: f64) -> Calibration {
+        let with_margin = |bytes: u64, min: u64| (bytes as f64 * (1.0 + margin)).max(min as f64);
+        let heap = with_margin(usage.peak_vm, MIN_MEMORY);
+        let stack = with_margin(usage.stack, MIN_STACK);
+        Calibration {
+            peak_vm: usage.peak_vm,
+            peak_rss: usage.peak_rss,
+            stack: usage.stack,
+            heap_lim: Limit::MiB((heap / (1024.0 * 1024.0)).ceil() as f32),
+            stack_lim: Limit::KiB((stack / 1024.0).ceil() as f32),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{config::Config, error::K2Error, isolation, process::SchedPolicy, util};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The file which controls whether turbo boost is disabled, if the machine uses
+/// the `intel_pstate` driver.
+const INTEL_NO_TURBO: &str = "/sys/devices/system/cpu/intel_pstate/no_turbo";
+/// The file which controls whether turbo boost is enabled, if the machine uses the
+/// `acpi-cpufreq` driver.
+const CPUFREQ_BOOST: &str = "/sys/devices/system/cpu/cpufreq/boost";
+/// The file which controls ASLR.
+const RANDOMIZE_VA_SPACE: &str = "/proc/sys/kernel/randomize_va_space";
+/// The file which limits how much CPU time real-time processes can use.
+const SCHED_RT_RUNTIME: &str = "/proc/sys/kernel/sched_rt_runtime_us";
+/// The file which describes the memory usage of the system.
+const MEMINFO: &str = "/proc/meminfo";
+
+/// What to do if the machine isn't set up for benchmarking.
+#[derive(Debug, Copy, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckPolicy {
+    /// Don't check anything.
+    Ignore,
+    /// Print a warning, and run the benchmarks anyway.
+    #[default]
+    Warn,
+    /// Refuse to run the benchmarks.
+    Abort,
+    /// Fix the settings which can be changed (this usually requires root), and
+    /// refuse to run the benchmarks if anything else is wrong.
+    Fix,
+}
+
+/// A setting which could make the benchmark results unreliable.
+struct Problem {
+    /// What's wrong.
+    description: String,
+    /// The files to write (and the values to write to them) to fix the problem.
+    /// Empty if the problem can't be fixed automatically.
+    fix: Vec<(PathBuf, &'static str)>,
+}
+
+/// Check that the machine is set up for benchmarking, and handle any problems
+/// according to `config.check_policy`. In a dry run, the problems which
+/// `CheckPolicy::Fix` would fix are only reported.
+///
+/// A setting which isn't exposed by the machine (e.g. there is no cpufreq driver)
+/// is not checked.
+pub(crate) fn run(config: &Config) -> Result<(), K2Error> {
+    if let CheckPolicy::Ignore = config.check_policy {
+        return Ok(());
+    }
+    for problem in problems(config)? {
+        match config.check_policy {
+            CheckPolicy::Ignore => (),
+            CheckPolicy::Warn => warn!("{}", problem.description),
+            CheckPolicy::Abort => return Err(K2Error::CheckFailed(problem.description)),
+            CheckPolicy::Fix => {
+                if problem.fix.is_empty() {
+                    return Err(K2Error::CheckFailed(problem.description));
+                }
+                // A dry run only reports what it would fix, since it must not change
+                // the machine.
+                if config.dry_run {
+                    warn!("{} (not fixed in a dry run)", problem.description);
+                    continue;
+                }
+                for (path, value) in &problem.fix {
+                    fs::write(path, value).map_err(|e| {
+                        K2Error::CheckFailed(format!(
+                            "{} (failed to write {}: {})",
+                            problem.description,
+                            path.display(),
+                            e
+                        ))
+                    })?;
+                    debug!("fixed: wrote {:?} to {}", value, path.display());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Find all the settings which could make the benchmark results unreliable.
+fn problems(config: &Config) -> Result<Vec<Problem>, K2Error> {
+    let mut problems = Vec::new();
+    let mut bad_governors = Vec::new();
+    for governor in util::scaling_governors() {
+        if read_setting(&governor)?.as_deref() != Some("performance") {
+            bad_governors.push(governor);
+        }
+    }
+    if !bad_governors.is_empty() {
+        problems.push(Problem {
+            description: format!(
+                "the CPU governor of {} CPU(s) is not `performance`",
+                bad_governors.len()
+            ),
+            fix: bad_governors
+                .into_iter()
+                .map(|governor| (governor, "performance"))
+                .collect(),
+        });
+    }
+    problems.extend(check_setting(
+        INTEL_NO_TURBO,
+        "1",
+        "turbo boost is enabled",
+    )?);
+    problems.extend(check_setting(CPUFREQ_BOOST, "0", "turbo boost is enabled")?);
+    let (aslr, aslr_description) = if config.aslr {
+        ("2", "ASLR is not fully enabled")
+    } else {
+        ("0", "ASLR is enabled")
+    };
+    problems.extend(check_setting(RANDOMIZE_VA_SPACE, aslr, aslr_description)?);
+    if let Some(SchedPolicy::Fifo(_)) = config.sched_policy {
+        problems.extend(check_setting(
+            SCHED_RT_RUNTIME,
+            "-1",
+            "real-time processes are throttled",
+        )?);
+    }
+    if let Some(cpus) = &config.isolated_cpus {
+        problems.extend(
+            isolation::problems(cpus)?
+                .into_iter()
+                .map(|description| Problem {
+                    description,
+                    fix: Default::default(),
+                }),
+        );
+    }
+    if swap_used()? > 0 {
+        problems.push(Problem {
+            description: "swap is in use".into(),
+            fix: Default::default(),
+        });
+    }
+    Ok(problems)
+}
+
+/// Check that the setting in the file at `path` has the `expected` value.
+fn check_setting(
+    path: &str,
+    expected: &'static str,
+    description: &str,
+) -> Result<Option<Problem>, K2Error> {
+    match read_setting(path)? {
+        Some(value) if value != expected => Ok(Some(Problem {
+            description: format!(
+                "{} ({} is {}, expected {})",
+                description, path, value, expected
+            ),
+            fix: vec![(path.into(), expected)],
+        })),
+        _ => Ok(None),
+    }
+}
+
+/// Read the setting in the file at `path`, or return `None` if the file doesn't
+/// exist.
+fn read_setting<P: AsRef<Path>>(path: P) -> Result<Option<String>, K2Error> {
+    if !path.as_ref().exists() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?.trim().to_string()))
+}
+
+/// The amount of swap in use, in KiB.
+fn swap_used() -> Result<u64, K2Error> {
+    let meminfo = match read_setting(MEMINFO)? {
+        Some(meminfo) => meminfo,
+        None => return Ok(0),
+    };
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| {
+                value
+                    .trim()
+                    .trim_end_matches("kB")
+                    .trim()
+                    .parse::<u64>()
+                    .ok()
+            })
+            .unwrap_or(0)
+    };
+    Ok(field("SwapTotal").saturating_sub(field("SwapFree")))
+}
@@ -0,0 +1,145 @@
+//! Recording which benchmarks and language implementations an experiment ran.
+//!
+//! When an experiment starts, the SHA-256 checksum of each benchmark file (or of
+//! the files of a project, see `Benchmark::project_files`) and of the executable
+//! of each language implementation (see `LangImpl::executable`) is recorded with
+//! each job which runs them (see `JobRecord::path_sha256` and
+//! `JobRecord::lang_impl_sha256`). Each time k2 starts, the checksums are computed
+//! again, and an experiment whose benchmarks or language implementations changed
+//! since it was started (e.g. because a VM was upgraded halfway through) isn't
+//! resumed (unless `Config::ignore_audit_mismatch` is set).
+//!
+//! The benchmarks of an experiment started by an older version of k2 are recorded
+//! the first time it is resumed. A file which doesn't exist (such as a benchmark
+//! which hasn't been built yet, or the name of a JVM class) has no checksum.
+
+use crate::{benchmark::Benchmark, db::K2Store, error::K2Error, util};
+
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use std::{
+    fs::File,
+    io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// The checksums of the files a benchmark is run with.
+#[derive(Debug, Clone)]
+pub struct BenchmarkChecksums {
+    /// The results key of the benchmark.
+    pub key: String,
+    /// The benchmark file (or the directory of a project), and its checksum.
+    pub path: (PathBuf, Option<String>),
+    /// The executable of the language implementation, and its checksum, or `None`
+    /// if the language implementation doesn't have one.
+    pub lang_impl: Option<(PathBuf, Option<String>)>,
+}
+
+/// The checksums of the files each of the `benchmarks` is run with.
+pub(crate) fn collect(benchmarks: &[Benchmark]) -> Result<Vec<BenchmarkChecksums>, K2Error> {
+    benchmarks
+        .iter()
+        .map(|bench| {
+            // A project is checksummed as a whole, since any of its files might be
+            // what the benchmark runs.
+            let path = match bench.entry_point() {
+                Some(_) => {
+                    let dir = PathBuf::from(bench.path()?);
+                    let checksum = sha256_files(&dir, &bench.project_files()?)?;
+                    (dir, checksum)
+                }
+                None => (bench.entry_path()?, sha256(&bench.absolute_path()?)?),
+            };
+            let lang_impl = bench.lang_impl().executable().map(|program| {
+                let program = util::program_path(program);
+                let checksum = sha256(&program)?;
+                Ok::<_, K2Error>((program, checksum))
+            });
+            Ok(BenchmarkChecksums {
+                key: bench.results_key()?,
+                path,
+                lang_impl: lang_impl.transpose()?,
+            })
+        })
+        .collect()
+}
+
+/// Check that the files the benchmarks are run with haven't changed since their
+/// `checksums` were recorded, and record those which weren't. If `ignore_mismatch`
+/// is set, the files which changed are only reported.
+///
+/// Returns `K2Error::ChecksumMismatch` listing the files which changed.
+pub(crate) fn check(
+    store: &mut K2Store,
+    checksums: &[BenchmarkChecksums],
+    ignore_mismatch: bool,
+) -> Result<(), K2Error> {
+    let mut changed = Vec::new();
+    for bench in checksums {
+        let (path_sha256, lang_impl_sha256) = store.checksums(&bench.key)?;
+        let files = [
+            (Some(&bench.path), path_sha256),
+            (bench.lang_impl.as_ref(), lang_impl_sha256),
+        ];
+        for (file, recorded) in files.iter() {
+            if let (Some((path, current)), Some(recorded)) = (file, recorded) {
+                let path = path.display().to_string();
+                if current.as_ref() != Some(recorded) && !changed.contains(&path) {
+                    changed.push(path);
+                }
+            }
+        }
+        store.record_checksums(
+            &bench.key,
+            bench.path.1.as_deref(),
+            bench
+                .lang_impl
+                .as_ref()
+                .and_then(|(_, checksum)| checksum.as_deref()),
+        )?;
+    }
+    if changed.is_empty() {
+        Ok(())
+    } else if ignore_mismatch {
+        warn!(
+            "files changed since the experiment started: {}",
+            changed.join(", ")
+        );
+        Ok(())
+    } else {
+        Err(K2Error::ChecksumMismatch(changed))
+    }
+}
+
+/// The SHA-256 checksum of the file at `path`, in hexadecimal, or `None` if there
+/// is no such file.
+pub fn sha256(path: &Path) -> Result<Option<String>, K2Error> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let mut file = File::open(path).map_err(|err| K2Error::file(path, err))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).map_err(|err| K2Error::file(path, err))?;
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+/// The SHA-256 checksum of the `files` of directory `dir`, in hexadecimal: of the
+/// path of each file (relative to `dir`) and of its checksum, in order. Returns
+/// `None` if there are no files.
+pub fn sha256_files(dir: &Path, files: &[PathBuf]) -> Result<Option<String>, K2Error> {
+    if files.is_empty() {
+        return Ok(None);
+    }
+    let mut hasher = Sha256::new();
+    for path in files {
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        let checksum = sha256(path)?.unwrap_or_default();
+        hasher.update(relative.as_os_str().as_bytes());
+        hasher.update(b"\0");
+        hasher.update(checksum.as_bytes());
+        hasher.update(b"\n");
+    }
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
@@ -0,0 +1,85 @@
+//! The clock the wall-clock times are measured with.
+//!
+//! `std::time::Instant` reads `CLOCK_MONOTONIC`, whose rate NTP adjusts to keep it
+//! in step with the time of day, so a time measured while the clock is being
+//! slewed is off by up to 0.05%. By default, k2 measures with `CLOCK_MONOTONIC_RAW`
+//! instead, which is never adjusted (see `Config::clock`). The clock, and its
+//! resolution, are recorded in the audit (`clock` and `clock_resolution_ns`), so
+//! an experiment can't be resumed with a different one.
+//!
+//! Benchmarks are told which clock to measure their in-process iterations with in
+//! `CLOCK_ENV` (e.g. `K2_CLOCK=monotonic_raw`), and, if `Config::cpu_time` is set,
+//! asked to report the CPU time of each iteration as well (see
+//! `measure::CPU_TIME_ENV`).
+
+use crate::error::K2Error;
+
+use serde::{Deserialize, Serialize};
+
+use std::{io, time::Duration};
+
+/// The environment variable which tells a benchmark which clock to measure its
+/// in-process iterations with (see `Clock::name`).
+pub const CLOCK_ENV: &str = "K2_CLOCK";
+
+/// A monotonic clock.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Clock {
+    /// `CLOCK_MONOTONIC_RAW`: the hardware clock, which NTP doesn't adjust.
+    #[default]
+    MonotonicRaw,
+    /// `CLOCK_MONOTONIC`: the clock `std::time::Instant` reads, whose rate NTP
+    /// adjusts.
+    Monotonic,
+}
+
+impl Clock {
+    /// The name of the clock, as recorded in the audit and passed to benchmarks
+    /// (e.g. `monotonic_raw`).
+    pub fn name(self) -> &'static str {
+        match self {
+            Clock::MonotonicRaw => "monotonic_raw",
+            Clock::Monotonic => "monotonic",
+        }
+    }
+
+    /// The time elapsed since an arbitrary point (usually boot), which only has
+    /// meaning relative to another reading of the same clock.
+    pub fn now(self) -> Result<Duration, K2Error> {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        if unsafe { libc::clock_gettime(self.id(), &mut ts) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+
+    /// The resolution of the clock, as reported by `clock_getres(2)`.
+    pub fn resolution(self) -> Result<Duration, K2Error> {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        if unsafe { libc::clock_getres(self.id(), &mut ts) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+
+    /// Check that the clock can be read on this machine.
+    pub(crate) fn check(self) -> Result<(), K2Error> {
+        self.now().map(|_| ()).map_err(|err| {
+            K2Error::InvalidConfig(format!("Invalid clock: {} ({})", self.name(), err))
+        })
+    }
+
+    fn id(self) -> libc::clockid_t {
+        match self {
+            Clock::MonotonicRaw => libc::CLOCK_MONOTONIC_RAW,
+            Clock::Monotonic => libc::CLOCK_MONOTONIC,
+        }
+    }
+}
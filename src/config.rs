@@ -1,37 +1,262 @@
-use std::{path::PathBuf, time::Duration};
+use crate::{
+    adaptive::AdaptiveConfig,
+    boot::BootMethod,
+    calibrate::CalibrationConfig,
+    checks::CheckPolicy,
+    clock::Clock,
+    config_file::ExperimentFile,
+    dmesg::DmesgPolicy,
+    error::K2Error,
+    limit::CgroupLimits,
+    load::LoadMonitorConfig,
+    manifest::OrderingStrategy,
+    notify::MailTransport,
+    overhead::OverheadConfig,
+    perf::PerfEvent,
+    process::{KillBehaviour, SchedPolicy},
+    profile::ProfileConfig,
+    services::ServicesConfig,
+};
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The maximum number of in-process iterations to run in quick mode.
+pub(crate) const QUICK_IN_PROC_ITERS: usize = 5;
 
 /// The configuration that specifies how to run the benchmarks.
-#[derive(Debug)]
-pub(crate) struct Config {
+///
+/// A `Config` can be (de)serialized with serde, e.g. to save the settings of an
+/// experiment alongside its results. Every setting is included, with the
+/// durations in seconds. Only the optional settings can be missing from a
+/// deserialized `Config` (they are then `None`): this is not the format of the
+/// configuration files read by `from_toml`, which only list the settings that
+/// differ from the defaults. To serialize a `Config` as TOML, convert it to a
+/// `toml::Value` first, which puts the tables after the other settings.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
     /// The path of the directory where to store the results and the manifest.
     pub results_dir: PathBuf,
+    /// The name of the experiment, if it shares `results_dir` with other
+    /// experiments (see `registry`). `None` by default.
+    pub name: Option<String>,
     /// Run the benchmarks in quick mode (for development/testing purposes).
+    ///
+    /// Quick mode runs each benchmark once, for a handful of in-process
+    /// iterations, without rebooting or waiting for the machine to cool down.
     pub quick: bool,
     /// Don't actually run the benchmarks (for development/testing purposes).
+    ///
+    /// The rest of the experiment is run as usual: the jobs are scheduled and
+    /// recorded, each job produces synthetic measurements, and the process
+    /// restarts itself between jobs instead of rebooting the machine.
     pub dry_run: bool,
     /// Automatically reboot between pexecs.
     pub reboot: bool,
+    /// How to resume the experiment after a reboot. Only used if `reboot` is set.
+    pub resume_on_boot: Option<BootMethod>,
+    /// Run all the jobs in a single k2 process, one after the other, rather than
+    /// restarting k2 between jobs. Only used if the machine doesn't reboot between
+    /// jobs.
+    pub loop_jobs: bool,
     /// The list of emails to send notifications/errors to.
     pub mail_to: Vec<String>,
-    /// The number of in-process iterations.
+    /// How to deliver the email notifications.
+    pub mail_transport: MailTransport,
+    /// The number of in-process iterations, unless the benchmark sets its own (see
+    /// `Benchmark::in_proc_iters`).
     pub in_proc_iters: usize,
     /// The number of process executions.
     pub pexecs: usize,
+    /// The maximum number of times to retry a job that failed. Failed jobs are
+    /// retried after all the other jobs have finished.
+    pub max_retries: usize,
+    /// The maximum number of times to run a job again because the benchmark asked
+    /// for it (see `measure::RERUN_PREFIX`). The job is run again straight away;
+    /// a job which asks to be run again more often fails.
+    pub max_reruns: usize,
+    /// The maximum number of times to start a job, counting the reruns, the
+    /// retries, and the runs which didn't finish (because the machine crashed, or
+    /// k2 was interrupted). k2 gives up on a job which was started this many times,
+    /// rather than running it forever.
+    pub max_attempts: usize,
     /// The amount of time to wait before taking the initial temperature reading.
+    #[serde(with = "crate::util::secs")]
     pub temp_read_pause: Duration,
+    /// If set, wait until the temperature of each sensor is within this many
+    /// degrees Celsius of its baseline before running each job.
+    pub temp_threshold: Option<f64>,
+    /// The maximum amount of time to wait for the machine to cool down.
+    #[serde(with = "crate::util::secs")]
+    pub temp_wait_timeout: Duration,
+    /// Drop the page cache before each job, so that the benchmarks start cold (see
+    /// `cache`). Requires root.
+    pub drop_caches: bool,
+    /// Read the files of the benchmarks before each job, so that they start warm
+    /// (see `cache`).
+    pub warm_cache: bool,
+    /// The amount of time to wait after each job before running the next one, so
+    /// that the work it left behind (such as dirty pages being written back) doesn't
+    /// disturb the next job. 0 by default.
+    #[serde(with = "crate::util::secs")]
+    pub post_job_pause: Duration,
+    /// The maximum amount of time a process execution is allowed to run for.
+    /// `None` by default.
+    #[serde(default, with = "crate::util::opt_secs")]
+    pub timeout: Option<Duration>,
+    /// What to do with a process execution that exceeds its timeout.
+    pub kill_behaviour: KillBehaviour,
+    /// How long to let the running process execution finish once k2 is asked to
+    /// stop (see `shutdown`), before killing it. If `None` (the default), it is
+    /// allowed to finish.
+    #[serde(default, with = "crate::util::opt_secs")]
+    pub interrupt_grace: Option<Duration>,
+    /// The CPUs to pin the benchmarks to. `None` by default.
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// The isolated CPUs to run the benchmarks on (see `isolation`). The benchmarks
+    /// are pinned to them, unless `cpu_affinity` is set. `None` by default.
+    pub isolated_cpus: Option<Vec<usize>>,
+    /// The disjoint sets of CPUs (slots) to run several jobs on at the same time,
+    /// for experiments which care about throughput rather than noise. Each job is
+    /// pinned to the CPUs of its slot, instead of `cpu_affinity`. If `None` (the
+    /// default), the jobs run one after the other.
+    pub slots: Option<Vec<Vec<usize>>>,
+    /// The NUMA node to bind the benchmarks to (see `numa`). `None` by default.
+    pub numa_node: Option<usize>,
+    /// Run each benchmark process in its own network namespace, without network
+    /// access (see `network`). `false` by default.
+    pub isolate_network: bool,
+    /// The services which must be stopped while the benchmarks run (see
+    /// `services`). `None` by default.
+    pub services: Option<ServicesConfig>,
+    /// The scheduling policy of the benchmark processes. If `None` (the default),
+    /// they inherit the policy of k2.
+    pub sched_policy: Option<SchedPolicy>,
+    /// The limits of the transient cgroup each process execution is run in. If
+    /// `None` (the default), the processes aren't run in their own cgroup.
+    pub cgroup: Option<CgroupLimits>,
+    /// Resume the experiment even if the environment changed since it started.
+    pub ignore_audit_mismatch: bool,
+    /// What to do if the machine isn't set up for benchmarking.
+    pub check_policy: CheckPolicy,
+    /// Whether ASLR is expected to be fully enabled (rather than disabled).
+    pub aslr: bool,
+    /// The clock the wall-clock times are measured with (see `clock`).
+    pub clock: Clock,
+    /// Ask the benchmarks to report the CPU time of each in-process iteration as
+    /// well as its wall-clock time (see `measure::CPU_TIME_ENV`).
+    pub cpu_time: bool,
+    /// Measure the energy used by each process execution (using RAPL).
+    pub energy: bool,
+    /// Sample the frequency of the CPUs, and count their thermal throttling events,
+    /// while each process execution runs (see `cpufreq`).
+    pub cpu_freq: bool,
+    /// Split the in-process iterations of each process execution into warmup and
+    /// steady state (see `analysis`).
+    pub warmup_analysis: bool,
+    /// The performance counters to collect for each process execution. Requires
+    /// the `perf` feature.
+    pub perf_events: Vec<PerfEvent>,
+    /// The process executions to profile (see `profile`). `None` by default.
+    pub profile: Option<ProfileConfig>,
+    /// How to calibrate the heap and stack limits of the benchmarks (see
+    /// `calibrate`). `None` by default.
+    pub calibration: Option<CalibrationConfig>,
+    /// When to stop running the process executions of a benchmark whose results are
+    /// precise enough (see `adaptive`). `None` by default: each benchmark runs
+    /// `pexecs` process executions.
+    pub adaptive: Option<AdaptiveConfig>,
+    /// How to measure the overhead of the language implementations (see
+    /// `overhead`). `None` by default.
+    pub overhead: Option<OverheadConfig>,
+    /// What to do when the kernel logs messages while a job runs (see `dmesg`).
+    pub dmesg: DmesgPolicy,
+    /// How to monitor the background load of the machine while each job runs (see
+    /// `load`). `None` by default.
+    pub load_monitor: Option<LoadMonitorConfig>,
+    /// The order in which to run the jobs.
+    pub ordering: OrderingStrategy,
+    /// The seed of the random number generator used to order the jobs. If `None`,
+    /// a random seed is used (and recorded).
+    pub rng_seed: Option<u64>,
 }
 
 impl Config {
     pub fn new(results_dir: PathBuf) -> Config {
         Config {
             results_dir,
+            name: None,
             quick: false,
             dry_run: false,
             reboot: false,
+            resume_on_boot: None,
+            loop_jobs: false,
             mail_to: Default::default(),
+            mail_transport: Default::default(),
             in_proc_iters: 40,
             pexecs: 1,
+            max_retries: 0,
+            max_reruns: 3,
+            max_attempts: 10,
             temp_read_pause: Duration::from_secs(60),
+            temp_threshold: None,
+            temp_wait_timeout: Duration::from_secs(600),
+            drop_caches: false,
+            warm_cache: false,
+            post_job_pause: Duration::from_secs(0),
+            timeout: None,
+            kill_behaviour: KillBehaviour::Terminate(Duration::from_secs(10)),
+            interrupt_grace: None,
+            cpu_affinity: None,
+            isolated_cpus: None,
+            slots: None,
+            numa_node: None,
+            isolate_network: false,
+            services: None,
+            sched_policy: None,
+            cgroup: None,
+            ignore_audit_mismatch: false,
+            check_policy: Default::default(),
+            aslr: true,
+            clock: Default::default(),
+            cpu_time: false,
+            energy: false,
+            cpu_freq: false,
+            warmup_analysis: false,
+            perf_events: Default::default(),
+            profile: None,
+            calibration: None,
+            adaptive: None,
+            overhead: None,
+            dmesg: Default::default(),
+            load_monitor: None,
+            ordering: Default::default(),
+            rng_seed: None,
+        }
+    }
+
+    /// If `quick` is set, override the settings which make the experiment slow.
+    pub fn apply_quick(&mut self) {
+        if self.quick {
+            self.reboot = false;
+            self.pexecs = 1;
+            self.in_proc_iters = self.in_proc_iters.min(QUICK_IN_PROC_ITERS);
+            self.temp_read_pause = Duration::from_secs(0);
+            self.temp_threshold = None;
+            self.post_job_pause = Duration::from_secs(0);
         }
     }
+
+    /// Load the configuration from the TOML file at `path`.
+    ///
+    /// Any benchmarks or language implementations declared in the file are
+    /// ignored (see `ExperimentBuilder::from_config_file`).
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Config, K2Error> {
+        ExperimentFile::load(path)?.config()
+    }
 }
@@ -0,0 +1,584 @@
+use crate::{
+    adaptive::AdaptiveConfig,
+    benchmark::{Benchmark, BenchmarkSpec},
+    calibrate::CalibrationConfig,
+    checks::CheckPolicy,
+    clock::Clock,
+    config::Config,
+    dmesg::DmesgPolicy,
+    error::K2Error,
+    hooks::HooksConfig,
+    lang_impl::{GenericNativeCode, GenericScriptingVm, Jvm, LangImpl, SshLangImpl, WasmRuntime},
+    limit::{CgroupLimits, Limit},
+    load::LoadMonitorConfig,
+    manifest::OrderingStrategy,
+    overhead::OverheadConfig,
+    perf::PerfEvent,
+    process::SchedPolicy,
+    profile::ProfileConfig,
+    services::ServicesConfig,
+    upload::{CodespeedUploader, ReBenchDbUploader, UploadMapping, Uploader},
+};
+
+use serde::Deserialize;
+
+use std::{collections::BTreeMap, fs, path::Path, path::PathBuf, sync::Arc, time::Duration};
+
+/// The contents of an experiment configuration file.
+///
+/// All the durations are specified in seconds. Any setting which isn't specified
+/// takes the default value from `Config::new`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ExperimentFile {
+    results_dir: PathBuf,
+    name: Option<String>,
+    quick: Option<bool>,
+    dry_run: Option<bool>,
+    reboot: Option<bool>,
+    loop_jobs: Option<bool>,
+    mail_to: Option<Vec<String>>,
+    in_proc_iters: Option<usize>,
+    pexecs: Option<usize>,
+    max_retries: Option<usize>,
+    max_reruns: Option<usize>,
+    max_attempts: Option<usize>,
+    temp_read_pause: Option<u64>,
+    temp_threshold: Option<f64>,
+    temp_wait_timeout: Option<u64>,
+    drop_caches: Option<bool>,
+    warm_cache: Option<bool>,
+    post_job_pause: Option<u64>,
+    timeout: Option<u64>,
+    interrupt_grace: Option<u64>,
+    cpu_affinity: Option<Vec<usize>>,
+    isolated_cpus: Option<Vec<usize>>,
+    slots: Option<Vec<Vec<usize>>>,
+    numa_node: Option<usize>,
+    isolate_network: Option<bool>,
+    services: Option<ServicesConfig>,
+    sched_policy: Option<SchedPolicy>,
+    cgroup: Option<CgroupEntry>,
+    ignore_audit_mismatch: Option<bool>,
+    check_policy: Option<CheckPolicy>,
+    aslr: Option<bool>,
+    clock: Option<Clock>,
+    cpu_time: Option<bool>,
+    energy: Option<bool>,
+    cpu_freq: Option<bool>,
+    warmup_analysis: Option<bool>,
+    perf_events: Option<Vec<PerfEvent>>,
+    profile: Option<ProfileConfig>,
+    calibration: Option<CalibrationConfig>,
+    adaptive: Option<AdaptiveConfig>,
+    overhead: Option<OverheadConfig>,
+    dmesg: Option<DmesgPolicy>,
+    load_monitor: Option<LoadMonitorConfig>,
+    ordering: Option<OrderingStrategy>,
+    rng_seed: Option<u64>,
+    /// The JSON lines file to copy the results of each job to (see `JsonlStore`).
+    pub jsonl: Option<PathBuf>,
+    /// The program (followed by its arguments) to run the benchmarks with, unless
+    /// they specify their own (see `ExperimentBuilder::wrapper`).
+    pub wrapper: Option<Vec<String>>,
+    /// The shell commands to run before and after the jobs (see `hooks`).
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    /// The dashboards to upload the results to once all the jobs have run.
+    #[serde(default)]
+    pub upload: Vec<UploadEntry>,
+    /// The language implementations, indexed by name.
+    #[serde(default)]
+    pub lang_impls: BTreeMap<String, LangImplEntry>,
+    #[serde(default)]
+    pub benchmarks: Vec<BenchmarkEntry>,
+    /// The benchmarks not to run on some language implementation (see
+    /// `ExperimentBuilder::skip`).
+    #[serde(default)]
+    pub skip: Vec<SkipEntry>,
+    /// The empty benchmarks which measure the overhead of the language
+    /// implementations (see `ExperimentBuilder::empty_benchmark`).
+    #[serde(default)]
+    pub empty_benchmarks: Vec<BenchmarkEntry>,
+}
+
+impl ExperimentFile {
+    /// Parse the TOML file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<ExperimentFile, K2Error> {
+        let contents = fs::read_to_string(path.as_ref())?;
+        toml::from_str(&contents)
+            .map_err(|e| K2Error::InvalidConfig(format!("{}: {}", path.as_ref().display(), e)))
+    }
+
+    /// Create a `Config` from the settings in this file.
+    pub fn config(&self) -> Result<Config, K2Error> {
+        let mut config = Config::new(self.results_dir.clone());
+        config.name = self.name.clone();
+        if let Some(quick) = self.quick {
+            config.quick = quick;
+        }
+        if let Some(dry_run) = self.dry_run {
+            config.dry_run = dry_run;
+        }
+        if let Some(reboot) = self.reboot {
+            config.reboot = reboot;
+        }
+        if let Some(loop_jobs) = self.loop_jobs {
+            config.loop_jobs = loop_jobs;
+        }
+        if let Some(mail_to) = &self.mail_to {
+            config.mail_to = mail_to.clone();
+        }
+        if let Some(in_proc_iters) = self.in_proc_iters {
+            config.in_proc_iters = in_proc_iters;
+        }
+        if let Some(pexecs) = self.pexecs {
+            config.pexecs = pexecs;
+        }
+        if let Some(max_retries) = self.max_retries {
+            config.max_retries = max_retries;
+        }
+        if let Some(max_reruns) = self.max_reruns {
+            config.max_reruns = max_reruns;
+        }
+        if let Some(max_attempts) = self.max_attempts {
+            config.max_attempts = max_attempts;
+        }
+        if let Some(temp_read_pause) = self.temp_read_pause {
+            config.temp_read_pause = Duration::from_secs(temp_read_pause);
+        }
+        if let Some(temp_wait_timeout) = self.temp_wait_timeout {
+            config.temp_wait_timeout = Duration::from_secs(temp_wait_timeout);
+        }
+        if let Some(drop_caches) = self.drop_caches {
+            config.drop_caches = drop_caches;
+        }
+        if let Some(warm_cache) = self.warm_cache {
+            config.warm_cache = warm_cache;
+        }
+        if let Some(post_job_pause) = self.post_job_pause {
+            config.post_job_pause = Duration::from_secs(post_job_pause);
+        }
+        config.temp_threshold = self.temp_threshold;
+        config.timeout = self.timeout.map(Duration::from_secs);
+        config.interrupt_grace = self.interrupt_grace.map(Duration::from_secs);
+        config.cpu_affinity = self.cpu_affinity.clone();
+        config.isolated_cpus = self.isolated_cpus.clone();
+        config.slots = self.slots.clone();
+        config.numa_node = self.numa_node;
+        if let Some(isolate_network) = self.isolate_network {
+            config.isolate_network = isolate_network;
+        }
+        config.services = self.services.clone();
+        config.sched_policy = self.sched_policy;
+        config.cgroup = self.cgroup.as_ref().map(CgroupEntry::build).transpose()?;
+        if let Some(ignore_audit_mismatch) = self.ignore_audit_mismatch {
+            config.ignore_audit_mismatch = ignore_audit_mismatch;
+        }
+        if let Some(check_policy) = self.check_policy {
+            config.check_policy = check_policy;
+        }
+        if let Some(aslr) = self.aslr {
+            config.aslr = aslr;
+        }
+        if let Some(clock) = self.clock {
+            config.clock = clock;
+        }
+        if let Some(cpu_time) = self.cpu_time {
+            config.cpu_time = cpu_time;
+        }
+        if let Some(energy) = self.energy {
+            config.energy = energy;
+        }
+        if let Some(cpu_freq) = self.cpu_freq {
+            config.cpu_freq = cpu_freq;
+        }
+        if let Some(warmup_analysis) = self.warmup_analysis {
+            config.warmup_analysis = warmup_analysis;
+        }
+        if let Some(perf_events) = &self.perf_events {
+            config.perf_events = perf_events.clone();
+        }
+        config.profile = self.profile.clone();
+        config.calibration = self.calibration.clone();
+        config.adaptive = self.adaptive.clone();
+        config.overhead = self.overhead.clone();
+        if let Some(dmesg) = self.dmesg {
+            config.dmesg = dmesg;
+        }
+        config.load_monitor = self.load_monitor.clone();
+        if let Some(ordering) = self.ordering {
+            config.ordering = ordering;
+        }
+        config.rng_seed = self.rng_seed;
+        Ok(config)
+    }
+}
+
+/// The limits of a transient cgroup (see `CgroupLimits`) declared in an experiment
+/// configuration file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CgroupEntry {
+    parent: Option<PathBuf>,
+    memory_max: Option<String>,
+    cpus: Option<f64>,
+    pids_max: Option<u64>,
+}
+
+impl CgroupEntry {
+    pub fn build(&self) -> Result<CgroupLimits, K2Error> {
+        let mut cgroup = CgroupLimits::new();
+        if let Some(parent) = &self.parent {
+            cgroup = cgroup.parent(parent);
+        }
+        if let Some(memory_max) = &self.memory_max {
+            cgroup = cgroup.memory_max(memory_max.parse::<Limit>()?);
+        }
+        if let Some(cpus) = self.cpus {
+            cgroup = cgroup.cpus(cpus);
+        }
+        if let Some(pids_max) = self.pids_max {
+            cgroup = cgroup.pids_max(pids_max);
+        }
+        Ok(cgroup)
+    }
+}
+
+/// A language implementation declared in an experiment configuration file.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum LangImplEntry {
+    /// A `GenericScriptingVm`.
+    Scripting {
+        path: String,
+        /// The name of this configuration of the language implementation, which
+        /// identifies it in the results keys (see `GenericScriptingVm::variant`).
+        variant: Option<String>,
+        /// The arguments to pass to the interpreter, before the benchmark.
+        #[serde(default)]
+        args: Vec<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+        /// The arguments which make the interpreter profile a process execution.
+        #[serde(default)]
+        profiler_args: Vec<String>,
+    },
+    /// A `Jvm`.
+    Jvm {
+        path: String,
+        /// The name of this configuration of the language implementation, which
+        /// identifies it in the results keys (see `GenericScriptingVm::variant`).
+        variant: Option<String>,
+        #[serde(default)]
+        classpath: Vec<String>,
+        #[serde(default)]
+        flags: Vec<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+        /// The flags which make the JVM profile a process execution.
+        #[serde(default)]
+        profiler_flags: Vec<String>,
+    },
+    /// A `GenericNativeCode`.
+    Native {
+        /// The name of this configuration of the language implementation, which
+        /// identifies it in the results keys (see `GenericScriptingVm::variant`).
+        variant: Option<String>,
+        /// The program which builds the benchmarks, followed by its arguments.
+        #[serde(default)]
+        build_command: Vec<String>,
+        compiler: Option<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+    },
+    /// A `WasmRuntime`.
+    Wasm {
+        path: String,
+        /// The name of this configuration of the language implementation, which
+        /// identifies it in the results keys (see `GenericScriptingVm::variant`).
+        variant: Option<String>,
+        #[serde(default)]
+        flags: Vec<String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+        /// The flags which make the runtime profile a process execution.
+        #[serde(default)]
+        profiler_flags: Vec<String>,
+    },
+    /// A `SshLangImpl`.
+    Ssh {
+        host: String,
+        path: String,
+        /// The name of this configuration of the language implementation, which
+        /// identifies it in the results keys (see `GenericScriptingVm::variant`).
+        variant: Option<String>,
+        remote_dir: Option<String>,
+        #[serde(default)]
+        ssh_options: BTreeMap<String, String>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+    },
+}
+
+impl LangImplEntry {
+    pub fn build(&self) -> Result<Arc<dyn LangImpl>, K2Error> {
+        Ok(match self {
+            LangImplEntry::Scripting {
+                path,
+                variant,
+                args,
+                env,
+                profiler_args,
+            } => {
+                let mut vm = GenericScriptingVm::new(path);
+                if let Some(variant) = variant {
+                    vm = vm.variant(variant);
+                }
+                let vm = args.iter().fold(vm, |vm, arg| vm.arg(arg));
+                let vm = profiler_args
+                    .iter()
+                    .fold(vm, |vm, arg| vm.profiler_arg(arg));
+                Arc::new(env.iter().fold(vm, |vm, (k, v)| vm.env(k, v)))
+            }
+            LangImplEntry::Jvm {
+                path,
+                variant,
+                classpath,
+                flags,
+                env,
+                profiler_flags,
+            } => {
+                let mut jvm = Jvm::new(path);
+                if let Some(variant) = variant {
+                    jvm = jvm.variant(variant);
+                }
+                let jvm = classpath.iter().fold(jvm, |jvm, cp| jvm.classpath(cp));
+                let jvm = flags.iter().fold(jvm, |jvm, flag| jvm.flag(flag));
+                let jvm = profiler_flags
+                    .iter()
+                    .fold(jvm, |jvm, flag| jvm.profiler_flag(flag));
+                Arc::new(env.iter().fold(jvm, |jvm, (k, v)| jvm.env(k, v)))
+            }
+            LangImplEntry::Native {
+                variant,
+                build_command,
+                compiler,
+                env,
+            } => {
+                let mut native = GenericNativeCode::new();
+                if let Some(variant) = variant {
+                    native = native.variant(variant);
+                }
+                if let Some((program, args)) = build_command.split_first() {
+                    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+                    native = native.build_command(program, &args);
+                }
+                if let Some(compiler) = compiler {
+                    native = native.compiler(compiler);
+                }
+                Arc::new(env.iter().fold(native, |native, (k, v)| native.env(k, v)))
+            }
+            LangImplEntry::Wasm {
+                path,
+                variant,
+                flags,
+                env,
+                profiler_flags,
+            } => {
+                let mut runtime = WasmRuntime::new(path)?;
+                if let Some(variant) = variant {
+                    runtime = runtime.variant(variant);
+                }
+                let runtime = flags
+                    .iter()
+                    .fold(runtime, |runtime, flag| runtime.flag(flag));
+                let runtime = profiler_flags
+                    .iter()
+                    .fold(runtime, |runtime, flag| runtime.profiler_flag(flag));
+                Arc::new(
+                    env.iter()
+                        .fold(runtime, |runtime, (k, v)| runtime.env(k, v)),
+                )
+            }
+            LangImplEntry::Ssh {
+                host,
+                path,
+                variant,
+                remote_dir,
+                ssh_options,
+                env,
+            } => {
+                let mut ssh = SshLangImpl::new(host, path);
+                if let Some(variant) = variant {
+                    ssh = ssh.variant(variant);
+                }
+                if let Some(remote_dir) = remote_dir {
+                    ssh = ssh.remote_dir(remote_dir);
+                }
+                let ssh = ssh_options
+                    .iter()
+                    .fold(ssh, |ssh, (k, v)| ssh.ssh_option(k, v));
+                Arc::new(env.iter().fold(ssh, |ssh, (k, v)| ssh.env(k, v)))
+            }
+        })
+    }
+}
+
+/// A dashboard declared in an experiment configuration file (see `upload`).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct UploadEntry {
+    kind: UploadKind,
+    url: String,
+    project: String,
+    commit: String,
+    branch: Option<String>,
+    environment: Option<String>,
+    /// The name of each language implementation, indexed by results key.
+    #[serde(default)]
+    executables: BTreeMap<String, String>,
+    /// The name of each benchmark, indexed by results key (without the language
+    /// implementation).
+    #[serde(default)]
+    benchmarks: BTreeMap<String, String>,
+}
+
+/// The kinds of dashboards the results can be uploaded to.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum UploadKind {
+    Codespeed,
+    Rebenchdb,
+}
+
+impl UploadEntry {
+    /// Create the uploader of this dashboard.
+    pub fn build(&self) -> Box<dyn Uploader> {
+        let mut mapping = UploadMapping::new(&self.project, &self.commit);
+        if let Some(branch) = &self.branch {
+            mapping = mapping.branch(branch);
+        }
+        if let Some(environment) = &self.environment {
+            mapping = mapping.environment(environment);
+        }
+        for (lang_impl, name) in &self.executables {
+            mapping = mapping.executable(lang_impl, name);
+        }
+        for (bench, name) in &self.benchmarks {
+            mapping = mapping.benchmark(bench, name);
+        }
+        match self.kind {
+            UploadKind::Codespeed => Box::new(CodespeedUploader::new(&self.url, mapping)),
+            UploadKind::Rebenchdb => Box::new(ReBenchDbUploader::new(&self.url, mapping)),
+        }
+    }
+}
+
+/// A benchmark declared in an experiment configuration file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BenchmarkEntry {
+    path: String,
+    /// What the language implementation runs, if `path` is a project directory.
+    entry: Option<String>,
+    /// The names of the language implementations to run this benchmark on.
+    pub lang_impls: Vec<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    /// The environment variables to set for this benchmark, which override those
+    /// of its language implementations.
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    /// The values of each parameter, indexed by parameter name. The values are
+    /// appended to `args` in order of parameter name.
+    #[serde(default)]
+    params: BTreeMap<String, Vec<String>>,
+    /// The working directory of the benchmark, if it isn't the directory which
+    /// contains the benchmark.
+    cwd: Option<PathBuf>,
+    /// The files to copy to the working directory of the benchmark before each
+    /// process execution.
+    #[serde(default)]
+    inputs: Vec<PathBuf>,
+    /// The file to feed to the standard input of the benchmark.
+    stdin_file: Option<PathBuf>,
+    /// The output the benchmark is expected to print.
+    expect_stdout: Option<String>,
+    /// The file which contains the output the benchmark is expected to print.
+    expect_stdout_file: Option<PathBuf>,
+    stack_lim: Option<String>,
+    heap_lim: Option<String>,
+    cpu_lim: Option<String>,
+    cgroup: Option<CgroupEntry>,
+    timeout: Option<u64>,
+    cpu_affinity: Option<Vec<usize>>,
+    numa_node: Option<usize>,
+    in_proc_iters: Option<usize>,
+    priority: Option<i32>,
+    /// The program (followed by its arguments) to run the benchmark with.
+    wrapper: Option<Vec<String>>,
+}
+
+impl BenchmarkEntry {
+    /// Create a benchmark which runs on `lang_impl`.
+    pub fn build(&self, lang_impl: Arc<dyn LangImpl>) -> Result<Benchmark, K2Error> {
+        self.spec()?.build(lang_impl)
+    }
+
+    /// Describe the benchmark declared by this entry.
+    fn spec(&self) -> Result<BenchmarkSpec, K2Error> {
+        let limit = |limit: &Option<String>| limit.as_deref().map(str::parse).transpose();
+        if let Some(wrapper) = &self.wrapper {
+            wrapper_cmd(wrapper)?;
+        }
+        Ok(BenchmarkSpec {
+            path: self.path.clone(),
+            entry: self.entry.clone(),
+            tags: self.tags.clone(),
+            args: self.args.clone(),
+            env: self.env.clone(),
+            params: self.params.clone().into_iter().collect(),
+            cwd: self.cwd.clone(),
+            inputs: self.inputs.clone(),
+            stdin_file: self.stdin_file.clone(),
+            expect_stdout: self.expect_stdout.clone(),
+            expect_stdout_file: self.expect_stdout_file.clone(),
+            wrapper: self.wrapper.clone().unwrap_or_default(),
+            stack_lim: limit(&self.stack_lim)?,
+            heap_lim: limit(&self.heap_lim)?,
+            cpu_lim: limit(&self.cpu_lim)?,
+            cgroup: self.cgroup.as_ref().map(CgroupEntry::build).transpose()?,
+            timeout: self.timeout.map(Duration::from_secs),
+            cpu_affinity: self.cpu_affinity.clone(),
+            numa_node: self.numa_node,
+            in_proc_iters: self.in_proc_iters,
+            priority: self.priority.unwrap_or_default(),
+        })
+    }
+}
+
+/// A benchmark not to run on a language implementation, declared in an experiment
+/// configuration file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SkipEntry {
+    /// The path of the benchmark.
+    pub path: String,
+    /// The name of the language implementation.
+    pub lang_impl: String,
+    /// Why the benchmark doesn't run on the language implementation.
+    pub reason: String,
+}
+
+/// Split a `wrapper` setting into the program and its arguments.
+pub(crate) fn wrapper_cmd(wrapper: &[String]) -> Result<(&str, Vec<&str>), K2Error> {
+    match wrapper {
+        [cmd, args @ ..] => Ok((cmd, args.iter().map(String::as_str).collect())),
+        [] => Err(K2Error::InvalidConfig(
+            "A wrapper must specify a program".into(),
+        )),
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{
+    error::K2Error,
+    measure::{MeasurementCollector, Measurements},
+    process::InvocationOutcome,
+};
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// The directory where the CPUs are exposed.
+const CPU_DIR: &str = "/sys/devices/system/cpu";
+/// How often to sample the frequency of the CPUs while a benchmark runs.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A CPU whose frequency or thermal throttling can be monitored.
+#[derive(Debug)]
+pub struct Cpu {
+    /// The name of the CPU, such as `cpu0`.
+    pub name: String,
+    /// The physical package (socket) the CPU is on.
+    pub package: u64,
+    /// The file which contains the current frequency of the CPU, in kHz, if the
+    /// machine supports CPU frequency scaling.
+    freq_path: Option<PathBuf>,
+    /// The directory which contains the thermal throttling counters of the CPU, if
+    /// the machine exposes them (only Intel CPUs do).
+    throttle_dir: Option<PathBuf>,
+}
+
+impl Cpu {
+    /// Read the current frequency of the CPU (in kHz), or `None` if it isn't
+    /// exposed.
+    pub fn read_freq(&self) -> Result<Option<u64>, K2Error> {
+        self.freq_path.as_ref().map(read_u64).transpose()
+    }
+
+    /// Read the number of times the core of the CPU was throttled because it was
+    /// too hot, or `None` if it isn't exposed.
+    pub fn read_core_throttles(&self) -> Result<Option<u64>, K2Error> {
+        self.read_throttles("core_throttle_count")
+    }
+
+    /// Read the number of times the package of the CPU was throttled because it was
+    /// too hot, or `None` if it isn't exposed. All the CPUs of a package share this
+    /// counter.
+    pub fn read_package_throttles(&self) -> Result<Option<u64>, K2Error> {
+        self.read_throttles("package_throttle_count")
+    }
+
+    fn read_throttles(&self, counter: &str) -> Result<Option<u64>, K2Error> {
+        match &self.throttle_dir {
+            Some(dir) if dir.join(counter).exists() => Ok(Some(read_u64(dir.join(counter))?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Find all the online CPUs whose frequency or thermal throttling counters are
+/// exposed. Returns an empty list if the machine exposes neither.
+pub fn cpus() -> Result<Vec<Cpu>, K2Error> {
+    let dir = Path::new(CPU_DIR);
+    if !dir.exists() {
+        return Ok(Default::default());
+    }
+    let mut cpus = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let num = match name.strip_prefix("cpu").map(str::parse::<u64>) {
+            Some(Ok(num)) => num,
+            _ => continue,
+        };
+        let freq_path = Some(path.join("cpufreq/scaling_cur_freq")).filter(|path| path.exists());
+        let throttle_dir = Some(path.join("thermal_throttle")).filter(|path| path.exists());
+        if freq_path.is_none() && throttle_dir.is_none() {
+            // The CPU is offline, or the machine doesn't expose either.
+            continue;
+        }
+        let package_path = path.join("topology/physical_package_id");
+        let package = if package_path.exists() {
+            read_u64(package_path)?
+        } else {
+            0
+        };
+        cpus.push((
+            num,
+            Cpu {
+                name,
+                package,
+                freq_path,
+                throttle_dir,
+            },
+        ));
+    }
+    cpus.sort_by_key(|(num, _)| *num);
+    Ok(cpus.into_iter().map(|(_, cpu)| cpu).collect())
+}
+
+/// The thermal throttling counters of the machine at some point in time.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Throttles {
+    /// The number of times a core was throttled, summed over all the CPUs, or
+    /// `None` if the counters aren't exposed.
+    pub core: Option<u64>,
+    /// The number of times a package was throttled, summed over all the packages,
+    /// or `None` if the counters aren't exposed.
+    pub package: Option<u64>,
+}
+
+/// Read the thermal throttling counters of each CPU in `cpus`.
+pub fn read_throttles(cpus: &[Cpu]) -> Result<Throttles, K2Error> {
+    let mut core = None;
+    let mut packages = BTreeMap::new();
+    for cpu in cpus {
+        if let Some(count) = cpu.read_core_throttles()? {
+            *core.get_or_insert(0) += count;
+        }
+        if let Some(count) = cpu.read_package_throttles()? {
+            packages.insert(cpu.package, count);
+        }
+    }
+    Ok(Throttles {
+        core,
+        package: Some(packages.values().sum()).filter(|_| !packages.is_empty()),
+    })
+}
+
+/// Read the current frequency (in kHz) of each CPU in `cpus` whose frequency is
+/// exposed.
+pub fn read_freqs(cpus: &[Cpu]) -> Result<Vec<u64>, K2Error> {
+    let mut freqs = Vec::new();
+    for cpu in cpus {
+        freqs.extend(cpu.read_freq()?);
+    }
+    Ok(freqs)
+}
+
+/// Collects the frequency of the CPUs while each process execution runs, and the
+/// number of times they were throttled because they were too hot.
+///
+/// The frequency of each CPU is sampled every 100 ms by a background thread, and
+/// recorded as `cpufreq:min_khz`, `cpufreq:max_khz` and `cpufreq:mean_khz` (over
+/// all the samples of all the CPUs). The number of thermal throttling events is
+/// recorded as `cpufreq:core_throttles` and `cpufreq:package_throttles`. A process
+/// execution during which the CPUs were throttled, or slowed down, is likely to be
+/// slower than the others.
+#[derive(Debug)]
+pub struct CpuFreqCollector {
+    cpus: Arc<Vec<Cpu>>,
+    sampler: Option<Sampler>,
+    before: Throttles,
+    after: Throttles,
+    freqs: Vec<u64>,
+}
+
+/// The background thread which samples the frequency of the CPUs.
+#[derive(Debug)]
+struct Sampler {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Result<Vec<u64>, K2Error>>,
+}
+
+impl CpuFreqCollector {
+    pub fn new() -> Result<CpuFreqCollector, K2Error> {
+        Ok(CpuFreqCollector {
+            cpus: Arc::new(cpus()?),
+            sampler: None,
+            before: Default::default(),
+            after: Default::default(),
+            freqs: Default::default(),
+        })
+    }
+}
+
+impl MeasurementCollector for CpuFreqCollector {
+    fn start(&mut self) -> Result<(), K2Error> {
+        self.before = read_throttles(&self.cpus)?;
+        self.freqs.clear();
+        let cpus = Arc::clone(&self.cpus);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_sampling = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut freqs = Vec::new();
+            loop {
+                // The frequency is sampled once more after the process exits, so
+                // that short process executions have at least two samples.
+                freqs.extend(read_freqs(&cpus)?);
+                if stop_sampling.load(Ordering::SeqCst) {
+                    return Ok(freqs);
+                }
+                thread::park_timeout(SAMPLE_INTERVAL);
+            }
+        });
+        self.sampler = Some(Sampler { stop, handle });
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), K2Error> {
+        if let Some(sampler) = self.sampler.take() {
+            sampler.stop.store(true, Ordering::SeqCst);
+            sampler.handle.thread().unpark();
+            self.freqs = sampler
+                .handle
+                .join()
+                .map_err(|_| K2Error::SensorError("The CPU frequency sampler panicked".into()))??;
+        }
+        self.after = read_throttles(&self.cpus)?;
+        Ok(())
+    }
+
+    fn collect(
+        &mut self,
+        _outcome: &InvocationOutcome,
+        measurements: &mut Measurements,
+    ) -> Result<(), K2Error> {
+        let per_pexec = &mut measurements.per_pexec;
+        if let (Some(min), Some(max)) = (self.freqs.iter().min(), self.freqs.iter().max()) {
+            let mean = self.freqs.iter().sum::<u64>() as f64 / self.freqs.len() as f64;
+            per_pexec.insert("cpufreq:min_khz".into(), *min as f64);
+            per_pexec.insert("cpufreq:max_khz".into(), *max as f64);
+            per_pexec.insert("cpufreq:mean_khz".into(), mean);
+        }
+        for (name, before, after) in &[
+            ("core_throttles", self.before.core, self.after.core),
+            ("package_throttles", self.before.package, self.after.package),
+        ] {
+            if let (Some(before), Some(after)) = (before, after) {
+                // The counters are reset if a CPU goes offline.
+                let events = after.saturating_sub(*before);
+                per_pexec.insert(format!("cpufreq:{}", name), events as f64);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_u64<P: AsRef<Path>>(path: P) -> Result<u64, K2Error> {
+    let contents = fs::read_to_string(path.as_ref())?;
+    contents.trim().parse::<u64>().map_err(|_| {
+        K2Error::SensorError(format!("Invalid reading from {}", path.as_ref().display()))
+    })
+}
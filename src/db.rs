@@ -1,12 +1,109 @@
 use crate::{
-    benchmark::Benchmark,
+    audit::Audit,
+    benchmark::{Benchmark, TagStore, TAG_LANG_IMPL_VERSION},
+    calibrate::Calibration,
     config::Config,
+    error::K2Error,
+    hooks::{HookPoint, HookRun},
+    load::{BackgroundProcess, LoadSample},
     manifest::{Job, JobStatus},
+    measure::Measurements,
+    overhead::Overhead,
+    services::StoppedService,
+    temperature::Temperatures,
 };
 
-use rusqlite::{self, params, Connection};
+use rusqlite::{self, params, types::Value, Connection, Row};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
 
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The number of jobs with each status.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct JobSummary {
+    pub outstanding: usize,
+    pub done: usize,
+    pub error: usize,
+    pub timed_out: usize,
+    pub wrong_answer: usize,
+    pub too_many_attempts: usize,
+    /// The jobs which weren't run because the results of their benchmark were
+    /// already precise enough (see `adaptive`).
+    #[serde(default)]
+    pub unneeded: usize,
+}
+
+impl fmt::Display for JobSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Outstanding: {}", self.outstanding)?;
+        writeln!(f, "Done: {}", self.done)?;
+        writeln!(f, "Error: {}", self.error)?;
+        writeln!(f, "Timed out: {}", self.timed_out)?;
+        writeln!(f, "Wrong answer: {}", self.wrong_answer)?;
+        writeln!(f, "Too many attempts: {}", self.too_many_attempts)?;
+        write!(f, "Unneeded: {}", self.unneeded)
+    }
+}
+
+/// A job, as recorded in the `job` table. The durations are serialized in seconds,
+/// and the times in seconds since the Unix epoch.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JobRecord {
+    /// The unique identifier of the job.
+    pub id: usize,
+    /// The key of the benchmark the job runs (`<lang_impl>:<path>`).
+    pub key: String,
+    pub status: JobStatus,
+    /// The number of times the job was retried after failing.
+    pub retries: usize,
+    /// The CPUs the job was pinned to, if any.
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// How long the last run of the job took.
+    #[serde(default, with = "crate::util::opt_secs")]
+    pub duration: Option<Duration>,
+    /// When the last run of the job started.
+    #[serde(default, with = "crate::util::opt_timestamp")]
+    pub started_at: Option<SystemTime>,
+    /// When the last run of the job finished.
+    #[serde(default, with = "crate::util::opt_timestamp")]
+    pub finished_at: Option<SystemTime>,
+    /// The index of the process execution the job is. This is `None` for some of
+    /// the jobs of experiments started by older versions of k2.
+    pub pexec: Option<usize>,
+    /// The path of the benchmark the job runs.
+    pub path: Option<String>,
+    /// The results key of the language implementation the job runs on.
+    pub lang_impl: Option<String>,
+    /// The NUMA node the job was bound to, if any.
+    pub numa_node: Option<usize>,
+    /// The number of in-process iterations the job ran (see
+    /// `Benchmark::in_proc_iters`). This is `None` if the job hasn't run, or ran
+    /// before the number was recorded.
+    pub in_proc_iters: Option<usize>,
+    /// The number of times the job was run again because its benchmark asked for it
+    /// (see `measure::RERUN_PREFIX`).
+    pub reruns: usize,
+    /// The number of times the job was started, whether or not it finished.
+    pub attempts: usize,
+    /// The number of attempts which k2 stopped in the middle of (e.g. because the
+    /// machine lost power, or k2 was interrupted), as found when the experiment was
+    /// resumed.
+    #[serde(default)]
+    pub interrupted: usize,
+    /// The SHA-256 checksum of the benchmark file (see `checksum`), or `None` if it
+    /// isn't a file.
+    pub path_sha256: Option<String>,
+    /// The SHA-256 checksum of the executable of the language implementation, or
+    /// `None` if it doesn't have one.
+    pub lang_impl_sha256: Option<String>,
+}
 
 /// A wrapper around the database connection.
 pub(crate) struct K2Store {
@@ -14,9 +111,13 @@ pub(crate) struct K2Store {
     db_path: PathBuf,
 }
 
-impl<'a> K2Store {
+impl K2Store {
     /// The k2 database file.
     pub const K2_DB: &'static str = "k2.db";
+    /// The version of the database schema, which is stored in the `user_version` of
+    /// the database. It must be incremented (and a migration added to `migrations`)
+    /// whenever the schema changes.
+    pub const SCHEMA_VERSION: i64 = 11;
 
     pub fn new<P: AsRef<Path>>(k2_dir: P) -> K2Store {
         // The database connection is not created until it's actually needed.
@@ -27,47 +128,1397 @@ impl<'a> K2Store {
     }
 
     /// Open a new connection to the SQLite database, and return a reference to it.
-    fn connection(&mut self) -> &Connection {
-        let db_path = &self.db_path;
-        self.connection.get_or_insert_with(|| {
-            Connection::open(db_path).expect("Failed to connect to the k2 database")
+    ///
+    /// Returns `K2Error::SchemaVersion` if the database was created by a different
+    /// version of k2, and hasn't been migrated.
+    fn connection(&mut self) -> Result<&Connection, K2Error> {
+        let connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => {
+                debug!("opening {}", self.db_path.display());
+                let connection = Connection::open(&self.db_path)?;
+                check_schema_version(&connection)?;
+                // The machine might reboot (or lose power) right after a job's
+                // results are stored, so every transaction is flushed to disk before
+                // it is committed.
+                connection
+                    .execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = FULL;")?;
+                connection
+            }
+        };
+        Ok(self.connection.get_or_insert(connection))
+    }
+
+    /// Run `f` in a transaction, which is committed if `f` succeeds, and rolled back
+    /// otherwise.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T, K2Error>
+    where
+        F: FnOnce(&mut K2Store) -> Result<T, K2Error>,
+    {
+        self.connection()?.execute_batch("BEGIN IMMEDIATE;")?;
+        match f(self) {
+            Ok(value) => {
+                self.connection()?.execute_batch("COMMIT;")?;
+                Ok(value)
+            }
+            Err(err) => {
+                debug!("rolling back a transaction: {}", err.chain());
+                // The error which caused the rollback is more useful than any error
+                // caused by the rollback itself.
+                let _ = self.connection()?.execute_batch("ROLLBACK;");
+                Err(err)
+            }
+        }
+    }
+
+    /// Create the `job` and `job_tag` tables.
+    ///
+    /// The `job` table records the status and key of each job, the number of times
+    /// it was retried after failing, the CPUs it was pinned to (if any), how long
+    /// (in seconds) its last run took, when its last run started and finished (in
+    /// seconds since the Unix epoch), the index of the process execution it is, and
+    /// the path and language implementation (results key) of its benchmark. Each
+    /// row of the `job_tag` table records a tag of the benchmark of a job.
+    ///
+    /// The `pexec`, `path` and `lang_impl` columns are `NULL` for the jobs of
+    /// experiments started by older versions of k2, if they couldn't be inferred
+    /// from the key of the job.
+    pub fn create_job_table(
+        &mut self,
+        config: &Config,
+        benchmarks: &[Benchmark],
+    ) -> Result<(), K2Error> {
+        // The jobs are created all at once, so that an experiment whose jobs were only
+        // partly created isn't mistaken for one whose jobs changed when it's resumed.
+        self.transaction(|store| {
+            let connection = store.connection()?;
+            create_job_tables(connection)?;
+            let mut stmt = connection.prepare(
+                "INSERT INTO job(job_id, key, status, pexec, path, lang_impl)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+            )?;
+            let mut tag_stmt = connection.prepare("INSERT INTO job_tag VALUES ($1, $2, $3)")?;
+            // The version of each language implementation is only looked up once.
+            let mut versions = HashMap::new();
+            let mut id = 0;
+            for pexec in 0..config.pexecs {
+                for bench in benchmarks {
+                    let job = Job::new(id, bench)?;
+                    id += 1;
+                    stmt.execute(params![
+                        job.id as i64,
+                        job.key,
+                        job.status as i64,
+                        pexec as i64,
+                        bench.path()?,
+                        bench.lang_impl().results_key()
+                    ])?;
+                    for (tag, value) in bench.tags() {
+                        tag_stmt.execute(params![job.id as i64, tag, value])?;
+                    }
+                    let lang_impl = bench.lang_impl();
+                    let version = versions
+                        .entry(lang_impl.results_key())
+                        .or_insert_with(|| lang_impl.version());
+                    if let Some(version) = &*version {
+                        tag_stmt.execute(params![job.id as i64, TAG_LANG_IMPL_VERSION, version])?;
+                    }
+                }
+            }
+            Ok(())
         })
     }
 
-    /// Create the `job` table.
+    /// Retrieve the job with identifier `id`.
+    pub fn job(&mut self, id: usize) -> Result<Job, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT key, status FROM job WHERE job_id = $1;")?;
+        let (key, status) = stmt.query_row(params![id as i64], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        let status = JobStatus::from_i64(status).ok_or(K2Error::Db(
+            rusqlite::Error::IntegralValueOutOfRange(1, status),
+        ))?;
+        Ok(Job { id, key, status })
+    }
+
+    /// Return the identifiers of the outstanding jobs, in job order.
+    pub fn outstanding_jobs(&mut self) -> Result<Vec<usize>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt =
+            connection.prepare("SELECT job_id FROM job WHERE status = $1 ORDER BY job_id;")?;
+        let rows = stmt.query_map(params![JobStatus::Outstanding as i64], |row| {
+            row.get::<_, i64>(0)
+        })?;
+        let jobs = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(jobs.into_iter().map(|job| job as usize).collect())
+    }
+
+    /// Return the key of each job, in job order.
+    pub fn job_keys(&mut self) -> Result<Vec<String>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT key FROM job ORDER BY job_id;")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Create the `measurement` and `pexec_measurement` tables.
     ///
-    /// The table created by this function records the status and key of each job.
-    pub fn create_job_table(&mut self, config: &Config, benchmarks: &[&'_ Benchmark]) {
-        let connection = self.connection();
+    /// Each row of the `measurement` table records the wall-clock time (in seconds)
+    /// of an in-process iteration of a job, whether it's an outlier (see
+    /// `analysis::outliers`), and its CPU time (in seconds, or `NULL` if the
+    /// benchmark didn't report it). Each row of the `pexec_measurement`
+    /// table records a named measurement taken once for the whole job (such as
+    /// `energy:package-0`, in joules).
+    pub fn create_measurement_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE measurement(
+                        job_id INTEGER NOT NULL,
+                        iter INTEGER NOT NULL,
+                        wallclock REAL NOT NULL,
+                        outlier INTEGER NOT NULL DEFAULT 0,
+                        cputime REAL,
+                        PRIMARY KEY(job_id, iter));", rusqlite::NO_PARAMS)?;
+        connection
+            .execute("CREATE TABLE pexec_measurement(
+                        job_id INTEGER NOT NULL,
+                        name TEXT NOT NULL,
+                        value REAL NOT NULL,
+                        PRIMARY KEY(job_id, name));", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record the `measurements` collected while running the job with identifier
+    /// `id`.
+    pub fn insert_measurements(
+        &mut self,
+        id: usize,
+        measurements: &Measurements,
+    ) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt =
+            connection.prepare("INSERT OR REPLACE INTO measurement VALUES ($1, $2, $3, $4, $5)")?;
+        for (iter, wallclock) in measurements.wallclock.iter().enumerate() {
+            let outlier = measurements.outliers.binary_search(&iter).is_ok();
+            stmt.execute(params![
+                id as i64,
+                iter as i64,
+                wallclock.as_secs_f64(),
+                outlier,
+                measurements.cputime.get(iter).map(Duration::as_secs_f64)
+            ])?;
+        }
+        let mut stmt =
+            connection.prepare("INSERT OR REPLACE INTO pexec_measurement VALUES ($1, $2, $3)")?;
+        for (name, value) in &measurements.per_pexec {
+            stmt.execute(params![id as i64, name, value])?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve all the jobs, in job order.
+    pub fn jobs(&mut self) -> Result<Vec<JobRecord>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt =
+            connection.prepare(&format!("SELECT {} FROM job ORDER BY job_id;", JOB_COLUMNS))?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, job_record)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Retrieve the record of the job with identifier `id`.
+    pub fn job_record(&mut self, id: usize) -> Result<JobRecord, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt =
+            connection.prepare(&format!("SELECT {} FROM job WHERE job_id = $1;", JOB_COLUMNS))?;
+        Ok(stmt.query_row(params![id as i64], job_record)?)
+    }
+
+    /// Retrieve the measurements of the job with identifier `id`.
+    pub fn measurements(&mut self, id: usize) -> Result<Measurements, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT wallclock, outlier, cputime FROM measurement WHERE job_id = $1 ORDER BY iter;",
+        )?;
+        let iters = stmt
+            .query_map(params![id as i64], |row| {
+                Ok((
+                    row.get::<_, f64>(0)?,
+                    row.get::<_, bool>(1)?,
+                    row.get::<_, Option<f64>>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let wallclock = iters
+            .iter()
+            .map(|(wallclock, _, _)| Duration::from_secs_f64(*wallclock))
+            .collect();
+        let outliers = iters
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, outlier, _))| *outlier)
+            .map(|(iter, _)| iter)
+            .collect();
+        // The CPU time is only recorded for the first iterations, if at all.
+        let cputime = iters
+            .iter()
+            .map_while(|(_, _, cputime)| cputime.map(Duration::from_secs_f64))
+            .collect();
+        let mut stmt =
+            connection.prepare("SELECT name, value FROM pexec_measurement WHERE job_id = $1;")?;
+        let per_pexec = stmt
+            .query_map(params![id as i64], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(Measurements {
+            wallclock,
+            cputime,
+            outliers,
+            per_pexec,
+        })
+    }
+
+    /// Retrieve the tags of the benchmark of the job with identifier `id`.
+    pub fn job_tags(&mut self, id: usize) -> Result<TagStore, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT tag, value FROM job_tag WHERE job_id = $1;")?;
+        let rows = stmt.query_map(params![id as i64], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Return the wall-clock times of the jobs which completed successfully,
+    /// grouped by job key. Each key maps to one list of in-process iteration
+    /// times (in seconds) per process execution, in job order.
+    pub fn wallclock_times(&mut self) -> Result<BTreeMap<String, Vec<Vec<f64>>>, K2Error> {
+        self.query_wallclock_times(None)
+    }
+
+    /// Return the wall-clock times of the jobs of `key` which completed successfully,
+    /// as `wallclock_times` does.
+    pub fn wallclock_times_of(&mut self, key: &str) -> Result<Vec<Vec<f64>>, K2Error> {
+        Ok(self
+            .query_wallclock_times(Some(key))?
+            .remove(key)
+            .unwrap_or_default())
+    }
+
+    /// The wall-clock times of the jobs which completed successfully, of all keys
+    /// or only of `key`.
+    fn query_wallclock_times(
+        &mut self,
+        key: Option<&str>,
+    ) -> Result<BTreeMap<String, Vec<Vec<f64>>>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT job.job_id, job.key, measurement.wallclock
+             FROM job LEFT JOIN measurement ON job.job_id = measurement.job_id
+             WHERE job.status = $1 AND ($2 IS NULL OR job.key = $2)
+             ORDER BY job.job_id, measurement.iter;",
+        )?;
+        let rows = stmt.query_map(params![JobStatus::Done as i64, key], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<f64>>(2)?,
+            ))
+        })?;
+        let mut times = BTreeMap::<_, Vec<Vec<f64>>>::new();
+        let mut prev_job = None;
+        for row in rows {
+            let (job_id, key, wallclock) = row?;
+            let pexecs = times.entry(key).or_default();
+            if prev_job != Some(job_id) {
+                pexecs.push(Vec::new());
+                prev_job = Some(job_id);
+            }
+            // A job without any measurements is still a process execution.
+            if let (Some(iters), Some(wallclock)) = (pexecs.last_mut(), wallclock) {
+                iters.push(wallclock);
+            }
+        }
+        Ok(times)
+    }
+
+    /// Create the `audit` table, which describes the environment the experiment
+    /// runs in (see `audit::collect`).
+    pub fn create_audit_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
         connection
-            .execute("CREATE TABLE job(
+            .execute("CREATE TABLE audit(
+                        key TEXT PRIMARY KEY,
+                        value TEXT NOT NULL);", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record the environment the experiment runs in.
+    pub fn insert_audit(&mut self, audit: &Audit) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("INSERT INTO audit VALUES ($1, $2)")?;
+        for (key, value) in audit {
+            stmt.execute(params![key, value])?;
+        }
+        Ok(())
+    }
+
+    /// Create the `profile` table, which records the profile directory of each
+    /// profiled job (see `profile`), if it doesn't exist.
+    pub fn create_profile_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS profile(
                         job_id INTEGER PRIMARY KEY,
-                        key TEXT NOT NULL,
-                        status INTEGER NOT NULL);", rusqlite::NO_PARAMS)
-            .expect("Failed to create the job table");
+                        path TEXT NOT NULL);", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record that the profile of the job with identifier `id` is in `path`
+    /// (relative to the results directory).
+    pub fn insert_profile(&mut self, id: usize, path: &Path) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT OR REPLACE INTO profile VALUES ($1, $2)",
+            params![id as i64, path.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    /// Retrieve the profile directory (relative to the results directory) of each
+    /// profiled job, indexed by job identifier.
+    pub fn profiles(&mut self) -> Result<BTreeMap<usize, PathBuf>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT job_id, path FROM profile")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, i64>(0)? as usize,
+                PathBuf::from(row.get::<_, String>(1)?),
+            ))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Create the tables of a merged results directory (see `results::merge`): the
+    /// job and measurement tables, without any jobs.
+    pub fn create_merged_tables(&mut self) -> Result<(), K2Error> {
+        create_job_tables(self.connection()?)?;
+        self.create_measurement_table()
+    }
+
+    /// Copy the jobs of the results database at `db_path`, along with their tags
+    /// and measurements, into this database (see `results::merge`). The jobs are
+    /// renumbered after those already in this database, and given the `tags` they
+    /// don't already have. Returns the number of jobs copied.
+    pub fn merge_from(&mut self, db_path: &Path, tags: &TagStore) -> Result<usize, K2Error> {
+        // Databases can't be attached in a transaction.
+        self.connection()?.execute(
+            "ATTACH DATABASE $1 AS source;",
+            params![db_path.to_string_lossy()],
+        )?;
+        let merged = self.transaction(|store| {
+            let connection = store.connection()?;
+            let offset: i64 = connection.query_row(
+                "SELECT COALESCE(MAX(job_id) + 1, 0) FROM job;",
+                rusqlite::NO_PARAMS,
+                |row| row.get(0),
+            )?;
+            let num_jobs = connection.execute(
+                "INSERT INTO job(job_id, key, status, retries, cpu_affinity, duration, started_at,
+                    finished_at, pexec, path, lang_impl, numa_node, reruns, attempts, path_sha256,
+                    lang_impl_sha256, in_proc_iters, interrupted)
+                 SELECT job_id + $1, key, status, retries, cpu_affinity, duration, started_at,
+                    finished_at, pexec, path, lang_impl, numa_node, reruns, attempts, path_sha256,
+                    lang_impl_sha256, in_proc_iters, interrupted
+                 FROM source.job;",
+                params![offset],
+            )?;
+            connection.execute(
+                "INSERT INTO job_tag SELECT job_id + $1, tag, value FROM source.job_tag;",
+                params![offset],
+            )?;
+            connection.execute(
+                "INSERT INTO measurement SELECT job_id + $1, iter, wallclock, outlier, cputime
+                 FROM source.measurement;",
+                params![offset],
+            )?;
+            connection.execute(
+                "INSERT INTO pexec_measurement SELECT job_id + $1, name, value
+                 FROM source.pexec_measurement;",
+                params![offset],
+            )?;
+            for (tag, value) in tags {
+                connection.execute(
+                    "INSERT OR IGNORE INTO job_tag SELECT job_id + $1, $2, $3 FROM source.job;",
+                    params![offset, tag, value],
+                )?;
+            }
+            Ok(num_jobs)
+        });
+        self.connection()?.execute_batch("DETACH DATABASE source;")?;
+        merged
+    }
+
+    /// Create the `experiment` table, which records the named experiments which
+    /// share this results directory (see `registry`), if it doesn't exist.
+    pub fn create_experiment_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS experiment(
+                        name TEXT PRIMARY KEY,
+                        uuid TEXT NOT NULL UNIQUE,
+                        created_at REAL NOT NULL);", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record the experiment called `name`, unless it's already recorded.
+    pub fn insert_experiment(
+        &mut self,
+        name: &str,
+        uuid: &str,
+        created_at: SystemTime,
+    ) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT OR IGNORE INTO experiment VALUES ($1, $2, $3)",
+            params![name, uuid, timestamp(created_at)],
+        )?;
+        Ok(())
+    }
+
+    /// Retrieve the name, the UUID and the start time of each named experiment, in
+    /// the order they started.
+    pub fn experiments(&mut self) -> Result<Vec<(String, String, SystemTime)>, K2Error> {
+        let connection = self.connection()?;
         let mut stmt = connection
-            .prepare("INSERT INTO job VALUES ($1, $2, $3)")
-            .expect("Failed to prepare query.");
-        let mut id = 0;
-        for _ in 0..config.pexecs {
-            for bench in benchmarks {
-                let job = Job::new(id, &bench);
-                id += 1;
-                stmt
-                    .execute(params![job.id as i64, job.key, job.status as i64])
-                    .expect("Failed to populate the job table");
+            .prepare("SELECT name, uuid, created_at FROM experiment ORDER BY created_at, name")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((row.get(0)?, row.get(1)?, from_timestamp(row.get(2)?)))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Create the `skipped` table, which records the benchmarks which aren't run on
+    /// some language implementation, and why (see `ExperimentBuilder::skip`), if it
+    /// doesn't exist.
+    pub fn create_skipped_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS skipped(
+                        key TEXT PRIMARY KEY,
+                        reason TEXT NOT NULL);", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record that the benchmark with the results key `key` is skipped because of
+    /// `reason`.
+    pub fn insert_skipped(&mut self, key: &str, reason: &str) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT OR REPLACE INTO skipped VALUES ($1, $2)",
+            params![key, reason],
+        )?;
+        Ok(())
+    }
+
+    /// Retrieve the reason each skipped benchmark is skipped, indexed by results key.
+    pub fn skipped(&mut self) -> Result<BTreeMap<String, String>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT key, reason FROM skipped")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Create the `calibration` table, which records the memory usage of each
+    /// benchmark in its calibration run, and the limits suggested for it (see
+    /// `calibrate`), if it doesn't exist.
+    pub fn create_calibration_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS calibration(
+                        key TEXT PRIMARY KEY,
+                        peak_vm INTEGER NOT NULL,
+                        peak_rss INTEGER NOT NULL,
+                        stack INTEGER NOT NULL,
+                        heap_lim TEXT NOT NULL,
+                        stack_lim TEXT NOT NULL);", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record the calibration of the benchmark with the results key `key`.
+    pub fn insert_calibration(
+        &mut self,
+        key: &str,
+        calibration: &Calibration,
+    ) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT OR REPLACE INTO calibration VALUES ($1, $2, $3, $4, $5, $6)",
+            params![
+                key,
+                calibration.peak_vm as i64,
+                calibration.peak_rss as i64,
+                calibration.stack as i64,
+                calibration.heap_lim.to_string(),
+                calibration.stack_lim.to_string()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Retrieve the calibration of each calibrated benchmark, indexed by results
+    /// key.
+    pub fn calibrations(&mut self) -> Result<BTreeMap<String, Calibration>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT key, peak_vm, peak_rss, stack, heap_lim, stack_lim FROM calibration",
+        )?;
+        let rows = stmt
+            .query_map(rusqlite::NO_PARAMS, |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)? as u64,
+                    row.get::<_, i64>(2)? as u64,
+                    row.get::<_, i64>(3)? as u64,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows.into_iter()
+            .map(|(key, peak_vm, peak_rss, stack, heap_lim, stack_lim)| {
+                let calibration = Calibration {
+                    peak_vm,
+                    peak_rss,
+                    stack,
+                    heap_lim: heap_lim.parse()?,
+                    stack_lim: stack_lim.parse()?,
+                };
+                Ok((key, calibration))
+            })
+            .collect()
+    }
+
+    /// Create the `overhead` table, which records the overhead of each language
+    /// implementation, measured by running an empty benchmark (see `overhead`), if
+    /// it doesn't exist.
+    pub fn create_overhead_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS overhead(
+                        lang_impl TEXT PRIMARY KEY,
+                        pexecs INTEGER NOT NULL,
+                        startup REAL NOT NULL,
+                        iteration REAL);", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record the overhead of the language implementation with the results key
+    /// `lang_impl`.
+    pub fn insert_overhead(&mut self, lang_impl: &str, overhead: &Overhead) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT OR REPLACE INTO overhead VALUES ($1, $2, $3, $4)",
+            params![
+                lang_impl,
+                overhead.pexecs as i64,
+                overhead.startup,
+                overhead.iteration
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Retrieve the overhead of each language implementation whose overhead was
+    /// measured, indexed by results key.
+    pub fn overheads(&mut self) -> Result<BTreeMap<String, Overhead>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt =
+            connection.prepare("SELECT lang_impl, pexecs, startup, iteration FROM overhead")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            let overhead = Overhead {
+                pexecs: row.get::<_, i64>(1)? as usize,
+                startup: row.get(2)?,
+                iteration: row.get(3)?,
+            };
+            Ok((row.get::<_, String>(0)?, overhead))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Create the `dmesg` table, which records the messages the kernel logged while
+    /// each job ran (see `dmesg`), if it doesn't exist.
+    pub fn create_dmesg_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS dmesg(
+                        job_id INTEGER NOT NULL,
+                        line INTEGER NOT NULL,
+                        message TEXT NOT NULL,
+                        PRIMARY KEY(job_id, line));", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record the messages the kernel logged while the job with identifier `id`
+    /// ran. These supersede the messages logged during any previous run of the job.
+    pub fn insert_kernel_messages(
+        &mut self,
+        id: usize,
+        messages: &[String],
+    ) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute("DELETE FROM dmesg WHERE job_id = $1", params![id as i64])?;
+        let mut stmt = connection.prepare("INSERT INTO dmesg VALUES ($1, $2, $3)")?;
+        for (line, message) in messages.iter().enumerate() {
+            stmt.execute(params![id as i64, line as i64, message])?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the messages the kernel logged while each job ran, indexed by job
+    /// identifier. Only the jobs during which the kernel logged messages are
+    /// included.
+    pub fn kernel_messages(&mut self) -> Result<BTreeMap<usize, Vec<String>>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt =
+            connection.prepare("SELECT job_id, message FROM dmesg ORDER BY job_id, line")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((row.get::<_, i64>(0)? as usize, row.get::<_, String>(1)?))
+        })?;
+        let mut messages = BTreeMap::<_, Vec<_>>::new();
+        for row in rows {
+            let (id, message) = row?;
+            messages.entry(id).or_default().push(message);
+        }
+        Ok(messages)
+    }
+
+    /// Create the `load_sample` and `load_process` tables, which record the load of
+    /// the machine while each job ran (see `load`), if they don't exist.
+    pub fn create_load_tables(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS load_sample(
+                        job_id INTEGER NOT NULL,
+                        sample INTEGER NOT NULL,
+                        load_avg REAL NOT NULL,
+                        background REAL NOT NULL,
+                        PRIMARY KEY(job_id, sample));", rusqlite::NO_PARAMS)?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS load_process(
+                        job_id INTEGER NOT NULL,
+                        sample INTEGER NOT NULL,
+                        rank INTEGER NOT NULL,
+                        pid INTEGER NOT NULL,
+                        command TEXT NOT NULL,
+                        cpus REAL NOT NULL,
+                        PRIMARY KEY(job_id, sample, rank));", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record the load of the machine while the job with identifier `id` ran. These
+    /// samples supersede the ones taken during any previous run of the job.
+    pub fn insert_load_samples(
+        &mut self,
+        id: usize,
+        samples: &[LoadSample],
+    ) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute("DELETE FROM load_sample WHERE job_id = $1", params![id as i64])?;
+        connection.execute("DELETE FROM load_process WHERE job_id = $1", params![id as i64])?;
+        let mut insert_sample =
+            connection.prepare("INSERT INTO load_sample VALUES ($1, $2, $3, $4)")?;
+        let mut insert_process =
+            connection.prepare("INSERT INTO load_process VALUES ($1, $2, $3, $4, $5, $6)")?;
+        for (idx, sample) in samples.iter().enumerate() {
+            insert_sample.execute(params![
+                id as i64,
+                idx as i64,
+                sample.load_avg,
+                sample.background
+            ])?;
+            for (rank, process) in sample.processes.iter().enumerate() {
+                insert_process.execute(params![
+                    id as i64,
+                    idx as i64,
+                    rank as i64,
+                    process.pid,
+                    process.command,
+                    process.cpus
+                ])?;
             }
         }
+        Ok(())
+    }
+
+    /// Retrieve the load of the machine while each job ran, indexed by job
+    /// identifier.
+    pub fn load_samples(&mut self) -> Result<BTreeMap<usize, Vec<LoadSample>>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT job_id, load_avg, background FROM load_sample ORDER BY job_id, sample",
+        )?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, i64>(0)? as usize,
+                LoadSample {
+                    load_avg: row.get(1)?,
+                    background: row.get(2)?,
+                    processes: Vec::new(),
+                },
+            ))
+        })?;
+        let mut samples = BTreeMap::<_, Vec<_>>::new();
+        for row in rows {
+            let (id, sample) = row?;
+            samples.entry(id).or_default().push(sample);
+        }
+        let mut stmt = connection.prepare(
+            "SELECT job_id, sample, pid, command, cpus FROM load_process
+             ORDER BY job_id, sample, rank",
+        )?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((
+                row.get::<_, i64>(0)? as usize,
+                row.get::<_, i64>(1)? as usize,
+                BackgroundProcess {
+                    pid: row.get(2)?,
+                    command: row.get(3)?,
+                    cpus: row.get(4)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (id, idx, process) = row?;
+            if let Some(sample) = samples
+                .get_mut(&id)
+                .and_then(|samples| samples.get_mut(idx))
+            {
+                sample.processes.push(process);
+            }
+        }
+        Ok(samples)
+    }
+
+    /// Create the `stopped_service` table, which records the services k2 stopped
+    /// (see `services`), if it doesn't exist.
+    pub fn create_service_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS stopped_service(
+                        name TEXT PRIMARY KEY,
+                        stopped_at REAL NOT NULL,
+                        restored_at REAL);", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record that k2 stopped the service `name` at `time`. If the service was
+    /// already stopped before (and started again by a reboot), the time it was
+    /// first stopped is kept.
+    pub fn insert_stopped_service(&mut self, name: &str, time: SystemTime) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT OR IGNORE INTO stopped_service(name, stopped_at) VALUES ($1, $2)",
+            params![name, timestamp(time)],
+        )?;
+        Ok(())
+    }
+
+    /// Record that k2 started the service `name` again at `time`.
+    pub fn update_service_restored(&mut self, name: &str, time: SystemTime) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute(
+            "UPDATE stopped_service SET restored_at = $1 WHERE name = $2",
+            params![timestamp(time), name],
+        )?;
+        Ok(())
+    }
+
+    /// Retrieve the services k2 stopped, in the order it first stopped them.
+    pub fn stopped_services(&mut self) -> Result<Vec<StoppedService>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT name, stopped_at, restored_at FROM stopped_service ORDER BY stopped_at",
+        )?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok(StoppedService {
+                name: row.get(0)?,
+                stopped_at: from_timestamp(row.get(1)?),
+                restored_at: row.get::<_, Option<f64>>(2)?.map(from_timestamp),
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Create the `hook_run` table, which records each run of the hooks of the
+    /// experiment (see `hooks`), if it doesn't exist.
+    pub fn create_hook_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE IF NOT EXISTS hook_run(
+                        run_id INTEGER PRIMARY KEY,
+                        point TEXT NOT NULL,
+                        job_id INTEGER,
+                        hook TEXT NOT NULL,
+                        exit_code INTEGER,
+                        error TEXT,
+                        started_at REAL NOT NULL,
+                        duration REAL NOT NULL);", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record a run of a hook.
+    pub fn insert_hook_run(&mut self, run: &HookRun) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute(
+            "INSERT INTO hook_run(point, job_id, hook, exit_code, error, started_at, duration)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            params![
+                run.point.name(),
+                run.job.map(|job| job as i64),
+                run.hook,
+                run.exit_code,
+                run.error,
+                timestamp(run.started_at),
+                run.duration.as_secs_f64()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Retrieve the runs of the hooks, in the order they ran.
+    pub fn hook_runs(&mut self) -> Result<Vec<HookRun>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT point, job_id, hook, exit_code, error, started_at, duration FROM hook_run
+             ORDER BY run_id",
+        )?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            let point = row.get::<_, String>(0)?;
+            Ok(HookRun {
+                point: HookPoint::from_name(&point).ok_or_else(|| {
+                    rusqlite::Error::InvalidColumnType(0, point, rusqlite::types::Type::Text)
+                })?,
+                job: row.get::<_, Option<i64>>(1)?.map(|job| job as usize),
+                hook: row.get(2)?,
+                exit_code: row.get(3)?,
+                error: row.get(4)?,
+                started_at: from_timestamp(row.get(5)?),
+                duration: Duration::from_secs_f64(row.get(6)?),
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Retrieve the environment the experiment was started in.
+    pub fn audit(&mut self) -> Result<Audit, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT key, value FROM audit")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Create the `temperature_baseline` table, which records the temperature of
+    /// each sensor at the start of the experiment.
+    pub fn create_temperature_baseline_table(&mut self) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection
+            .execute("CREATE TABLE temperature_baseline(
+                        sensor TEXT PRIMARY KEY,
+                        celsius REAL NOT NULL);", rusqlite::NO_PARAMS)?;
+        Ok(())
+    }
+
+    /// Record the baseline temperature of each sensor.
+    pub fn insert_temperature_baseline(&mut self, temps: &Temperatures) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("INSERT INTO temperature_baseline VALUES ($1, $2)")?;
+        for (sensor, celsius) in temps {
+            stmt.execute(params![sensor, celsius])?;
+        }
+        Ok(())
+    }
+
+    /// Retrieve the baseline temperature of each sensor.
+    pub fn temperature_baseline(&mut self) -> Result<Temperatures, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT sensor, celsius FROM temperature_baseline")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
     }
 
     /// Set the status of the job with identifier `id` to `status`.
-    pub fn update_status(&mut self, id: usize, status: JobStatus) {
-        let connection = self.connection();
+    pub fn update_status(&mut self, id: usize, status: JobStatus) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("UPDATE job SET status = $1 WHERE job_id = $2;")?;
+        stmt.execute(params![status as i64, id as i64])?;
+        Ok(())
+    }
+
+    /// Count the jobs with each status.
+    pub fn job_summary(&mut self) -> Result<JobSummary, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT status, COUNT(*) FROM job GROUP BY status;")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        let mut summary = JobSummary::default();
+        for row in rows {
+            let (status, count) = row?;
+            let count = count as usize;
+            match JobStatus::from_i64(status) {
+                Some(JobStatus::Outstanding) => summary.outstanding += count,
+                Some(JobStatus::Done) => summary.done += count,
+                Some(JobStatus::Error) => summary.error += count,
+                Some(JobStatus::TimedOut) => summary.timed_out += count,
+                Some(JobStatus::WrongAnswer) => summary.wrong_answer += count,
+                Some(JobStatus::TooManyAttempts) => summary.too_many_attempts += count,
+                Some(JobStatus::Unneeded) => summary.unneeded += count,
+                None => {
+                    return Err(K2Error::Db(rusqlite::Error::IntegralValueOutOfRange(
+                        0, status,
+                    )))
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Record how long the last run of the job with identifier `id` took.
+    pub fn update_duration(&mut self, id: usize, duration: Duration) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("UPDATE job SET duration = $1 WHERE job_id = $2;")?;
+        stmt.execute(params![duration.as_secs_f64(), id as i64])?;
+        Ok(())
+    }
+
+    /// Record that the job with identifier `id` started running at `time`, and count
+    /// the attempt. The job is marked as running until `end_attempt` is called.
+    pub fn start_attempt(&mut self, id: usize, time: SystemTime) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "UPDATE job SET started_at = $1, attempts = attempts + 1, running = 1
+             WHERE job_id = $2;",
+        )?;
+        stmt.execute(params![timestamp(time), id as i64])?;
+        Ok(())
+    }
+
+    /// Record that the last attempt of the job with identifier `id` ended, whether
+    /// or not the job succeeded.
+    pub fn end_attempt(&mut self, id: usize) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("UPDATE job SET running = 0 WHERE job_id = $1;")?;
+        stmt.execute(params![id as i64])?;
+        Ok(())
+    }
+
+    /// Count the last attempt of each job which is still marked as running as
+    /// interrupted: k2 stopped before the attempt ended. Returns the identifiers of
+    /// the jobs.
+    pub fn record_interrupted(&mut self) -> Result<Vec<usize>, K2Error> {
+        self.transaction(|store| {
+            let connection = store.connection()?;
+            let mut stmt =
+                connection.prepare("SELECT job_id FROM job WHERE running = 1 ORDER BY job_id;")?;
+            let jobs = stmt
+                .query_map(rusqlite::NO_PARAMS, |row| Ok(row.get::<_, i64>(0)? as usize))?
+                .collect::<Result<Vec<_>, _>>()?;
+            connection.execute(
+                "UPDATE job SET interrupted = interrupted + 1, running = 0 WHERE running = 1;",
+                rusqlite::NO_PARAMS,
+            )?;
+            Ok(jobs)
+        })
+    }
+
+    /// Record when the last run of the job with identifier `id` finished.
+    pub fn update_finished_at(&mut self, id: usize, time: SystemTime) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("UPDATE job SET finished_at = $1 WHERE job_id = $2;")?;
+        stmt.execute(params![timestamp(time), id as i64])?;
+        Ok(())
+    }
+
+    /// Create the `reboot` table, which records when the machine rebooted (or, in a
+    /// dry run, when the process restarted) between jobs.
+    pub fn create_reboot_table(&mut self) -> Result<(), K2Error> {
+        create_reboot_table(self.connection()?)
+    }
+
+    /// Record that reboot number `num` happened at `time`.
+    pub fn insert_reboot(&mut self, num: usize, time: SystemTime) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("INSERT OR REPLACE INTO reboot VALUES ($1, $2)")?;
+        stmt.execute(params![num as i64, timestamp(time)])?;
+        Ok(())
+    }
+
+    /// Return the mean time between the start of the first job and the end of the
+    /// last one, per job run so far, or `None` if no job has finished yet. Unlike
+    /// `mean_job_duration`, this includes the time spent rebooting between jobs.
+    pub fn mean_job_interval(&mut self) -> Result<Option<Duration>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT (MAX(finished_at) - MIN(started_at)) / COUNT(finished_at) FROM job;",
+        )?;
+        let mean = stmt.query_row(rusqlite::NO_PARAMS, |row| row.get::<_, Option<f64>>(0))?;
+        Ok(mean
+            .filter(|mean| *mean >= 0.0)
+            .map(Duration::from_secs_f64))
+    }
+
+    /// Return the mean duration of the jobs which have run so far, or `None` if no
+    /// job has run yet.
+    pub fn mean_job_duration(&mut self) -> Result<Option<Duration>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("SELECT AVG(duration) FROM job;")?;
+        let mean = stmt.query_row(rusqlite::NO_PARAMS, |row| row.get::<_, Option<f64>>(0))?;
+        Ok(mean.map(Duration::from_secs_f64))
+    }
+
+    /// Return the identifiers of the jobs which failed (with an error or a
+    /// timeout), and which were retried fewer than `max_retries` times.
+    pub fn retryable_jobs(&mut self, max_retries: usize) -> Result<Vec<usize>, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(
+            "SELECT job_id FROM job WHERE status IN ($1, $2) AND retries < $3 ORDER BY job_id;",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                JobStatus::Error as i64,
+                JobStatus::TimedOut as i64,
+                // `max_retries` might not fit in an i64 (e.g. usize::MAX).
+                max_retries.min(i64::MAX as usize) as i64
+            ],
+            |row| row.get::<_, i64>(0),
+        )?;
+        let jobs = rows.collect::<Result<Vec<_>, _>>()?;
+        Ok(jobs.into_iter().map(|job| job as usize).collect())
+    }
+
+    /// Mark the job with identifier `id` as outstanding, and increment its retry
+    /// count.
+    pub fn mark_retry(&mut self, id: usize) -> Result<(), K2Error> {
+        let connection = self.connection()?;
         let mut stmt = connection
-            .prepare("UPDATE job SET status = $1 WHERE job_id = $2;")
-            .expect("Failed to prepare query.");
-        stmt
-            .execute(params![status as i64, id as i64])
-            .expect("Failed to create the job table");
+            .prepare("UPDATE job SET status = $1, retries = retries + 1 WHERE job_id = $2;")?;
+        stmt.execute(params![JobStatus::Outstanding as i64, id as i64])?;
+        Ok(())
+    }
+
+    /// Record that the outstanding jobs of `key` won't be run, because the results
+    /// of their benchmark are already precise enough. Returns the number of jobs.
+    pub fn mark_unneeded(&mut self, key: &str) -> Result<usize, K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection
+            .prepare("UPDATE job SET status = $1 WHERE key = $2 AND status = $3;")?;
+        let unneeded = stmt.execute(params![
+            JobStatus::Unneeded as i64,
+            key,
+            JobStatus::Outstanding as i64
+        ])?;
+        Ok(unneeded)
+    }
+
+    /// Record that the job with identifier `id` is run again because its benchmark
+    /// asked for it.
+    pub fn mark_rerun(&mut self, id: usize) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("UPDATE job SET reruns = reruns + 1 WHERE job_id = $1;")?;
+        stmt.execute(params![id as i64])?;
+        Ok(())
+    }
+
+    /// Check whether the table called `name` exists.
+    pub fn has_table(&mut self, name: &str) -> Result<bool, K2Error> {
+        table_exists(self.connection()?, name)
+    }
+
+    /// Export the contents of the database as a set of CSV files in `out_dir`.
+    ///
+    /// The following files are created (if the corresponding tables exist):
+    /// * `jobs.csv`: the key, status and metadata of each job.
+    /// * `tags.csv`: the tags of each job, one tag per row.
+    /// * `measurements.csv`: the wall-clock time of each in-process iteration,
+    ///   whether it's an outlier, and its CPU time (if it was reported).
+    /// * `pexec_measurements.csv`: the measurements taken once per job, such as
+    ///   the temperatures read before and after each job.
+    /// * `temperature_baseline.csv`: the temperatures read at the start of the
+    ///   experiment.
+    /// * `reboots.csv`: when each reboot happened.
+    /// * `audit.csv`: the environment the experiment ran in.
+    pub fn export_csv<P: AsRef<Path>>(&mut self, out_dir: P) -> Result<(), K2Error> {
+        fs::create_dir_all(&out_dir).map_err(|err| K2Error::file(out_dir.as_ref(), err))?;
+        // The table each file is exported from, the name of the file, and the query
+        // which produces its contents.
+        let exports = [
+            ("job", "jobs.csv", "SELECT job_id, key,
+                CASE status
+                    WHEN 0 THEN 'outstanding'
+                    WHEN 1 THEN 'done'
+                    WHEN 2 THEN 'error'
+                    WHEN 3 THEN 'timed_out'
+                    WHEN 4 THEN 'wrong_answer'
+                    WHEN 5 THEN 'too_many_attempts'
+                    WHEN 6 THEN 'unneeded'
+                END AS status,
+                retries, cpu_affinity, duration, started_at, finished_at,
+                pexec, path, lang_impl, numa_node, reruns, attempts,
+                path_sha256, lang_impl_sha256, in_proc_iters, interrupted
+                FROM job ORDER BY job_id;"),
+            ("job_tag", "tags.csv", "SELECT job_id, tag, value
+                FROM job_tag ORDER BY job_id, tag;"),
+            ("measurement", "measurements.csv", "SELECT job_id, iter, wallclock, outlier, cputime
+                FROM measurement ORDER BY job_id, iter;"),
+            ("pexec_measurement", "pexec_measurements.csv", "SELECT job_id, name, value
+                FROM pexec_measurement ORDER BY job_id, name;"),
+            ("temperature_baseline", "temperature_baseline.csv", "SELECT sensor, celsius
+                FROM temperature_baseline ORDER BY sensor;"),
+            ("reboot", "reboots.csv", "SELECT reboot_id, rebooted_at
+                FROM reboot ORDER BY reboot_id;"),
+            ("audit", "audit.csv", "SELECT key, value FROM audit ORDER BY key;"),
+        ];
+        for (table, file_name, query) in exports.iter() {
+            if self.has_table(table)? {
+                self.export_query(query, out_dir.as_ref().join(file_name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the results of `query` to the CSV file at `path`.
+    fn export_query<P: AsRef<Path>>(&mut self, query: &str, path: P) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare(query)?;
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        let header = stmt
+            .column_names()
+            .iter()
+            .map(|name| csv_field(name))
+            .collect::<Vec<_>>();
+        writeln!(out, "{}", header.join(","))?;
+        let num_cols = stmt.column_count();
+        let mut rows = stmt.query(rusqlite::NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            let mut fields = Vec::with_capacity(num_cols);
+            for i in 0..num_cols {
+                let field = match row.get::<_, Value>(i)? {
+                    Value::Null => String::new(),
+                    Value::Integer(i) => i.to_string(),
+                    Value::Real(r) => r.to_string(),
+                    Value::Text(t) => csv_field(&t),
+                    Value::Blob(_) => String::new(),
+                };
+                fields.push(field);
+            }
+            writeln!(out, "{}", fields.join(","))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Record the CPUs the job with identifier `id` was pinned to.
+    pub fn update_cpu_affinity(&mut self, id: usize, cpus: &[usize]) -> Result<(), K2Error> {
+        let cpus = cpus
+            .iter()
+            .map(|cpu| cpu.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("UPDATE job SET cpu_affinity = $1 WHERE job_id = $2;")?;
+        stmt.execute(params![cpus, id as i64])?;
+        Ok(())
+    }
+
+    /// Record the NUMA node the job with identifier `id` was bound to.
+    pub fn update_numa_node(&mut self, id: usize, node: usize) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt = connection.prepare("UPDATE job SET numa_node = $1 WHERE job_id = $2;")?;
+        stmt.execute(params![node as i64, id as i64])?;
+        Ok(())
+    }
+
+    /// Record the number of in-process iterations the job with identifier `id` ran.
+    pub fn update_in_proc_iters(&mut self, id: usize, in_proc_iters: usize) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        let mut stmt =
+            connection.prepare("UPDATE job SET in_proc_iters = $1 WHERE job_id = $2;")?;
+        stmt.execute(params![in_proc_iters as i64, id as i64])?;
+        Ok(())
+    }
+
+    /// Retrieve the checksums recorded for the benchmark with the results key `key`
+    /// (see `checksum`): those of the benchmark file and of the executable of its
+    /// language implementation.
+    pub fn checksums(&mut self, key: &str) -> Result<(Option<String>, Option<String>), K2Error> {
+        let connection = self.connection()?;
+        let checksums = connection.query_row(
+            "SELECT path_sha256, lang_impl_sha256 FROM job WHERE key = $1 LIMIT 1;",
+            params![key],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        );
+        match checksums {
+            Ok(checksums) => Ok(checksums),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok((None, None)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Record the checksums of the benchmark with the results key `key`, for the
+    /// jobs which don't have them yet.
+    pub fn record_checksums(
+        &mut self,
+        key: &str,
+        path_sha256: Option<&str>,
+        lang_impl_sha256: Option<&str>,
+    ) -> Result<(), K2Error> {
+        let connection = self.connection()?;
+        connection.execute(
+            "UPDATE job SET
+                path_sha256 = COALESCE(path_sha256, $1),
+                lang_impl_sha256 = COALESCE(lang_impl_sha256, $2)
+             WHERE key = $3;",
+            params![path_sha256, lang_impl_sha256, key],
+        )?;
+        Ok(())
+    }
+}
+
+/// Quote `field` if necessary, so that it can be used as a CSV field.
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Create the `job` and `job_tag` tables, without any jobs (see
+/// `K2Store::create_job_table`).
+fn create_job_tables(connection: &Connection) -> Result<(), K2Error> {
+    connection
+        .execute("CREATE TABLE job(
+                    job_id INTEGER PRIMARY KEY,
+                    key TEXT NOT NULL,
+                    status INTEGER NOT NULL,
+                    retries INTEGER NOT NULL DEFAULT 0,
+                    cpu_affinity TEXT,
+                    duration REAL,
+                    started_at REAL,
+                    finished_at REAL,
+                    pexec INTEGER,
+                    path TEXT,
+                    lang_impl TEXT,
+                    numa_node INTEGER,
+                    reruns INTEGER NOT NULL DEFAULT 0,
+                    attempts INTEGER NOT NULL DEFAULT 0,
+                    path_sha256 TEXT,
+                    lang_impl_sha256 TEXT,
+                    in_proc_iters INTEGER,
+                    running INTEGER NOT NULL DEFAULT 0,
+                    interrupted INTEGER NOT NULL DEFAULT 0);", rusqlite::NO_PARAMS)?;
+    create_job_tag_table(connection)
+}
+
+/// Create the `job_tag` table, which records the tags of the benchmark of each job.
+pub(crate) fn create_job_tag_table(connection: &Connection) -> Result<(), K2Error> {
+    connection
+        .execute("CREATE TABLE job_tag(
+                    job_id INTEGER NOT NULL,
+                    tag TEXT NOT NULL,
+                    value TEXT NOT NULL,
+                    PRIMARY KEY(job_id, tag));", rusqlite::NO_PARAMS)?;
+    Ok(())
+}
+
+/// Create the `reboot` table.
+pub(crate) fn create_reboot_table(connection: &Connection) -> Result<(), K2Error> {
+    connection
+        .execute("CREATE TABLE reboot(
+                    reboot_id INTEGER PRIMARY KEY,
+                    rebooted_at REAL NOT NULL);", rusqlite::NO_PARAMS)?;
+    Ok(())
+}
+
+/// The number of seconds between the Unix epoch and `time`.
+pub(crate) fn timestamp(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// The columns of the `job` table read by `job_record`.
+const JOB_COLUMNS: &str = "job_id, key, status, retries, cpu_affinity, duration, started_at,
+    finished_at, pexec, path, lang_impl, numa_node, reruns, attempts, path_sha256,
+    lang_impl_sha256, in_proc_iters, interrupted";
+
+/// Convert a row with the `JOB_COLUMNS` of the `job` table into a `JobRecord`.
+fn job_record(row: &Row) -> rusqlite::Result<JobRecord> {
+    let status = row.get::<_, i64>(2)?;
+    let cpu_affinity = row.get::<_, Option<String>>(4)?;
+    Ok(JobRecord {
+        id: row.get::<_, i64>(0)? as usize,
+        key: row.get(1)?,
+        status: JobStatus::from_i64(status)
+            .ok_or(rusqlite::Error::IntegralValueOutOfRange(2, status))?,
+        retries: row.get::<_, i64>(3)? as usize,
+        cpu_affinity: cpu_affinity
+            .map(|cpus| cpus.split(',').filter_map(|cpu| cpu.parse().ok()).collect()),
+        duration: row.get::<_, Option<f64>>(5)?.map(Duration::from_secs_f64),
+        started_at: row.get::<_, Option<f64>>(6)?.map(from_timestamp),
+        finished_at: row.get::<_, Option<f64>>(7)?.map(from_timestamp),
+        pexec: row.get::<_, Option<i64>>(8)?.map(|pexec| pexec as usize),
+        path: row.get(9)?,
+        lang_impl: row.get(10)?,
+        numa_node: row.get::<_, Option<i64>>(11)?.map(|node| node as usize),
+        reruns: row.get::<_, i64>(12)? as usize,
+        attempts: row.get::<_, i64>(13)? as usize,
+        path_sha256: row.get(14)?,
+        lang_impl_sha256: row.get(15)?,
+        in_proc_iters: row.get::<_, Option<i64>>(16)?.map(|iters| iters as usize),
+        interrupted: row.get::<_, i64>(17)? as usize,
+    })
+}
+
+/// The time `timestamp` seconds after the Unix epoch.
+fn from_timestamp(timestamp: f64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs_f64(timestamp.max(0.0))
+}
+
+/// Check whether the table called `name` exists in the database.
+pub(crate) fn table_exists(connection: &Connection, name: &str) -> Result<bool, K2Error> {
+    let mut stmt = connection
+        .prepare("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = $1;")?;
+    let count = stmt.query_row(params![name], |row| row.get::<_, i64>(0))?;
+    Ok(count > 0)
+}
+
+/// The schema version of the database. Databases created before the schema was
+/// versioned have version 0.
+pub(crate) fn schema_version(connection: &Connection) -> Result<i64, K2Error> {
+    Ok(connection.query_row("PRAGMA user_version;", rusqlite::NO_PARAMS, |row| row.get(0))?)
+}
+
+pub(crate) fn set_schema_version(connection: &Connection, version: i64) -> Result<(), K2Error> {
+    // PRAGMA statements can't have parameters.
+    connection.execute_batch(&format!("PRAGMA user_version = {};", version))?;
+    Ok(())
+}
+
+/// Check that the database uses the current schema. A database without any tables
+/// was just created, so it is marked as using the current schema.
+fn check_schema_version(connection: &Connection) -> Result<(), K2Error> {
+    let version = schema_version(connection)?;
+    if version == K2Store::SCHEMA_VERSION {
+        return Ok(());
+    }
+    let num_tables = connection.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table';",
+        rusqlite::NO_PARAMS,
+        |row| row.get::<_, i64>(0),
+    )?;
+    if version == 0 && num_tables == 0 {
+        return set_schema_version(connection, K2Store::SCHEMA_VERSION);
     }
+    Err(K2Error::SchemaVersion {
+        found: version,
+        expected: K2Store::SCHEMA_VERSION,
+    })
 }
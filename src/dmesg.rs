@@ -0,0 +1,108 @@
+//! Monitoring the kernel log while the benchmarks run.
+//!
+//! The kernel logs the events which can silently disturb a benchmark, such as
+//! thermal throttling, OOM kills and machine check exceptions. The messages the
+//! kernel logs while a job runs are read from `/dev/kmsg` (which requires root if
+//! `kernel.dmesg_restrict` is set), and recorded in the `dmesg` table of the
+//! results database (see `Results::kernel_messages`).
+
+use crate::error::K2Error;
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    fs::OpenOptions,
+    io::{ErrorKind, Read},
+    os::unix::fs::OpenOptionsExt,
+};
+
+/// The device the kernel log is read from.
+const KMSG: &str = "/dev/kmsg";
+/// The maximum size of a record of the kernel log. A read from `/dev/kmsg` into a
+/// smaller buffer fails.
+const MAX_RECORD_LEN: usize = 8192;
+
+/// What to do when the kernel logs messages while a job runs.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DmesgPolicy {
+    /// Don't read the kernel log.
+    #[default]
+    Ignore,
+    /// Record the messages with the results of the job.
+    Record,
+    /// Record the messages, and fail the job (see `K2Error::KernelMessages`), so
+    /// that it is run again once the other jobs have run, if `max_retries` allows.
+    Rerun,
+}
+
+/// A message of the kernel log.
+#[derive(Debug)]
+struct Record {
+    /// The sequence number of the message, which increases with each message.
+    seq: u64,
+    /// The message, prefixed with the time it was logged at (in seconds since
+    /// boot), as printed by `dmesg`.
+    text: String,
+}
+
+/// The sequence number of the last message of the kernel log, or `None` if the
+/// log is empty.
+pub(crate) fn last_seq() -> Result<Option<u64>, K2Error> {
+    Ok(read_kmsg()?.last().map(|record| record.seq))
+}
+
+/// The messages the kernel logged after the message with sequence number `seq`
+/// (or all of them, if `seq` is `None`).
+pub(crate) fn messages_since(seq: Option<u64>) -> Result<Vec<String>, K2Error> {
+    Ok(read_kmsg()?
+        .into_iter()
+        .filter(|record| seq.is_none_or(|seq| record.seq > seq))
+        .map(|record| record.text)
+        .collect())
+}
+
+/// Read the messages still in the kernel log, oldest first.
+fn read_kmsg() -> Result<Vec<Record>, K2Error> {
+    let mut kmsg = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(KMSG)?;
+    let mut records = Vec::new();
+    let mut buf = vec![0; MAX_RECORD_LEN];
+    loop {
+        // Each read returns a single record.
+        match kmsg.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => records.extend(parse_record(&String::from_utf8_lossy(&buf[..len]))),
+            // There are no more records.
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            // The record was overwritten before it could be read: the next read
+            // returns the oldest record still in the log.
+            Err(err) if err.raw_os_error() == Some(libc::EPIPE) => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(records)
+}
+
+/// Parse a record of `/dev/kmsg`, which has the form
+/// `<priority>,<sequence>,<timestamp>,<flags>[,...];<message>`, followed by
+/// continuation lines (such as the device the message is about). The timestamp is
+/// in microseconds since boot.
+fn parse_record(record: &str) -> Option<Record> {
+    let (header, message) = record.split_once(';')?;
+    let mut fields = header.split(',');
+    let seq = fields.nth(1)?.parse().ok()?;
+    let usecs = fields.next()?.parse::<u64>().ok()?;
+    let message = message.lines().next().unwrap_or_default();
+    Some(Record {
+        seq,
+        text: format!(
+            "[{:5}.{:06}] {}",
+            usecs / 1_000_000,
+            usecs % 1_000_000,
+            message
+        ),
+    })
+}
@@ -0,0 +1,151 @@
+use crate::{
+    error::K2Error,
+    measure::{MeasurementCollector, Measurements},
+    process::InvocationOutcome,
+};
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The directory where the RAPL domains are exposed.
+const POWERCAP_DIR: &str = "/sys/class/powercap";
+/// The prefix of the name of each RAPL zone directory.
+const RAPL_PREFIX: &str = "intel-rapl:";
+
+/// The energy used by each RAPL zone (in joules), indexed by zone name.
+pub type Energy = BTreeMap<String, f64>;
+
+/// A RAPL zone (a package, or a domain within a package, such as its cores).
+#[derive(Debug)]
+pub struct RaplZone {
+    /// The name used to identify the zone in the results, such as `package-0` or
+    /// `package-0:core`.
+    pub name: String,
+    /// The file which contains the energy counter of the zone, in microjoules.
+    energy_path: PathBuf,
+    /// The value at which the energy counter wraps around.
+    max_energy: u64,
+}
+
+impl RaplZone {
+    /// Read the energy counter of the zone, in microjoules.
+    pub fn read(&self) -> Result<u64, K2Error> {
+        read_u64(&self.energy_path)
+    }
+}
+
+/// Find all the RAPL zones of the machine. Returns an empty list if the machine
+/// doesn't support RAPL.
+pub fn zones() -> Result<Vec<RaplZone>, K2Error> {
+    let dir = Path::new(POWERCAP_DIR);
+    if !dir.exists() {
+        return Ok(Default::default());
+    }
+    let mut zone_dirs = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<Vec<_>, _>>()?;
+    zone_dirs.retain(|name| name.starts_with(RAPL_PREFIX));
+    zone_dirs.sort();
+    let mut zones = Vec::new();
+    for zone_dir in &zone_dirs {
+        let path = dir.join(zone_dir);
+        if !path.join("energy_uj").exists() {
+            continue;
+        }
+        // The zone `intel-rapl:0:1` is a domain of the package `intel-rapl:0`.
+        let mut name = zone_name(&path)?;
+        if let Some((parent, _)) = zone_dir.rsplit_once(':') {
+            if parent != RAPL_PREFIX.trim_end_matches(':') {
+                name = format!("{}:{}", zone_name(&dir.join(parent))?, name);
+            }
+        }
+        zones.push(RaplZone {
+            name,
+            energy_path: path.join("energy_uj"),
+            max_energy: read_u64(path.join("max_energy_range_uj"))?,
+        });
+    }
+    Ok(zones)
+}
+
+/// Read the energy counter of each zone in `zones`.
+pub fn read_all(zones: &[RaplZone]) -> Result<Vec<u64>, K2Error> {
+    zones.iter().map(|zone| zone.read()).collect()
+}
+
+/// Compute the energy used by each zone between the `before` and `after`
+/// readings of `read_all`.
+pub fn energy_used(zones: &[RaplZone], before: &[u64], after: &[u64]) -> Energy {
+    zones
+        .iter()
+        .zip(before.iter().zip(after))
+        .map(|(zone, (before, after))| {
+            // The counter wraps around when it reaches `max_energy`.
+            let microjoules = if after >= before {
+                after - before
+            } else {
+                zone.max_energy - before + after
+            };
+            (zone.name.clone(), microjoules as f64 / 1_000_000.0)
+        })
+        .collect()
+}
+
+/// Collects the energy used by each process execution (in joules), for each
+/// RAPL zone of the machine.
+#[derive(Debug)]
+pub struct EnergyCollector {
+    zones: Vec<RaplZone>,
+    before: Vec<u64>,
+    energy: Energy,
+}
+
+impl EnergyCollector {
+    pub fn new() -> Result<EnergyCollector, K2Error> {
+        Ok(EnergyCollector {
+            zones: zones()?,
+            before: Default::default(),
+            energy: Default::default(),
+        })
+    }
+}
+
+impl MeasurementCollector for EnergyCollector {
+    fn start(&mut self) -> Result<(), K2Error> {
+        self.before = read_all(&self.zones)?;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), K2Error> {
+        let after = read_all(&self.zones)?;
+        self.energy = energy_used(&self.zones, &self.before, &after);
+        Ok(())
+    }
+
+    fn collect(
+        &mut self,
+        _outcome: &InvocationOutcome,
+        measurements: &mut Measurements,
+    ) -> Result<(), K2Error> {
+        for (zone, joules) in &self.energy {
+            measurements
+                .per_pexec
+                .insert(format!("energy:{}", zone), *joules);
+        }
+        Ok(())
+    }
+}
+
+fn zone_name(path: &Path) -> Result<String, K2Error> {
+    Ok(fs::read_to_string(path.join("name"))?.trim().to_string())
+}
+
+fn read_u64<P: AsRef<Path>>(path: P) -> Result<u64, K2Error> {
+    let contents = fs::read_to_string(path.as_ref())?;
+    contents.trim().parse::<u64>().map_err(|_| {
+        K2Error::SensorError(format!("Invalid reading from {}", path.as_ref().display()))
+    })
+}
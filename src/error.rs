@@ -1,7 +1,282 @@
+//! The errors of k2.
+//!
+//! `K2Error` implements `std::error::Error`: the errors which wrap another error
+//! (such as `K2Error::File`, or `K2Error::Job`) describe what k2 was doing when it
+//! failed, and return the underlying error from `source`. Use `K2Error::chain` to
+//! describe the whole chain of errors at once.
+
+use std::{error::Error, fmt, io, path::PathBuf, time::Duration};
+
 /// An error describing why an experiment failed.
 #[derive(Debug)]
 pub enum K2Error {
     Unknown,
-    ExecutionFailed,
+    /// The benchmark process exited with a non-zero exit code, or was killed by a
+    /// signal.
+    ExecutionFailed {
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+        stderr: String,
+    },
+    /// The benchmark asked to be run again (see `measure::RERUN_PREFIX`).
     RerunError,
+    /// The job was started this many times without finishing (see
+    /// `Config::max_attempts`).
+    TooManyAttempts(usize),
+    /// A benchmark reported a malformed measurement.
+    InvalidMeasurement(String),
+    /// The benchmark process didn't finish before its timeout expired, or exceeded
+    /// its CPU time limit. Contains the timeout (or the limit).
+    TimedOut(Duration),
+    /// The benchmark didn't print the output it was expected to (see
+    /// `Benchmark::expect_stdout`).
+    WrongAnswer(String),
+    /// An I/O operation failed.
+    Io(io::Error),
+    /// An I/O operation on `path` failed.
+    File {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// A query against the results database failed.
+    Db(rusqlite::Error),
+    /// A query against a Postgres result store failed.
+    #[cfg(feature = "postgres")]
+    Postgres(pg::Error),
+    /// The manifest header is malformed.
+    ManifestCorrupted(String),
+    /// The results database was created by a different version of k2. Results
+    /// created by older versions can be upgraded using `migrations::migrate`.
+    SchemaVersion {
+        found: i64,
+        expected: i64,
+    },
+    /// A manifest header field is too large to fit in its fixed-width slot.
+    ManifestFieldOverflow {
+        field: &'static str,
+        value: usize,
+    },
+    /// An executable could not be found in ${PATH}.
+    ExecutableNotFound(String),
+    /// A temperature sensor couldn't be read.
+    SensorError(String),
+    /// The machine didn't cool down to its baseline temperature in time.
+    TooHot,
+    /// The experiment couldn't be set up to resume after a reboot.
+    BootSetup(String),
+    /// A notification couldn't be sent.
+    Notification(String),
+    /// The experiment configuration is invalid.
+    InvalidConfig(String),
+    /// A mandatory tag was not set.
+    MissingTag(&'static str),
+    /// The environment changed since the experiment started. Contains the names of
+    /// the audit properties which changed.
+    AuditMismatch(Vec<String>),
+    /// The benchmark files or the executables of the language implementations
+    /// changed since the experiment started (see `checksum`). Contains the paths of
+    /// the files which changed.
+    ChecksumMismatch(Vec<String>),
+    /// The benchmarks or the number of process executions changed since the
+    /// experiment started, so the jobs recorded in the results no longer match the
+    /// experiment.
+    JobMismatch(String),
+    /// The benchmarks of a language implementation couldn't be built.
+    BuildFailed(String),
+    /// The machine isn't set up for benchmarking (see `checks::CheckPolicy`).
+    CheckFailed(String),
+    /// The kernel logged these messages while the job ran (see
+    /// `dmesg::DmesgPolicy::Rerun`).
+    KernelMessages(Vec<String>),
+    /// The other processes of the machine used this many CPUs while the job ran
+    /// (see `load::LoadMonitorConfig::rerun`).
+    BackgroundLoad(f64),
+    /// A plot of the results couldn't be drawn.
+    Plot(String),
+    /// The results couldn't be uploaded to a dashboard.
+    Upload(String),
+    /// A hook failed (see `hooks`).
+    HookFailed(String),
+    /// Another instance of k2 is running the experiment in `results_dir` (see
+    /// `lock`). Contains the process ID of the other instance, if it's known.
+    ExperimentLocked {
+        results_dir: PathBuf,
+        pid: Option<u32>,
+    },
+    /// k2 was asked to stop by this signal (see `shutdown`). The experiment can be
+    /// resumed.
+    Interrupted(i32),
+    /// Running job `id`, which runs the benchmark with the results key `key`,
+    /// failed.
+    Job {
+        id: usize,
+        key: String,
+        source: Box<K2Error>,
+    },
+}
+
+impl K2Error {
+    /// An error which says that the I/O operation on `path` failed with `source`.
+    pub fn file<P: Into<PathBuf>>(path: P, source: io::Error) -> K2Error {
+        K2Error::File {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// An error which says that `path` doesn't exist.
+    pub fn not_found<P: Into<PathBuf>>(path: P) -> K2Error {
+        K2Error::file(path, io::Error::from_raw_os_error(libc::ENOENT))
+    }
+
+    /// Describe the error, followed by each of the errors which caused it, such as
+    /// `job 3 (fib:sh) failed: before_job hook false: the command exited with exit
+    /// status: 1`.
+    pub fn chain(&self) -> String {
+        let mut description = self.to_string();
+        let mut source = self.source();
+        while let Some(err) = source {
+            description.push_str(&format!(": {}", err));
+            source = err.source();
+        }
+        description
+    }
+}
+
+impl fmt::Display for K2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            K2Error::Unknown => write!(f, "unknown error"),
+            K2Error::ExecutionFailed {
+                exit_code,
+                signal,
+                stderr,
+            } => {
+                match (exit_code, signal) {
+                    (Some(code), _) => write!(f, "the benchmark exited with exit code {}", code)?,
+                    (None, Some(signal)) => {
+                        write!(f, "the benchmark was killed by signal {}", signal)?
+                    }
+                    (None, None) => write!(f, "the benchmark failed")?,
+                }
+                // The last line is usually the one which says what went wrong.
+                match stderr.lines().rev().find(|line| !line.trim().is_empty()) {
+                    Some(line) => write!(f, " ({})", line.trim()),
+                    None => Ok(()),
+                }
+            }
+            K2Error::RerunError => write!(f, "the benchmark asked to be run again"),
+            K2Error::TooManyAttempts(attempts) => {
+                write!(
+                    f,
+                    "the job was started {} times without finishing",
+                    attempts
+                )
+            }
+            K2Error::InvalidMeasurement(msg) => write!(f, "invalid measurement: {}", msg),
+            K2Error::TimedOut(limit) => write!(
+                f,
+                "the benchmark didn't finish within {:.1}s",
+                limit.as_secs_f64()
+            ),
+            K2Error::WrongAnswer(msg) => write!(f, "wrong answer: {}", msg),
+            // The underlying errors describe themselves.
+            K2Error::Io(err) => write!(f, "{}", err),
+            K2Error::File { path, .. } => write!(f, "{}", path.display()),
+            K2Error::Db(err) => write!(f, "results database: {}", err),
+            #[cfg(feature = "postgres")]
+            K2Error::Postgres(err) => write!(f, "Postgres result store: {}", err),
+            K2Error::ManifestCorrupted(msg) => write!(f, "the manifest is corrupted: {}", msg),
+            K2Error::SchemaVersion { found, expected } => write!(
+                f,
+                "the results database has schema version {}, but k2 expects {} \
+                 (run `k2 migrate` to upgrade it)",
+                found, expected
+            ),
+            K2Error::ManifestFieldOverflow { field, value } => write!(
+                f,
+                "{} {} is too large to fit in the manifest header",
+                field, value
+            ),
+            K2Error::ExecutableNotFound(name) => write!(f, "{} not found in $PATH", name),
+            K2Error::SensorError(msg) => write!(f, "temperature sensor: {}", msg),
+            K2Error::TooHot => write!(f, "the machine didn't cool down in time"),
+            K2Error::BootSetup(msg) => write!(f, "couldn't set up resuming on boot: {}", msg),
+            K2Error::Notification(msg) => write!(f, "couldn't send a notification: {}", msg),
+            K2Error::InvalidConfig(msg) => write!(f, "invalid configuration: {}", msg),
+            K2Error::MissingTag(tag) => write!(f, "the mandatory tag {} is not set", tag),
+            K2Error::AuditMismatch(names) => write!(
+                f,
+                "the environment changed since the experiment started: {}",
+                names.join(", ")
+            ),
+            K2Error::ChecksumMismatch(paths) => write!(
+                f,
+                "files changed since the experiment started: {}",
+                paths.join(", ")
+            ),
+            K2Error::JobMismatch(msg) => write!(f, "the jobs changed: {}", msg),
+            K2Error::BuildFailed(msg) => write!(f, "build failed: {}", msg),
+            K2Error::CheckFailed(msg) => write!(f, "the machine isn't ready: {}", msg),
+            K2Error::KernelMessages(messages) => write!(
+                f,
+                "the kernel logged {} message(s) while the job ran, starting with: {}",
+                messages.len(),
+                messages.first().map(String::as_str).unwrap_or_default()
+            ),
+            K2Error::BackgroundLoad(cpus) => {
+                write!(f, "other processes used {:.2} CPUs while the job ran", cpus)
+            }
+            K2Error::Plot(msg) => write!(f, "couldn't draw the plot: {}", msg),
+            K2Error::Upload(msg) => write!(f, "couldn't upload the results: {}", msg),
+            K2Error::HookFailed(msg) => write!(f, "hook failed: {}", msg),
+            K2Error::ExperimentLocked { results_dir, pid } => {
+                write!(
+                    f,
+                    "the experiment in {} is already being run by another instance of k2",
+                    results_dir.display()
+                )?;
+                match pid {
+                    Some(pid) => write!(f, " (pid {})", pid),
+                    None => Ok(()),
+                }
+            }
+            K2Error::Interrupted(signal) => write!(f, "interrupted by signal {}", signal),
+            K2Error::Job { id, key, .. } => write!(f, "job {} ({}) failed", id, key),
+        }
+    }
+}
+
+impl Error for K2Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            // `Display` already describes these errors.
+            K2Error::Io(err) => err.source(),
+            K2Error::Db(err) => err.source(),
+            #[cfg(feature = "postgres")]
+            K2Error::Postgres(err) => err.source(),
+            K2Error::File { source, .. } => Some(source),
+            K2Error::Job { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for K2Error {
+    fn from(err: io::Error) -> K2Error {
+        K2Error::Io(err)
+    }
+}
+
+impl From<rusqlite::Error> for K2Error {
+    fn from(err: rusqlite::Error) -> K2Error {
+        K2Error::Db(err)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<pg::Error> for K2Error {
+    fn from(err: pg::Error) -> K2Error {
+        K2Error::Postgres(err)
+    }
 }
@@ -1,109 +1,1493 @@
 use crate::{
-    benchmark::Benchmark,
+    adaptive::AdaptiveConfig,
+    analysis, audit,
+    benchmark::{Benchmark, TAG_PATH},
+    boot::{self, BootMethod},
+    cache,
+    calibrate::{Calibration, CalibrationConfig},
+    checks::{self, CheckPolicy},
+    checksum,
+    clock::Clock,
     config::Config,
+    config_file::{self, ExperimentFile, UploadEntry},
+    cpufreq::CpuFreqCollector,
     db::K2Store,
+    dmesg::{self, DmesgPolicy},
+    energy::EnergyCollector,
     error::K2Error,
-    manifest::{JobStatus, ManifestManager},
+    hooks::{self, Hook, HookContext, HookPoint},
+    isolation,
+    lang_impl::LangImpl,
+    limit::CgroupLimits,
+    load::{LoadMonitor, LoadMonitorConfig, LoadSample},
+    lock::ExperimentLock,
+    log,
+    manifest::{JobStatus, ManifestManager, OrderingStrategy},
+    measure::{MeasurementCollector, Measurements, RusageCollector, WallclockCollector},
+    migrations, network,
+    notify::{Event, MailTransport, Mailer, Notifier},
+    numa,
+    overhead::{Overhead, OverheadConfig},
+    overrides,
+    perf::{self, PerfCollector, PerfEvent},
+    process::{KillBehaviour, SchedPolicy},
+    profile::{self, Profile, ProfileConfig},
+    registry,
+    results::{ExperimentStatus, Results},
+    services::{self, ServicesConfig},
+    shutdown,
+    store::{JsonlStore, ResultStore},
+    suite::SuiteManifest,
+    temperature::{self, Sensor, TemperatureCollector},
+    upload::Uploader,
     util,
 };
 
+use clap::ArgMatches;
+use tracing::{debug, info, info_span, warn};
+
 use std::{
-    fs,
+    collections::{HashMap, HashSet},
+    fs, io, mem, panic,
     path::{Path, PathBuf},
-    time::Duration,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
 /// The experiment runner.
-pub struct Experiment<'a> {
+pub struct Experiment {
     /// The configuration variables.
     config: Config,
     /// The benchmarks to run.
-    benchmarks: Vec<&'a Benchmark<'a>>,
+    benchmarks: Vec<Benchmark>,
+    /// The empty benchmarks which measure the overhead of the language
+    /// implementations (see `overhead`).
+    empty_benchmarks: Vec<Benchmark>,
+    /// The index of each benchmark in `benchmarks`, indexed by results key.
+    bench_idxs: HashMap<String, usize>,
     /// An interface to the manifest used to schedule benchmark execution.
     manifest: ManifestManager,
     /// Whether is is the first run of the experiment.
     first_run: bool,
+    /// Whether the experiment has been started, i.e. whether everything done before
+    /// its first job (see `prepare`) has been done. This is recorded by the
+    /// `temperature_baseline` table, which is created last, so that if k2 stops (or
+    /// fails) before then, it's all done again when the experiment is resumed. (So
+    /// is an experiment started by an older version of k2, which didn't record it.)
+    started: bool,
     /// An interface to the underlying database.
     store: K2Store,
+    /// The temperature sensors of the machine.
+    sensors: Vec<Sensor>,
+    /// The notifiers to tell about the progress of the experiment.
+    notifiers: Vec<Box<dyn Notifier>>,
+    /// The collectors which take measurements around each process execution. Each
+    /// slot (see `Config::slots`) has its own set of collectors; if the jobs run
+    /// one after the other, there is a single set.
+    collectors: Vec<Vec<Box<dyn MeasurementCollector>>>,
+    /// The stores the results of each job are copied to.
+    result_stores: Vec<Box<dyn ResultStore>>,
+    /// The dashboards the results are uploaded to once all the jobs have run.
+    uploaders: Vec<Box<dyn Uploader>>,
+    /// The hooks to run before and after the jobs, with the points they run at.
+    hooks: Vec<(HookPoint, Hook)>,
+    /// A description of the filter which selected the benchmarks to run (see
+    /// `ExperimentBuilder::only_tag`), which is recorded in the audit.
+    filter: String,
+    /// The lock on the results directory, which is held while the experiment runs
+    /// (see `lock`).
+    _lock: ExperimentLock,
 }
 
-impl<'a> Experiment<'a> {
+/// The results key of a benchmark which isn't run, and why. See
+/// `ExperimentBuilder::skip`.
+type Skipped = (String, String);
+
+impl Experiment {
     // Private: experiments should always be created through the ExperimentBuilder.
-    fn new(config: Config, benchmarks: Vec<&'a Benchmark>) -> Self {
-        let first_run = if Path::new(&config.results_dir).exists() {
-            false
-        } else {
-            // Create a directory to store the results and the manifest.
-            fs::create_dir(&config.results_dir).expect("Failed to create results dir");
-            true
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        mut config: Config,
+        benchmarks: Vec<Benchmark>,
+        empty_benchmarks: Vec<Benchmark>,
+        mut notifiers: Vec<Box<dyn Notifier>>,
+        user_collectors: Vec<Box<dyn MeasurementCollector>>,
+        result_stores: Vec<Box<dyn ResultStore>>,
+        uploaders: Vec<Box<dyn Uploader>>,
+        hooks: Vec<(HookPoint, Hook)>,
+        filter: String,
+        skipped: Vec<Skipped>,
+    ) -> Result<Self, K2Error> {
+        log::init();
+        // This must happen before the manifest is created, since quick mode changes
+        // the number of jobs.
+        config.apply_quick();
+        perf::check_supported(&config.perf_events)?;
+        let lang_impls = lang_impls(&benchmarks);
+        check_results_keys(&lang_impls)?;
+        let bench_idxs = bench_idxs(&benchmarks)?;
+        for bench in &benchmarks {
+            bench.limits(&config).check()?;
+            if let (false, Some(node)) = (config.dry_run, bench.effective_numa_node(&config)) {
+                numa::node(node)?;
+            }
+        }
+        if let Some(sched_policy) = config.sched_policy {
+            sched_policy.check()?;
+        }
+        if let Some(slots) = &config.slots {
+            check_slots(slots)?;
+            // Each slot needs its own collectors.
+            if slots.len() > 1 && !user_collectors.is_empty() {
+                return Err(K2Error::InvalidConfig(
+                    "Measurement collectors can't be added to experiments which run several slots"
+                        .into(),
+                ));
+            }
+        }
+        services::check_supported(&config)?;
+        if config.isolate_network && !config.dry_run {
+            network::check()?;
+        }
+        if config.dmesg != DmesgPolicy::Ignore && !config.dry_run {
+            // Make sure the kernel log can be read before anything is run.
+            dmesg::last_seq()?;
+        }
+        // A named experiment is stored in its own namespace of the shared results
+        // directory (see `registry`).
+        let shared_dir = match &config.name {
+            Some(name) => {
+                let namespace = registry::experiment_dir(&config.results_dir, name);
+                Some(mem::replace(&mut config.results_dir, namespace))
+            }
+            None => None,
         };
-        let manifest = ManifestManager::new(&config, &benchmarks);
-        let store = K2Store::new(&config.results_dir);
-        Experiment {
+        // Create a directory to store the results and the manifest, unless it exists
+        // already. (The namespace of a named experiment is created along with the
+        // shared directory.)
+        let created = match &shared_dir {
+            Some(_) => fs::create_dir_all(&config.results_dir),
+            None => fs::create_dir(&config.results_dir),
+        };
+        match created {
+            Err(err) if err.kind() != io::ErrorKind::AlreadyExists => {
+                return Err(K2Error::file(&config.results_dir, err));
+            }
+            _ => (),
+        }
+        // Nothing else may run the experiment until this instance of k2 exits. Another
+        // instance might be starting the experiment too, so whether it's just starting
+        // is only decided once the lock is held: it is, until its manifest is created.
+        let lock = ExperimentLock::acquire(&config.results_dir)?;
+        let first_run = !ManifestManager::exists(&config.results_dir);
+        if first_run {
+            // Set up the language implementations before the manifest is created, so
+            // that the experiment can be started again if this fails.
+            for lang_impl in &lang_impls {
+                lang_impl.setup()?;
+            }
+            if let (Some(shared_dir), Some(name)) = (&shared_dir, &config.name) {
+                registry::register(shared_dir, name)?;
+            }
+        } else {
+            // The experiment might have been started by an older version of k2.
+            migrations::migrate(&config.results_dir)?;
+        }
+        log::open(&config.results_dir)?;
+        if first_run {
+            info!("starting experiment {}", config.results_dir.display());
+        } else {
+            debug!("resuming experiment {}", config.results_dir.display());
+        }
+        let mut manifest = ManifestManager::new(&config, &benchmarks)?;
+        let mut store = K2Store::new(&config.results_dir);
+        // Experiments started by older versions of k2 don't have a job table until
+        // their first job has run.
+        if first_run || !store.has_table("job")? {
+            // Create a table to store the status of each job.
+            store.create_job_table(&config, &benchmarks)?;
+            // Create a table to store the measurements of each job.
+            store.create_measurement_table()?;
+            // Create a table to store when each reboot happened.
+            store.create_reboot_table()?;
+        } else {
+            check_jobs(&mut store, &config, &benchmarks)?;
+            // The experiment might have been interrupted while its results were
+            // being stored.
+            manifest.repair(&mut store)?;
+            // Or while a job ran, in which case the job is run again.
+            for job in store.record_interrupted()? {
+                warn!("job {} was interrupted, and will be run again", job);
+            }
+        }
+        if config.profile.is_some() {
+            store.create_profile_table()?;
+        }
+        if config.dmesg != DmesgPolicy::Ignore {
+            store.create_dmesg_table()?;
+        }
+        if config.load_monitor.is_some() {
+            store.create_load_tables()?;
+        }
+        if config.services.is_some() {
+            store.create_service_table()?;
+        }
+        if !hooks.is_empty() {
+            store.create_hook_table()?;
+        }
+        if !skipped.is_empty() {
+            store.create_skipped_table()?;
+            for (key, reason) in &skipped {
+                store.insert_skipped(key, reason)?;
+            }
+        }
+        let started = !first_run && store.has_table("temperature_baseline")?;
+        let sensors = temperature::sensors()?;
+        if !config.mail_to.is_empty() {
+            let mailer = Mailer::new(config.mail_transport.clone(), config.mail_to.clone());
+            notifiers.push(Box::new(mailer));
+        }
+        let num_slots = config.slots.as_ref().map_or(1, Vec::len);
+        let mut user_collectors = Some(user_collectors);
+        let mut collectors = Vec::new();
+        for _ in 0..num_slots {
+            let mut slot_collectors: Vec<Box<dyn MeasurementCollector>> = vec![
+                Box::new(TemperatureCollector::new()?),
+                Box::new(RusageCollector),
+            ];
+            if config.energy {
+                slot_collectors.push(Box::new(EnergyCollector::new()?));
+            }
+            if config.cpu_freq {
+                slot_collectors.push(Box::new(CpuFreqCollector::new()?));
+            }
+            if !config.perf_events.is_empty() {
+                slot_collectors.push(Box::new(PerfCollector));
+            }
+            // The user's collectors can't be copied, so only the first slot has them
+            // (which was checked to be the only one).
+            slot_collectors.extend(user_collectors.take().into_iter().flatten());
+            // The wall-clock collector is started last (and stopped first), so that
+            // the other collectors don't add to the time it measures.
+            slot_collectors.push(Box::new(WallclockCollector::new(config.clock)));
+            collectors.push(slot_collectors);
+        }
+        Ok(Experiment {
             config,
             benchmarks,
+            empty_benchmarks,
+            bench_idxs,
             manifest,
             first_run,
+            started,
             store,
+            sensors,
+            notifiers,
+            collectors,
+            result_stores,
+            uploaders,
+            hooks,
+            filter,
+            _lock: lock,
+        })
+    }
+
+    /// The name used to identify the experiment in notifications.
+    fn name(&self) -> String {
+        self.config.results_dir.display().to_string()
+    }
+
+    /// How to resume the experiment after a reboot, or `None` if the experiment
+    /// doesn't need to be resumed after a reboot (it doesn't reboot, or this is a
+    /// dry run).
+    fn boot_method(&self) -> Option<BootMethod> {
+        if self.config.reboot && !self.config.dry_run {
+            self.config.resume_on_boot
+        } else {
+            None
         }
     }
 
-    /// Run the experiment. If experiment completes successfully, return a String
-    /// which represents the path of the results file; otherwise, return a `K2Error`.
-    pub fn run(mut self) -> Result<PathBuf, K2Error> {
-        // Run the next outstanding benchmark.
-        if let Some(job) = self.manifest.next_job() {
-            // `job` is the index of the next job to run. Each benchmark is run
-            // `config.pexecs` times, so we use modular arithmetic to work out the
-            // index of the next benchmark to run.
-            let bench = &self.benchmarks[job % self.benchmarks.len()];
-            let result = bench.run(&self.config);
-            let status = match result {
-                Ok(_) => JobStatus::Done,
-                Err(K2Error::RerunError) => JobStatus::Outstanding,
-                Err(_) => JobStatus::Error,
+    /// Whether the jobs run one after the other in this process, rather than k2
+    /// restarting (or the machine rebooting) between jobs (see `Config::loop_jobs`).
+    fn loops(&self) -> bool {
+        self.config.loop_jobs && (!self.config.reboot || self.config.dry_run)
+    }
+
+    /// Run the hooks of `point` (see `hooks`). Job hooks are told about job `job`,
+    /// which runs benchmark `bench_idx`, and about its `status` once it has run.
+    fn run_hooks(
+        &mut self,
+        point: HookPoint,
+        job: Option<(usize, usize)>,
+        status: Option<JobStatus>,
+    ) -> Result<(), K2Error> {
+        // Nothing is run in a dry run.
+        if self.config.dry_run || !self.hooks.iter().any(|(p, _)| *p == point) {
+            return Ok(());
+        }
+        let benchmark = job
+            .map(|(_, bench_idx)| self.benchmarks[bench_idx].results_key())
+            .transpose()?;
+        let context = HookContext {
+            point,
+            results_dir: &self.config.results_dir,
+            job: job.map(|(job, _)| job),
+            benchmark: benchmark.as_deref(),
+            status,
+        };
+        hooks::run(&self.hooks, &context, &mut self.store)
+    }
+
+    /// Tell all the notifiers about `event`. Failing to send a notification doesn't
+    /// stop the experiment.
+    fn notify(&self, event: Event) {
+        let name = self.name();
+        for notifier in &self.notifiers {
+            if let Err(err) = notifier.notify(&name, &event) {
+                warn!("failed to send notification: {}", err.chain());
+            }
+        }
+    }
+
+    /// Upload the results of the experiment to the dashboards. The results are still
+    /// in the results directory, so failing to upload them doesn't stop the
+    /// experiment.
+    fn upload_results(&self) {
+        if self.uploaders.is_empty() {
+            return;
+        }
+        let name = self.name();
+        let mut results = match Results::open(&self.config.results_dir) {
+            Ok(results) => results,
+            Err(err) => {
+                warn!("failed to read the results: {}", err.chain());
+                return;
+            }
+        };
+        for uploader in &self.uploaders {
+            if let Err(err) = uploader.upload(&name, &mut results) {
+                warn!("failed to upload the results: {}", err.chain());
+            }
+        }
+    }
+
+    /// The index in `benchmarks` of the benchmark job `job` runs.
+    fn bench_idx(&mut self, job: usize) -> Result<usize, K2Error> {
+        // The job table records the key of the benchmark each job runs.
+        let key = self.store.job(job)?.key;
+        self.bench_idxs.get(&key).copied().ok_or_else(|| {
+            K2Error::JobMismatch(format!(
+                "Job {} runs {}, which isn't one of the benchmarks of the experiment",
+                job, key
+            ))
+        })
+    }
+
+    /// Get the machine ready to run the next job(s), which run benchmarks
+    /// `bench_idxs`: check its settings, record its baseline temperature if the
+    /// experiment hasn't been started yet (see `started`), wait for it to cool down,
+    /// and prepare the cache.
+    fn prepare(&mut self, bench_idxs: &[usize]) -> Result<(), K2Error> {
+        // The settings might have been reset by a reboot, so they are checked
+        // before each job.
+        checks::run(&self.config)?;
+        services::run(&self.config, &mut self.store)?;
+        // Interrupts and kernel threads might have been moved back to the
+        // isolated CPUs by a reboot (or started there since the last job).
+        if let (false, Some(cpus)) = (self.config.dry_run, &self.config.isolated_cpus) {
+            let report = isolation::isolate(cpus)?;
+            info!(
+                "moved {} IRQs and {} kernel threads off CPU(s) {} \
+                 ({} IRQs and {} kernel threads couldn't be moved)",
+                report.irqs_moved,
+                report.kthreads_moved,
+                isolation::format_cpu_list(cpus),
+                report.irqs_not_moved,
+                report.kthreads_not_moved
+            );
+        }
+        if !self.started {
+            let num_jobs = self.config.pexecs * self.benchmarks.len();
+            self.notify(Event::ExperimentStarted { num_jobs });
+            if let Some(method) = self.boot_method() {
+                // Make sure the experiment is resumed after each reboot.
+                boot::install(method, &self.config.results_dir)?;
+            }
+            self.run_hooks(HookPoint::BeforeExperiment, None, None)?;
+            // Let the machine settle before taking the baseline temperature
+            // readings. Nothing is run in a dry run, so there is no need to wait.
+            if !self.config.dry_run {
+                thread::sleep(self.config.temp_read_pause);
+            }
+            let baseline = temperature::read_all(&self.sensors)?;
+            self.store.transaction(|store| {
+                store.create_temperature_baseline_table()?;
+                store.insert_temperature_baseline(&baseline)
+            })?;
+            self.started = true;
+        }
+        if let (false, Some(threshold)) = (self.config.dry_run, self.config.temp_threshold) {
+            let baseline = self.store.temperature_baseline()?;
+            temperature::wait_until_cool(
+                &self.sensors,
+                &baseline,
+                threshold,
+                self.config.temp_wait_timeout,
+            )?;
+        }
+        // Nothing is run in a dry run, so the cache doesn't matter.
+        if !self.config.dry_run {
+            if self.config.drop_caches {
+                cache::drop_caches()?;
+            }
+            if self.config.warm_cache {
+                let mut files = Vec::new();
+                for &bench_idx in bench_idxs {
+                    files.extend(self.benchmarks[bench_idx].files()?);
+                }
+                cache::warm(&files)?;
+            }
+        }
+        // k2 might have been asked to stop while the machine cooled down.
+        shutdown::check()
+    }
+
+    /// Run each benchmark which hasn't been calibrated yet once, without any limits,
+    /// and record its memory usage (see `calibrate`). Nothing is run in a dry run.
+    ///
+    /// k2 restarts between jobs, so if the suggested limits are applied, they are
+    /// applied each time it starts.
+    fn calibrate(&mut self, calibration: &CalibrationConfig) -> Result<(), K2Error> {
+        self.store.create_calibration_table()?;
+        let mut calibrations = self.store.calibrations()?;
+        for bench in &self.benchmarks {
+            let key = bench.results_key()?;
+            if self.config.dry_run || calibrations.contains_key(&key) {
+                continue;
+            }
+            info!("calibrating {}", key);
+            let usage = bench.calibrate(&self.config)?;
+            let calibrated = Calibration::new(&usage, calibration.margin);
+            info!(
+                "{} used {} KiB of address space, {} KiB of RSS and {} KiB of stack: \
+                 suggested heap_lim = \"{}\", stack_lim = \"{}\"",
+                key,
+                calibrated.peak_vm / 1024,
+                calibrated.peak_rss / 1024,
+                calibrated.stack / 1024,
+                calibrated.heap_lim,
+                calibrated.stack_lim
+            );
+            self.store.insert_calibration(&key, &calibrated)?;
+            calibrations.insert(key, calibrated);
+            shutdown::check()?;
+        }
+        if calibration.apply {
+            for bench in &mut self.benchmarks {
+                if let Some(calibrated) = calibrations.get(&bench.results_key()?) {
+                    bench.heap_lim = bench.heap_lim.or(Some(calibrated.heap_lim));
+                    bench.stack_lim = bench.stack_lim.or(Some(calibrated.stack_lim));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the empty benchmark of each language implementation whose overhead hasn't
+    /// been measured yet, and record its overhead (see `overhead`). Nothing is run
+    /// in a dry run.
+    fn measure_overhead(&mut self, overhead: &OverheadConfig) -> Result<(), K2Error> {
+        self.store.create_overhead_table()?;
+        let measured = self.store.overheads()?;
+        for bench in &self.empty_benchmarks {
+            let key = bench.lang_impl().results_key();
+            if self.config.dry_run || measured.contains_key(&key) {
+                continue;
+            }
+            info!("measuring the overhead of {}", key);
+            let mut pexecs = Vec::with_capacity(overhead.pexecs);
+            for _ in 0..overhead.pexecs {
+                pexecs.push(bench.run_empty(&self.config)?);
+                shutdown::check()?;
+            }
+            let measured = Overhead::new(&pexecs);
+            info!(
+                "{} takes {:.6}s to start and {} per in-process iteration",
+                key,
+                measured.startup,
+                measured
+                    .iteration
+                    .map_or("an unknown time".into(), |secs| format!("{:.6}s", secs))
+            );
+            self.store.insert_overhead(&key, &measured)?;
+        }
+        Ok(())
+    }
+
+    /// Mark the outstanding jobs of the benchmarks `bench_idxs` as unneeded if their
+    /// results are already precise enough (see `adaptive`), and move past them in
+    /// the manifest.
+    fn stop_converged(&mut self, bench_idxs: &[usize]) -> Result<(), K2Error> {
+        let adaptive = match &self.config.adaptive {
+            Some(adaptive) => adaptive.clone(),
+            None => return Ok(()),
+        };
+        for &bench_idx in bench_idxs {
+            let key = self.benchmarks[bench_idx].results_key()?;
+            let pexecs = self.store.wallclock_times_of(&key)?;
+            if let Some(width) = adaptive.stops(&pexecs) {
+                let unneeded = self.store.mark_unneeded(&key)?;
+                if unneeded > 0 {
+                    info!(
+                        "{} converged after {} process executions (CI width {:.2}%): \
+                         skipping its {} remaining jobs",
+                        key,
+                        pexecs.len(),
+                        width * 100.0,
+                        unneeded
+                    );
+                }
+            }
+        }
+        self.manifest.skip_finished(&mut self.store)
+    }
+
+    /// How to profile job `job`, or `None` if it isn't profiled.
+    fn profile(&mut self, job: usize) -> Result<Option<Profile>, K2Error> {
+        match (&self.config.profile, self.config.dry_run) {
+            (Some(profile), false) => {
+                let pexec = self.store.job_record(job)?.pexec;
+                profile.profile(&self.config.results_dir, job, pexec)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Start monitoring the kernel log and the load of the machine, if the
+    /// experiment is configured to.
+    fn start_monitors(&self) -> Result<Monitors, K2Error> {
+        let dry_run = self.config.dry_run;
+        let dmesg_seq = if self.config.dmesg != DmesgPolicy::Ignore && !dry_run {
+            Some(dmesg::last_seq()?)
+        } else {
+            None
+        };
+        let load_monitor = match (&self.config.load_monitor, dry_run) {
+            (Some(load_monitor), false) => Some(load_monitor.start()?),
+            _ => None,
+        };
+        Ok(Monitors {
+            dmesg_seq,
+            load_monitor,
+        })
+    }
+
+    /// Report the `disturbances` which happened while `jobs` ran.
+    fn report_disturbances(&self, jobs: &[usize], disturbances: &Disturbances) {
+        let jobs = match jobs {
+            [job] => format!("job {}", job),
+            jobs => format!(
+                "jobs {}",
+                jobs.iter()
+                    .map(|job| job.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let kernel_messages = disturbances.kernel_messages.as_deref().unwrap_or_default();
+        if !kernel_messages.is_empty() {
+            warn!(
+                "the kernel logged {} message(s) while {} ran:\n{}",
+                kernel_messages.len(),
+                jobs,
+                kernel_messages.join("\n")
+            );
+        }
+        if let Some(load_monitor) = &self.config.load_monitor {
+            let samples = disturbances.load_samples.as_deref().unwrap_or_default();
+            if let Some(background) = load_monitor.exceeded(samples) {
+                warn!(
+                    "other processes used up to {:.2} CPUs while {} ran (the threshold is {})",
+                    background, jobs, load_monitor.threshold
+                );
+            }
+        }
+    }
+
+    /// The error to fail a job with because of the `disturbances` which happened
+    /// while it ran, or `None` if the job shouldn't be run again (see
+    /// `DmesgPolicy::Rerun` and `LoadMonitorConfig::rerun`).
+    fn disturbed(&self, disturbances: &Disturbances) -> Option<K2Error> {
+        match &disturbances.kernel_messages {
+            Some(messages) if !messages.is_empty() && self.config.dmesg == DmesgPolicy::Rerun => {
+                return Some(K2Error::KernelMessages(messages.clone()));
+            }
+            _ => (),
+        }
+        let load_monitor = self.config.load_monitor.as_ref()?;
+        let samples = disturbances.load_samples.as_deref().unwrap_or_default();
+        load_monitor
+            .exceeded(samples)
+            .filter(|_| load_monitor.rerun)
+            .map(K2Error::BackgroundLoad)
+    }
+
+    /// The status of job `job`, which ran benchmark `bench_idx`, given the `result`
+    /// of running it, and its measurements, if it succeeded. The notifiers are told
+    /// about the jobs which failed.
+    fn job_status(
+        &mut self,
+        job: usize,
+        bench_idx: usize,
+        result: Result<Measurements, K2Error>,
+    ) -> Result<(JobStatus, Option<Measurements>), K2Error> {
+        Ok(match result {
+            Ok(mut measurements) => {
+                analysis::mark_outliers(&mut measurements);
+                if self.config.warmup_analysis {
+                    analysis::record(&mut measurements);
+                }
+                (JobStatus::Done, Some(measurements))
+            }
+            // An interrupted job didn't fail: it is run again when the experiment is
+            // resumed.
+            Err(K2Error::Interrupted(_)) => (JobStatus::Outstanding, None),
+            Err(K2Error::RerunError)
+                if self.store.job_record(job)?.reruns < self.config.max_reruns =>
+            {
+                self.store.mark_rerun(job)?;
+                (JobStatus::Outstanding, None)
+            }
+            Err(err) => {
+                let key = self.benchmarks[bench_idx].results_key()?;
+                self.notify(Event::JobFailed {
+                    id: job,
+                    key: &key,
+                    error: &err,
+                });
+                let status = match err {
+                    K2Error::TimedOut(_) => JobStatus::TimedOut,
+                    K2Error::WrongAnswer(_) => JobStatus::WrongAnswer,
+                    K2Error::TooManyAttempts(_) => JobStatus::TooManyAttempts,
+                    _ => JobStatus::Error,
+                };
+                (status, None)
+            }
+        })
+    }
+
+    /// Say which job `err` happened in (job `job`, which runs benchmark
+    /// `bench_idx`).
+    fn job_error(&self, job: usize, bench_idx: usize, err: K2Error) -> K2Error {
+        match err {
+            // Nothing went wrong with the job: k2 was asked to stop.
+            K2Error::Interrupted(_) => err,
+            err => K2Error::Job {
+                id: job,
+                key: self.benchmarks[bench_idx].results_key().unwrap_or_default(),
+                source: Box::new(err),
+            },
+        }
+    }
+
+    /// Give up on the next job (or the next job of each slot) if it was already
+    /// started `Config::max_attempts` times, by marking it as
+    /// `JobStatus::TooManyAttempts`. Returns whether k2 gave up on any job.
+    fn give_up_exhausted(&mut self) -> Result<bool, K2Error> {
+        let next_jobs = if self.manifest.num_slots() > 0 {
+            (0..self.manifest.num_slots())
+                .map(|slot| (Some(slot), self.manifest.next_slot_job(slot)))
+                .collect::<Vec<_>>()
+        } else {
+            vec![(None, self.manifest.next_job())]
+        };
+        let mut gave_up = false;
+        for (slot, job) in next_jobs {
+            let job = match job {
+                Some(job) => job,
+                None => continue,
             };
-            // If we've just run the first job, create all the necessary tables.
-            if self.first_run {
-                // Create a table to store the status of each job.
-                self.store.create_job_table(&self.config, &self.benchmarks);
-                // FIXME: create a table for the measurements too.
-            }
-            // Update the status of the job we've just run.
-            self.manifest.update_status(status);
+            let attempts = self.store.job_record(job)?.attempts;
+            if attempts < self.config.max_attempts {
+                continue;
+            }
+            warn!("giving up on job {} after {} attempts", job, attempts);
+            let bench_idx = self.bench_idx(job)?;
+            let (status, _) =
+                self.job_status(job, bench_idx, Err(K2Error::TooManyAttempts(attempts)))?;
+            // As in `run_job`, the status is stored before the manifest is updated.
+            match slot {
+                Some(slot) => {
+                    self.store.update_status(job, status)?;
+                    self.manifest.update_slot_status(slot, status);
+                }
+                None => {
+                    self.manifest.update_measurements(Measurements::default());
+                    self.manifest.update_status(status);
+                    self.manifest.store_job(&mut self.store)?;
+                }
+            }
+            self.manifest.sync()?;
+            copy_results(
+                &mut self.store,
+                &mut self.result_stores,
+                job,
+                &Measurements::default(),
+            );
+            gave_up = true;
+        }
+        Ok(gave_up)
+    }
+
+    /// Run job `job`, which runs benchmark `bench_idx`, and record its results.
+    fn run_job(&mut self, job: usize, bench_idx: usize) -> Result<(), K2Error> {
+        let _span = info_span!("job", id = job).entered();
+        debug!("running {}", self.benchmarks[bench_idx].results_key()?);
+        self.run_hooks(HookPoint::BeforeJob, Some((job, bench_idx)), None)?;
+        let profile = self.profile(job)?;
+        let monitors = self.start_monitors()?;
+        self.store.start_attempt(job, SystemTime::now())?;
+        let start = Instant::now();
+        let mut result = self.benchmarks[bench_idx].run(
+            &self.config,
+            job,
+            None,
+            profile.clone(),
+            &mut self.collectors[0],
+        );
+        let duration = start.elapsed();
+        let finished_at = SystemTime::now();
+        let disturbances = monitors.stop()?;
+        self.report_disturbances(&[job], &disturbances);
+        // A job which k2 stopped in the middle of is counted as interrupted when the
+        // experiment is resumed.
+        let ended = !matches!(result, Err(K2Error::Interrupted(_)));
+        if result.is_ok() {
+            if let Some(err) = self.disturbed(&disturbances) {
+                result = Err(err);
+            }
+        }
+        let (status, measurements) = self.job_status(job, bench_idx, result)?;
+        debug!(status = status.name(), ?duration, "finished");
+        // The measurements of the previous job must not be stored with this one if
+        // it failed.
+        self.manifest
+            .update_measurements(measurements.unwrap_or_default());
+        // Update the status of the job we've just run.
+        self.manifest.update_status(status);
+        // k2 stops instead of rebooting if it was asked to (see `run`).
+        let reboots = !self.loops() && shutdown::requested().is_none();
+        if reboots {
             // Increment `num_reboots`, since we are about to reboot before running
             // the next job.
             self.manifest.update_num_reboots();
-            // FIXME: Record the measurements for this benchmark.
-            // Persist all the changes.
-            self.manifest.sync(&mut self.store);
-            // Reboot before running the next job.
-            Err(util::reboot(self.config.reboot))
-        } else {
-            // There are no more benchmarks to run: return the path.
-            Ok(self.config.results_dir.join(K2Store::K2_DB))
+        }
+        let run = JobRun {
+            job,
+            cpus: self.benchmarks[bench_idx]
+                .effective_cpu_affinity(&self.config)
+                .cloned(),
+            numa_node: self.benchmarks[bench_idx].effective_numa_node(&self.config),
+            in_proc_iters: self.benchmarks[bench_idx].effective_in_proc_iters(&self.config),
+            profile_dir: profile_dir(job, profile),
+            duration,
+            finished_at,
+        };
+        // Persist all the changes. The results of the job are stored in a single
+        // transaction, and the manifest is only updated once they are, so that
+        // `ManifestManager::repair` can tell whether the job ran if the machine
+        // loses power in between.
+        // An outstanding job is run again, so nothing is stored about this run.
+        let outstanding = status == JobStatus::Outstanding;
+        let manifest = &self.manifest;
+        self.store.transaction(|store| {
+            if ended {
+                store.end_attempt(job)?;
+            }
+            if !outstanding {
+                manifest.store_job(store)?;
+                run.store(store, &disturbances)?;
+            }
+            if reboots {
+                store.insert_reboot(manifest.num_reboots(), SystemTime::now())?;
+            }
+            Ok(())
+        })?;
+        self.manifest.sync()?;
+        if !outstanding {
+            copy_results(
+                &mut self.store,
+                &mut self.result_stores,
+                job,
+                self.manifest.measurements(),
+            );
+        }
+        self.run_hooks(HookPoint::AfterJob, Some((job, bench_idx)), Some(status))
+    }
+
+    /// Run the next job of each slot (see `Config::slots`), all at the same time,
+    /// and record their results. `round` contains the job each slot runs and the
+    /// index of its benchmark, or `None` if the slot has no jobs left.
+    ///
+    /// The kernel log and the load of the machine are monitored while the jobs run,
+    /// so whatever disturbs one of the jobs is recorded with all of them.
+    fn run_slots(&mut self, round: Vec<Option<(usize, usize)>>) -> Result<(), K2Error> {
+        debug!("running jobs {:?} in parallel slots", round);
+        for slot_job in &round {
+            self.run_hooks(HookPoint::BeforeJob, *slot_job, None)?;
+        }
+        let mut profiles = Vec::new();
+        for slot_job in &round {
+            profiles.push(match slot_job {
+                Some((job, _)) => self.profile(*job)?,
+                None => None,
+            });
+        }
+        let jobs = round
+            .iter()
+            .flatten()
+            .map(|(job, _)| *job)
+            .collect::<Vec<_>>();
+        let monitors = self.start_monitors()?;
+        let started_at = SystemTime::now();
+        self.store.transaction(|store| {
+            for job in &jobs {
+                store.start_attempt(*job, started_at)?;
+            }
+            Ok(())
+        })?;
+        let slots = self.config.slots.clone().unwrap_or_default();
+        let (config, benchmarks) = (&self.config, &self.benchmarks);
+        // Each slot has its own collectors, so that the jobs can be measured
+        // independently.
+        let collectors = &mut self.collectors;
+        let outcomes = thread::scope(|scope| {
+            let handles = round
+                .iter()
+                .zip(&profiles)
+                .zip(collectors)
+                .zip(&slots)
+                .filter_map(|(((slot_job, profile), collectors), cpus)| {
+                    let (job, bench_idx) = (*slot_job)?;
+                    let profile = profile.clone();
+                    Some(scope.spawn(move || {
+                        let start = Instant::now();
+                        let bench = &benchmarks[bench_idx];
+                        let result = bench.run(config, job, Some(cpus), profile, collectors);
+                        (result, start.elapsed(), SystemTime::now())
+                    }))
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|err| panic::resume_unwind(err))
+                })
+                .collect::<Vec<_>>()
+        });
+        let disturbances = monitors.stop()?;
+        self.report_disturbances(&jobs, &disturbances);
+        let mut ended = Vec::new();
+        let mut finished = Vec::new();
+        let mut statuses = Vec::new();
+        let slot_jobs = round
+            .into_iter()
+            .zip(profiles)
+            .enumerate()
+            .filter_map(|(slot, (slot_job, profile))| Some((slot, slot_job?, profile)));
+        for ((slot, (job, bench_idx), profile), outcome) in slot_jobs.zip(outcomes) {
+            let (mut result, duration, finished_at) = outcome;
+            if !matches!(result, Err(K2Error::Interrupted(_))) {
+                ended.push(job);
+            }
+            if result.is_ok() {
+                if let Some(err) = self.disturbed(&disturbances) {
+                    result = Err(err);
+                }
+            }
+            let (status, measurements) = self.job_status(job, bench_idx, result)?;
+            debug!(job, slot, status = status.name(), ?duration, "finished");
+            self.manifest.update_slot_status(slot, status);
+            statuses.push((job, bench_idx, status));
+            if status == JobStatus::Outstanding {
+                // The job is run again, so nothing is stored about this run.
+                continue;
+            }
+            let run = JobRun {
+                job,
+                cpus: Some(slots[slot].clone()),
+                numa_node: self.benchmarks[bench_idx].effective_numa_node(&self.config),
+                in_proc_iters: self.benchmarks[bench_idx].effective_in_proc_iters(&self.config),
+                profile_dir: profile_dir(job, profile),
+                duration,
+                finished_at,
+            };
+            finished.push((run, status, measurements.unwrap_or_default()));
+        }
+        let reboots = !self.loops() && shutdown::requested().is_none();
+        if reboots {
+            self.manifest.update_num_reboots();
+        }
+        // As in `run_job`, the manifest is only updated once the results of all the
+        // jobs are stored.
+        let manifest = &self.manifest;
+        self.store.transaction(|store| {
+            for job in &ended {
+                store.end_attempt(*job)?;
+            }
+            for (run, status, measurements) in &finished {
+                store.update_status(run.job, *status)?;
+                store.insert_measurements(run.job, measurements)?;
+                run.store(store, &disturbances)?;
+            }
+            if reboots {
+                store.insert_reboot(manifest.num_reboots(), SystemTime::now())?;
+            }
+            Ok(())
+        })?;
+        self.manifest.sync()?;
+        for (run, _, measurements) in &finished {
+            copy_results(
+                &mut self.store,
+                &mut self.result_stores,
+                run.job,
+                measurements,
+            );
+        }
+        for (job, bench_idx, status) in statuses {
+            self.run_hooks(HookPoint::AfterJob, Some((job, bench_idx)), Some(status))?;
+        }
+        Ok(())
+    }
+
+    /// Report the progress of the experiment.
+    pub fn status(&mut self) -> Result<ExperimentStatus, K2Error> {
+        ExperimentStatus::read(&self.manifest, &mut self.store)
+    }
+
+    /// Run the experiment. If experiment completes successfully, return a String
+    /// which represents the path of the results file; otherwise, return a `K2Error`.
+    pub fn run(mut self) -> Result<PathBuf, K2Error> {
+        // Let the running jobs finish if k2 is interrupted (see `shutdown`).
+        shutdown::install()?;
+        let mut audit = audit::collect(&self.config);
+        if let Some(seed) = self.manifest.seed() {
+            audit.insert("rng_seed".into(), seed.to_string());
+        }
+        audit.insert("benchmark_filter".into(), self.filter.clone());
+        // k2 might have stopped after creating the manifest, but before recording the
+        // audit.
+        if self.first_run || !self.store.has_table("audit")? {
+            self.store.create_audit_table()?;
+            self.store.insert_audit(&audit)?;
+        } else if !self.config.ignore_audit_mismatch {
+            // Make sure the experiment is resumed in the same environment it was
+            // started in.
+            audit::check(&self.store.audit()?, &audit)?;
+        }
+        // Make sure the benchmarks are still run with the same files.
+        let checksums = checksum::collect(&self.benchmarks)?;
+        checksum::check(
+            &mut self.store,
+            &checksums,
+            self.config.ignore_audit_mismatch,
+        )?;
+        if let Some(calibration) = self.config.calibration.clone() {
+            self.calibrate(&calibration)?;
+        }
+        if let Some(overhead) = self.config.overhead.clone() {
+            self.measure_overhead(&overhead)?;
+        }
+        loop {
+            // If we've run out of jobs, give the ones that failed another chance.
+            if self.manifest.next_job().is_none() && self.config.max_retries > 0 {
+                self.manifest
+                    .reset_errored(&mut self.store, self.config.max_retries)?;
+            }
+            // Run the next outstanding benchmark (or, if the jobs run in parallel
+            // slots, the next benchmark of each slot).
+            let job = match self.manifest.next_job() {
+                Some(job) => job,
+                None => break,
+            };
+            if self.give_up_exhausted()? {
+                continue;
+            }
+            if self.manifest.num_slots() > 0 {
+                let mut round = Vec::new();
+                for slot in 0..self.manifest.num_slots() {
+                    let slot_job = match self.manifest.next_slot_job(slot) {
+                        Some(job) => Some((job, self.bench_idx(job)?)),
+                        None => None,
+                    };
+                    round.push(slot_job);
+                }
+                let bench_idxs = round
+                    .iter()
+                    .flatten()
+                    .map(|(_, idx)| *idx)
+                    .collect::<Vec<_>>();
+                self.prepare(&bench_idxs)?;
+                self.run_slots(round)?;
+                self.stop_converged(&bench_idxs)?;
+            } else {
+                let bench_idx = self.bench_idx(job)?;
+                self.prepare(&[bench_idx])?;
+                self.run_job(job, bench_idx)
+                    .map_err(|err| self.job_error(job, bench_idx, err))?;
+                self.stop_converged(&[bench_idx])?;
+            }
+            // Stop here, rather than rebooting or running the next job, if k2 was
+            // interrupted: the experiment can be resumed later.
+            shutdown::check()?;
+            // Rebooting lets the machine settle anyway, and nothing is run in a dry run.
+            let settles = self.config.reboot || self.config.dry_run;
+            if !settles
+                && !self.config.post_job_pause.is_zero()
+                && self.manifest.next_job().is_some()
+            {
+                debug!(
+                    "pausing for {:?} before the next job",
+                    self.config.post_job_pause
+                );
+                shutdown::sleep(self.config.post_job_pause)?;
+            }
+            if !self.loops() {
+                // Reboot before running the next job. A dry run never reboots the
+                // machine: it restarts the process instead.
+                debug!("restarting before the next job");
+                return Err(util::reboot(self.config.reboot && !self.config.dry_run));
+            }
+            // The next job runs in this process, as if it had been restarted.
+            self.first_run = false;
+        }
+        if let Some(method) = self.boot_method() {
+            // The experiment is over, so it no longer needs to be resumed.
+            boot::uninstall(method, &self.config.results_dir)?;
+        }
+        if self.config.services.is_some() {
+            services::restore(&mut self.store)?;
+        }
+        if !self.first_run {
+            info!("all the jobs have run");
+            self.run_hooks(HookPoint::AfterExperiment, None, None)?;
+            self.upload_results();
+            let summary = self.store.job_summary()?;
+            self.notify(Event::ExperimentFinished { summary: &summary });
+        }
+        // There are no more benchmarks to run: return the path.
+        Ok(self.config.results_dir.join(K2Store::K2_DB))
+    }
+}
+
+/// The monitors which watch the machine while jobs run (see `dmesg` and `load`).
+struct Monitors {
+    /// The sequence number of the last message of the kernel log before the jobs
+    /// started, if the kernel log is monitored.
+    dmesg_seq: Option<Option<u64>>,
+    load_monitor: Option<LoadMonitor>,
+}
+
+impl Monitors {
+    /// Stop monitoring the machine, and return what the monitors saw.
+    fn stop(self) -> Result<Disturbances, K2Error> {
+        let load_samples = self.load_monitor.map(LoadMonitor::stop).transpose()?;
+        let kernel_messages = self.dmesg_seq.map(dmesg::messages_since).transpose()?;
+        Ok(Disturbances {
+            kernel_messages,
+            load_samples,
+        })
+    }
+}
+
+/// What the `Monitors` saw while the jobs ran.
+struct Disturbances {
+    /// The messages the kernel logged, if the kernel log is monitored.
+    kernel_messages: Option<Vec<String>>,
+    /// The load of the machine, if it is monitored.
+    load_samples: Option<Vec<LoadSample>>,
+}
+
+/// How a job ran, which is stored with its results.
+struct JobRun {
+    job: usize,
+    /// The CPUs the job was pinned to, if any.
+    cpus: Option<Vec<usize>>,
+    /// The NUMA node the job was bound to, if any.
+    numa_node: Option<usize>,
+    /// The number of in-process iterations the job ran.
+    in_proc_iters: usize,
+    /// The profile of the job, relative to the results directory, if it was
+    /// profiled.
+    profile_dir: Option<PathBuf>,
+    duration: Duration,
+    finished_at: SystemTime,
+}
+
+impl JobRun {
+    /// Store how the job ran, and the `disturbances` which happened while it ran,
+    /// in `store`.
+    fn store(&self, store: &mut K2Store, disturbances: &Disturbances) -> Result<(), K2Error> {
+        store.update_duration(self.job, self.duration)?;
+        store.update_finished_at(self.job, self.finished_at)?;
+        if let Some(cpus) = &self.cpus {
+            store.update_cpu_affinity(self.job, cpus)?;
+        }
+        if let Some(node) = self.numa_node {
+            store.update_numa_node(self.job, node)?;
+        }
+        store.update_in_proc_iters(self.job, self.in_proc_iters)?;
+        if let Some(profile_dir) = &self.profile_dir {
+            store.insert_profile(self.job, profile_dir)?;
+        }
+        if let Some(kernel_messages) = &disturbances.kernel_messages {
+            store.insert_kernel_messages(self.job, kernel_messages)?;
+        }
+        if let Some(samples) = &disturbances.load_samples {
+            store.insert_load_samples(self.job, samples)?;
+        }
+        Ok(())
+    }
+}
+
+/// The profile directory of job `job`, if it was run with `profile`. The profile is
+/// only linked from the results if the profiler wrote one.
+fn profile_dir(job: usize, profile: Option<Profile>) -> Option<PathBuf> {
+    profile
+        .filter(|profile| !profile.is_empty())
+        .map(|_| profile::profile_dir(job))
+}
+
+/// Copy the results of the job with identifier `job`, whose measurements are
+/// `measurements`, from `store` to the other `result_stores`. The jobs are only
+/// scheduled using the local results database, so failing to copy the results
+/// doesn't stop the experiment.
+fn copy_results(
+    store: &mut K2Store,
+    result_stores: &mut [Box<dyn ResultStore>],
+    job: usize,
+    measurements: &Measurements,
+) {
+    if result_stores.is_empty() {
+        return;
+    }
+    let record = match store.job_record(job) {
+        Ok(record) => record,
+        Err(err) => {
+            warn!("failed to read the results of job {}: {}", job, err.chain());
+            return;
+        }
+    };
+    for result_store in result_stores {
+        if let Err(err) = result_store.store_job(&record, measurements) {
+            warn!("failed to copy the results of job {}: {}", job, err.chain());
+        }
+    }
+}
+
+/// Check an experiment before it is created, so that a broken experiment
+/// definition is reported straight away, rather than once the jobs are running:
+/// there must be something to run, each benchmark must be runnable by its
+/// language implementation (see `LangImpl::check`), and the results directory must
+/// be writable.
+fn validate(
+    config: &Config,
+    benchmarks: &[Benchmark],
+    empty_benchmarks: &[Benchmark],
+) -> Result<(), K2Error> {
+    if benchmarks.is_empty() {
+        return Err(K2Error::InvalidConfig(
+            "The experiment has no benchmarks".into(),
+        ));
+    }
+    if config.pexecs == 0 {
+        return Err(K2Error::InvalidConfig(
+            "The number of process executions must be at least 1".into(),
+        ));
+    }
+    if config.in_proc_iters == 0
+        || benchmarks
+            .iter()
+            .any(|bench| bench.in_proc_iters == Some(0))
+    {
+        return Err(K2Error::InvalidConfig(
+            "The number of in-process iterations must be at least 1".into(),
+        ));
+    }
+    if let Some(name) = &config.name {
+        registry::check_name(name)?;
+    }
+    config.clock.check()?;
+    if config.drop_caches && !config.dry_run {
+        cache::check()?;
+    }
+    if let Some(calibration) = &config.calibration {
+        calibration.check()?;
+    }
+    if let Some(adaptive) = &config.adaptive {
+        adaptive.check()?;
+    }
+    if let Some(overhead) = &config.overhead {
+        overhead.check()?;
+        if empty_benchmarks.is_empty() {
+            return Err(K2Error::InvalidConfig(
+                "Measuring the overhead requires an empty benchmark".into(),
+            ));
+        }
+    }
+    for bench in benchmarks.iter().chain(empty_benchmarks) {
+        bench.lang_impl().check(bench)?;
+    }
+    util::check_writable(&config.results_dir)
+}
+
+/// Check that the `slots` are disjoint sets of CPUs.
+fn check_slots(slots: &[Vec<usize>]) -> Result<(), K2Error> {
+    if slots.is_empty() || slots.iter().any(Vec::is_empty) {
+        return Err(K2Error::InvalidConfig(
+            "Each slot must have at least one CPU".into(),
+        ));
+    }
+    let mut cpus = HashSet::new();
+    for cpu in slots.iter().flatten() {
+        if !cpus.insert(cpu) {
+            return Err(K2Error::InvalidConfig(format!(
+                "CPU {} is in more than one slot",
+                cpu
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The distinct language implementations the `benchmarks` run on.
+fn lang_impls(benchmarks: &[Benchmark]) -> Vec<Arc<dyn LangImpl>> {
+    let mut lang_impls: Vec<Arc<dyn LangImpl>> = Vec::new();
+    for bench in benchmarks {
+        let lang_impl = bench.lang_impl();
+        if !lang_impls.iter().any(|other| Arc::ptr_eq(other, lang_impl)) {
+            lang_impls.push(Arc::clone(lang_impl));
+        }
+    }
+    lang_impls
+}
+
+/// Check that no two language implementations have the same results key, since
+/// their results would be indistinguishable.
+fn check_results_keys(lang_impls: &[Arc<dyn LangImpl>]) -> Result<(), K2Error> {
+    let mut keys = HashSet::new();
+    for lang_impl in lang_impls {
+        let key = lang_impl.results_key();
+        if !keys.insert(key.clone()) {
+            return Err(K2Error::InvalidConfig(format!(
+                "Several language implementations have the results key {} (use `variant` to \
+                 tell them apart)",
+                key
+            )));
         }
     }
+    Ok(())
+}
+
+/// Map the results key of each benchmark to its index in `benchmarks`. Jobs are
+/// matched to their benchmark by key, so no two benchmarks can have the same key.
+fn bench_idxs(benchmarks: &[Benchmark]) -> Result<HashMap<String, usize>, K2Error> {
+    let mut idxs = HashMap::new();
+    for (idx, bench) in benchmarks.iter().enumerate() {
+        let key = bench.results_key()?;
+        if idxs.insert(key.clone(), idx).is_some() {
+            return Err(K2Error::InvalidConfig(format!(
+                "Benchmark {} was added more than once",
+                key
+            )));
+        }
+    }
+    Ok(idxs)
+}
+
+/// Check that the jobs recorded in `store` are the jobs of the experiment being
+/// resumed. Adding, removing or reordering the benchmarks (or changing the number of
+/// process executions) would otherwise leave jobs which can't be run, or change the
+/// number of process executions of some benchmarks.
+fn check_jobs(
+    store: &mut K2Store,
+    config: &Config,
+    benchmarks: &[Benchmark],
+) -> Result<(), K2Error> {
+    let recorded = store.job_keys()?;
+    let expected = benchmarks
+        .iter()
+        .map(|bench| bench.results_key())
+        .collect::<Result<Vec<_>, _>>()?;
+    let num_jobs = config.pexecs * expected.len();
+    if recorded.len() != num_jobs {
+        return Err(K2Error::JobMismatch(format!(
+            "The experiment was started with {} jobs, but {} benchmarks with {} process \
+             executions each make {} jobs",
+            recorded.len(),
+            expected.len(),
+            config.pexecs,
+            num_jobs
+        )));
+    }
+    for (id, key) in recorded.iter().enumerate() {
+        let expected_key = &expected[id % expected.len()];
+        if key != expected_key {
+            return Err(K2Error::JobMismatch(format!(
+                "Job {} was started as {}, but is now {}",
+                id, key, expected_key
+            )));
+        }
+    }
+    Ok(())
 }
 
 /// A builder used to construct an `Experiment`.
 ///
 /// This populates a `Config` struct with values, and collects the benchmarks
 /// to run.
-pub struct ExperimentBuilder<'a> {
+pub struct ExperimentBuilder {
     config: Config,
-    benchmarks: Vec<&'a Benchmark<'a>>,
+    benchmarks: Vec<Benchmark>,
+    notifiers: Vec<Box<dyn Notifier>>,
+    collectors: Vec<Box<dyn MeasurementCollector>>,
+    result_stores: Vec<Box<dyn ResultStore>>,
+    uploaders: Vec<Box<dyn Uploader>>,
+    /// The hooks to run before and after the jobs. See `before_job`.
+    hooks: Vec<(HookPoint, Hook)>,
+    /// The tags the benchmarks to run must match one of. See `only_tag`.
+    only_tags: Vec<(String, String)>,
+    /// The tags of the benchmarks to skip. See `skip_tag`.
+    skip_tags: Vec<(String, String)>,
+    /// The predicates the benchmarks to run must satisfy, with their names. See
+    /// `filter`.
+    filters: Vec<(String, BenchmarkPredicate)>,
+    /// The path of each benchmark not to run on a language implementation, with
+    /// the results key of the language implementation and the reason. See `skip`.
+    skips: Vec<(String, String, String)>,
+    /// The benchmarks skipped before they were added (e.g. by the `skip` patterns
+    /// of a suite manifest).
+    skipped: Vec<Skipped>,
+    /// The program (followed by its arguments) to run the benchmarks which don't
+    /// have a wrapper of their own with. See `wrapper`.
+    wrapper: Vec<String>,
+    /// The empty benchmark of each language implementation whose overhead is
+    /// measured. See `empty_benchmark`.
+    empty_benchmarks: Vec<Benchmark>,
 }
 
-impl<'a> ExperimentBuilder<'a> {
+/// A predicate which selects the benchmarks to run. See `ExperimentBuilder::filter`.
+type BenchmarkPredicate = Box<dyn Fn(&Benchmark) -> bool>;
+
+impl ExperimentBuilder {
     /// Set up a new experiment builder.
     ///
     /// The experiment results and manifest are stored in `results_dir`.
     pub fn new<P: AsRef<Path>>(results_dir: P) -> Self {
+        ExperimentBuilder::from_config(Config::new(results_dir.as_ref().into()))
+    }
+
+    /// Set up a new experiment builder with the settings of `config` (which may
+    /// have been deserialized, see `Config`).
+    pub fn from_config(config: Config) -> Self {
         ExperimentBuilder {
-            config: Config::new(results_dir.as_ref().into()),
+            config,
             benchmarks: Default::default(),
+            notifiers: Default::default(),
+            collectors: Default::default(),
+            result_stores: Default::default(),
+            uploaders: Default::default(),
+            hooks: Default::default(),
+            only_tags: Default::default(),
+            skip_tags: Default::default(),
+            filters: Default::default(),
+            skips: Default::default(),
+            skipped: Default::default(),
+            wrapper: Default::default(),
+            empty_benchmarks: Default::default(),
+        }
+    }
+
+    /// Set up a new experiment builder using the configuration, language
+    /// implementations and benchmarks declared in the TOML file at `path`.
+    pub fn from_config_file<P: AsRef<Path>>(path: P) -> Result<ExperimentBuilder, K2Error> {
+        let mut expb = ExperimentBuilder::from_config(Config::from_toml(&path)?);
+        let file = ExperimentFile::load(&path)?;
+        if let Some(jsonl) = &file.jsonl {
+            expb = expb.result_store(JsonlStore::open(jsonl)?);
+        }
+        if let Some(wrapper) = &file.wrapper {
+            let (cmd, args) = config_file::wrapper_cmd(wrapper)?;
+            expb = expb.wrapper(cmd, &args);
+        }
+        expb.uploaders
+            .extend(file.upload.iter().map(UploadEntry::build));
+        expb.hooks.extend(file.hooks.hooks());
+        let lang_impls = file
+            .lang_impls
+            .iter()
+            .map(|(name, entry)| Ok((name.as_str(), entry.build()?)))
+            .collect::<Result<HashMap<_, Arc<dyn LangImpl>>, K2Error>>()?;
+        let lang_impl = |name: &str| {
+            lang_impls.get(name).ok_or_else(|| {
+                K2Error::InvalidConfig(format!("Unknown language implementation {}", name))
+            })
+        };
+        for entry in &file.benchmarks {
+            for name in &entry.lang_impls {
+                expb = expb.benchmark(entry.build(Arc::clone(lang_impl(name)?))?);
+            }
+        }
+        for skip in &file.skip {
+            expb = expb.skip(&skip.path, &**lang_impl(&skip.lang_impl)?, &skip.reason);
+        }
+        for entry in &file.empty_benchmarks {
+            for name in &entry.lang_impls {
+                expb = expb.empty_benchmark(entry.build(Arc::clone(lang_impl(name)?))?);
+            }
+        }
+        Ok(expb)
+    }
+
+    /// Set up a new experiment builder which runs the benchmarks of the suite
+    /// declared in the TOML manifest at `path` (see `suite`), with the default
+    /// settings. The results are stored next to the manifest, in `<name>_results`
+    /// (see `SuiteManifest::results_dir`), unless `results_dir` is called.
+    pub fn from_suite<P: AsRef<Path>>(path: P) -> Result<ExperimentBuilder, K2Error> {
+        let path = path.as_ref();
+        let suite = SuiteManifest::load(path)?;
+        let mut expb = ExperimentBuilder::new(SuiteManifest::results_dir(path));
+        for bench in suite.benchmarks() {
+            expb = expb.benchmark(bench.clone());
+        }
+        for (bench, reason) in suite.skipped() {
+            for bench in bench.expand() {
+                expb.skipped.push((bench.results_key()?, reason.clone()));
+            }
         }
+        Ok(expb)
+    }
+
+    /// The settings of the experiment so far.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Override the settings of the experiment which are set by the `K2_*`
+    /// environment variables (see `overrides`).
+    pub fn apply_env(self) -> Result<Self, K2Error> {
+        overrides::apply_env(self)
+    }
+
+    /// Override the settings of the experiment with the command-line options of
+    /// `overrides::args` which are present in `matches`. These take precedence over
+    /// the environment variables, so this should be called after `apply_env`.
+    pub fn apply_cli(self, matches: &ArgMatches) -> Self {
+        overrides::apply_cli(self, matches)
     }
 
     pub fn results_dir<P: AsRef<Path>>(mut self, results_dir: P) -> Self {
@@ -111,6 +1495,14 @@ impl<'a> ExperimentBuilder<'a> {
         self
     }
 
+    /// Name the experiment, so that it can share the results directory with other
+    /// named experiments. Its results are stored in its own namespace of the results
+    /// directory (see `registry`).
+    pub fn name(mut self, name: &str) -> Self {
+        self.config.name = Some(name.to_string());
+        self
+    }
+
     pub fn quick(mut self, quick: bool) -> Self {
         self.config.quick = quick;
         self
@@ -126,11 +1518,32 @@ impl<'a> ExperimentBuilder<'a> {
         self
     }
 
+    /// Run all the outstanding jobs in this process, one after the other (each in a
+    /// fresh child process), instead of restarting k2 between jobs. This keeps the
+    /// state of k2 (such as its debugger) across jobs. The manifest is still updated
+    /// after each job, so an interrupted experiment can be resumed as usual.
+    ///
+    /// This has no effect if the machine reboots between jobs (see `reboot`).
+    pub fn loop_jobs(mut self, loop_jobs: bool) -> Self {
+        self.config.loop_jobs = loop_jobs;
+        self
+    }
+
+    pub fn resume_on_boot(mut self, method: BootMethod) -> Self {
+        self.config.resume_on_boot = Some(method);
+        self
+    }
+
     pub fn mail_to(mut self, mail_to: Vec<String>) -> Self {
         self.config.mail_to = mail_to;
         self
     }
 
+    pub fn mail_transport(mut self, mail_transport: MailTransport) -> Self {
+        self.config.mail_transport = mail_transport;
+        self
+    }
+
     pub fn in_proc_iters(mut self, in_proc_iters: usize) -> Self {
         self.config.in_proc_iters = in_proc_iters;
         self
@@ -141,20 +1554,512 @@ impl<'a> ExperimentBuilder<'a> {
         self
     }
 
+    /// Retry each job that fails at most `max_retries` times.
+    pub fn retry_errored(mut self, max_retries: usize) -> Self {
+        self.config.max_retries = max_retries;
+        self
+    }
+
+    /// Run each job again at most `max_reruns` times when its benchmark asks for it
+    /// (see `measure::RERUN_PREFIX`).
+    pub fn max_reruns(mut self, max_reruns: usize) -> Self {
+        self.config.max_reruns = max_reruns;
+        self
+    }
+
+    /// Give up on each job once it was started `max_attempts` times.
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.config.max_attempts = max_attempts;
+        self
+    }
+
     pub fn temp_read_pause(mut self, temp_read_pause: Duration) -> Self {
         self.config.temp_read_pause = temp_read_pause;
         self
     }
 
-    /// Add `bench` to the list of benchmarks to run.
-    pub fn benchmark(mut self, bench: &'a Benchmark) -> Self {
-        self.benchmarks.push(bench);
+    pub fn temp_threshold(mut self, temp_threshold: f64) -> Self {
+        self.config.temp_threshold = Some(temp_threshold);
+        self
+    }
+
+    pub fn temp_wait_timeout(mut self, temp_wait_timeout: Duration) -> Self {
+        self.config.temp_wait_timeout = temp_wait_timeout;
+        self
+    }
+
+    /// Drop the page cache (after writing back the dirty pages) before each job, so
+    /// that each job starts with a cold cache, whatever ran before it (see
+    /// `cache`). This requires root.
+    pub fn drop_caches(mut self, drop_caches: bool) -> Self {
+        self.config.drop_caches = drop_caches;
+        self
+    }
+
+    /// Read the files of the benchmarks before each job, so that each job starts
+    /// with them cached (see `cache`). If the caches are also dropped, they are
+    /// dropped first.
+    pub fn warm_cache(mut self, warm_cache: bool) -> Self {
+        self.config.warm_cache = warm_cache;
+        self
+    }
+
+    /// Wait for `post_job_pause` after each job before running the next one, to let
+    /// the machine settle (this is separate from waiting for it to cool down, see
+    /// `temp_threshold`). There is no pause if the machine reboots between jobs.
+    pub fn post_job_pause(mut self, post_job_pause: Duration) -> Self {
+        self.config.post_job_pause = post_job_pause;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    pub fn cpu_affinity(mut self, cpus: Vec<usize>) -> Self {
+        self.config.cpu_affinity = Some(cpus);
+        self
+    }
+
+    /// Run several jobs at the same time, one on each of the disjoint sets of CPUs
+    /// `slots`, for experiments which care about throughput rather than noise.
+    /// Each job is pinned to the CPUs of its slot (instead of `cpu_affinity`), and
+    /// the jobs are dealt to the slots when the experiment starts.
+    ///
+    /// All the slots run their next job together, between two reboots: the
+    /// experiment moves on once the slowest of them is done.
+    pub fn slots(mut self, slots: Vec<Vec<usize>>) -> Self {
+        self.config.slots = Some(slots);
         self
     }
 
+    /// Run the benchmarks on `cpus`, which must be isolated from the rest of the
+    /// system (see `isolation`). The benchmarks are pinned to `cpus`, unless
+    /// `cpu_affinity` is set.
+    pub fn isolated_cpus(mut self, cpus: Vec<usize>) -> Self {
+        self.config.isolated_cpus = Some(cpus);
+        self
+    }
+
+    /// Bind the benchmarks to NUMA node `node`: their memory is only allocated on
+    /// the node, and they only run on its CPUs, unless `cpu_affinity` is set (see
+    /// `numa`).
+    pub fn numa_node(mut self, node: usize) -> Self {
+        self.config.numa_node = Some(node);
+        self
+    }
+
+    /// Run each benchmark process in its own network namespace, so that the
+    /// benchmarks which try to use the network fail rather than measure it (see
+    /// `network`). This requires root.
+    pub fn isolate_network(mut self, isolate_network: bool) -> Self {
+        self.config.isolate_network = isolate_network;
+        self
+    }
+
+    /// Check that the `services` are stopped before each job, and stop them if
+    /// `ServicesConfig::stop` is set (see `services`).
+    pub fn services(mut self, services: ServicesConfig) -> Self {
+        self.config.services = Some(services);
+        self
+    }
+
+    /// Run the benchmark processes with the scheduling policy `sched_policy` (for
+    /// example, `SchedPolicy::Fifo(50)`, so that they aren't preempted by
+    /// background daemons).
+    pub fn sched_policy(mut self, sched_policy: SchedPolicy) -> Self {
+        self.config.sched_policy = Some(sched_policy);
+        self
+    }
+
+    /// Run each process execution in its own transient cgroup, with the specified
+    /// limits. This can be overridden for each benchmark (see `Benchmark::cgroup`).
+    pub fn cgroup(mut self, cgroup: CgroupLimits) -> Self {
+        self.config.cgroup = Some(cgroup);
+        self
+    }
+
+    pub fn kill_behaviour(mut self, kill_behaviour: KillBehaviour) -> Self {
+        self.config.kill_behaviour = kill_behaviour;
+        self
+    }
+
+    /// Once k2 is interrupted (see `shutdown`), let the running process execution
+    /// run for at most `grace` before killing it according to `kill_behaviour`. By
+    /// default, it is allowed to finish.
+    pub fn interrupt_grace(mut self, grace: Duration) -> Self {
+        self.config.interrupt_grace = Some(grace);
+        self
+    }
+
+    /// Resume the experiment even if the environment (see `audit`) changed since
+    /// the experiment started.
+    pub fn ignore_audit_mismatch(mut self, ignore: bool) -> Self {
+        self.config.ignore_audit_mismatch = ignore;
+        self
+    }
+
+    /// Set the order in which to run the jobs. Defaults to
+    /// `OrderingStrategy::Shuffle`.
+    pub fn ordering(mut self, ordering: OrderingStrategy) -> Self {
+        self.config.ordering = ordering;
+        self
+    }
+
+    /// Set the seed of the random number generator used to order the jobs, so that
+    /// experiments with the same seed, benchmarks and ordering strategy run their
+    /// jobs in the same order. The seed of each experiment is recorded in its audit
+    /// table.
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.config.rng_seed = Some(seed);
+        self
+    }
+
+    /// Set what to do if the machine isn't set up for benchmarking (see `checks`).
+    pub fn check_policy(mut self, check_policy: CheckPolicy) -> Self {
+        self.config.check_policy = check_policy;
+        self
+    }
+
+    /// Set whether ASLR is expected to be fully enabled (the default) or disabled.
+    pub fn aslr(mut self, aslr: bool) -> Self {
+        self.config.aslr = aslr;
+        self
+    }
+
+    /// Measure the wall-clock times with `clock`, instead of `CLOCK_MONOTONIC_RAW`
+    /// (see `clock`). The benchmarks are asked to measure their in-process
+    /// iterations with the same clock.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.config.clock = clock;
+        self
+    }
+
+    /// Ask the benchmarks to report the CPU time of each in-process iteration as
+    /// well as its wall-clock time (see `measure::CPU_TIME_PREFIX`), to tell the
+    /// time they spent running from the time they spent waiting.
+    pub fn cpu_time(mut self, cpu_time: bool) -> Self {
+        self.config.cpu_time = cpu_time;
+        self
+    }
+
+    /// Measure the energy used by each process execution, using the RAPL counters
+    /// of the machine. This usually requires root.
+    pub fn energy(mut self, energy: bool) -> Self {
+        self.config.energy = energy;
+        self
+    }
+
+    /// Sample the frequency of the CPUs while each process execution runs, and
+    /// count the times they were throttled because they were too hot, so that the
+    /// process executions which ran on a slowed-down machine can be told apart.
+    pub fn cpu_freq(mut self, cpu_freq: bool) -> Self {
+        self.config.cpu_freq = cpu_freq;
+        self
+    }
+
+    /// Split the in-process iterations of each process execution into warmup and
+    /// steady state, using changepoint detection, and record the length of the
+    /// warmup and the statistics of the steady state with the measurements of the
+    /// process execution (see `analysis::record`).
+    pub fn warmup_analysis(mut self, warmup_analysis: bool) -> Self {
+        self.config.warmup_analysis = warmup_analysis;
+        self
+    }
+
+    /// Collect the performance counters in `events` for each process execution.
+    /// Requires the `perf` feature.
+    pub fn perf_events(mut self, events: Vec<PerfEvent>) -> Self {
+        self.config.perf_events = events;
+        self
+    }
+
+    /// Choose what to do when the kernel logs messages (which might be about
+    /// thermal throttling, OOM kills or hardware errors) while a job runs. The
+    /// kernel log isn't read by default.
+    pub fn dmesg(mut self, policy: DmesgPolicy) -> Self {
+        self.config.dmesg = policy;
+        self
+    }
+
+    /// Sample the load of the machine while each job runs, and report the jobs during
+    /// which other processes used more CPU time than `load_monitor` allows (see
+    /// `load`).
+    pub fn load_monitor(mut self, load_monitor: LoadMonitorConfig) -> Self {
+        self.config.load_monitor = Some(load_monitor);
+        self
+    }
+
+    /// Profile the process executions selected by `profile`, storing their profiles
+    /// in the results directory (see `profile`).
+    pub fn profile(mut self, profile: ProfileConfig) -> Self {
+        self.config.profile = Some(profile);
+        self
+    }
+
+    /// Run each benchmark once, without any limits, before the first job, to
+    /// suggest heap and stack limits for it (see `calibrate`).
+    pub fn calibrate(mut self, calibration: CalibrationConfig) -> Self {
+        self.config.calibration = Some(calibration);
+        self
+    }
+
+    /// Stop running the process executions of a benchmark once the confidence
+    /// interval of its mean wall-clock time is narrow enough (see `adaptive`). Each
+    /// benchmark still runs at most `pexecs` process executions.
+    pub fn adaptive(mut self, adaptive: AdaptiveConfig) -> Self {
+        self.config.adaptive = Some(adaptive);
+        self
+    }
+
+    /// Measure the overhead of the language implementations with `overhead`,
+    /// instead of the default settings (see `overhead`). The overhead is only
+    /// measured on the language implementations which have an empty benchmark.
+    pub fn overhead(mut self, overhead: OverheadConfig) -> Self {
+        self.config.overhead = Some(overhead);
+        self
+    }
+
+    /// Measure the overhead of the language implementation of `bench`, which must do
+    /// nothing in each of its in-process iterations, by running it before the first
+    /// job (see `overhead`). This replaces any other empty benchmark of the same
+    /// language implementation. `bench` isn't one of the benchmarks of the
+    /// experiment, so it has no jobs.
+    pub fn empty_benchmark(mut self, bench: Benchmark) -> Self {
+        let key = bench.lang_impl().results_key();
+        self.empty_benchmarks
+            .retain(|empty| empty.lang_impl().results_key() != key);
+        self.empty_benchmarks.push(bench);
+        self
+    }
+
+    /// Add a notifier to tell about the progress of the experiment, in addition to
+    /// the email notifications sent to `mail_to`.
+    pub fn notifier<N: Notifier + 'static>(mut self, notifier: N) -> Self {
+        self.notifiers.push(Box::new(notifier));
+        self
+    }
+
+    /// Add a collector to take measurements around each process execution, in
+    /// addition to the built-in ones.
+    pub fn collector<C: MeasurementCollector + 'static>(mut self, collector: C) -> Self {
+        self.collectors.push(Box::new(collector));
+        self
+    }
+
+    /// Copy the results of each job to `store` (such as a `JsonlStore`, or a
+    /// `PgStore` shared by several machines), in addition to the results database in
+    /// `results_dir`.
+    pub fn result_store<S: ResultStore + 'static>(mut self, store: S) -> Self {
+        self.result_stores.push(Box::new(store));
+        self
+    }
+
+    /// Upload the results to `uploader` (such as a `CodespeedUploader`) once all the
+    /// jobs have run.
+    pub fn uploader<U: Uploader + 'static>(mut self, uploader: U) -> Self {
+        self.uploaders.push(Box::new(uploader));
+        self
+    }
+
+    /// Run `hook` before each job, once the machine has cooled down (e.g. to drop
+    /// the filesystem caches with `Hook::command`). A hook which fails stops the
+    /// experiment before the job runs (see `hooks`).
+    pub fn before_job(mut self, hook: Hook) -> Self {
+        self.hooks.push((HookPoint::BeforeJob, hook));
+        self
+    }
+
+    /// Run `hook` after each job, once its results are stored (e.g. to reset a
+    /// device the benchmark uses).
+    pub fn after_job(mut self, hook: Hook) -> Self {
+        self.hooks.push((HookPoint::AfterJob, hook));
+        self
+    }
+
+    /// Run `hook` once, before the first job of the experiment.
+    pub fn before_experiment(mut self, hook: Hook) -> Self {
+        self.hooks.push((HookPoint::BeforeExperiment, hook));
+        self
+    }
+
+    /// Run `hook` once all the jobs have run, before the results are uploaded.
+    pub fn after_experiment(mut self, hook: Hook) -> Self {
+        self.hooks.push((HookPoint::AfterExperiment, hook));
+        self
+    }
+
+    /// Add `bench` to the list of benchmarks to run. A benchmark with parameters
+    /// (see `Benchmark::param`) adds one benchmark for each combination of their
+    /// values.
+    pub fn benchmark(mut self, bench: Benchmark) -> Self {
+        self.benchmarks.extend(bench.expand());
+        self
+    }
+
+    /// Add all of `benches` to the list of benchmarks to run (e.g. the benchmarks
+    /// of a `BenchmarkSuite`).
+    pub fn benchmarks<I: IntoIterator<Item = Benchmark>>(mut self, benches: I) -> Self {
+        for bench in benches {
+            self = self.benchmark(bench);
+        }
+        self
+    }
+
+    /// Run the benchmarks which don't have a wrapper of their own through `cmd` (see
+    /// `Benchmark::wrapper`).
+    pub fn wrapper(mut self, cmd: &str, args: &[&str]) -> Self {
+        self.wrapper = std::iter::once(cmd)
+            .chain(args.iter().copied())
+            .map(str::to_string)
+            .collect();
+        self
+    }
+
+    /// Only run the benchmarks whose tag `t` matches `val` (see
+    /// `Benchmark::matches_tag`). If this is called several times, the benchmarks
+    /// which match any of the tags are run.
+    ///
+    /// The benchmarks are selected when the experiment is built, so the filter must
+    /// be the same each time the experiment is resumed.
+    pub fn only_tag(mut self, t: &str, val: &str) -> Self {
+        self.only_tags.push((t.to_string(), val.to_string()));
+        self
+    }
+
+    /// Don't run the benchmarks whose tag `t` matches `val`. This takes precedence
+    /// over `only_tag`.
+    pub fn skip_tag(mut self, t: &str, val: &str) -> Self {
+        self.skip_tags.push((t.to_string(), val.to_string()));
+        self
+    }
+
+    /// Only run the benchmarks for which `predicate` returns `true`. The `name` of
+    /// the predicate is recorded in the audit, in place of the predicate itself.
+    pub fn filter<F: Fn(&Benchmark) -> bool + 'static>(mut self, name: &str, predicate: F) -> Self {
+        self.filters.push((name.to_string(), Box::new(predicate)));
+        self
+    }
+
+    /// Don't run the benchmark at `path` on `lang_impl` (e.g. because it doesn't work
+    /// on it). No jobs are created for it, but it is recorded in the results, with
+    /// the `reason` it is skipped (see `Results::skipped`).
+    ///
+    /// As with `skip_tag`, the benchmarks are skipped when the experiment is built,
+    /// so the same benchmarks must be skipped each time the experiment is resumed.
+    pub fn skip(mut self, path: &str, lang_impl: &dyn LangImpl, reason: &str) -> Self {
+        self.skips.push((
+            path.to_string(),
+            lang_impl.results_key(),
+            reason.to_string(),
+        ));
+        self
+    }
+
+    /// The reason `bench` is skipped, or `None` if it isn't (see `skip`).
+    fn skip_reason(&self, bench: &Benchmark) -> Option<&str> {
+        let lang_impl = bench.lang_impl().results_key();
+        self.skips
+            .iter()
+            .find(|(path, key, _)| bench.matches_tag(TAG_PATH, path) && *key == lang_impl)
+            .map(|(_, _, reason)| reason.as_str())
+    }
+
+    /// Whether `bench` is selected by the filters of the experiment.
+    fn selects(&self, bench: &Benchmark) -> bool {
+        let matches = |(t, val): &(String, String)| bench.matches_tag(t, val);
+        (self.only_tags.is_empty() || self.only_tags.iter().any(matches))
+            && !self.skip_tags.iter().any(matches)
+            && self.filters.iter().all(|(_, predicate)| predicate(bench))
+    }
+
+    /// Describe the filters of the experiment, for the audit.
+    fn filter_description(&self) -> String {
+        let only_tags = self
+            .only_tags
+            .iter()
+            .map(|(t, val)| format!("only_tag({}={})", t, val));
+        let skip_tags = self
+            .skip_tags
+            .iter()
+            .map(|(t, val)| format!("skip_tag({}={})", t, val));
+        let filters = self
+            .filters
+            .iter()
+            .map(|(name, _)| format!("filter({})", name));
+        let skips = self
+            .skips
+            .iter()
+            .map(|(path, lang_impl, _)| format!("skip({} on {})", path, lang_impl));
+        let filter = only_tags
+            .chain(skip_tags)
+            .chain(filters)
+            .chain(skips)
+            .collect::<Vec<_>>();
+        if filter.is_empty() {
+            "none".into()
+        } else {
+            filter.join(", ")
+        }
+    }
+
     /// Consume the builder and create an `Experiment` with the `config` and
-    /// `benchmarks` recorded.
-    pub fn build(self) -> Experiment<'a> {
-        Experiment::new(self.config, self.benchmarks)
+    /// `benchmarks` recorded. Only the benchmarks selected by the filters (see
+    /// `only_tag`, `skip_tag` and `filter`), and not skipped (see `skip`), are part
+    /// of the experiment.
+    pub fn build(mut self) -> Result<Experiment, K2Error> {
+        let filter = self.filter_description();
+        let mut benchmarks = mem::take(&mut self.benchmarks);
+        let mut empty_benchmarks = mem::take(&mut self.empty_benchmarks);
+        if let [cmd, args @ ..] = self.wrapper.as_slice() {
+            let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+            let wrap = |benchmarks: Vec<Benchmark>| {
+                benchmarks
+                    .into_iter()
+                    .map(|bench| {
+                        if bench.wrapper_cmd().is_empty() {
+                            bench.wrapper(cmd, &args)
+                        } else {
+                            bench
+                        }
+                    })
+                    .collect()
+            };
+            benchmarks = wrap(benchmarks);
+            // The overhead of the wrapper is part of the overhead of each benchmark.
+            empty_benchmarks = wrap(empty_benchmarks);
+        }
+        if !empty_benchmarks.is_empty() && self.config.overhead.is_none() {
+            self.config.overhead = Some(OverheadConfig::new());
+        }
+        let num_benchmarks = benchmarks.len();
+        benchmarks.retain(|bench| self.selects(bench));
+        let mut skipped = mem::take(&mut self.skipped);
+        for bench in &benchmarks {
+            if let Some(reason) = self.skip_reason(bench) {
+                skipped.push((bench.results_key()?, reason.to_string()));
+            }
+        }
+        benchmarks.retain(|bench| self.skip_reason(bench).is_none());
+        if benchmarks.is_empty() && num_benchmarks > 0 {
+            return Err(K2Error::InvalidConfig(format!(
+                "No benchmarks match the filter: {}",
+                filter
+            )));
+        }
+        validate(&self.config, &benchmarks, &empty_benchmarks)?;
+        Experiment::new(
+            self.config,
+            benchmarks,
+            empty_benchmarks,
+            self.notifiers,
+            self.collectors,
+            self.result_stores,
+            self.uploaders,
+            self.hooks,
+            filter,
+            skipped,
+        )
     }
 }
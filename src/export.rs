@@ -0,0 +1,156 @@
+use crate::{
+    analysis::BootstrapOpts,
+    db::csv_field,
+    error::K2Error,
+    manifest::ManifestManager,
+    results::{open_store, Results},
+};
+
+use serde_json::{json, Map};
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Export the results of the experiment in `results_dir` as CSV files in `out_dir`.
+///
+/// See `K2Store::export_csv` for a description of the files created.
+pub fn export_csv<P: AsRef<Path>, Q: AsRef<Path>>(
+    results_dir: P,
+    out_dir: Q,
+) -> Result<(), K2Error> {
+    open_store(results_dir.as_ref())?.export_csv(out_dir)
+}
+
+/// Export the summary statistics of the experiment in `results_dir` (see
+/// `Results::statistics`) as a CSV file at `out_path`, with one row per results key.
+/// The wall-clock times are in seconds, and each statistic is followed by the
+/// bounds of its confidence interval.
+pub fn export_statistics_csv<P: AsRef<Path>, Q: AsRef<Path>>(
+    results_dir: P,
+    out_path: Q,
+    opts: &BootstrapOpts,
+) -> Result<(), K2Error> {
+    let statistics = Results::open(results_dir)?.statistics(opts)?;
+    let mut out = BufWriter::new(fs::File::create(out_path)?);
+    writeln!(
+        out,
+        "key,pexecs,iters,mean,mean_lower,mean_upper,median,median_lower,median_upper"
+    )?;
+    for (key, stats) in &statistics {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{}",
+            csv_field(key),
+            stats.pexecs,
+            stats.iters,
+            stats.mean.value,
+            stats.mean.lower,
+            stats.mean.upper,
+            stats.median.value,
+            stats.median.lower,
+            stats.median.upper
+        )?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Export the results of the experiment in `results_dir` to `out_path`, in the
+/// Krun results format.
+///
+/// Only the jobs which completed successfully are exported. The file is written
+/// uncompressed: Krun (and tools such as warmup_stats) expect results files to be
+/// compressed with `bzip2`.
+///
+/// Krun identifies each benchmark by a `<benchmark>:<vm>:<variant>` key. The
+/// benchmark is the path of the benchmark without its extension, the VM is the
+/// file name of the language implementation, and the variant is `default-<ext>`.
+pub fn export_krun_json<P: AsRef<Path>, Q: AsRef<Path>>(
+    results_dir: P,
+    out_path: Q,
+) -> Result<(), K2Error> {
+    let results_dir = results_dir.as_ref();
+    let mut store = open_store(results_dir)?;
+    let wallclock_times = store
+        .wallclock_times()?
+        .into_iter()
+        .map(|(key, times)| (krun_key(&key), times))
+        .collect::<BTreeMap<_, _>>();
+    let summary = store.job_summary()?;
+    let mut audit = if store.has_table("audit")? {
+        store.audit()?
+    } else {
+        Default::default()
+    };
+    // Krun records its configuration separately from the rest of the audit.
+    let config = audit.remove("config").unwrap_or_default();
+    let starting_temperatures = store
+        .temperature_baseline()?
+        .values()
+        .copied()
+        .collect::<Vec<_>>();
+    // k2 doesn't collect any performance counters, so there is an empty list of
+    // counter readings for each process execution.
+    let no_counters = wallclock_times
+        .iter()
+        .map(|(key, times)| (key.clone(), json!(vec![Vec::<f64>::new(); times.len()])))
+        .collect::<Map<_, _>>();
+    let results = json!({
+        "audit": audit,
+        "config": config,
+        "wallclock_times": wallclock_times,
+        "core_cycle_counts": no_counters,
+        "aperf_counts": no_counters,
+        "mperf_counts": no_counters,
+        "instr_data": {},
+        "eta_estimates": Map::new(),
+        "reboots": ManifestManager::open(results_dir)?.num_reboots(),
+        "starting_temperatures": starting_temperatures,
+        "error_flag": summary.error
+            + summary.timed_out
+            + summary.wrong_answer
+            + summary.too_many_attempts
+            > 0,
+    });
+    fs::write(out_path, results.to_string())?;
+    Ok(())
+}
+
+/// Convert a k2 job key (`<lang_impl>:<benchmark path>[<params>]{<wrapper>}`) into
+/// a Krun key. The parameters and the wrapper of the benchmark (if any) are
+/// appended to its name.
+fn krun_key(key: &str) -> String {
+    let (key, wrapper) = match key.rfind('{') {
+        Some(idx) if key.ends_with('}') => key.split_at(idx),
+        _ => (key, ""),
+    };
+    let (key, params) = match key.rfind('[') {
+        Some(idx) if key.ends_with(']') => key.split_at(idx),
+        _ => (key, ""),
+    };
+    let params = format!("{}{}", params, wrapper);
+    let mut parts = key.splitn(2, ':');
+    let (lang_impl, bench) = match (parts.next(), parts.next()) {
+        (Some(lang_impl), Some(bench)) => (Path::new(lang_impl), Path::new(bench)),
+        _ => return format!("{}{}::default", key, params),
+    };
+    let vm = lang_impl
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let variant = bench
+        .extension()
+        .map(|ext| format!("default-{}", ext.to_string_lossy()))
+        .unwrap_or_else(|| "default".to_string());
+    format!(
+        "{}{}:{}:{}",
+        bench.with_extension("").display(),
+        params,
+        vm,
+        variant
+    )
+}
@@ -0,0 +1,239 @@
+//! Running hooks before and after each job, and at the start and end of the
+//! experiment.
+//!
+//! A hook is either a shell command (such as
+//! `sync && echo 3 > /proc/sys/vm/drop_caches`, to drop the filesystem caches
+//! before each job) or a closure (see `ExperimentBuilder::before_job` and
+//! friends). Each time a hook runs, it is recorded in the `hook_run` table of the
+//! results database, along with the exit code of its command (see
+//! `Results::hook_runs`).
+//!
+//! A hook which fails stops the experiment with `K2Error::HookFailed`, so that the
+//! benchmarks don't run on a machine which isn't in the state the hook was meant
+//! to put it in. The experiment can be resumed once the problem is fixed: the
+//! hooks which run before a job run again, but the job isn't run twice.
+//!
+//! The hooks run in the order they were added. Nothing is run in a dry run.
+
+use crate::{db::K2Store, error::K2Error, manifest::JobStatus};
+
+use serde::Deserialize;
+use tracing::debug;
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// When a hook runs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HookPoint {
+    /// Before the first job of the experiment (after the machine is checked).
+    BeforeExperiment,
+    /// Once all the jobs have run.
+    AfterExperiment,
+    /// Before each job (after the machine has cooled down).
+    BeforeJob,
+    /// After each job, once its results are stored.
+    AfterJob,
+}
+
+impl HookPoint {
+    /// The name of the hook point, as recorded in the results database.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookPoint::BeforeExperiment => "before_experiment",
+            HookPoint::AfterExperiment => "after_experiment",
+            HookPoint::BeforeJob => "before_job",
+            HookPoint::AfterJob => "after_job",
+        }
+    }
+
+    /// Convert the name of a hook point back to a `HookPoint`.
+    pub fn from_name(name: &str) -> Option<HookPoint> {
+        match name {
+            "before_experiment" => Some(HookPoint::BeforeExperiment),
+            "after_experiment" => Some(HookPoint::AfterExperiment),
+            "before_job" => Some(HookPoint::BeforeJob),
+            "after_job" => Some(HookPoint::AfterJob),
+            _ => None,
+        }
+    }
+}
+
+/// What a hook is told about the experiment when it runs.
+#[derive(Debug, Clone)]
+pub struct HookContext<'a> {
+    pub point: HookPoint,
+    /// The results directory of the experiment.
+    pub results_dir: &'a Path,
+    /// The job the hook runs before or after, if it is a job hook.
+    pub job: Option<usize>,
+    /// The results key of the benchmark of the job, if it is a job hook.
+    pub benchmark: Option<&'a str>,
+    /// The status of the job, if the hook runs after it.
+    pub status: Option<JobStatus>,
+}
+
+/// A closure run as a hook. Returning an error fails the hook.
+type HookFn = Box<dyn Fn(&HookContext) -> Result<(), K2Error> + Send>;
+
+/// Something to run before or after the jobs (see `HookPoint`).
+pub enum Hook {
+    /// A shell command, run with `sh -c`. The command is told about the experiment
+    /// through the `K2_HOOK`, `K2_RESULTS_DIR`, `K2_JOB`, `K2_BENCHMARK` and
+    /// `K2_JOB_STATUS` environment variables (the last three are only set if they
+    /// apply). The hook fails if the command exits with a non-zero exit code.
+    Command(String),
+    /// A closure, with the name it is recorded under.
+    Closure { name: String, run: HookFn },
+}
+
+impl Hook {
+    /// A hook which runs the shell command `cmd`.
+    pub fn command(cmd: &str) -> Hook {
+        Hook::Command(cmd.to_string())
+    }
+
+    /// A hook which calls `run`. The `name` of the hook is recorded in the results
+    /// database, in place of the closure itself.
+    pub fn closure<F>(name: &str, run: F) -> Hook
+    where
+        F: Fn(&HookContext) -> Result<(), K2Error> + Send + 'static,
+    {
+        Hook::Closure {
+            name: name.to_string(),
+            run: Box::new(run),
+        }
+    }
+
+    /// The command of the hook, or the name of its closure.
+    pub fn description(&self) -> &str {
+        match self {
+            Hook::Command(cmd) => cmd,
+            Hook::Closure { name, .. } => name,
+        }
+    }
+
+    /// Run the hook. Returns the exit code of its command (if it has one), and why
+    /// it failed, if it did.
+    fn run(&self, context: &HookContext) -> (Option<i32>, Option<String>) {
+        let cmd = match self {
+            Hook::Command(cmd) => cmd,
+            Hook::Closure { run, .. } => return (None, run(context).err().map(|e| e.chain())),
+        };
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(cmd)
+            .stdin(Stdio::null())
+            .env("K2_HOOK", context.point.name())
+            .env("K2_RESULTS_DIR", context.results_dir);
+        if let Some(job) = context.job {
+            command.env("K2_JOB", job.to_string());
+        }
+        if let Some(benchmark) = context.benchmark {
+            command.env("K2_BENCHMARK", benchmark);
+        }
+        if let Some(status) = context.status {
+            command.env("K2_JOB_STATUS", status.name());
+        }
+        match command.status() {
+            Ok(status) if status.success() => (status.code(), None),
+            Ok(status) => (
+                status.code(),
+                Some(format!("the command exited with {}", status)),
+            ),
+            Err(err) => (
+                None,
+                Some(format!("the command couldn't be started: {}", err)),
+            ),
+        }
+    }
+}
+
+/// A run of a hook, as recorded in the results database.
+#[derive(Debug, Clone)]
+pub struct HookRun {
+    pub point: HookPoint,
+    /// The job the hook ran before or after, if it is a job hook.
+    pub job: Option<usize>,
+    /// The command of the hook, or the name of its closure.
+    pub hook: String,
+    /// The exit code of the command of the hook. This is `None` for closures, and
+    /// for commands which were killed by a signal or couldn't be started.
+    pub exit_code: Option<i32>,
+    /// Why the hook failed, if it did.
+    pub error: Option<String>,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+}
+
+/// The hooks of an experiment configuration file, as shell commands.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HooksConfig {
+    #[serde(default)]
+    before_experiment: Vec<String>,
+    #[serde(default)]
+    after_experiment: Vec<String>,
+    #[serde(default)]
+    before_job: Vec<String>,
+    #[serde(default)]
+    after_job: Vec<String>,
+}
+
+impl HooksConfig {
+    /// The hooks, with the points they run at.
+    pub(crate) fn hooks(&self) -> Vec<(HookPoint, Hook)> {
+        let points = [
+            (HookPoint::BeforeExperiment, &self.before_experiment),
+            (HookPoint::AfterExperiment, &self.after_experiment),
+            (HookPoint::BeforeJob, &self.before_job),
+            (HookPoint::AfterJob, &self.after_job),
+        ];
+        points
+            .iter()
+            .flat_map(|(point, cmds)| cmds.iter().map(move |cmd| (*point, Hook::command(cmd))))
+            .collect()
+    }
+}
+
+/// Run the `hooks` of `context.point`, one after the other, and record each of
+/// them in `store`. Stops at the first hook which fails.
+pub(crate) fn run(
+    hooks: &[(HookPoint, Hook)],
+    context: &HookContext,
+    store: &mut K2Store,
+) -> Result<(), K2Error> {
+    for (_, hook) in hooks.iter().filter(|(point, _)| *point == context.point) {
+        debug!(
+            "running {} hook {}",
+            context.point.name(),
+            hook.description()
+        );
+        let started_at = SystemTime::now();
+        let start = Instant::now();
+        let (exit_code, error) = hook.run(context);
+        let run = HookRun {
+            point: context.point,
+            job: context.job,
+            hook: hook.description().to_string(),
+            exit_code,
+            error,
+            started_at,
+            duration: start.elapsed(),
+        };
+        store.insert_hook_run(&run)?;
+        if let Some(error) = run.error {
+            return Err(K2Error::HookFailed(format!(
+                "{} hook {}: {}",
+                context.point.name(),
+                run.hook,
+                error
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,214 @@
+//! Running the benchmarks on isolated CPUs.
+//!
+//! The kernel can be told to keep the scheduler off some CPUs (`isolcpus`), and to
+//! stop their scheduling-clock interrupts (`nohz_full`). Nothing runs on these
+//! CPUs unless it is pinned to them, so a benchmark pinned to them isn't disturbed
+//! by the rest of the system. However, interrupts and kernel threads can still run
+//! on any CPU: before each job, they are moved to the other (housekeeping) CPUs,
+//! wherever the kernel allows it.
+//!
+//! The CPUs have to be isolated on the kernel command line, so the isolation is
+//! checked (see `checks`) rather than set up by k2.
+
+use crate::{error::K2Error, process};
+
+use std::{fs, mem};
+
+/// The file which lists the CPUs isolated with `isolcpus`.
+const ISOLATED: &str = "/sys/devices/system/cpu/isolated";
+/// The file which lists the CPUs in `nohz_full` mode.
+const NOHZ_FULL: &str = "/sys/devices/system/cpu/nohz_full";
+/// The file which lists the online CPUs.
+const ONLINE: &str = "/sys/devices/system/cpu/online";
+/// The directory which contains the settings of each IRQ.
+const IRQ_DIR: &str = "/proc/irq";
+/// The CPUs the kernel assigns new IRQs to.
+const DEFAULT_SMP_AFFINITY: &str = "/proc/irq/default_smp_affinity";
+/// The CPUs unbound workqueues run on.
+const WORKQUEUE_CPUMASK: &str = "/sys/devices/virtual/workqueue/cpumask";
+/// The pid of `kthreadd`, the parent of all the kernel threads.
+const KTHREADD_PID: libc::pid_t = 2;
+
+/// What `isolate` managed to move off the isolated CPUs. Per-CPU interrupts and
+/// kernel threads can't be moved.
+#[derive(Debug, Default)]
+pub struct IsolationReport {
+    /// The number of IRQs moved to the housekeeping CPUs.
+    pub irqs_moved: usize,
+    /// The number of IRQs which couldn't be moved.
+    pub irqs_not_moved: usize,
+    /// The number of kernel threads moved to the housekeeping CPUs.
+    pub kthreads_moved: usize,
+    /// The number of kernel threads which couldn't be moved.
+    pub kthreads_not_moved: usize,
+}
+
+/// Move the IRQs, unbound workqueues and kernel threads off `cpus`, onto the other
+/// online CPUs. This usually requires root: anything which can't be moved is left
+/// alone, and counted in the report.
+pub(crate) fn isolate(cpus: &[usize]) -> Result<IsolationReport, K2Error> {
+    let housekeeping = online_cpus()?
+        .into_iter()
+        .filter(|cpu| !cpus.contains(cpu))
+        .collect::<Vec<_>>();
+    if housekeeping.is_empty() {
+        return Err(K2Error::InvalidConfig(
+            "At least one online CPU must not be isolated".into(),
+        ));
+    }
+    let mut report = IsolationReport::default();
+    let cpu_list = format_cpu_list(&housekeeping);
+    for entry in fs::read_dir(IRQ_DIR)? {
+        let path = entry?.path().join("smp_affinity_list");
+        if !path.exists() {
+            continue;
+        }
+        match fs::write(&path, &cpu_list) {
+            Ok(()) => report.irqs_moved += 1,
+            Err(_) => report.irqs_not_moved += 1,
+        }
+    }
+    let mask = cpu_mask(&housekeeping);
+    let _ = fs::write(DEFAULT_SMP_AFFINITY, &mask);
+    let _ = fs::write(WORKQUEUE_CPUMASK, &mask);
+    let cpu_set = process::cpu_set(&housekeeping);
+    for pid in kernel_threads()? {
+        let size = mem::size_of::<libc::cpu_set_t>();
+        if unsafe { libc::sched_setaffinity(pid, size, &cpu_set) } == 0 {
+            report.kthreads_moved += 1;
+        } else {
+            report.kthreads_not_moved += 1;
+        }
+    }
+    Ok(report)
+}
+
+/// Describe what's wrong with the isolation of `cpus`: each of them should be
+/// isolated, and in `nohz_full` mode.
+pub(crate) fn problems(cpus: &[usize]) -> Result<Vec<String>, K2Error> {
+    let mut problems = Vec::new();
+    for (path, name) in &[(ISOLATED, "isolcpus"), (NOHZ_FULL, "nohz_full")] {
+        let isolated = read_cpu_list(path)?;
+        let missing = cpus
+            .iter()
+            .copied()
+            .filter(|cpu| !isolated.contains(cpu))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            problems.push(format!(
+                "CPU(s) {} are not isolated with {}",
+                format_cpu_list(&missing),
+                name
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+/// The CPUs isolated with `isolcpus`, and the CPUs in `nohz_full` mode, as
+/// recorded in the audit.
+pub(crate) fn kernel_isolation() -> (String, String) {
+    let list = |path| {
+        read_cpu_list(path)
+            .map(|cpus| format_cpu_list(&cpus))
+            .unwrap_or_default()
+    };
+    (list(ISOLATED), list(NOHZ_FULL))
+}
+
+/// The online CPUs.
+fn online_cpus() -> Result<Vec<usize>, K2Error> {
+    read_cpu_list(ONLINE)
+}
+
+/// Read the list of CPUs in the file at `path`. A missing file is an empty list.
+fn read_cpu_list(path: &str) -> Result<Vec<usize>, K2Error> {
+    match fs::read_to_string(path) {
+        Ok(list) => parse_cpu_list(&list),
+        Err(_) => Ok(Default::default()),
+    }
+}
+
+/// Parse a list of CPUs in the format used by the kernel (e.g. `0-3,6,8-9`).
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>, K2Error> {
+    let invalid = || K2Error::InvalidConfig(format!("Invalid CPU list: {}", list.trim()));
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let start = start.parse::<usize>().map_err(|_| invalid())?;
+        let end = end.parse::<usize>().map_err(|_| invalid())?;
+        cpus.extend(start..=end);
+    }
+    Ok(cpus)
+}
+
+/// Format `cpus` as a list of CPUs in the format used by the kernel (e.g.
+/// `0-3,6,8-9`).
+pub fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut cpus = cpus.to_vec();
+    cpus.sort_unstable();
+    cpus.dedup();
+    let mut ranges = Vec::<(usize, usize)>::new();
+    for cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == cpu => *end = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Format `cpus` as a hexadecimal CPU mask, in 32-bit groups separated by commas
+/// (e.g. `00000001,0000000f`).
+fn cpu_mask(cpus: &[usize]) -> String {
+    let num_groups = cpus.iter().max().map_or(1, |max| max / 32 + 1);
+    let mut groups = vec![0u32; num_groups];
+    for cpu in cpus {
+        groups[cpu / 32] |= 1 << (cpu % 32);
+    }
+    groups
+        .iter()
+        .rev()
+        .map(|group| format!("{:08x}", group))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The pids of `kthreadd` and of all the kernel threads it started.
+fn kernel_threads() -> Result<Vec<libc::pid_t>, K2Error> {
+    let mut pids = Vec::new();
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_str().and_then(|pid| pid.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // The process might have exited since the directory was listed.
+        let stat = match fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        if pid == KTHREADD_PID || parent_pid(&stat) == Some(KTHREADD_PID) {
+            pids.push(pid);
+        }
+    }
+    Ok(pids)
+}
+
+/// The parent pid in the contents of `/proc/<pid>/stat`, which is the second field
+/// after the name of the process. The name is in parentheses, and may itself
+/// contain spaces and parentheses.
+fn parent_pid(stat: &str) -> Option<libc::pid_t> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
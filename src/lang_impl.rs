@@ -1,75 +1,1078 @@
-use crate::benchmark::Benchmark;
+use crate::{
+    benchmark::{input_file_name, Benchmark, TagStore},
+    error::K2Error,
+    process::{self, InvocationOutcome, ProcessOpts},
+    profile::Profile,
+    util,
+};
 
-use std::{collections::HashMap, path::PathBuf, process::Command};
+use tracing::{debug, info};
 
-pub trait LangImpl {
-    fn results_key(&self) -> &str;
-    /// Run the language implementation on the specified benchmark.
-    fn invoke(&self, benchmark: &Benchmark);
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    os::unix::fs::PermissionsExt,
+    path::{self, Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+/// A language implementation. Language implementations are shared between
+/// benchmarks (and possibly threads), so they must be `Send` and `Sync`.
+pub trait LangImpl: Send + Sync {
+    /// The key which identifies the language implementation in the results.
+    ///
+    /// Two language implementations in the same experiment must not have the same
+    /// key: the key should therefore include anything which affects the benchmarks
+    /// (see `results_key`).
+    fn results_key(&self) -> String;
+    /// Prepare the language implementation (e.g. by building the benchmarks). This
+    /// is called once per experiment, before any benchmark is run.
+    fn setup(&self) -> Result<(), K2Error> {
+        Ok(())
+    }
+    /// Check that the language implementation can run `benchmark` (e.g. that its
+    /// interpreter and the benchmark exist). This is called when the experiment is
+    /// built, so that a broken experiment definition is reported before anything
+    /// runs.
+    fn check(&self, benchmark: &Benchmark) -> Result<(), K2Error> {
+        benchmark.check_path()?;
+        benchmark.check_files()
+    }
+    /// The tags which describe the language implementation (e.g. the version of a
+    /// compiler). These are added to the tags of each benchmark run on it.
+    fn tags(&self) -> TagStore {
+        Default::default()
+    }
+    /// The version of the language implementation (e.g. `Python 3.11.4`), which is
+    /// recorded in the `lang_impl_version` tag of each job (see
+    /// `TAG_LANG_IMPL_VERSION`). This is called once per experiment, when it starts.
+    /// `None` by default, or if the version can't be found. (The version of the
+    /// compiler of `GenericNativeCode` is recorded in its `compiler_version` tag.)
+    fn version(&self) -> Option<String> {
+        None
+    }
+    /// The executable which runs the benchmarks (e.g. the interpreter), whose
+    /// checksum is recorded in the results (see `checksum`). `None` by default.
+    fn executable(&self) -> Option<PathBuf> {
+        None
+    }
+    /// Copy the files `benchmark` needs to where it runs (by default, its input
+    /// files to its working directory, see `Benchmark::input`). This is called
+    /// before each process execution, before the measurements start, so that the
+    /// time it takes isn't measured.
+    fn stage(&self, benchmark: &Benchmark) -> Result<(), K2Error> {
+        benchmark.stage_inputs()
+    }
+    /// Run the language implementation on the specified benchmark, once it has been
+    /// staged (see `stage`).
+    ///
+    /// Implementations are expected to spawn the benchmark using `process::run`,
+    /// which enforces the options specified in `opts`. An unsuccessful exit is not
+    /// an error: it is reported through the returned `InvocationOutcome`.
+    fn invoke(
+        &self,
+        benchmark: &Benchmark,
+        opts: &ProcessOpts,
+    ) -> Result<InvocationOutcome, K2Error>;
 }
 
 pub struct GenericScriptingVm {
     /// The path of the interpreter.
     interp_path: PathBuf,
+    /// The arguments to pass to the interpreter, before the path of the benchmark.
+    args: Vec<String>,
     /// The environment to use when running the VM.
     env: HashMap<String, String>,
+    /// The name of this configuration of the interpreter.
+    variant: Option<String>,
+    /// The arguments which make the interpreter profile a process execution.
+    profiler_args: Vec<String>,
 }
 
 impl GenericScriptingVm {
     pub fn new(path: &str) -> GenericScriptingVm {
         GenericScriptingVm {
             interp_path: PathBuf::from(path),
+            args: Default::default(),
             env: Default::default(),
+            variant: None,
+            profiler_args: Default::default(),
         }
     }
 
+    /// Name this configuration of the interpreter. The name replaces the hash of
+    /// the arguments and environment in the results key.
+    pub fn variant(mut self, name: &str) -> GenericScriptingVm {
+        self.variant = Some(name.to_string());
+        self
+    }
+
+    /// Add an argument to pass to the interpreter, before the path of the benchmark
+    /// (e.g. `--jit off`).
+    ///
+    /// The arguments are part of the results key, so that the same interpreter can
+    /// be run with different arguments in the same experiment.
+    pub fn arg(mut self, arg: &str) -> GenericScriptingVm {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Add several arguments to pass to the interpreter (see `arg`).
+    pub fn args(self, args: &[&str]) -> GenericScriptingVm {
+        args.iter().fold(self, |vm, arg| vm.arg(arg))
+    }
+
     pub fn env(mut self, k: &str, v: &str) -> GenericScriptingVm {
         self.env.insert(k.to_string(), v.to_string());
         self
     }
+
+    /// Add an argument which makes the interpreter profile the benchmark, in the
+    /// process executions which are profiled (see `ProfileConfig`). `{profile_dir}`
+    /// is replaced by the directory to write the profile to. The interpreter then
+    /// isn't run under `perf record`.
+    pub fn profiler_arg(mut self, arg: &str) -> GenericScriptingVm {
+        self.profiler_args.push(arg.to_string());
+        self
+    }
 }
 
 impl LangImpl for GenericScriptingVm {
-    fn results_key(&self) -> &str {
-        self.interp_path
-            .to_str()
-            .expect("The path should be valid unicode!")
+    fn results_key(&self) -> String {
+        results_key(
+            &self.interp_path.to_string_lossy(),
+            self.variant.as_deref(),
+            prefixed("arg", &self.args).chain(env_settings(&self.env)),
+        )
+    }
+
+    fn check(&self, benchmark: &Benchmark) -> Result<(), K2Error> {
+        check_program(&self.interp_path)?;
+        benchmark.check_path()?;
+        benchmark.check_files()
+    }
+
+    fn version(&self) -> Option<String> {
+        program_version(Command::new(&self.interp_path).arg("--version"))
+    }
+
+    fn executable(&self) -> Option<PathBuf> {
+        Some(self.interp_path.clone())
     }
 
-    fn invoke(&self, benchmark: &Benchmark) {
-        let _ = Command::new(&self.interp_path)
-            .arg(benchmark.path())
+    fn invoke(
+        &self,
+        benchmark: &Benchmark,
+        opts: &ProcessOpts,
+    ) -> Result<InvocationOutcome, K2Error> {
+        let profile = perf_profile(opts, &self.profiler_args);
+        let mut cmd = local_command(&self.interp_path, benchmark, profile)?;
+        cmd.args(&self.args)
+            .args(vm_profiler_args(opts, &self.profiler_args))
+            .arg(benchmark.absolute_path()?)
             .args(benchmark.args())
-            .envs(&self.env)
-            .output()
-            .expect("failed to execute process");
+            .envs(benchmark_env(&self.env, benchmark));
+        process::run(&mut cmd, opts)
+    }
+}
+
+/// A JVM-based language implementation.
+///
+/// The path of a benchmark run on a `Jvm` is either the path of a jar file (if
+/// it ends in `.jar`), or the name of the main class to run. So is the entry point
+/// of a project (see `Benchmark::entry`), whose jar file is in its directory.
+pub struct Jvm {
+    /// The path of the `java` executable.
+    java_path: PathBuf,
+    /// The classpath entries.
+    classpath: Vec<PathBuf>,
+    /// The flags to pass to the JVM.
+    flags: Vec<String>,
+    /// The environment to use when running the VM.
+    env: HashMap<String, String>,
+    /// The name of this configuration of the JVM.
+    variant: Option<String>,
+    /// The flags which make the JVM profile a process execution.
+    profiler_flags: Vec<String>,
+}
+
+impl Jvm {
+    pub fn new(path: &str) -> Jvm {
+        Jvm {
+            java_path: PathBuf::from(path),
+            classpath: Default::default(),
+            flags: Default::default(),
+            env: Default::default(),
+            variant: None,
+            profiler_flags: Default::default(),
+        }
+    }
+
+    /// Name this configuration of the JVM. The name replaces the hash of the
+    /// classpath, flags and environment in the results key.
+    pub fn variant(mut self, name: &str) -> Jvm {
+        self.variant = Some(name.to_string());
+        self
+    }
+
+    /// Add `path` to the classpath.
+    pub fn classpath(mut self, path: &str) -> Jvm {
+        self.classpath.push(PathBuf::from(path));
+        self
+    }
+
+    /// Add a flag to pass to the JVM (e.g. `-XX:+UseG1GC`).
+    ///
+    /// The heap and stack sizes shouldn't be set using this method: they are
+    /// derived from the `heap_lim` and `stack_lim` of each benchmark.
+    pub fn flag(mut self, flag: &str) -> Jvm {
+        self.flags.push(flag.to_string());
+        self
+    }
+
+    pub fn env(mut self, k: &str, v: &str) -> Jvm {
+        self.env.insert(k.to_string(), v.to_string());
+        self
+    }
+
+    /// Add a flag which makes the JVM profile the benchmark, in the process
+    /// executions which are profiled (see `ProfileConfig`), such as
+    /// `-XX:StartFlightRecording=filename={profile_dir}/recording.jfr`.
+    /// `{profile_dir}` is replaced by the directory to write the profile to. The
+    /// JVM then isn't run under `perf record`.
+    pub fn profiler_flag(mut self, flag: &str) -> Jvm {
+        self.profiler_flags.push(flag.to_string());
+        self
+    }
+
+    /// Build the arguments to pass to `java` in order to run `benchmark`.
+    fn jvm_args(&self, benchmark: &Benchmark) -> Result<Vec<String>, K2Error> {
+        let mut args = self.flags.clone();
+        if let Some(heap_kib) = benchmark.heap_lim.and_then(|lim| lim.kib()) {
+            args.push(format!("-Xmx{}k", heap_kib));
+        }
+        if let Some(stack_kib) = benchmark.stack_lim.and_then(|lim| lim.kib()) {
+            args.push(format!("-Xss{}k", stack_kib));
+        }
+        if !self.classpath.is_empty() {
+            // The benchmark runs in its own working directory.
+            let classpath = self
+                .classpath
+                .iter()
+                .map(path::absolute)
+                .collect::<Result<Vec<_>, _>>()?;
+            let classpath = env::join_paths(classpath)
+                .map_err(|e| K2Error::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+            args.push("-cp".into());
+            args.push(classpath.to_string_lossy().into_owned());
+        }
+        let entry = match benchmark.entry_point() {
+            Some(entry) => entry,
+            None => benchmark.path()?,
+        };
+        if entry.ends_with(".jar") {
+            args.push("-jar".into());
+            args.push(benchmark.absolute_path()?.to_string_lossy().into_owned());
+        } else {
+            args.push(entry.into());
+        }
+        Ok(args)
+    }
+}
+
+impl LangImpl for Jvm {
+    fn results_key(&self) -> String {
+        let classpath = self
+            .classpath
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        results_key(
+            &self.java_path.to_string_lossy(),
+            self.variant.as_deref(),
+            prefixed("classpath", &classpath)
+                .chain(prefixed("flag", &self.flags))
+                .chain(env_settings(&self.env)),
+        )
+    }
+
+    fn check(&self, benchmark: &Benchmark) -> Result<(), K2Error> {
+        check_program(&self.java_path)?;
+        for path in &self.classpath {
+            if !path.exists() {
+                return Err(K2Error::not_found(path));
+            }
+        }
+        // The path (or the entry point) of the benchmark might be the name of a class.
+        let entry = match benchmark.entry_point() {
+            Some(entry) => entry,
+            None => benchmark.path()?,
+        };
+        if entry.ends_with(".jar") {
+            benchmark.check_path()?;
+        }
+        benchmark.check_files()
+    }
+
+    /// The first line of `java -version` (e.g. `openjdk version "17.0.2" 2022-01-18`).
+    fn version(&self) -> Option<String> {
+        program_version(Command::new(&self.java_path).arg("-version"))
+    }
+
+    fn executable(&self) -> Option<PathBuf> {
+        Some(self.java_path.clone())
+    }
+
+    fn invoke(
+        &self,
+        benchmark: &Benchmark,
+        opts: &ProcessOpts,
+    ) -> Result<InvocationOutcome, K2Error> {
+        let profile = perf_profile(opts, &self.profiler_flags);
+        let mut cmd = local_command(&self.java_path, benchmark, profile)?;
+        cmd.args(vm_profiler_args(opts, &self.profiler_flags))
+            .args(self.jvm_args(benchmark)?)
+            .args(benchmark.args())
+            .envs(benchmark_env(&self.env, benchmark));
+        // The heap size is enforced by the JVM itself (see `jvm_args`). Limiting the
+        // address space would prevent the JVM from reserving the memory it needs.
+        let mut opts = opts.clone();
+        opts.limits.heap = None;
+        process::run(&mut cmd, &opts)
+    }
+}
+
+/// A WebAssembly runtime, such as `wasmtime` or `wasmer`.
+///
+/// The path of a benchmark run on a `WasmRuntime` is the path of a `.wasm` module,
+/// which is run using `<runtime> <flags> <module> <args>`. The module is run with
+/// WASI, so the environment of the benchmark (including the number of in-process
+/// iterations) is passed using `--env`.
+pub struct WasmRuntime {
+    /// The path of the runtime executable.
+    runtime_path: PathBuf,
+    /// The flags to pass to the runtime (e.g. `--wasm-features=simd`).
+    flags: Vec<String>,
+    /// The environment of the WebAssembly module.
+    env: HashMap<String, String>,
+    /// The version of the runtime, which is part of the results key.
+    version: String,
+    /// The name of this configuration of the runtime.
+    variant: Option<String>,
+    /// The flags which make the runtime profile a process execution.
+    profiler_flags: Vec<String>,
+}
+
+impl WasmRuntime {
+    /// Create a `WasmRuntime` for the runtime at `path`, whose version is found by
+    /// running `<path> --version`.
+    pub fn new(path: &str) -> Result<WasmRuntime, K2Error> {
+        let output = Command::new(path).arg("--version").output()?;
+        let version = String::from_utf8_lossy(&output.stdout);
+        // Both `wasmtime` and `wasmer` print `<name> <version>`.
+        let version = match version.split_whitespace().nth(1) {
+            Some(version) if output.status.success() => version.to_string(),
+            _ => {
+                return Err(K2Error::InvalidConfig(format!(
+                    "Couldn't find the version of {}",
+                    path
+                )))
+            }
+        };
+        Ok(WasmRuntime {
+            runtime_path: PathBuf::from(path),
+            flags: Default::default(),
+            env: Default::default(),
+            version,
+            variant: None,
+            profiler_flags: Default::default(),
+        })
+    }
+
+    /// Name this configuration of the runtime. The name replaces the hash of the
+    /// flags and environment in the results key.
+    pub fn variant(mut self, name: &str) -> WasmRuntime {
+        self.variant = Some(name.to_string());
+        self
+    }
+
+    /// Add a flag to pass to the runtime, before the path of the module.
+    pub fn flag(mut self, flag: &str) -> WasmRuntime {
+        self.flags.push(flag.to_string());
+        self
+    }
+
+    pub fn env(mut self, k: &str, v: &str) -> WasmRuntime {
+        self.env.insert(k.to_string(), v.to_string());
+        self
+    }
+
+    /// Add a flag which makes the runtime profile the module, in the process
+    /// executions which are profiled (see `ProfileConfig`), such as
+    /// `--profile=jitdump`. `{profile_dir}` is replaced by the directory to write
+    /// the profile to. The runtime then isn't run under `perf record`.
+    pub fn profiler_flag(mut self, flag: &str) -> WasmRuntime {
+        self.profiler_flags.push(flag.to_string());
+        self
+    }
+}
+
+impl LangImpl for WasmRuntime {
+    fn results_key(&self) -> String {
+        results_key(
+            &format!("{}@{}", self.runtime_path.display(), self.version),
+            self.variant.as_deref(),
+            prefixed("flag", &self.flags).chain(env_settings(&self.env)),
+        )
+    }
+
+    fn check(&self, benchmark: &Benchmark) -> Result<(), K2Error> {
+        check_program(&self.runtime_path)?;
+        benchmark.check_path()?;
+        benchmark.check_files()
+    }
+
+    fn version(&self) -> Option<String> {
+        Some(self.version.clone())
+    }
+
+    fn executable(&self) -> Option<PathBuf> {
+        Some(self.runtime_path.clone())
+    }
+
+    fn invoke(
+        &self,
+        benchmark: &Benchmark,
+        opts: &ProcessOpts,
+    ) -> Result<InvocationOutcome, K2Error> {
+        let profile = perf_profile(opts, &self.profiler_flags);
+        let mut cmd = local_command(&self.runtime_path, benchmark, profile)?;
+        cmd.args(&self.flags)
+            .args(vm_profiler_args(opts, &self.profiler_flags));
+        for (k, v) in opts.measure_env() {
+            cmd.arg(format!("--env={}={}", k, v));
+        }
+        for (k, v) in &benchmark_env(&self.env, benchmark) {
+            cmd.arg(format!("--env={}={}", k, v));
+        }
+        cmd.arg(benchmark.absolute_path()?).args(benchmark.args());
+        // Runtimes reserve large amounts of address space for the linear memory of
+        // the module, which a limit on the address space would prevent.
+        let mut opts = opts.clone();
+        opts.limits.heap = None;
+        process::run(&mut cmd, &opts)
+    }
+}
+
+/// A language implementation on a remote host, which is accessed over SSH.
+///
+/// Before each process execution (and before its measurements start), the
+/// benchmark (or the whole directory of a project, see `Benchmark::entry`) and its
+/// input files are copied to `remote_dir` on the host (keeping the relative path of
+/// the benchmark and of its working directory), and the benchmark is then run there
+/// using `interp_path`.
+/// Everything else stays on the machine running k2: the benchmark reports its
+/// measurements on its standard output, which `ssh` forwards.
+///
+/// The resource limits and the CPU affinity of the benchmark are applied on the
+/// host (using `ulimit` and `taskset`). Any measurements taken by k2 itself (such
+/// as the resource usage or the performance counters) describe the local `ssh`
+/// process rather than the benchmark, so the benchmarks should report their own
+/// wall-clock times. If the benchmark times out, only the local `ssh` process is
+/// killed. For the same reason, its process executions are never profiled.
+pub struct SshLangImpl {
+    /// The host to run the benchmarks on (anything `ssh` accepts, such as
+    /// `user@host` or the name of a host in `~/.ssh/config`).
+    host: String,
+    /// The path of the interpreter on the remote host.
+    interp_path: String,
+    /// The directory on the remote host the benchmarks are copied to.
+    remote_dir: String,
+    /// The path of the local `ssh` executable.
+    ssh_path: PathBuf,
+    /// The options to pass to `ssh`.
+    ssh_opts: Vec<String>,
+    /// The environment to use when running the VM on the remote host.
+    env: HashMap<String, String>,
+    /// The name of this configuration of the interpreter.
+    variant: Option<String>,
+}
+
+impl SshLangImpl {
+    pub fn new(host: &str, interp_path: &str) -> SshLangImpl {
+        SshLangImpl {
+            host: host.to_string(),
+            interp_path: interp_path.to_string(),
+            remote_dir: "k2-benchmarks".into(),
+            ssh_path: PathBuf::from("ssh"),
+            ssh_opts: Default::default(),
+            env: Default::default(),
+            variant: None,
+        }
+    }
+
+    /// Name this configuration of the interpreter. The name replaces the hash of
+    /// the environment in the results key.
+    pub fn variant(mut self, name: &str) -> SshLangImpl {
+        self.variant = Some(name.to_string());
+        self
+    }
+
+    /// Set the directory on the remote host the benchmarks are copied to. A relative
+    /// path is relative to the home directory of the remote user. Defaults to
+    /// `k2-benchmarks`.
+    pub fn remote_dir(mut self, dir: &str) -> SshLangImpl {
+        self.remote_dir = dir.to_string();
+        self
+    }
+
+    /// Set the path of the local `ssh` executable.
+    pub fn ssh_path(mut self, path: &str) -> SshLangImpl {
+        self.ssh_path = PathBuf::from(path);
+        self
+    }
+
+    /// Pass `-o <k>=<v>` to `ssh` (e.g. `Port` or `IdentityFile`).
+    pub fn ssh_option(mut self, k: &str, v: &str) -> SshLangImpl {
+        self.ssh_opts.push("-o".into());
+        self.ssh_opts.push(format!("{}={}", k, v));
+        self
+    }
+
+    pub fn env(mut self, k: &str, v: &str) -> SshLangImpl {
+        self.env.insert(k.to_string(), v.to_string());
+        self
+    }
+
+    /// The path `benchmark` is copied to on the remote host.
+    fn remote_path(&self, benchmark: &str) -> String {
+        format!(
+            "{}/{}",
+            self.remote_dir.trim_end_matches('/'),
+            benchmark.trim_start_matches("./").trim_start_matches('/')
+        )
+    }
+
+    /// Create a `ssh` command which runs the shell command `script` on the host.
+    fn ssh(&self, script: &str) -> Command {
+        let mut cmd = Command::new(&self.ssh_path);
+        // Batch mode prevents `ssh` from waiting for a password that will never be
+        // entered.
+        cmd.args(["-o", "BatchMode=yes"])
+            .args(&self.ssh_opts)
+            .arg(&self.host)
+            .arg(script);
+        cmd
+    }
+
+    /// Copy the file at `path` to `remote_path` on the host.
+    fn copy(&self, path: &Path, remote_path: &str) -> Result<(), K2Error> {
+        let remote_dir = Path::new(remote_path)
+            .parent()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let script = format!(
+            "mkdir -p {} && cat > {}",
+            shell_quote(&remote_dir),
+            shell_quote(remote_path)
+        );
+        debug!(
+            "copying {} to {}:{}",
+            path.display(),
+            self.host,
+            remote_path
+        );
+        let output = self.ssh(&script).stdin(File::open(path)?).output()?;
+        if !output.status.success() {
+            return Err(K2Error::Io(io::Error::other(format!(
+                "Failed to copy {} to {}:{}: {}",
+                path.display(),
+                self.host,
+                remote_path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Copy the contents of the directory at `path` to directory `remote_path` on the
+    /// host, using `tar`.
+    fn copy_dir(&self, path: &Path, remote_path: &str) -> Result<(), K2Error> {
+        let script = format!(
+            "mkdir -p {} && tar -C {} -xf -",
+            shell_quote(remote_path),
+            shell_quote(remote_path)
+        );
+        debug!(
+            "copying {} to {}:{}",
+            path.display(),
+            self.host,
+            remote_path
+        );
+        let mut tar = Command::new("tar")
+            .arg("-C")
+            .arg(path)
+            .args(["-cf", "-", "."])
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let archive = tar.stdout.take().map_or_else(Stdio::null, Stdio::from);
+        let output = self.ssh(&script).stdin(archive).output()?;
+        let tar_status = tar.wait()?;
+        if !output.status.success() || !tar_status.success() {
+            return Err(K2Error::Io(io::Error::other(format!(
+                "Failed to copy {} to {}:{}: {}",
+                path.display(),
+                self.host,
+                remote_path,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Build the shell command which runs `benchmark` (copied to `remote_path`) on
+    /// the host, in directory `remote_cwd`.
+    fn remote_script(
+        &self,
+        benchmark: &Benchmark,
+        remote_cwd: &str,
+        remote_path: &str,
+        opts: &ProcessOpts,
+    ) -> String {
+        let remote_cwd = remote_arg(remote_cwd);
+        let mut script = format!("mkdir -p {} && cd {} && ", remote_cwd, remote_cwd);
+        // A percentage of the RAM is converted using the RAM of this machine, rather
+        // than that of the remote machine.
+        let ulimit =
+            |value: Option<u64>| value.map_or("unlimited".to_string(), |value| value.to_string());
+        if let Some(stack) = &opts.limits.stack {
+            script.push_str(&format!("ulimit -s {} && ", ulimit(stack.kib())));
+        }
+        if let Some(heap) = &opts.limits.heap {
+            script.push_str(&format!("ulimit -v {} && ", ulimit(heap.kib())));
+        }
+        if let Some(cpu) = &opts.limits.cpu {
+            script.push_str(&format!("ulimit -t {} && ", ulimit(cpu.secs())));
+        }
+        script.push_str("exec env");
+        for (k, v) in opts.measure_env() {
+            script.push_str(&format!(" {}", shell_quote(&format!("{}={}", k, v))));
+        }
+        for (k, v) in &benchmark_env(&self.env, benchmark) {
+            script.push_str(&format!(" {}", shell_quote(&format!("{}={}", k, v))));
+        }
+        if let Some(cpus) = &opts.cpu_affinity {
+            let cpus = cpus
+                .iter()
+                .map(|cpu| cpu.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            script.push_str(&format!(" taskset -c {}", cpus));
+        }
+        if let Some(node) = opts.numa_node {
+            script.push_str(&format!(" numactl --membind={}", node));
+            if opts.cpu_affinity.is_none() {
+                script.push_str(&format!(" --cpunodebind={}", node));
+            }
+        }
+        for arg in benchmark.wrapper_cmd() {
+            script.push_str(&format!(" {}", shell_quote(arg)));
+        }
+        script.push_str(&format!(
+            " {} {}",
+            shell_quote(&self.interp_path),
+            remote_arg(remote_path)
+        ));
+        for arg in benchmark.args() {
+            script.push_str(&format!(" {}", shell_quote(arg)));
+        }
+        script
     }
 }
 
+impl LangImpl for SshLangImpl {
+    /// The key identifies both the interpreter and the host.
+    fn results_key(&self) -> String {
+        results_key(
+            &format!("{}@{}", self.interp_path, self.host),
+            self.variant.as_deref(),
+            env_settings(&self.env),
+        )
+    }
+
+    fn check(&self, benchmark: &Benchmark) -> Result<(), K2Error> {
+        // The interpreter is on the remote host, so it can only be checked once the
+        // benchmarks run.
+        check_program(&self.ssh_path)?;
+        benchmark.check_path()?;
+        benchmark.check_files()
+    }
+
+    /// The version of the interpreter on the remote host.
+    fn version(&self) -> Option<String> {
+        let script = format!("{} --version", shell_quote(&self.interp_path));
+        program_version(&mut self.ssh(&script))
+    }
+
+    /// Copy the benchmark and its input files to the remote host.
+    fn stage(&self, benchmark: &Benchmark) -> Result<(), K2Error> {
+        // A project is copied as a whole, since the entry point might use any of its
+        // files.
+        let path = benchmark.path()?;
+        match benchmark.entry_point() {
+            Some(_) => self.copy_dir(Path::new(path), &self.remote_path(path))?,
+            None => self.copy(Path::new(path), &self.remote_path(path))?,
+        }
+        let remote_cwd = self.remote_path(&benchmark.working_dir()?.to_string_lossy());
+        for input in benchmark.inputs() {
+            let remote_input = format!(
+                "{}/{}",
+                remote_cwd.trim_end_matches('/'),
+                input_file_name(input)?
+            );
+            self.copy(input, &remote_input)?;
+        }
+        Ok(())
+    }
+
+    fn invoke(
+        &self,
+        benchmark: &Benchmark,
+        opts: &ProcessOpts,
+    ) -> Result<InvocationOutcome, K2Error> {
+        if opts.isolate_network {
+            // Isolating `ssh` would cut it off from the remote host.
+            return Err(K2Error::InvalidConfig(format!(
+                "The network of the benchmarks run on {} can't be isolated",
+                self.host
+            )));
+        }
+        let path = benchmark.path()?;
+        let remote_path = match benchmark.entry_point() {
+            Some(entry) => format!("{}/{}", self.remote_path(path).trim_end_matches('/'), entry),
+            None => self.remote_path(path),
+        };
+        let remote_cwd = self.remote_path(&benchmark.working_dir()?.to_string_lossy());
+        let script = self.remote_script(benchmark, &remote_cwd, &remote_path, opts);
+        let mut cmd = self.ssh(&script);
+        // The limits, the CPU affinity and the NUMA binding are applied on the remote
+        // host (see `remote_script`), rather than to `ssh`.
+        let mut opts = opts.clone();
+        opts.limits = Default::default();
+        opts.cpu_affinity = None;
+        opts.numa_node = None;
+        process::run(&mut cmd, &opts)
+    }
+}
+
+/// Build a results key from `base` (such as the path of the interpreter), followed
+/// by either the name of the `variant`, or a hash of the `settings` which affect
+/// the benchmarks (such as flags and environment variables). This ensures that
+/// differently configured instances of the same language implementation don't
+/// share a key.
+///
+/// The hash is stable across machines and versions of k2, so that an experiment
+/// can be resumed.
+pub fn results_key<I: Iterator<Item = String>>(
+    base: &str,
+    variant: Option<&str>,
+    settings: I,
+) -> String {
+    if let Some(variant) = variant {
+        return format!("{}+{}", base, variant);
+    }
+    let settings = settings.collect::<Vec<_>>();
+    if settings.is_empty() {
+        return base.to_string();
+    }
+    // 64-bit FNV-1a, over the settings separated by NUL bytes.
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for byte in settings.join("\0").bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{}#{:016x}", base, hash)
+}
+
+/// Describe each of `values` as the setting `<name>=<value>`.
+fn prefixed<'v>(name: &'v str, values: &'v [String]) -> impl Iterator<Item = String> + 'v {
+    values
+        .iter()
+        .map(move |value| format!("{}={}", name, value))
+}
+
+/// Describe each variable in `env` as the setting `env=<k>=<v>`, sorted by name.
+fn env_settings(env: &HashMap<String, String>) -> impl Iterator<Item = String> {
+    let mut env = env
+        .iter()
+        .map(|(k, v)| format!("env={}={}", k, v))
+        .collect::<Vec<_>>();
+    env.sort();
+    env.into_iter()
+}
+
+/// Quote `s` so that it is interpreted literally by a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// The environment of `benchmark` on a language implementation which sets the
+/// variables in `env`. The variables set for the benchmark take precedence.
+fn benchmark_env(env: &HashMap<String, String>, benchmark: &Benchmark) -> HashMap<String, String> {
+    let mut env = env.clone();
+    env.extend(
+        benchmark
+            .env_vars()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone())),
+    );
+    env
+}
+
+/// Quote the remote path `path` for the remote shell. A relative path is relative
+/// to the home directory of the remote user, even once the script has changed its
+/// working directory.
+fn remote_arg(path: &str) -> String {
+    if path.starts_with('/') {
+        shell_quote(path)
+    } else {
+        format!("~/{}", shell_quote(path))
+    }
+}
+
+/// Create a command which runs `program` (through the wrapper of `benchmark`, if
+/// any, and under `perf record` if `profile` is set) in the working directory of
+/// `benchmark`.
+fn local_command(
+    program: &Path,
+    benchmark: &Benchmark,
+    profile: Option<&Profile>,
+) -> Result<Command, K2Error> {
+    let argv = match benchmark.wrapper_cmd() {
+        [wrapper, args @ ..] => {
+            let mut argv = vec![program_path(Path::new(wrapper))?.into_os_string()];
+            argv.extend(args.iter().map(OsString::from));
+            argv.push(program_path(program)?.into_os_string());
+            argv
+        }
+        [] => vec![program_path(program)?.into_os_string()],
+    };
+    let mut cmd = match profile {
+        Some(profile) => {
+            let mut cmd = profile.perf_record();
+            cmd.args(&argv);
+            cmd
+        }
+        None => {
+            let mut cmd = Command::new(&argv[0]);
+            cmd.args(&argv[1..]);
+            cmd
+        }
+    };
+    cmd.current_dir(path::absolute(benchmark.working_dir()?)?);
+    Ok(cmd)
+}
+
+/// The profile to run a process execution under `perf record` for: `None` if it
+/// isn't profiled, or if its language implementation profiles it with
+/// `profiler_args` instead.
+fn perf_profile<'a>(opts: &'a ProcessOpts, profiler_args: &[String]) -> Option<&'a Profile> {
+    opts.profile.as_ref().filter(|_| profiler_args.is_empty())
+}
+
+/// The arguments which make a language implementation profile a process execution
+/// (`profiler_args`, with the profile directory filled in), if it is profiled.
+fn vm_profiler_args(opts: &ProcessOpts, profiler_args: &[String]) -> Vec<String> {
+    opts.profile
+        .as_ref()
+        .map_or_else(Vec::new, |profile| profile.profiler_args(profiler_args))
+}
+
+/// The path to run `program` by, which remains valid in the working directory of a
+/// benchmark. Programs named without a directory are looked up in `PATH`.
+fn program_path(program: &Path) -> Result<PathBuf, K2Error> {
+    if program.components().count() > 1 {
+        Ok(path::absolute(program)?)
+    } else {
+        Ok(program.to_path_buf())
+    }
+}
+
+/// The version a program reports when it's run by `cmd` (e.g. `python --version`):
+/// the first line of its standard output or, if that's empty, of its standard
+/// error (where `java -version` prints it). Returns `None` if the program fails.
+fn program_version(cmd: &mut Command) -> Option<String> {
+    let output = cmd.stdin(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    [&output.stdout, &output.stderr]
+        .iter()
+        .filter_map(|out| {
+            String::from_utf8_lossy(out)
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .map(String::from)
+        })
+        .next()
+}
+
+/// Check that `program` (see `program_path`) is an executable file.
+fn check_program(program: &Path) -> Result<(), K2Error> {
+    if program.components().count() <= 1 {
+        util::find_executable(&program.to_string_lossy())?;
+        return Ok(());
+    }
+    let metadata = fs::metadata(program).map_err(|err| K2Error::file(program, err))?;
+    if !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+        return Err(K2Error::InvalidConfig(format!(
+            "{} is not executable",
+            program.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Native code, such as a C or Rust program.
+///
+/// The path of a benchmark run on `GenericNativeCode` is the path of an executable.
+/// The benchmarks are either pre-built, or built by the build command (e.g. `make`)
+/// at the start of the experiment.
 pub struct GenericNativeCode {
     /// The environment to use.
     pub env: HashMap<String, String>,
+    /// The program (followed by its arguments) which builds the benchmarks, or an
+    /// empty list if the benchmarks are pre-built.
+    build_cmd: Vec<String>,
+    /// The compiler used by the build command.
+    compiler: Option<PathBuf>,
+    /// The version of the compiler, which is only asked for once (see `tags`).
+    compiler_version: OnceLock<String>,
+    /// The name of this configuration.
+    variant: Option<String>,
 }
 
 impl GenericNativeCode {
     pub fn new() -> GenericNativeCode {
         GenericNativeCode {
             env: Default::default(),
+            build_cmd: Default::default(),
+            compiler: None,
+            compiler_version: OnceLock::new(),
+            variant: None,
         }
     }
 
+    /// Name this configuration. The name replaces the hash of the build command
+    /// and environment in the results key.
+    pub fn variant(mut self, name: &str) -> GenericNativeCode {
+        self.variant = Some(name.to_string());
+        self
+    }
+
     pub fn env(mut self, k: &str, v: &str) -> GenericNativeCode {
         self.env.insert(k.to_string(), v.to_string());
         self
     }
+
+    /// Set the program which builds the benchmarks, and its arguments (e.g. the
+    /// compiler flags). It is run once, from the current directory, before the
+    /// first benchmark of the experiment.
+    pub fn build_command(mut self, program: &str, args: &[&str]) -> GenericNativeCode {
+        self.build_cmd = std::iter::once(program)
+            .chain(args.iter().copied())
+            .map(str::to_string)
+            .collect();
+        self
+    }
+
+    /// Set the compiler used by the build command. Its version is recorded in the
+    /// `compiler_version` tag of each benchmark.
+    pub fn compiler(mut self, path: &str) -> GenericNativeCode {
+        self.compiler = Some(PathBuf::from(path));
+        self
+    }
+}
+
+impl Default for GenericNativeCode {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl LangImpl for GenericNativeCode {
-    fn results_key(&self) -> &str {
-        unimplemented!("results_key");
+    fn results_key(&self) -> String {
+        let base = match &self.compiler {
+            Some(compiler) => compiler.to_string_lossy(),
+            None => "native".into(),
+        };
+        results_key(
+            &base,
+            self.variant.as_deref(),
+            prefixed("build", &self.build_cmd).chain(env_settings(&self.env)),
+        )
+    }
+
+    fn setup(&self) -> Result<(), K2Error> {
+        let (program, args) = match self.build_cmd.split_first() {
+            Some(cmd) => cmd,
+            None => return Ok(()),
+        };
+        info!("building the benchmarks: {}", self.build_cmd.join(" "));
+        let output = Command::new(program).args(args).output()?;
+        if !output.status.success() {
+            return Err(K2Error::BuildFailed(format!(
+                "{}: {}",
+                self.build_cmd.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
+    fn tags(&self) -> TagStore {
+        let mut tags = TagStore::new();
+        if !self.build_cmd.is_empty() {
+            tags.insert("build_command".into(), self.build_cmd.join(" "));
+        }
+        if let Some(compiler) = &self.compiler {
+            // The tags are asked for by each benchmark, but the compiler only needs
+            // to be run once.
+            let version = self.compiler_version.get_or_init(|| {
+                program_version(Command::new(compiler).arg("--version"))
+                    .unwrap_or_else(|| "unknown".into())
+            });
+            tags.insert("compiler_version".into(), version.clone());
+        }
+        tags
+    }
+
+    fn check(&self, benchmark: &Benchmark) -> Result<(), K2Error> {
+        match self.build_cmd.first() {
+            // The benchmarks don't exist until they are built.
+            Some(program) => check_program(Path::new(program))?,
+            None => benchmark.check_path()?,
+        }
+        benchmark.check_files()
     }
 
-    fn invoke(&self, _benchmark: &Benchmark) {
-        unimplemented!("invoke");
+    fn invoke(
+        &self,
+        benchmark: &Benchmark,
+        opts: &ProcessOpts,
+    ) -> Result<InvocationOutcome, K2Error> {
+        let mut cmd = local_command(
+            &benchmark.absolute_path()?,
+            benchmark,
+            opts.profile.as_ref(),
+        )?;
+        cmd.args(benchmark.args())
+            .envs(benchmark_env(&self.env, benchmark));
+        process::run(&mut cmd, opts)
     }
 }
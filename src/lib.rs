@@ -1,9 +1,52 @@
+pub mod adaptive;
+pub mod analysis;
+pub mod audit;
 pub mod benchmark;
+pub mod boot;
+pub mod cache;
+pub mod calibrate;
+pub mod checks;
+pub mod checksum;
+pub mod clock;
 pub mod config;
+mod config_file;
+pub mod cpufreq;
 pub mod db;
+pub mod dmesg;
+pub mod energy;
 pub mod error;
 pub mod experiment;
+pub mod export;
+pub mod hooks;
+pub mod isolation;
 pub mod lang_impl;
 pub mod limit;
+pub mod load;
+pub mod lock;
+pub mod log;
 pub mod manifest;
+pub mod measure;
+pub mod migrations;
+pub mod network;
+pub mod notify;
+pub mod numa;
+pub mod output;
+pub mod overhead;
+pub mod overrides;
+pub mod perf;
+#[cfg(feature = "plot")]
+pub mod plot;
+pub mod process;
+pub mod profile;
+pub mod registry;
+pub mod report;
+pub mod results;
+pub mod services;
+pub mod shutdown;
+pub mod store;
+pub mod suite;
+pub mod temperature;
+pub mod upload;
 pub mod util;
+
+pub use results::{status, ExperimentStatus, Results};
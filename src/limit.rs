@@ -1,6 +1,529 @@
-#[derive(Debug)]
+use crate::error::K2Error;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use tracing::warn;
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io, mem,
+    os::unix::io::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+/// The cgroup the transient cgroups of the benchmark processes are created in, by
+/// default.
+pub const DEFAULT_CGROUP_PARENT: &str = "/sys/fs/cgroup/k2";
+/// The period of the CPU bandwidth limit of a cgroup (`cpu.max`), in microseconds.
+const CPU_PERIOD: u64 = 100_000;
+/// How many times to try to remove a transient cgroup whose processes were killed,
+/// but haven't exited yet.
+const CGROUP_REMOVE_ATTEMPTS: usize = 100;
+
+/// The number of transient cgroups created by this process so far.
+static NUM_CGROUPS: AtomicUsize = AtomicUsize::new(0);
+
+/// The smallest stack limit: any less, and most programs can't even start.
+pub const MIN_STACK: u64 = 64 * 1024;
+/// The smallest limit on the memory of a benchmark (its heap, address space, or the
+/// memory of its cgroup), in bytes. A smaller limit is most likely a mistake (such
+/// as `KiB` instead of `MiB`).
+pub const MIN_MEMORY: u64 = 16 * 1024 * 1024;
+
+/// A limit on a resource: either a size (such as the size of the stack), or a
+/// time (the CPU time).
+///
+/// Absolute sizes don't port between machines with different amounts of RAM, so a
+/// size can also be a percentage of the RAM of the machine k2 runs on.
+#[derive(Debug, Copy, Clone)]
 pub enum Limit {
+    /// An exact size, in bytes.
+    Bytes(u64),
     KiB(f32),
     MiB(f32),
     GiB(f32),
+    /// A percentage (greater than 0, and at most 100) of the RAM of the machine.
+    PercentOfRam(f64),
+    /// A CPU time, in seconds.
+    Secs(f32),
+    /// No limit at all, whether on a size or a time.
+    Unlimited,
+}
+
+impl Limit {
+    /// Whether this is a time limit rather than a size limit. `Unlimited` is
+    /// neither.
+    pub fn is_time(&self) -> bool {
+        matches!(self, Limit::Secs(_))
+    }
+
+    /// Whether this is a size limit rather than a time limit. `Unlimited` is
+    /// neither.
+    pub fn is_size(&self) -> bool {
+        !self.is_time() && !self.is_unlimited()
+    }
+
+    /// Whether this is `Unlimited`.
+    pub fn is_unlimited(&self) -> bool {
+        matches!(self, Limit::Unlimited)
+    }
+
+    /// The value of this limit in bytes, rounded down, or `None` if it's
+    /// `Unlimited`. A percentage of the RAM is converted using the RAM of this
+    /// machine.
+    ///
+    /// Panics if this is a time limit.
+    pub fn bytes(&self) -> Option<u64> {
+        let bytes = match *self {
+            Limit::Bytes(bytes) => return Some(bytes),
+            Limit::KiB(kib) => f64::from(kib) * 1024.0,
+            Limit::MiB(mib) => f64::from(mib) * 1024.0 * 1024.0,
+            Limit::GiB(gib) => f64::from(gib) * 1024.0 * 1024.0 * 1024.0,
+            Limit::PercentOfRam(percent) => total_ram() as f64 * percent / 100.0,
+            Limit::Secs(_) => panic!("{:?} is not a size limit", self),
+            Limit::Unlimited => return None,
+        };
+        Some(bytes as u64)
+    }
+
+    /// The value of this limit in KiB, rounded down, or `None` if it's `Unlimited`.
+    ///
+    /// Panics if this is a time limit.
+    pub fn kib(&self) -> Option<u64> {
+        self.bytes().map(|bytes| bytes / 1024)
+    }
+
+    /// The value of this limit in seconds, rounded up (so that a limit of less
+    /// than a second doesn't become no time at all), or `None` if it's
+    /// `Unlimited`.
+    ///
+    /// Panics if this is a size limit.
+    pub fn secs(&self) -> Option<u64> {
+        match *self {
+            Limit::Secs(secs) => Some(f64::from(secs).ceil() as u64),
+            Limit::Unlimited => None,
+            _ => panic!("{:?} is not a time limit", self),
+        }
+    }
+
+    /// Check that the value of this limit is valid: a positive time, or a positive
+    /// size of at least `min` bytes (or a percentage of the RAM between 0 and 100).
+    pub fn check(&self, min: u64) -> Result<(), K2Error> {
+        let valid = match *self {
+            Limit::KiB(value) | Limit::MiB(value) | Limit::GiB(value) => {
+                value.is_finite() && value > 0.0
+            }
+            Limit::Secs(secs) => secs.is_finite() && secs > 0.0,
+            Limit::PercentOfRam(percent) => percent > 0.0 && percent <= 100.0,
+            Limit::Bytes(_) | Limit::Unlimited => true,
+        };
+        if !valid {
+            return Err(K2Error::InvalidConfig(format!("Invalid limit: {}", self)));
+        }
+        match self.bytes_if_size() {
+            Some(bytes) if bytes < min => Err(K2Error::InvalidConfig(format!(
+                "Limit {} is less than the minimum of {} bytes",
+                self, min
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The value of this limit in bytes, or `None` if it isn't a size.
+    fn bytes_if_size(&self) -> Option<u64> {
+        if self.is_size() {
+            self.bytes()
+        } else {
+            None
+        }
+    }
+}
+
+/// The total amount of RAM of this machine, in bytes. This only makes
+/// async-signal-safe calls, so that the limits can be converted between `fork` and
+/// `exec`.
+pub fn total_ram() -> u64 {
+    let mut info: libc::sysinfo = unsafe { mem::zeroed() };
+    if unsafe { libc::sysinfo(&mut info) } != 0 {
+        return 0;
+    }
+    (info.totalram as u64).saturating_mul(u64::from(info.mem_unit))
+}
+
+/// Formats the limit the way `from_str` parses it (e.g. `8KiB`, `25%` or `30s`).
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Bytes(bytes) => write!(f, "{}B", bytes),
+            Limit::KiB(kib) => write!(f, "{}KiB", kib),
+            Limit::MiB(mib) => write!(f, "{}MiB", mib),
+            Limit::GiB(gib) => write!(f, "{}GiB", gib),
+            Limit::PercentOfRam(percent) => write!(f, "{}%", percent),
+            Limit::Secs(secs) => write!(f, "{}s", secs),
+            Limit::Unlimited => f.write_str("unlimited"),
+        }
+    }
+}
+
+/// A limit is serialized as a string, such as `"2.5GiB"`.
+impl Serialize for Limit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Limit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Limit, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl FromStr for Limit {
+    type Err = K2Error;
+
+    /// Parse a limit of the form `<value><unit>` (e.g. `8KiB`, `2.5 GiB`, `25%` or
+    /// `30s`), where `<unit>` is one of `B` (a whole number of bytes), `KiB`, `MiB`,
+    /// `GiB`, `%` (of the RAM) or `s` (seconds), or the limit `unlimited`.
+    fn from_str(s: &str) -> Result<Limit, K2Error> {
+        let invalid = || K2Error::InvalidConfig(format!("Invalid limit: {}", s));
+        let s = s.trim();
+        if s == "unlimited" {
+            return Ok(Limit::Unlimited);
+        }
+        if let Some(percent) = s.strip_suffix('%') {
+            let percent = percent.trim().parse::<f64>().map_err(|_| invalid())?;
+            return Ok(Limit::PercentOfRam(percent));
+        }
+        let split = s
+            .find(|c: char| c.is_ascii_alphabetic())
+            .ok_or_else(invalid)?;
+        let (value, unit) = s.split_at(split);
+        let value = value.trim();
+        if unit == "B" {
+            return value
+                .parse::<u64>()
+                .map(Limit::Bytes)
+                .map_err(|_| invalid());
+        }
+        let value = value.parse::<f32>().map_err(|_| invalid())?;
+        match unit {
+            "KiB" => Ok(Limit::KiB(value)),
+            "MiB" => Ok(Limit::MiB(value)),
+            "GiB" => Ok(Limit::GiB(value)),
+            "s" => Ok(Limit::Secs(value)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The resource limits to apply to a benchmark process.
+#[derive(Debug, Clone, Default)]
+pub struct ResourceLimits {
+    /// The maximum size of the stack (`RLIMIT_STACK`).
+    pub stack: Option<Limit>,
+    /// The maximum size of the address space (`RLIMIT_AS`).
+    pub heap: Option<Limit>,
+    /// The maximum CPU time (`RLIMIT_CPU`). Unlike a timeout, this also applies to
+    /// each process the benchmark forks, which inherit the limit.
+    pub cpu: Option<Limit>,
+    /// The limits of the transient cgroup the process is run in, if any.
+    pub cgroup: Option<CgroupLimits>,
+}
+
+impl ResourceLimits {
+    /// Apply the limits to the current process.
+    ///
+    /// This is meant to be called in the child process, between `fork` and `exec`,
+    /// so it must only make async-signal-safe calls.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        if let Some(stack) = self.stack {
+            setrlimit(libc::RLIMIT_STACK, stack)?;
+        }
+        if let Some(heap) = self.heap {
+            setrlimit(libc::RLIMIT_AS, heap)?;
+        }
+        if let Some(cpu) = self.cpu {
+            setrlimit(libc::RLIMIT_CPU, cpu)?;
+        }
+        Ok(())
+    }
+
+    /// Check that each limit has the right kind (the stack and memory limits must be
+    /// sizes, and the CPU limit must be a time, unless they are `Unlimited`), and a
+    /// valid value (the minimum sizes are `MIN_STACK` and `MIN_MEMORY`).
+    pub(crate) fn check(&self) -> Result<(), K2Error> {
+        let limits = [
+            ("stack", self.stack, false, MIN_STACK),
+            ("heap", self.heap, false, MIN_MEMORY),
+            ("CPU", self.cpu, true, 0),
+            (
+                "cgroup memory",
+                self.cgroup.as_ref().and_then(|cgroup| cgroup.memory_max),
+                false,
+                MIN_MEMORY,
+            ),
+        ];
+        for (name, limit, is_time, min) in &limits {
+            if let Some(limit) = limit {
+                if !limit.is_unlimited() && limit.is_time() != *is_time {
+                    return Err(K2Error::InvalidConfig(format!(
+                        "Invalid {} limit: {}",
+                        name, limit
+                    )));
+                }
+                limit.check(*min)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Set both the soft and hard limit of `resource` to `lim`.
+///
+/// The process is sent `SIGXCPU` when it exceeds a CPU time limit, which it could
+/// ignore, so the hard CPU time limit (at which it is killed) is one second later.
+fn setrlimit(resource: libc::__rlimit_resource_t, lim: Limit) -> io::Result<()> {
+    let value =
+        |value: Option<u64>| value.map_or(libc::RLIM_INFINITY, |value| value as libc::rlim_t);
+    let (cur, max) = if lim.is_time() {
+        (value(lim.secs()), value(lim.secs().map(|secs| secs + 1)))
+    } else {
+        (value(lim.bytes()), value(lim.bytes()))
+    };
+    let rlim = libc::rlimit {
+        rlim_cur: cur,
+        rlim_max: max,
+    };
+    if unsafe { libc::setrlimit(resource, &rlim) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// The limits of the transient cgroup (v2) each benchmark process is run in.
+///
+/// Unlike the limits set with `setrlimit` (see `ResourceLimits`), which apply to
+/// each process separately, the limits of a cgroup apply to the benchmark process
+/// and all its descendants as a whole (e.g. a VM and the compiler workers it
+/// forks). If the memory controller is available, the peak memory usage of the
+/// cgroup is recorded as `cgroup:memory_peak` (see `measure::RusageCollector`).
+///
+/// The transient cgroups are created in `parent`, which is created if it doesn't
+/// exist. The controllers the limits require are enabled in `parent` and its
+/// ancestors, which usually requires k2 to run as root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CgroupLimits {
+    /// The cgroup the transient cgroups are created in.
+    pub parent: PathBuf,
+    /// The maximum amount of memory (`memory.max`).
+    pub memory_max: Option<Limit>,
+    /// The maximum CPU bandwidth, as a number of CPUs (`cpu.max`). For example,
+    /// `1.5` allows the processes to use one and a half CPUs' worth of CPU time.
+    pub cpus: Option<f64>,
+    /// The maximum number of processes (`pids.max`).
+    pub pids_max: Option<u64>,
+}
+
+impl Default for CgroupLimits {
+    fn default() -> Self {
+        CgroupLimits::new()
+    }
+}
+
+impl CgroupLimits {
+    /// Run each benchmark process in a transient cgroup in `DEFAULT_CGROUP_PARENT`,
+    /// without any limits.
+    pub fn new() -> CgroupLimits {
+        CgroupLimits {
+            parent: PathBuf::from(DEFAULT_CGROUP_PARENT),
+            memory_max: None,
+            cpus: None,
+            pids_max: None,
+        }
+    }
+
+    pub fn parent<P: AsRef<Path>>(mut self, parent: P) -> Self {
+        self.parent = parent.as_ref().to_path_buf();
+        self
+    }
+
+    pub fn memory_max(mut self, memory_max: Limit) -> Self {
+        self.memory_max = Some(memory_max);
+        self
+    }
+
+    pub fn cpus(mut self, cpus: f64) -> Self {
+        self.cpus = Some(cpus);
+        self
+    }
+
+    pub fn pids_max(mut self, pids_max: u64) -> Self {
+        self.pids_max = Some(pids_max);
+        self
+    }
+
+    /// The controllers the limits require.
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() {
+            controllers.push("memory");
+        }
+        if self.cpus.is_some() {
+            controllers.push("cpu");
+        }
+        if self.pids_max.is_some() {
+            controllers.push("pids");
+        }
+        controllers
+    }
+}
+
+/// A transient cgroup, which is removed (along with any processes left in it) when
+/// dropped.
+pub(crate) struct Cgroup {
+    path: PathBuf,
+    /// The `cgroup.procs` file of the cgroup, which processes join it through.
+    procs: File,
+}
+
+impl Cgroup {
+    /// Create a transient cgroup in `limits.parent`, and apply `limits` to it.
+    pub fn create(limits: &CgroupLimits) -> Result<Cgroup, K2Error> {
+        fs::create_dir_all(&limits.parent)?;
+        enable_controllers(&limits.parent, &limits.controllers())?;
+        // The memory controller reports the peak memory usage, even if the memory
+        // isn't limited. It might not be available, though.
+        let _ = enable_controllers(&limits.parent, &["memory"]);
+        let path = limits.parent.join(format!(
+            "k2-{}-{}",
+            process::id(),
+            NUM_CGROUPS.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir(&path)?;
+        let procs = OpenOptions::new()
+            .write(true)
+            .open(path.join("cgroup.procs"))?;
+        let cgroup = Cgroup { path, procs };
+        if let Some(memory_max) = limits.memory_max {
+            let memory_max = memory_max
+                .bytes()
+                .map_or("max".to_string(), |bytes| bytes.to_string());
+            cgroup.write("memory.max", &memory_max)?;
+        }
+        if let Some(cpus) = limits.cpus {
+            let quota = (cpus * CPU_PERIOD as f64) as u64;
+            cgroup.write("cpu.max", &format!("{} {}", quota, CPU_PERIOD))?;
+        }
+        if let Some(pids_max) = limits.pids_max {
+            cgroup.write("pids.max", &pids_max.to_string())?;
+        }
+        Ok(cgroup)
+    }
+
+    /// The file descriptor to pass to `join_cgroup`.
+    pub fn procs_fd(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+
+    /// The peak memory usage of the processes in the cgroup, in bytes, or `None`
+    /// if the kernel doesn't report it (`memory.peak` requires Linux 5.19).
+    pub fn memory_peak(&self) -> Option<u64> {
+        fs::read_to_string(self.path.join("memory.peak"))
+            .ok()
+            .and_then(|peak| peak.trim().parse().ok())
+    }
+
+    /// Kill the processes in the cgroup (which requires Linux 5.14).
+    pub fn kill(&self) {
+        let _ = fs::write(self.path.join("cgroup.kill"), "1");
+    }
+
+    fn write(&self, file: &str, value: &str) -> Result<(), K2Error> {
+        fs::write(self.path.join(file), value).map_err(|e| {
+            K2Error::InvalidConfig(format!(
+                "Failed to set {} of {} to {}: {}",
+                file,
+                self.path.display(),
+                value,
+                e
+            ))
+        })
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Kill any processes left behind by the benchmark (e.g. if it timed out).
+        // A cgroup can only be removed once all its processes have exited.
+        self.kill();
+        for _ in 0..CGROUP_REMOVE_ATTEMPTS {
+            if fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        warn!("failed to remove cgroup {}", self.path.display());
+    }
+}
+
+/// Move the current process into the cgroup whose `cgroup.procs` file is open as
+/// `procs_fd` (see `Cgroup::procs_fd`).
+///
+/// This is meant to be called in the child process, between `fork` and `exec`, so
+/// it must only make async-signal-safe calls.
+pub(crate) fn join_cgroup(procs_fd: RawFd) -> io::Result<()> {
+    // Writing 0 moves the process which writes it.
+    if unsafe { libc::write(procs_fd, b"0".as_ptr() as *const libc::c_void, 1) } == 1 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Enable `controllers` for the children of cgroup `dir`. A controller can only be
+/// enabled if it's enabled in the parent of `dir`, so it's enabled in each
+/// ancestor of `dir` first (up to the root of the cgroup hierarchy).
+fn enable_controllers(dir: &Path, controllers: &[&str]) -> Result<(), K2Error> {
+    let mut ancestors = dir
+        .ancestors()
+        .take_while(|dir| dir.join("cgroup.controllers").exists())
+        .collect::<Vec<_>>();
+    if ancestors.is_empty() {
+        return Err(K2Error::InvalidConfig(format!(
+            "{} isn't in a cgroup v2 hierarchy",
+            dir.display()
+        )));
+    }
+    ancestors.reverse();
+    for dir in ancestors {
+        let enabled = fs::read_to_string(dir.join("cgroup.subtree_control"))?;
+        let enabled = enabled.split_whitespace().collect::<Vec<_>>();
+        for controller in controllers {
+            if enabled.contains(controller) {
+                continue;
+            }
+            fs::write(
+                dir.join("cgroup.subtree_control"),
+                format!("+{}", controller),
+            )
+            .map_err(|e| {
+                K2Error::InvalidConfig(format!(
+                    "Failed to enable the {} controller in {}: {}",
+                    controller,
+                    dir.display(),
+                    e
+                ))
+            })?;
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,271 @@
+//! Monitoring the background load of the machine while the benchmarks run.
+//!
+//! Shared machines sometimes run other work (such as cron jobs) in the middle of
+//! an experiment, which slows the benchmarks down. While each job runs, the load
+//! average and the CPU usage of every process other than k2 and the processes it
+//! started are sampled once a second. The samples, and the processes which used the
+//! most CPU time during each of them, are recorded in the `load_sample` and
+//! `load_process` tables of the results database (see `Results::background_load`).
+//! A job during which the other processes used more CPU time than a threshold is
+//! reported, and can be run again.
+
+use crate::error::K2Error;
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// The file which contains the load averages of the machine.
+const LOADAVG: &str = "/proc/loadavg";
+/// How often to sample the load of the machine.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// The shortest interval a sample can cover. The CPU time of each process is only
+/// counted in clock ticks, so the CPU usage over a shorter interval is too
+/// imprecise to be recorded.
+const MIN_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How to monitor the background load of the machine.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoadMonitorConfig {
+    /// The number of CPUs the other processes can use (on average, over a sample)
+    /// before the job is reported.
+    pub threshold: f64,
+    /// The number of processes to record with each sample, starting with the one
+    /// which used the most CPU time.
+    #[serde(default = "default_top")]
+    pub top: usize,
+    /// Fail the jobs during which the threshold was exceeded (see
+    /// `K2Error::BackgroundLoad`), so that they are run again once the other jobs
+    /// have run, if `max_retries` allows.
+    #[serde(default)]
+    pub rerun: bool,
+}
+
+impl LoadMonitorConfig {
+    /// Report the jobs during which the other processes used more than `threshold`
+    /// CPUs, and record the 5 processes which used the most CPU time with each
+    /// sample.
+    pub fn new(threshold: f64) -> LoadMonitorConfig {
+        LoadMonitorConfig {
+            threshold,
+            top: default_top(),
+            rerun: false,
+        }
+    }
+
+    /// Record the `top` processes which used the most CPU time with each sample.
+    pub fn top(mut self, top: usize) -> Self {
+        self.top = top;
+        self
+    }
+
+    /// Set whether to fail (and rerun) the jobs during which the threshold was
+    /// exceeded.
+    pub fn rerun(mut self, rerun: bool) -> Self {
+        self.rerun = rerun;
+        self
+    }
+
+    /// Start sampling the load of the machine in the background.
+    pub(crate) fn start(&self) -> Result<LoadMonitor, K2Error> {
+        let top = self.top;
+        let mut before = snapshot()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_sampling = Arc::clone(&stop);
+        let handle = thread::spawn(move || {
+            let mut samples = Vec::new();
+            let mut taken_at = Instant::now();
+            loop {
+                thread::park_timeout(SAMPLE_INTERVAL.saturating_sub(taken_at.elapsed()));
+                let stopping = stop_sampling.load(Ordering::SeqCst);
+                let interval = taken_at.elapsed();
+                if interval >= SAMPLE_INTERVAL || (stopping && interval >= MIN_SAMPLE_INTERVAL) {
+                    let after = snapshot()?;
+                    taken_at = Instant::now();
+                    samples.push(sample(&before, &after, interval, top)?);
+                    before = after;
+                }
+                if stopping {
+                    return Ok(samples);
+                }
+            }
+        });
+        Ok(LoadMonitor { stop, handle })
+    }
+
+    /// The highest number of CPUs the other processes used in any of `samples`, if
+    /// it exceeds the threshold.
+    pub(crate) fn exceeded(&self, samples: &[LoadSample]) -> Option<f64> {
+        let max = samples
+            .iter()
+            .map(|sample| sample.background)
+            .fold(0.0, f64::max);
+        Some(max).filter(|&max| max > self.threshold)
+    }
+}
+
+fn default_top() -> usize {
+    5
+}
+
+/// The load of the machine over an interval (normally `SAMPLE_INTERVAL`).
+#[derive(Debug, Clone)]
+pub struct LoadSample {
+    /// The 1-minute load average at the end of the interval. The benchmark itself
+    /// counts towards it.
+    pub load_avg: f64,
+    /// The number of CPUs used, on average over the interval, by the processes
+    /// other than k2 and the processes it started.
+    pub background: f64,
+    /// The processes which used the most CPU time over the interval, starting with
+    /// the one which used the most.
+    pub processes: Vec<BackgroundProcess>,
+}
+
+/// A process other than k2 and the processes it started.
+#[derive(Debug, Clone)]
+pub struct BackgroundProcess {
+    pub pid: u32,
+    /// The name of the executable of the process.
+    pub command: String,
+    /// The number of CPUs the process used, on average over the interval.
+    pub cpus: f64,
+}
+
+/// Samples the load of the machine in a background thread, until it is stopped.
+#[derive(Debug)]
+pub(crate) struct LoadMonitor {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<Result<Vec<LoadSample>, K2Error>>,
+}
+
+impl LoadMonitor {
+    /// Stop sampling, and return the samples taken since the monitor was started.
+    pub(crate) fn stop(self) -> Result<Vec<LoadSample>, K2Error> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+        self.handle
+            .join()
+            .map_err(|_| K2Error::SensorError("The load monitor panicked".into()))?
+    }
+}
+
+/// The state of a process, as reported by `/proc/<pid>/stat`.
+#[derive(Debug)]
+struct ProcStat {
+    ppid: u32,
+    command: String,
+    /// The CPU time the process used so far (in user and kernel mode), in clock
+    /// ticks.
+    ticks: u64,
+}
+
+/// Read the state of all the processes of the machine, indexed by pid.
+fn snapshot() -> Result<HashMap<u32, ProcStat>, K2Error> {
+    let mut procs = HashMap::new();
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = match entry.file_name().to_str().and_then(|pid| pid.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // The process might have exited since the directory was listed.
+        let stat = match fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        if let Some(stat) = parse_stat(&stat) {
+            procs.insert(pid, stat);
+        }
+    }
+    Ok(procs)
+}
+
+/// Parse the contents of `/proc/<pid>/stat`. The name of the process is in
+/// parentheses, and may itself contain spaces and parentheses: the parent pid is
+/// the second field after it, and the user and system times are the 12th and 13th.
+fn parse_stat(stat: &str) -> Option<ProcStat> {
+    let (head, fields) = stat.rsplit_once(')')?;
+    let (_, command) = head.split_once('(')?;
+    let fields = fields.split_whitespace().collect::<Vec<_>>();
+    let utime = fields.get(11)?.parse::<u64>().ok()?;
+    let stime = fields.get(12)?.parse::<u64>().ok()?;
+    Some(ProcStat {
+        ppid: fields.get(1)?.parse().ok()?,
+        command: command.to_string(),
+        ticks: utime + stime,
+    })
+}
+
+/// Compute the load of the machine between the `before` and `after` snapshots,
+/// which were taken `interval` apart.
+fn sample(
+    before: &HashMap<u32, ProcStat>,
+    after: &HashMap<u32, ProcStat>,
+    interval: Duration,
+    top: usize,
+) -> Result<LoadSample, K2Error> {
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+    let k2 = own_processes(after);
+    let mut processes = after
+        .iter()
+        .filter(|(pid, _)| !k2.contains(pid))
+        .filter_map(|(&pid, stat)| {
+            // A process which isn't in `before` was started during the interval.
+            let ticks_before = before.get(&pid).map_or(0, |before| before.ticks);
+            let ticks = stat.ticks.saturating_sub(ticks_before);
+            if ticks == 0 {
+                return None;
+            }
+            Some(BackgroundProcess {
+                pid,
+                command: stat.command.clone(),
+                cpus: ticks as f64 / ticks_per_sec / interval.as_secs_f64(),
+            })
+        })
+        .collect::<Vec<_>>();
+    processes.sort_by(|a, b| b.cpus.total_cmp(&a.cpus));
+    let background = processes.iter().map(|process| process.cpus).sum();
+    processes.truncate(top);
+    Ok(LoadSample {
+        load_avg: read_load_avg()?,
+        background,
+        processes,
+    })
+}
+
+/// The pids of k2 and of all the processes it started (such as the benchmark).
+fn own_processes(procs: &HashMap<u32, ProcStat>) -> HashSet<u32> {
+    let mut children = HashMap::<_, Vec<_>>::new();
+    for (&pid, stat) in procs {
+        children.entry(stat.ppid).or_default().push(pid);
+    }
+    let mut own = HashSet::new();
+    let mut pending = vec![std::process::id()];
+    while let Some(pid) = pending.pop() {
+        if own.insert(pid) {
+            pending.extend(children.get(&pid).into_iter().flatten());
+        }
+    }
+    own
+}
+
+/// Read the 1-minute load average of the machine.
+fn read_load_avg() -> Result<f64, K2Error> {
+    let loadavg = fs::read_to_string(LOADAVG)?;
+    loadavg
+        .split_whitespace()
+        .next()
+        .and_then(|load_avg| load_avg.parse().ok())
+        .ok_or_else(|| K2Error::SensorError(format!("Invalid reading from {}", LOADAVG)))
+}
@@ -0,0 +1,66 @@
+//! Making sure that only one instance of k2 runs an experiment at a time.
+//!
+//! Two instances of k2 which run the same experiment (e.g. one started by a stale
+//! cron entry, and one started by hand) would both run the next job of the
+//! manifest, and corrupt it. While k2 runs an experiment, it holds an exclusive
+//! lock (see `flock(2)`) on the `LOCK_FILE` of its results directory, which records
+//! the process ID of the instance which holds it, and any other instance fails
+//! with `K2Error::ExperimentLocked`.
+//!
+//! The lock is released when k2 exits, or crashes, or restarts itself between jobs
+//! (the lock file isn't inherited across `exec`), so a stale lock file never needs
+//! to be removed by hand.
+
+use crate::error::K2Error;
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// The lock file (relative to the results directory).
+pub const LOCK_FILE: &str = "k2.lock";
+
+/// An exclusive lock on a results directory, which is held until it is dropped.
+#[derive(Debug)]
+pub(crate) struct ExperimentLock {
+    _file: File,
+}
+
+impl ExperimentLock {
+    /// Lock `results_dir`, or fail with `K2Error::ExperimentLocked` if another
+    /// process holds the lock.
+    pub(crate) fn acquire(results_dir: &Path) -> Result<ExperimentLock, K2Error> {
+        let path = results_dir.join(LOCK_FILE);
+        let file_err = |err| K2Error::file(&path, err);
+        // The file isn't truncated until the lock is held, so the process ID of the
+        // instance which holds it can be read.
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(file_err)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                return Err(file_err(err));
+            }
+            let mut pid = String::new();
+            file.read_to_string(&mut pid).map_err(file_err)?;
+            return Err(K2Error::ExperimentLocked {
+                results_dir: PathBuf::from(results_dir),
+                pid: pid.trim().parse().ok(),
+            });
+        }
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| writeln!(file, "{}", process::id()))
+            .map_err(file_err)?;
+        Ok(ExperimentLock { _file: file })
+    }
+}
@@ -0,0 +1,193 @@
+//! The experiment log.
+//!
+//! k2 reports what it does through `tracing`. Unless the program which runs the
+//! experiment has installed a `tracing` subscriber of its own, `init` installs one
+//! which prints the notable events to stderr (as k2 always has), and records every
+//! event, including the debug events, in the `k2.log` file of the results directory,
+//! so that what happened during a long experiment can be pieced together once it is
+//! over. The log is appended to each time the experiment is resumed; once it grows
+//! larger than `MAX_LOG_SIZE`, it is rotated: `k2.log` becomes `k2.log.1`,
+//! `k2.log.1` becomes `k2.log.2`, and so on, and the oldest file is dropped.
+//!
+//! The events printed to stderr can be chosen by setting the `K2_LOG` environment
+//! variable to a level, such as `debug` (the default is `info`).
+
+use crate::error::K2Error;
+
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt::{
+        self,
+        format::{self, FormatEvent, FormatFields},
+        FmtContext,
+    },
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    Layer, Registry,
+};
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, Once},
+};
+
+/// The name of the log file in the results directory.
+pub const LOG_FILE: &str = "k2.log";
+/// The size the log file can grow to before it is rotated.
+const MAX_LOG_SIZE: u64 = 16 * 1024 * 1024;
+/// The number of rotated log files to keep, in addition to `LOG_FILE`.
+const MAX_ROTATED: usize = 4;
+/// The most that is logged before the results directory is created, after which the
+/// events are dropped until it is.
+const MAX_PENDING: usize = 1024 * 1024;
+/// The environment variable which sets the level of the events printed to stderr.
+const LOG_LEVEL_VAR: &str = "K2_LOG";
+
+/// The log file of the experiment which is running.
+static LOG: Mutex<ExperimentLog> = Mutex::new(ExperimentLog {
+    path: None,
+    file: None,
+    size: 0,
+    pending: Vec::new(),
+});
+
+/// Install the subscriber which prints k2's events to stderr and records them in
+/// the experiment log, unless a subscriber is already installed.
+pub fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        let level = env::var(LOG_LEVEL_VAR)
+            .ok()
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(LevelFilter::INFO);
+        let stderr = fmt::layer()
+            .event_format(StderrFormat)
+            .with_writer(io::stderr)
+            .with_filter(level);
+        let file = fmt::layer()
+            .with_writer(|| LogWriter)
+            .with_filter(LevelFilter::DEBUG);
+        // The program might want the events to go somewhere else, in which case it
+        // records them itself.
+        let _ =
+            tracing::subscriber::set_global_default(Registry::default().with(stderr).with(file));
+    });
+}
+
+/// Record the experiment log in `results_dir` (which must exist) from now on. The
+/// events logged before this is called are written to the log first.
+pub(crate) fn open(results_dir: &Path) -> Result<(), K2Error> {
+    let mut log = LOG.lock().unwrap_or_else(|err| err.into_inner());
+    let path = results_dir.join(LOG_FILE);
+    log.open(path.clone())
+        .map_err(|err| K2Error::file(path, err))?;
+    Ok(())
+}
+
+/// The log file, or, until it is opened, the events to write to it.
+struct ExperimentLog {
+    path: Option<PathBuf>,
+    file: Option<File>,
+    /// The size of the log file.
+    size: u64,
+    pending: Vec<u8>,
+}
+
+impl ExperimentLog {
+    fn open(&mut self, path: PathBuf) -> io::Result<()> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        self.path = Some(path);
+        let pending = std::mem::take(&mut self.pending);
+        self.write_all(&pending)
+    }
+
+    /// Move the log file out of the way (see the module documentation), and start
+    /// a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = match self.path.take() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let rotated = |idx: usize| PathBuf::from(format!("{}.{}", path.display(), idx));
+        for idx in (1..MAX_ROTATED).rev() {
+            // Fewer files might have been rotated so far.
+            let _ = fs::rename(rotated(idx), rotated(idx + 1));
+        }
+        fs::rename(&path, rotated(1))?;
+        self.open(path)
+    }
+}
+
+impl Write for ExperimentLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() {
+            if self.pending.len() < MAX_PENDING {
+                self.pending.extend_from_slice(buf);
+            }
+            return Ok(buf.len());
+        }
+        if self.size > 0 && self.size + buf.len() as u64 > MAX_LOG_SIZE {
+            self.rotate()?;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return Ok(buf.len()),
+        };
+        file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes each event to the experiment log.
+struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A thread which panicked while logging can't have left the log in a state
+        // which matters.
+        LOG.lock().unwrap_or_else(|err| err.into_inner()).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        LOG.lock().unwrap_or_else(|err| err.into_inner()).flush()
+    }
+}
+
+/// Prints events the way k2 has always reported problems: `k2: warning: ...`.
+struct StderrFormat;
+
+impl<S, N> FormatEvent<S, N> for StderrFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: format::Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        write!(writer, "k2: ")?;
+        match *event.metadata().level() {
+            Level::ERROR => write!(writer, "error: ")?,
+            Level::WARN => write!(writer, "warning: ")?,
+            _ => (),
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
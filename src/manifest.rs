@@ -1,18 +1,158 @@
-use crate::{benchmark::Benchmark, config::Config, db::K2Store, util::num_digits};
+use crate::{
+    benchmark::Benchmark, config::Config, db::K2Store, error::K2Error, measure::Measurements,
+    util::num_digits,
+};
 
-use rand::{self, seq::SliceRandom};
+use rand::{self, rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
 
 use std::{
+    cmp::Reverse,
+    collections::HashSet,
     fs::{self, File, OpenOptions},
     io::{BufRead, BufReader, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
 };
 
-#[derive(Debug, Copy, Clone)]
-pub(crate) enum JobStatus {
+/// The status of a job. It is serialized as its `name`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
     Outstanding,
     Done,
     Error,
+    /// The job was killed because it exceeded its timeout.
+    TimedOut,
+    /// The benchmark didn't print the output it was expected to (see
+    /// `Benchmark::expect_stdout`).
+    WrongAnswer,
+    /// The job was started `Config::max_attempts` times without finishing, so k2
+    /// gave up on it.
+    TooManyAttempts,
+    /// The job wasn't run, because the results of its benchmark were already
+    /// precise enough (see `adaptive`).
+    Unneeded,
+}
+
+impl JobStatus {
+    /// Convert the representation of a status in the database back to a
+    /// `JobStatus`.
+    pub fn from_i64(status: i64) -> Option<JobStatus> {
+        match status {
+            0 => Some(JobStatus::Outstanding),
+            1 => Some(JobStatus::Done),
+            2 => Some(JobStatus::Error),
+            3 => Some(JobStatus::TimedOut),
+            4 => Some(JobStatus::WrongAnswer),
+            5 => Some(JobStatus::TooManyAttempts),
+            6 => Some(JobStatus::Unneeded),
+            _ => None,
+        }
+    }
+
+    /// The name of the status, as used in exported results.
+    pub fn name(self) -> &'static str {
+        match self {
+            JobStatus::Outstanding => "outstanding",
+            JobStatus::Done => "done",
+            JobStatus::Error => "error",
+            JobStatus::TimedOut => "timed_out",
+            JobStatus::WrongAnswer => "wrong_answer",
+            JobStatus::TooManyAttempts => "too_many_attempts",
+            JobStatus::Unneeded => "unneeded",
+        }
+    }
+}
+
+/// The order in which the jobs of an experiment are run.
+///
+/// Job `i` is process execution `i / n` of benchmark `i % n`, where `n` is the
+/// number of benchmarks. A *round* consists of one process execution of each
+/// benchmark. Running the jobs in rounds ensures that the process executions of a
+/// benchmark are spread over the whole experiment, so that they aren't all
+/// affected by the same drift (e.g. the time of day).
+///
+/// If the benchmarks have different priorities (see `Benchmark::priority`), all
+/// the jobs of the benchmarks with a higher priority run before those of the
+/// benchmarks with a lower one. The jobs of the benchmarks with the same priority
+/// keep the order given by the strategy.
+#[derive(Debug, Copy, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderingStrategy {
+    /// Shuffle all the jobs.
+    #[default]
+    Shuffle,
+    /// Run the jobs in rounds, and shuffle the jobs of each round.
+    Blocked,
+    /// Run the jobs in rounds, where each round is a rotation of the same random
+    /// permutation of the benchmarks (a cyclic Latin square). Each benchmark runs at
+    /// each position within a round equally often, and always after the same
+    /// benchmark.
+    LatinSquare,
+    /// Run the jobs in rounds, where each round runs the benchmarks in the order
+    /// they were added to the experiment.
+    Interleaved,
+}
+
+impl OrderingStrategy {
+    /// Order the `pexecs` process executions of each of `num_benchmarks`
+    /// benchmarks.
+    fn order<R: Rng>(self, num_benchmarks: usize, pexecs: usize, rng: &mut R) -> Vec<usize> {
+        let num_jobs = num_benchmarks * pexecs;
+        let round = |r: usize, benchmarks: &[usize]| {
+            benchmarks
+                .iter()
+                .map(|b| r * num_benchmarks + b)
+                .collect::<Vec<_>>()
+        };
+        let benchmarks = (0..num_benchmarks).collect::<Vec<_>>();
+        match self {
+            OrderingStrategy::Shuffle => {
+                let mut ordering = (0..num_jobs).collect::<Vec<_>>();
+                ordering.shuffle(rng);
+                ordering
+            }
+            OrderingStrategy::Blocked => (0..pexecs)
+                .flat_map(|r| {
+                    let mut jobs = round(r, &benchmarks);
+                    jobs.shuffle(rng);
+                    jobs
+                })
+                .collect(),
+            OrderingStrategy::LatinSquare => {
+                let mut permutation = benchmarks;
+                permutation.shuffle(rng);
+                (0..pexecs)
+                    .flat_map(|r| {
+                        let mut rotation = permutation.clone();
+                        if num_benchmarks > 0 {
+                            rotation.rotate_left(r % num_benchmarks);
+                        }
+                        round(r, &rotation)
+                    })
+                    .collect()
+            }
+            OrderingStrategy::Interleaved => {
+                (0..pexecs).flat_map(|r| round(r, &benchmarks)).collect()
+            }
+        }
+    }
+
+    /// Order the `pexecs` process executions of each benchmark, where
+    /// `priorities[b]` is the priority of benchmark `b`.
+    fn order_by_priority<R: Rng>(
+        self,
+        priorities: &[i32],
+        pexecs: usize,
+        rng: &mut R,
+    ) -> Vec<usize> {
+        let num_benchmarks = priorities.len();
+        let mut ordering = self.order(num_benchmarks, pexecs, rng);
+        // The sort is stable, so the jobs with the same priority stay in order.
+        ordering.sort_by_key(|job| Reverse(priorities[job % num_benchmarks]));
+        ordering
+    }
 }
 
 #[derive(Debug)]
@@ -28,45 +168,91 @@ pub(crate) struct Job {
 }
 
 impl Job {
-    pub fn new(id: usize, bench: &Benchmark) -> Job {
-        Job {
+    pub fn new(id: usize, bench: &Benchmark) -> Result<Job, K2Error> {
+        Ok(Job {
             id,
-            key: bench.results_key(),
+            key: bench.results_key()?,
             status: JobStatus::Outstanding,
-        }
+        })
     }
 }
 
+/// The `version` field of the manifest header. This is the first field of the
+/// header, and was introduced in version 2 of the format: a header without it is a
+/// version 1 header.
+const VERSION: &str = "version";
+/// The current version of the manifest header format.
+const MANIFEST_VERSION: usize = 4;
 /// The `num_reboots` field of the manifest header.
 const NUM_REBOOTS: &str = "num_reboots";
-/// The `num_reboots` field has a fixed width of 8 bytes.
-const NUM_REBOOTS_BYTES: usize = 8;
 /// The `next_idx` field of the manifest header.
 const NEXT_IDX: &str = "next_idx";
-/// The `next_idx` field has a fixed width of 4 bytes.
-const NEXT_IDX_BYTES: usize = 4;
+/// The prefix of the `slot<n>_next_idx` fields of the manifest header, which were
+/// introduced in version 4 of the format. The header of an experiment whose jobs
+/// run in parallel slots (see `Config::slots`) has one such field for each slot.
+const SLOT_PREFIX: &str = "slot";
+/// The suffix of the `slot<n>_next_idx` fields of the manifest header.
+const SLOT_SUFFIX: &str = "_next_idx";
+/// The `seed` field of the manifest header, which was introduced in version 3 of
+/// the format. The field is missing if the header was migrated from an older
+/// version.
+const SEED: &str = "seed";
 /// The `ordering` field of the manifest header.
 const ORDERING: &str = "ordering";
 
+/// The widths of the `num_reboots` and `next_idx` fields in version `version` of
+/// the manifest header format.
+///
+/// The fields have a fixed width, so that they can be updated in place. In version
+/// 1, they were 8 and 4 bytes wide respectively, which limited experiments to
+/// 10,000 jobs. Since version 2, they are wide enough for any `u64`.
+fn field_widths(version: usize) -> Result<(usize, usize), K2Error> {
+    match version {
+        1 => Ok((8, 4)),
+        2..=4 => Ok((20, 20)),
+        _ => Err(K2Error::ManifestCorrupted(format!(
+            "Unsupported {}: {}",
+            VERSION, version
+        ))),
+    }
+}
+
 /// The type of an offset in the manifest header file.
 type Offset = u64;
 
-/// Format `value` as a string of `width` bytes, padding with zeroes if necessary.
-///
-/// # Panics
+/// The name of the `slot<n>_next_idx` field of slot `slot`.
+fn slot_field(slot: usize) -> String {
+    format!("{}{}{}", SLOT_PREFIX, slot, SLOT_SUFFIX)
+}
+
+/// The slot whose `slot<n>_next_idx` field is `key`, or `None` if `key` isn't such
+/// a field.
+fn field_slot(key: &str) -> Option<usize> {
+    key.strip_prefix(SLOT_PREFIX)?
+        .strip_suffix(SLOT_SUFFIX)?
+        .parse()
+        .ok()
+}
+
+/// Format the value of `field` as a string of `width` bytes, padding with zeroes
+/// if necessary.
 ///
-/// Panics if the string representation of `value` exceeds `width` bytes.
-fn format_int_field(value: usize, width: usize) -> String {
+/// Returns an error if the string representation of `value` exceeds `width` bytes.
+fn format_int_field(field: &'static str, value: usize, width: usize) -> Result<String, K2Error> {
     let bytes = num_digits(value);
-    assert!(bytes <= width, "{} <= {} is false", bytes, width);
+    if bytes > width {
+        return Err(K2Error::ManifestFieldOverflow { field, value });
+    }
     let padding = width - bytes;
-    format!("{}{}", "0".repeat(padding as usize), value)
+    Ok(format!("{}{}", "0".repeat(padding), value))
 }
 
 #[derive(Debug)]
 struct ManifestHeader {
     /// The path of the header.
     hdr_path: PathBuf,
+    /// The version of the header format.
+    version: usize,
     /// The value of the `num_reboots` field.
     num_reboots: usize,
     /// The offset of the `num_reboots` field.
@@ -75,6 +261,16 @@ struct ManifestHeader {
     next_idx: usize,
     /// The offset of the `next_idx` field.
     next_idx_offset: Offset,
+    /// The value and offset of the `slot<n>_next_idx` field of each slot, if the
+    /// jobs run in parallel slots.
+    ///
+    /// The jobs are dealt to the slots in turn: slot `n` of `N` runs the jobs at
+    /// indices `n`, `n + N`, `n + 2N` and so on of the `ordering`, and its
+    /// `slot<n>_next_idx` field is the index of the next job it runs. In this case,
+    /// `next_idx` is the lowest of these indices: all the jobs before it have run.
+    slot_idxs: Vec<(usize, Offset)>,
+    /// The seed of the random number generator used to order the jobs, if known.
+    seed: Option<u64>,
     /// The value of the `ordering` field. This field indicates the order in which
     /// to run the jobs.
     ordering: Vec<usize>,
@@ -84,103 +280,194 @@ impl ManifestHeader {
     /// The name of the manifest header file.
     const MANIFEST_HDR: &'static str = "manifest.k2";
 
-    pub fn new<P: AsRef<Path>>(results_dir: P, num_jobs: usize) -> ManifestHeader {
+    pub fn new<P: AsRef<Path>>(
+        results_dir: P,
+        priorities: &[i32],
+        pexecs: usize,
+        strategy: OrderingStrategy,
+        seed: Option<u64>,
+        num_slots: usize,
+    ) -> Result<ManifestHeader, K2Error> {
         let hdr_path = results_dir.as_ref().join(Self::MANIFEST_HDR);
         if !Path::new(&hdr_path).exists() {
+            // A seed is picked (and recorded) even if none was requested, so that
+            // the ordering can always be reproduced.
+            let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+            debug!("creating the manifest with {:?} ordering and seed {}", strategy, seed);
             // Create a blank manifest header file. The `ordering` field contains a
-            // permutation of the numbers from 0 to `num_jobs` (the jobs are run in
-            // random order).
+            // permutation of the numbers from 0 to the number of jobs, which is
+            // determined by `strategy`.
             ManifestHeader {
                 hdr_path: hdr_path.clone(),
+                version: MANIFEST_VERSION,
                 num_reboots: 0,
                 num_reboots_offset: 0,
                 next_idx: 0,
                 next_idx_offset: 0,
-                ordering: ManifestHeader::random_ordering(num_jobs),
+                slot_idxs: (0..num_slots).map(|slot| (slot, 0)).collect(),
+                seed: Some(seed),
+                ordering: strategy.order_by_priority(
+                    priorities,
+                    pexecs,
+                    &mut StdRng::seed_from_u64(seed),
+                ),
             }
-            .write();
+            .write(&hdr_path)?;
         }
         // Parse the file to work out the actual field offsets.
         ManifestHeader::parse(&hdr_path)
     }
 
-    fn parse<P: AsRef<Path>>(path: P) -> ManifestHeader {
+    /// Rewrite the header using the current version of the format.
+    ///
+    /// The new header is written to a temporary file, which then replaces the old
+    /// header, so that the header isn't lost if k2 is interrupted.
+    fn migrate(&self) -> Result<(), K2Error> {
+        let tmp_path = self.hdr_path.with_extension("k2.tmp");
+        if tmp_path.exists() {
+            fs::remove_file(&tmp_path)?;
+        }
+        self.write(&tmp_path)?;
+        fs::rename(&tmp_path, &self.hdr_path)?;
+        Ok(())
+    }
+
+    fn parse<P: AsRef<Path>>(path: P) -> Result<ManifestHeader, K2Error> {
         // The fields of the manifest header.
+        let mut version = 1;
         let mut num_reboots: Option<(usize, Offset)> = None;
         let mut next_idx: Option<(usize, Offset)> = None;
+        let mut slot_idxs = Vec::new();
+        let mut seed = None;
         let mut ordering: Option<Vec<usize>> = None;
-        let file = File::open(&path).expect("Failed to read manifest header");
+        let file = File::open(&path).map_err(|err| K2Error::file(path.as_ref(), err))?;
         // The offset of the current line.
         let mut offset = 0;
         for line in BufReader::new(file).lines() {
-            let line = line.expect("Failed to read line");
+            let line = line?;
             // Each line is a key-value pair.
             let mut pair = line.split('=');
-            let key = pair.next().expect("No key specified");
-            let value = pair.next().expect("No value specified");
-            assert!(pair.next().is_none(), "Broken line: {}", line);
+            let (key, value) = match (pair.next(), pair.next(), pair.next()) {
+                (Some(key), Some(value), None) => (key, value),
+                _ => return Err(K2Error::ManifestCorrupted(format!("Broken line: {}", line))),
+            };
             match key {
+                SEED => {
+                    seed = Some(value.parse::<u64>().map_err(|_| {
+                        K2Error::ManifestCorrupted(format!("{} must be a u64", SEED))
+                    })?);
+                }
                 ORDERING => {
                     let value = value
                         .split(',')
-                        .map(|x| x.parse::<usize>().unwrap())
-                        .collect();
+                        .filter(|x| !x.is_empty())
+                        .map(|x| x.parse::<usize>())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| {
+                            K2Error::ManifestCorrupted(format!("Invalid {}: {}", ORDERING, value))
+                        })?;
                     ordering = Some(value)
                 }
+                VERSION => {
+                    // The version determines the width of the other fields, so it
+                    // must come first.
+                    if offset != 0 {
+                        return Err(K2Error::ManifestCorrupted(format!(
+                            "{} must be the first key",
+                            VERSION
+                        )));
+                    }
+                    version = value.parse::<usize>().map_err(|_| {
+                        K2Error::ManifestCorrupted(format!("{} must be a usize", VERSION))
+                    })?;
+                    field_widths(version)?;
+                }
                 key => {
                     // Get the actual width of this field.
                     let val_bytes = value.len();
-                    let value = value
-                        .to_string()
-                        .parse::<usize>()
-                        .unwrap_or_else(|_| panic!("{} must be a usize", key));
+                    let value = value.parse::<usize>().map_err(|_| {
+                        K2Error::ManifestCorrupted(format!("{} must be a usize", key))
+                    })?;
                     // The offset of this field. Add 1 to skip over the '='.
                     let val_offset = (offset + key.len() + 1) as Offset;
                     // Get the expected width of this field.
+                    let (num_reboots_bytes, next_idx_bytes) = field_widths(version)?;
                     let width = match key {
                         NUM_REBOOTS => {
                             num_reboots = Some((value, val_offset));
-                            NUM_REBOOTS_BYTES
+                            num_reboots_bytes
                         }
                         NEXT_IDX => {
                             next_idx = Some((value, val_offset));
-                            NEXT_IDX_BYTES
+                            next_idx_bytes
+                        }
+                        // The slots are listed in order.
+                        key if field_slot(key) == Some(slot_idxs.len()) => {
+                            slot_idxs.push((value, val_offset));
+                            next_idx_bytes
+                        }
+                        &_ => {
+                            return Err(K2Error::ManifestCorrupted(format!(
+                                "Unexpected key {}",
+                                key
+                            )))
                         }
-                        &_ => panic!("Unexpected key {}", key),
                     };
                     // Make sure the `num_reboots` and `next_idx` fields have the
                     // expected width.
-                    assert!(val_bytes == width);
+                    if val_bytes != width {
+                        return Err(K2Error::ManifestCorrupted(format!(
+                            "{} must be {} bytes wide",
+                            key, width
+                        )));
+                    }
                 }
             }
             // Add 1 to the offset of the current line to account for newline character.
             offset += line.len() + 1;
         }
-        let (num_reboots, num_reboots_offset) = num_reboots
-            .unwrap_or_else(|| panic!("{} key not set", NUM_REBOOTS));
-        let (next_idx, next_idx_offset) = next_idx
-            .unwrap_or_else(|| panic!("{} key not set", NEXT_IDX));
-        ManifestHeader {
+        let missing = |key| K2Error::ManifestCorrupted(format!("{} key not set", key));
+        let (num_reboots, num_reboots_offset) = num_reboots.ok_or_else(|| missing(NUM_REBOOTS))?;
+        let (next_idx, next_idx_offset) = next_idx.ok_or_else(|| missing(NEXT_IDX))?;
+        Ok(ManifestHeader {
             hdr_path: PathBuf::from(path.as_ref()),
+            version,
             num_reboots,
             num_reboots_offset,
             next_idx,
             next_idx_offset,
-            ordering: ordering.expect("ordering key not set"),
-        }
+            slot_idxs,
+            seed,
+            ordering: ordering.ok_or_else(|| missing(ORDERING))?,
+        })
     }
 
-    /// Create the manifest header file.
-    fn write(&self) {
-        let num_reboots = format_int_field(self.num_reboots, NUM_REBOOTS_BYTES);
-        let next_idx = format_int_field(self.next_idx, NEXT_IDX_BYTES);
-        if !Path::new(&self.hdr_path).exists() {
-            let manifest_hdr = format!("{}={}\n{}={}\n{}={}",
+    /// Create a manifest header file at `path`, using the current version of the
+    /// format.
+    fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), K2Error> {
+        let (num_reboots_bytes, next_idx_bytes) = field_widths(MANIFEST_VERSION)?;
+        let num_reboots = format_int_field(NUM_REBOOTS, self.num_reboots, num_reboots_bytes)?;
+        let next_idx = format_int_field(NEXT_IDX, self.next_idx, next_idx_bytes)?;
+        let mut slot_idxs = String::new();
+        for (slot, (slot_idx, _)) in self.slot_idxs.iter().enumerate() {
+            let slot_idx = format_int_field(NEXT_IDX, *slot_idx, next_idx_bytes)?;
+            slot_idxs.push_str(&format!("{}={}\n", slot_field(slot), slot_idx));
+        }
+        let seed = match self.seed {
+            Some(seed) => format!("{}={}\n", SEED, seed),
+            None => String::new(),
+        };
+        if !path.as_ref().exists() {
+            let manifest_hdr = format!("{}={}\n{}={}\n{}={}\n{}{}{}={}",
+                VERSION, MANIFEST_VERSION,
                 NUM_REBOOTS, num_reboots,
                 NEXT_IDX, next_idx,
+                slot_idxs,
+                seed,
                 ORDERING, self.ordering_str());
-            fs::write(&self.hdr_path, manifest_hdr).expect("Failed to write the manifest header");
+            fs::write(path, manifest_hdr)?;
         }
+        Ok(())
     }
 
     fn ordering_str(&self) -> String {
@@ -188,26 +475,89 @@ impl ManifestHeader {
         ordering.join(",")
     }
 
-    /// Update the `num_reboots` and `next_idx` fields.
-    fn sync(&self) {
-        let num_reboots = format_int_field(self.num_reboots, NUM_REBOOTS_BYTES);
-        let next_idx = format_int_field(self.next_idx, NEXT_IDX_BYTES);
-        match OpenOptions::new().write(true).open(&self.hdr_path) {
-            Ok(mut f) => {
-                f.seek(SeekFrom::Start(self.num_reboots_offset)).unwrap();
-                f.write(num_reboots.as_bytes()).unwrap();
-                f.seek(SeekFrom::Start(self.next_idx_offset)).unwrap();
-                f.write(next_idx.as_bytes()).unwrap();
+    /// Update the `num_reboots`, `next_idx` and `slot<n>_next_idx` fields.
+    fn sync(&self) -> Result<(), K2Error> {
+        let (num_reboots_bytes, next_idx_bytes) = field_widths(self.version)?;
+        let num_reboots = format_int_field(NUM_REBOOTS, self.num_reboots, num_reboots_bytes)?;
+        let next_idx = format_int_field(NEXT_IDX, self.next_idx, next_idx_bytes)?;
+        let mut f = OpenOptions::new().write(true).open(&self.hdr_path)?;
+        f.seek(SeekFrom::Start(self.num_reboots_offset))?;
+        f.write_all(num_reboots.as_bytes())?;
+        f.seek(SeekFrom::Start(self.next_idx_offset))?;
+        f.write_all(next_idx.as_bytes())?;
+        for (slot_idx, offset) in &self.slot_idxs {
+            let slot_idx = format_int_field(NEXT_IDX, *slot_idx, next_idx_bytes)?;
+            f.seek(SeekFrom::Start(*offset))?;
+            f.write_all(slot_idx.as_bytes())?;
+        }
+        // The machine is about to reboot, possibly without flushing its caches.
+        f.sync_data()?;
+        Ok(())
+    }
+
+    /// The indices (in `ordering`) of the jobs which haven't run yet.
+    fn remaining(&self) -> Vec<usize> {
+        if self.slot_idxs.is_empty() {
+            return (self.next_idx..self.ordering.len()).collect();
+        }
+        let mut remaining = self
+            .slot_idxs
+            .iter()
+            .flat_map(|(slot_idx, _)| {
+                (*slot_idx..self.ordering.len()).step_by(self.slot_idxs.len())
+            })
+            .collect::<Vec<_>>();
+        remaining.sort_unstable();
+        remaining
+    }
+
+    /// Move slot `slot` on to its next job, and update `next_idx` accordingly.
+    fn advance_slot(&mut self, slot: usize) {
+        self.slot_idxs[slot].0 += self.slot_idxs.len();
+        self.next_idx = self
+            .slot_idxs
+            .iter()
+            .map(|(slot_idx, _)| *slot_idx)
+            .min()
+            .unwrap_or_default();
+    }
+
+    /// Append `jobs` to the `ordering` field.
+    ///
+    /// The `ordering` field is the last field of the header, so the new jobs are
+    /// simply appended to the file.
+    fn extend_ordering(&mut self, jobs: &[usize]) -> Result<(), K2Error> {
+        let mut f = OpenOptions::new().append(true).open(&self.hdr_path)?;
+        for job in jobs {
+            if self.ordering.is_empty() {
+                write!(f, "{}", job)?;
+            } else {
+                write!(f, ",{}", job)?;
             }
-            Err(err) => panic!("Failed to open manifest header: {}", err),
+            self.ordering.push(*job);
         }
+        f.sync_data()?;
+        Ok(())
     }
+}
 
-    /// Generate a random permutation for the job ordering.
-    fn random_ordering(num_jobs: usize) -> Vec<usize> {
-        let mut ordering: Vec<usize> = (0..num_jobs).collect();
-        ordering.shuffle(&mut rand::thread_rng());
-        ordering
+/// Upgrade the manifest header in `results_dir` (if any) to the current version of
+/// the format. Returns whether the header was upgraded.
+pub(crate) fn migrate_header<P: AsRef<Path>>(results_dir: P) -> Result<bool, K2Error> {
+    let hdr_path = results_dir.as_ref().join(ManifestHeader::MANIFEST_HDR);
+    if !hdr_path.exists() {
+        return Ok(false);
+    }
+    let hdr = ManifestHeader::parse(hdr_path)?;
+    if hdr.version < MANIFEST_VERSION {
+        info!(
+            "upgrading the manifest from version {} to {}",
+            hdr.version, MANIFEST_VERSION
+        );
+        hdr.migrate()?;
+        Ok(true)
+    } else {
+        Ok(false)
     }
 }
 
@@ -216,21 +566,65 @@ pub(crate) struct ManifestManager {
     manifest_hdr: ManifestHeader,
     /// The status of the current job.
     cur_status: JobStatus,
+    /// The measurements collected while running the current job.
+    cur_measurements: Measurements,
 }
 
 impl ManifestManager {
-    pub fn new(config: &Config, benchmarks: &[&'_ Benchmark]) -> ManifestManager {
-        let num_jobs = config.pexecs * benchmarks.len();
-        let manifest_hdr = ManifestHeader::new(&config.results_dir, num_jobs);
-        ManifestManager {
+    pub fn new(config: &Config, benchmarks: &[Benchmark]) -> Result<ManifestManager, K2Error> {
+        let num_slots = config.slots.as_ref().map_or(0, Vec::len);
+        let priorities = benchmarks
+            .iter()
+            .map(|bench| bench.priority)
+            .collect::<Vec<_>>();
+        let manifest_hdr = ManifestHeader::new(
+            &config.results_dir,
+            &priorities,
+            config.pexecs,
+            config.ordering,
+            config.rng_seed,
+            num_slots,
+        )?;
+        // The jobs are dealt to the slots when the experiment starts.
+        if manifest_hdr.slot_idxs.len() != num_slots {
+            return Err(K2Error::InvalidConfig(format!(
+                "The experiment was started with {} slots, but {} are configured",
+                manifest_hdr.slot_idxs.len(),
+                num_slots
+            )));
+        }
+        Ok(ManifestManager {
             manifest_hdr,
             cur_status: JobStatus::Outstanding,
-        }
+            cur_measurements: Default::default(),
+        })
+    }
+
+    /// Whether the manifest of the experiment in `results_dir` has been created.
+    pub(crate) fn exists<P: AsRef<Path>>(results_dir: P) -> bool {
+        results_dir
+            .as_ref()
+            .join(ManifestHeader::MANIFEST_HDR)
+            .exists()
+    }
+
+    /// Opens the manifest of an existing experiment, without creating a new one.
+    pub fn open<P: AsRef<Path>>(results_dir: P) -> Result<ManifestManager, K2Error> {
+        let hdr_path = results_dir.as_ref().join(ManifestHeader::MANIFEST_HDR);
+        Ok(ManifestManager {
+            manifest_hdr: ManifestHeader::parse(hdr_path)?,
+            cur_status: JobStatus::Outstanding,
+            cur_measurements: Default::default(),
+        })
     }
 
     /// Returns the index of the next job to run, or `None` if there are no more
-    /// outstanding jobs.
+    /// outstanding jobs. If the jobs run in parallel slots, this is the next job of
+    /// the first slot which has any left.
     pub fn next_job(&self) -> Option<usize> {
+        if !self.manifest_hdr.slot_idxs.is_empty() {
+            return (0..self.num_slots()).find_map(|slot| self.next_slot_job(slot));
+        }
         if self.manifest_hdr.next_idx < self.manifest_hdr.ordering.len() {
             Some(self.manifest_hdr.ordering[self.manifest_hdr.next_idx])
         } else {
@@ -238,32 +632,174 @@ impl ManifestManager {
         }
     }
 
+    /// Returns the number of parallel slots the jobs run in, which is 0 if they run
+    /// one after the other.
+    pub fn num_slots(&self) -> usize {
+        self.manifest_hdr.slot_idxs.len()
+    }
+
+    /// Returns the next job slot `slot` runs, or `None` if it has no more
+    /// outstanding jobs.
+    pub fn next_slot_job(&self, slot: usize) -> Option<usize> {
+        let (slot_idx, _) = self.manifest_hdr.slot_idxs[slot];
+        self.manifest_hdr.ordering.get(slot_idx).copied()
+    }
+
+    /// Returns the number of jobs which haven't been run yet.
+    pub fn num_remaining(&self) -> usize {
+        self.manifest_hdr.remaining().len()
+    }
+
+    /// Returns the seed used to order the jobs, or `None` if the experiment was
+    /// started by a version of k2 which didn't record it.
+    pub fn seed(&self) -> Option<u64> {
+        self.manifest_hdr.seed
+    }
+
+    /// Returns the number of times the experiment has rebooted so far.
+    pub fn num_reboots(&self) -> usize {
+        self.manifest_hdr.num_reboots
+    }
+
     /// Updates the status of the current job to `status`.
     pub fn update_status(&mut self, status: JobStatus) {
         self.cur_status = status;
         match status {
-            JobStatus::Done | JobStatus::Error => {
+            JobStatus::Outstanding => {}
+            _ => {
+                // The width of the field is checked when the header is synced.
                 self.manifest_hdr.next_idx += 1;
-                let bytes = num_digits(self.manifest_hdr.next_idx);
-                assert!(bytes <= NEXT_IDX_BYTES, "{} <= {} is false", bytes, NEXT_IDX_BYTES);
             }
-            _ => {}
         }
     }
 
+    /// Updates the status of the job slot `slot` has just run to `status`. Unlike
+    /// `update_status`, this doesn't record the status: it must be stored
+    /// separately.
+    pub fn update_slot_status(&mut self, slot: usize, status: JobStatus) {
+        if status != JobStatus::Outstanding {
+            // The width of the field is checked when the header is synced.
+            self.manifest_hdr.advance_slot(slot);
+        }
+    }
+
+    /// Records the measurements of the current job.
+    pub fn update_measurements(&mut self, measurements: Measurements) {
+        self.cur_measurements = measurements;
+    }
+
+    /// Returns the measurements of the current job.
+    pub fn measurements(&self) -> &Measurements {
+        &self.cur_measurements
+    }
+
+    /// Re-queues the jobs which failed (with an error or a timeout) fewer than
+    /// `max_retries` times, by appending them to the job ordering. Returns the
+    /// number of jobs re-queued.
+    pub fn reset_errored(
+        &mut self,
+        store: &mut K2Store,
+        max_retries: usize,
+    ) -> Result<usize, K2Error> {
+        let jobs = store.retryable_jobs(max_retries)?;
+        store.transaction(|store| {
+            for job in &jobs {
+                store.mark_retry(*job)?;
+            }
+            Ok(())
+        })?;
+        // If this is interrupted, `repair` appends the jobs to the ordering.
+        self.manifest_hdr.extend_ordering(&jobs)?;
+        if !jobs.is_empty() {
+            info!("retrying jobs {:?}", jobs);
+        }
+        Ok(jobs.len())
+    }
+
     /// Increments the number of reboots.
     pub fn update_num_reboots(&mut self) {
-        let bytes = num_digits(self.manifest_hdr.num_reboots);
-        assert!(bytes <= NUM_REBOOTS_BYTES, "{} <= {} is false", bytes, NUM_REBOOTS_BYTES);
+        // The width of the field is checked when the header is synced.
         self.manifest_hdr.num_reboots += 1;
     }
 
-    /// Writes the manifest header and the status of the current job.
-    pub fn sync(&self, store: &mut K2Store) {
-        self.manifest_hdr.sync();
-        store.update_status(
-            self.manifest_hdr.ordering[self.manifest_hdr.next_idx - 1],
-            self.cur_status,
-        );
+    /// Writes the status and measurements of the current job to `store`.
+    pub fn store_job(&self, store: &mut K2Store) -> Result<(), K2Error> {
+        let job_id = self.manifest_hdr.ordering[self.manifest_hdr.next_idx - 1];
+        store.update_status(job_id, self.cur_status)?;
+        store.insert_measurements(job_id, &self.cur_measurements)
+    }
+
+    /// Writes the manifest header. This must be called after `store_job`.
+    pub fn sync(&self) -> Result<(), K2Error> {
+        self.manifest_hdr.sync()
+    }
+
+    /// Moves past the next jobs which aren't outstanding in the job table of `store`
+    /// (such as the jobs marked as `JobStatus::Unneeded`), so they aren't run.
+    pub fn skip_finished(&mut self, store: &mut K2Store) -> Result<(), K2Error> {
+        if self.skip_over(store)? {
+            self.manifest_hdr.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Advances past the next jobs (of each slot) which aren't outstanding. Returns
+    /// whether any job was skipped.
+    fn skip_over(&mut self, store: &mut K2Store) -> Result<bool, K2Error> {
+        let mut skipped = false;
+        if self.manifest_hdr.slot_idxs.is_empty() {
+            while let Some(job) = self.next_job() {
+                if store.job(job)?.status == JobStatus::Outstanding {
+                    break;
+                }
+                self.manifest_hdr.next_idx += 1;
+                skipped = true;
+            }
+        } else {
+            // Each slot might have been interrupted after storing the results of
+            // its job.
+            for slot in 0..self.num_slots() {
+                while let Some(job) = self.next_slot_job(slot) {
+                    if store.job(job)?.status == JobStatus::Outstanding {
+                        break;
+                    }
+                    self.manifest_hdr.advance_slot(slot);
+                    skipped = true;
+                }
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Reconciles the manifest with the job table of `store`, in case the experiment
+    /// was interrupted after updating one, but not the other. Returns whether the
+    /// manifest had to be repaired.
+    ///
+    /// The results of each job are stored before the manifest is updated, so the
+    /// next jobs in the manifest might have already run. Similarly, the jobs
+    /// re-queued by `reset_errored` are marked as outstanding before they are added
+    /// to the manifest.
+    pub fn repair(&mut self, store: &mut K2Store) -> Result<bool, K2Error> {
+        let mut repaired = self.skip_over(store)?;
+        let scheduled = self
+            .manifest_hdr
+            .remaining()
+            .into_iter()
+            .map(|idx| self.manifest_hdr.ordering[idx])
+            .collect::<HashSet<_>>();
+        let unscheduled = store
+            .outstanding_jobs()?
+            .into_iter()
+            .filter(|job| !scheduled.contains(job))
+            .collect::<Vec<_>>();
+        if !unscheduled.is_empty() {
+            self.manifest_hdr.extend_ordering(&unscheduled)?;
+            repaired = true;
+        }
+        if repaired {
+            warn!("the manifest didn't match the results database, and was repaired");
+            self.manifest_hdr.sync()?;
+        }
+        Ok(repaired)
     }
 }
@@ -0,0 +1,229 @@
+use crate::{clock::Clock, error::K2Error, process::InvocationOutcome};
+
+use serde::{Deserialize, Serialize};
+
+use std::{collections::BTreeMap, time::Duration};
+
+/// The environment variable which tells a benchmark how many in-process iterations
+/// to run.
+pub const IN_PROC_ITERS_ENV: &str = "K2_IN_PROC_ITERS";
+
+/// The prefix of the lines a benchmark prints to stdout to report the wall-clock
+/// time of an in-process iteration.
+///
+/// Each in-process iteration is expected to print a line of the form
+/// `k2:wallclock:<secs>`, where `<secs>` is the wall-clock time of the iteration
+/// in seconds. All other lines are ignored.
+pub const WALLCLOCK_PREFIX: &str = "k2:wallclock:";
+
+/// The environment variable which is set to `1` when a benchmark should report the
+/// CPU time of each in-process iteration (see `Config::cpu_time`).
+pub const CPU_TIME_ENV: &str = "K2_CPU_TIME";
+
+/// The prefix of the lines a benchmark prints to stdout to report the CPU time of
+/// an in-process iteration, when `CPU_TIME_ENV` is set.
+///
+/// Each in-process iteration is expected to print a line of the form
+/// `k2:cputime:<secs>`, where `<secs>` is the CPU time the process used during the
+/// iteration (e.g. measured with `CLOCK_PROCESS_CPUTIME_ID`), in seconds.
+pub const CPU_TIME_PREFIX: &str = "k2:cputime:";
+
+/// Whether `line` of the standard output of a benchmark reports a measurement.
+pub(crate) fn is_measurement(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with(WALLCLOCK_PREFIX) || line.starts_with(CPU_TIME_PREFIX)
+}
+
+/// The line a benchmark (or the language implementation which runs it) prints to
+/// stderr to ask for the job to be run again, because it noticed that something
+/// disturbed it (such as a garbage collector misbehaving, or a device resetting).
+///
+/// The line has the form `k2:rerun`, optionally followed by `:<reason>`. The
+/// measurements of the job are discarded, and the job is run again (see
+/// `Config::max_reruns`), whatever the exit code of the benchmark.
+pub const RERUN_PREFIX: &str = "k2:rerun";
+
+/// Find the request to run the job again on the `stderr` of a benchmark (see
+/// `RERUN_PREFIX`). Returns the reason given for it, which may be empty.
+pub(crate) fn rerun_request(stderr: &[u8]) -> Option<String> {
+    let stderr = String::from_utf8_lossy(stderr);
+    stderr.lines().find_map(|line| {
+        let rest = line.trim().strip_prefix(RERUN_PREFIX)?;
+        match rest.strip_prefix(':') {
+            Some(reason) => Some(reason.trim().to_string()),
+            None if rest.is_empty() => Some(String::new()),
+            None => None,
+        }
+    })
+}
+
+/// The measurements collected during a single process execution. The wall-clock
+/// and CPU times are serialized in seconds.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Measurements {
+    /// The wall-clock time of each in-process iteration.
+    #[serde(with = "crate::util::vec_secs")]
+    pub wallclock: Vec<Duration>,
+    /// The CPU time of each in-process iteration, if the benchmark reported it (see
+    /// `CPU_TIME_PREFIX`).
+    #[serde(with = "crate::util::vec_secs")]
+    pub cputime: Vec<Duration>,
+    /// The indices of the in-process iterations whose wall-clock time is an outlier
+    /// (see `analysis::outliers`), in increasing order.
+    pub outliers: Vec<usize>,
+    /// The measurements taken once for the whole process execution (such as the
+    /// energy used), indexed by name.
+    pub per_pexec: BTreeMap<String, f64>,
+}
+
+impl Measurements {
+    /// Parse the per-iteration measurements reported by a benchmark on its
+    /// standard output.
+    pub fn from_stdout(stdout: &str) -> Result<Measurements, K2Error> {
+        let mut measurements = Measurements::default();
+        for line in stdout.lines() {
+            let (times, secs) = if let Some(secs) = line.trim().strip_prefix(WALLCLOCK_PREFIX) {
+                (&mut measurements.wallclock, secs)
+            } else if let Some(secs) = line.trim().strip_prefix(CPU_TIME_PREFIX) {
+                (&mut measurements.cputime, secs)
+            } else {
+                continue;
+            };
+            let invalid = || K2Error::InvalidMeasurement(line.to_string());
+            let secs = secs.trim().parse::<f64>().map_err(|_| invalid())?;
+            // A time which is negative, infinite or too large for a `Duration` is
+            // rejected too.
+            times.push(Duration::try_from_secs_f64(secs).map_err(|_| invalid())?);
+        }
+        Ok(measurements)
+    }
+
+    /// Create the synthetic measurements of a dry run, which consist of
+    /// `num_iters` iterations that took no time at all.
+    pub(crate) fn synthetic(num_iters: usize) -> Measurements {
+        Measurements {
+            wallclock: vec![Duration::from_secs(0); num_iters],
+            ..Default::default()
+        }
+    }
+
+    /// The number of in-process iterations measured.
+    pub fn num_iters(&self) -> usize {
+        self.wallclock.len()
+    }
+}
+
+/// Collects measurements around each process execution.
+///
+/// Before a benchmark process is started, `start` is called on each collector, in
+/// the order the collectors were registered. Once the process exits, `stop` is
+/// called on each collector, in reverse order, followed by `collect`, if the
+/// process exited successfully.
+pub trait MeasurementCollector: Send {
+    /// Called right before the benchmark process is started.
+    fn start(&mut self) -> Result<(), K2Error> {
+        Ok(())
+    }
+
+    /// Called right after the benchmark process exits.
+    fn stop(&mut self) -> Result<(), K2Error> {
+        Ok(())
+    }
+
+    /// Add the measurements of the process execution which produced `outcome` to
+    /// `measurements`. Measurements taken once per process execution should be
+    /// added to `Measurements::per_pexec`, under a name prefixed by the name of
+    /// the collector (e.g. `rusage:max_rss_kib`).
+    fn collect(
+        &mut self,
+        outcome: &InvocationOutcome,
+        measurements: &mut Measurements,
+    ) -> Result<(), K2Error>;
+}
+
+/// Collects the wall-clock time (and the CPU time, if reported) of each in-process
+/// iteration, from the standard output of the benchmark.
+///
+/// If the benchmark doesn't report any, the whole process execution is recorded
+/// as a single in-process iteration, timed with the clock of the collector (see
+/// `clock`).
+#[derive(Debug, Default)]
+pub struct WallclockCollector {
+    clock: Clock,
+    start: Option<Duration>,
+    elapsed: Duration,
+}
+
+impl WallclockCollector {
+    /// A collector which times the process executions with `clock`.
+    pub fn new(clock: Clock) -> WallclockCollector {
+        WallclockCollector {
+            clock,
+            ..Default::default()
+        }
+    }
+}
+
+impl MeasurementCollector for WallclockCollector {
+    fn start(&mut self) -> Result<(), K2Error> {
+        self.start = Some(self.clock.now()?);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), K2Error> {
+        self.elapsed = match self.start.take() {
+            Some(start) => self.clock.now()?.saturating_sub(start),
+            None => Duration::default(),
+        };
+        Ok(())
+    }
+
+    fn collect(
+        &mut self,
+        outcome: &InvocationOutcome,
+        measurements: &mut Measurements,
+    ) -> Result<(), K2Error> {
+        let stdout = String::from_utf8_lossy(&outcome.stdout);
+        let reported = Measurements::from_stdout(&stdout)?;
+        measurements.wallclock = reported.wallclock;
+        measurements.cputime = reported.cputime;
+        if measurements.num_iters() == 0 {
+            measurements.wallclock.push(self.elapsed);
+        }
+        Ok(())
+    }
+}
+
+/// Collects the resources used by each process execution (see
+/// `process::ResourceUsage`), and its peak memory usage if it was run in a cgroup
+/// (`cgroup:memory_peak`, in bytes).
+#[derive(Debug, Default)]
+pub struct RusageCollector;
+
+impl MeasurementCollector for RusageCollector {
+    fn collect(
+        &mut self,
+        outcome: &InvocationOutcome,
+        measurements: &mut Measurements,
+    ) -> Result<(), K2Error> {
+        let rusage = &outcome.rusage;
+        for (name, value) in &[
+            ("max_rss_kib", rusage.max_rss_kib as f64),
+            ("user_time", rusage.user_time.as_secs_f64()),
+            ("sys_time", rusage.sys_time.as_secs_f64()),
+            ("minor_faults", rusage.minor_faults as f64),
+            ("major_faults", rusage.major_faults as f64),
+        ] {
+            measurements
+                .per_pexec
+                .insert(format!("rusage:{}", name), *value);
+        }
+        if let Some(memory_peak) = outcome.memory_peak {
+            measurements
+                .per_pexec
+                .insert("cgroup:memory_peak".into(), memory_peak as f64);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! Upgrades the results directories created by older versions of k2.
+//!
+//! Both the manifest header and the results database are versioned. The manifest
+//! header records the version of its format in its `version` field, and the
+//! database records the version of its schema in its `user_version`.
+
+use crate::{
+    analysis,
+    db::{self, K2Store},
+    error::K2Error,
+    manifest,
+};
+
+use rusqlite::{params, Connection, NO_PARAMS};
+
+use std::path::Path;
+
+/// A migration, which upgrades the database schema by one version.
+type Migration = fn(&Connection) -> Result<(), K2Error>;
+
+/// The migrations of the database schema, indexed by the version they upgrade
+/// from.
+const DB_MIGRATIONS: [Migration; K2Store::SCHEMA_VERSION as usize] = [
+    db_v0_to_v1,
+    db_v1_to_v2,
+    db_v2_to_v3,
+    db_v3_to_v4,
+    db_v4_to_v5,
+    db_v5_to_v6,
+    db_v6_to_v7,
+    db_v7_to_v8,
+    db_v8_to_v9,
+    db_v9_to_v10,
+    db_v10_to_v11,
+];
+
+/// What `migrate` upgraded.
+#[derive(Debug, Default)]
+pub struct Migrated {
+    /// Whether the manifest header was upgraded.
+    pub manifest: bool,
+    /// The schema version the database was upgraded from, if it was upgraded.
+    pub db_from: Option<i64>,
+}
+
+/// Upgrade the results directory `results_dir` in place, so that it can be used by
+/// this version of k2.
+///
+/// Returns `K2Error::SchemaVersion` if the results were created by a newer version
+/// of k2.
+pub fn migrate<P: AsRef<Path>>(results_dir: P) -> Result<Migrated, K2Error> {
+    let mut migrated = Migrated {
+        manifest: manifest::migrate_header(&results_dir)?,
+        ..Default::default()
+    };
+    let db_path = results_dir.as_ref().join(K2Store::K2_DB);
+    if !db_path.exists() {
+        return Ok(migrated);
+    }
+    let mut connection = Connection::open(db_path)?;
+    let version = db::schema_version(&connection)?;
+    if version > K2Store::SCHEMA_VERSION {
+        return Err(K2Error::SchemaVersion {
+            found: version,
+            expected: K2Store::SCHEMA_VERSION,
+        });
+    }
+    for from in version..K2Store::SCHEMA_VERSION {
+        // Each migration is applied atomically, so that an interrupted migration
+        // can simply be run again.
+        let tx = connection.transaction()?;
+        DB_MIGRATIONS[from as usize](&tx)?;
+        db::set_schema_version(&tx, from + 1)?;
+        tx.commit()?;
+    }
+    if version < K2Store::SCHEMA_VERSION {
+        migrated.db_from = Some(version);
+    }
+    Ok(migrated)
+}
+
+/// Upgrade a database created before the schema was versioned.
+///
+/// Depending on the version of k2 which created it, the database might be missing
+/// some of the columns of the `job` table or the `pexec_measurement` table, or it
+/// might still record the temperatures in a separate `temperature` table.
+fn db_v0_to_v1(connection: &Connection) -> Result<(), K2Error> {
+    if db::table_exists(connection, "job")? {
+        let mut stmt = connection.prepare("PRAGMA table_info(job);")?;
+        let columns = stmt
+            .query_map(NO_PARAMS, |row| row.get::<_, String>(1))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let new_columns = [
+            ("retries", "INTEGER NOT NULL DEFAULT 0"),
+            ("cpu_affinity", "TEXT"),
+            ("duration", "REAL"),
+        ];
+        for (column, definition) in new_columns.iter() {
+            if !columns.iter().any(|c| c == column) {
+                connection.execute(
+                    &format!("ALTER TABLE job ADD COLUMN {} {};", column, definition),
+                    NO_PARAMS,
+                )?;
+            }
+        }
+    }
+    let has_temperatures = db::table_exists(connection, "temperature")?;
+    if (has_temperatures || db::table_exists(connection, "measurement")?)
+        && !db::table_exists(connection, "pexec_measurement")?
+    {
+        connection.execute(
+            "CREATE TABLE pexec_measurement(
+                        job_id INTEGER NOT NULL,
+                        name TEXT NOT NULL,
+                        value REAL NOT NULL,
+                        PRIMARY KEY(job_id, name));",
+            NO_PARAMS,
+        )?;
+    }
+    if has_temperatures {
+        // The temperatures are now recorded as `temperature:<phase>:<sensor>`.
+        connection.execute(
+            "INSERT OR REPLACE INTO pexec_measurement
+                        SELECT job_id,
+                            'temperature:' || CASE phase WHEN 0 THEN 'before' ELSE 'after' END
+                                || ':' || sensor,
+                            celsius
+                        FROM temperature;",
+            NO_PARAMS,
+        )?;
+        connection.execute("DROP TABLE temperature;", NO_PARAMS)?;
+    }
+    Ok(())
+}
+
+/// Add the `pexec`, `path` and `lang_impl` columns to the `job` table, and create the
+/// `job_tag` table.
+///
+/// The tags of the benchmarks weren't recorded, so the `job_tag` table is left
+/// empty. The `path` and `lang_impl` of each job are inferred from its key
+/// (`<lang_impl>:<path>`). Its `pexec` is inferred from the layout of the job table,
+/// in which the jobs of each process execution are consecutive and in the same
+/// order.
+fn db_v1_to_v2(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "job")? {
+        return Ok(());
+    }
+    connection.execute("ALTER TABLE job ADD COLUMN pexec INTEGER;", NO_PARAMS)?;
+    connection.execute("ALTER TABLE job ADD COLUMN path TEXT;", NO_PARAMS)?;
+    connection.execute("ALTER TABLE job ADD COLUMN lang_impl TEXT;", NO_PARAMS)?;
+    db::create_job_tag_table(connection)?;
+    let mut stmt = connection.prepare("SELECT job_id, key FROM job ORDER BY job_id;")?;
+    let jobs = stmt
+        .query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    // The first process execution ends when a key is repeated.
+    let num_benchmarks = (0..jobs.len())
+        .find(|idx| jobs[..*idx].iter().any(|(_, key)| *key == jobs[*idx].1))
+        .unwrap_or(jobs.len());
+    let regular = jobs
+        .iter()
+        .enumerate()
+        .all(|(idx, (id, key))| *id == idx as i64 && *key == jobs[idx % num_benchmarks].1)
+        && jobs.len() % num_benchmarks.max(1) == 0;
+    let mut update = connection
+        .prepare("UPDATE job SET pexec = $1, path = $2, lang_impl = $3 WHERE job_id = $4;")?;
+    for (id, key) in &jobs {
+        let pexec = if regular {
+            Some(*id / num_benchmarks as i64)
+        } else {
+            None
+        };
+        let (lang_impl, path) = match key.rsplit_once(':') {
+            Some((lang_impl, path)) => (Some(lang_impl), Some(path)),
+            None => (None, None),
+        };
+        update.execute(params![pexec, path, lang_impl, id])?;
+    }
+    Ok(())
+}
+
+/// Add the `started_at` and `finished_at` columns to the `job` table, and create the
+/// `reboot` table. The times of the jobs and reboots which happened before the
+/// migration are unknown.
+fn db_v2_to_v3(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "job")? {
+        return Ok(());
+    }
+    connection.execute("ALTER TABLE job ADD COLUMN started_at REAL;", NO_PARAMS)?;
+    connection.execute("ALTER TABLE job ADD COLUMN finished_at REAL;", NO_PARAMS)?;
+    db::create_reboot_table(connection)
+}
+
+/// Add the `outlier` column to the `measurement` table, and flag the outliers among
+/// the measurements recorded before the migration.
+fn db_v3_to_v4(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "measurement")? {
+        return Ok(());
+    }
+    connection.execute(
+        "ALTER TABLE measurement ADD COLUMN outlier INTEGER NOT NULL DEFAULT 0;",
+        NO_PARAMS,
+    )?;
+    let mut stmt =
+        connection.prepare("SELECT job_id, wallclock FROM measurement ORDER BY job_id, iter;")?;
+    let rows = stmt
+        .query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, f64>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut update = connection
+        .prepare("UPDATE measurement SET outlier = 1 WHERE job_id = $1 AND iter = $2;")?;
+    for job in rows.chunk_by(|(a, _), (b, _)| a == b) {
+        let times = job
+            .iter()
+            .map(|(_, wallclock)| *wallclock)
+            .collect::<Vec<_>>();
+        for iter in analysis::outliers(&times) {
+            update.execute(params![job[0].0, iter as i64])?;
+        }
+    }
+    Ok(())
+}
+
+/// Add the `numa_node` column to the `job` table. The jobs which ran before the
+/// migration weren't bound to a NUMA node.
+fn db_v4_to_v5(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "job")? {
+        return Ok(());
+    }
+    connection.execute("ALTER TABLE job ADD COLUMN numa_node INTEGER;", NO_PARAMS)?;
+    Ok(())
+}
+
+/// Add the `reruns` column to the `job` table. Benchmarks couldn't ask to be run
+/// again before the migration.
+fn db_v5_to_v6(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "job")? {
+        return Ok(());
+    }
+    connection.execute(
+        "ALTER TABLE job ADD COLUMN reruns INTEGER NOT NULL DEFAULT 0;",
+        NO_PARAMS,
+    )?;
+    Ok(())
+}
+
+/// Add the `attempts` column to the `job` table. The attempts made before the
+/// migration weren't counted.
+fn db_v6_to_v7(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "job")? {
+        return Ok(());
+    }
+    connection.execute(
+        "ALTER TABLE job ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;",
+        NO_PARAMS,
+    )?;
+    Ok(())
+}
+
+/// Add the `path_sha256` and `lang_impl_sha256` columns to the `job` table. They
+/// are filled in the next time the experiment is resumed (see `checksum`).
+fn db_v7_to_v8(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "job")? {
+        return Ok(());
+    }
+    connection.execute_batch(
+        "ALTER TABLE job ADD COLUMN path_sha256 TEXT;
+         ALTER TABLE job ADD COLUMN lang_impl_sha256 TEXT;",
+    )?;
+    Ok(())
+}
+
+/// Add the `in_proc_iters` column to the `job` table. The number of in-process
+/// iterations of the jobs which ran before the migration wasn't recorded.
+fn db_v8_to_v9(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "job")? {
+        return Ok(());
+    }
+    connection.execute(
+        "ALTER TABLE job ADD COLUMN in_proc_iters INTEGER;",
+        NO_PARAMS,
+    )?;
+    Ok(())
+}
+
+/// Add the `running` and `interrupted` columns to the `job` table. The attempts
+/// interrupted before the migration weren't recorded.
+fn db_v9_to_v10(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "job")? {
+        return Ok(());
+    }
+    connection.execute(
+        "ALTER TABLE job ADD COLUMN running INTEGER NOT NULL DEFAULT 0;",
+        NO_PARAMS,
+    )?;
+    connection.execute(
+        "ALTER TABLE job ADD COLUMN interrupted INTEGER NOT NULL DEFAULT 0;",
+        NO_PARAMS,
+    )?;
+    Ok(())
+}
+
+/// Add the `cputime` column to the `measurement` table. The CPU time of the
+/// iterations which ran before the migration wasn't recorded.
+fn db_v10_to_v11(connection: &Connection) -> Result<(), K2Error> {
+    if !db::table_exists(connection, "measurement")? {
+        return Ok(());
+    }
+    connection.execute(
+        "ALTER TABLE measurement ADD COLUMN cputime REAL;",
+        NO_PARAMS,
+    )?;
+    Ok(())
+}
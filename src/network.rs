@@ -0,0 +1,65 @@
+//! Running the benchmarks without network access.
+//!
+//! A benchmark which unexpectedly uses the network (to phone home, or to download
+//! its dependencies) is both unreliable and perturbed by network I/O. If
+//! `Config::isolate_network` is set, each benchmark process is started in a new
+//! network namespace, whose only interface is the loopback interface: the
+//! benchmark can still talk to itself over `localhost`, but any attempt to reach
+//! another machine fails (usually with `ENETUNREACH`), which makes the job fail
+//! rather than silently measure the network.
+//!
+//! Creating a network namespace requires root.
+
+use crate::error::K2Error;
+
+use std::{io, mem};
+
+/// The name of the loopback interface.
+const LOOPBACK: &[u8] = b"lo";
+
+/// Check that the benchmark processes can be started in their own network
+/// namespace.
+pub(crate) fn check() -> Result<(), K2Error> {
+    if unsafe { libc::geteuid() } == 0 {
+        Ok(())
+    } else {
+        Err(K2Error::InvalidConfig(
+            "Isolating the network of the benchmarks requires root".into(),
+        ))
+    }
+}
+
+/// Move the calling process into a new network namespace, and bring its loopback
+/// interface up. The processes it starts inherit the namespace.
+///
+/// This is called between `fork` and `exec`, so it must not allocate.
+pub(crate) fn isolate() -> io::Result<()> {
+    if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    loopback_up()
+}
+
+/// Bring the loopback interface of the current network namespace up, which is
+/// down in a new namespace.
+fn loopback_up() -> io::Result<()> {
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if sock < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut ifr = unsafe { mem::zeroed::<libc::ifreq>() };
+    for (dst, src) in ifr.ifr_name.iter_mut().zip(LOOPBACK) {
+        *dst = *src as libc::c_char;
+    }
+    ifr.ifr_ifru.ifru_flags =
+        (libc::IFF_UP | libc::IFF_LOOPBACK | libc::IFF_RUNNING) as libc::c_short;
+    let ret = unsafe { libc::ioctl(sock, libc::SIOCSIFFLAGS as _, &ifr) };
+    // The error of `ioctl` must be read before `close` overwrites it.
+    let result = if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    };
+    unsafe { libc::close(sock) };
+    result
+}
@@ -0,0 +1,366 @@
+use crate::{db::JobSummary, error::K2Error, util};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::json;
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+/// How long to wait for an SMTP server to accept a connection, and for each of its
+/// replies. The notifications are sent between jobs, so a server which hangs
+/// mustn't stall the experiment.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How to deliver emails.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case")]
+pub enum MailTransport {
+    /// Pipe the emails to the `sendmail` executable at the specified path.
+    Sendmail(PathBuf),
+    /// Send the emails to an SMTP server. Neither TLS nor authentication are
+    /// supported, so this is only suitable for a relay on the local network.
+    Smtp { host: String, port: u16 },
+}
+
+impl<'de> Deserialize<'de> for MailTransport {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        util::deserialize_variant(deserializer)
+    }
+}
+
+impl<'de> util::Variants<'de> for MailTransport {
+    fn deserialize_derived<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        MailTransport::deserialize(deserializer)
+    }
+}
+
+impl Serialize for MailTransport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Smtp<'a> {
+            host: &'a str,
+            port: u16,
+        }
+
+        match self {
+            MailTransport::Sendmail(path) => util::serialize_variant(serializer, "sendmail", path),
+            MailTransport::Smtp { host, port } => {
+                util::serialize_variant(serializer, "smtp", &Smtp { host, port: *port })
+            }
+        }
+    }
+}
+
+impl Default for MailTransport {
+    fn default() -> MailTransport {
+        MailTransport::Sendmail(PathBuf::from("/usr/sbin/sendmail"))
+    }
+}
+
+/// An event in the life of an experiment.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// The experiment has started.
+    ExperimentStarted { num_jobs: usize },
+    /// A job has failed.
+    JobFailed {
+        id: usize,
+        key: &'a str,
+        error: &'a K2Error,
+    },
+    /// All the jobs have finished.
+    ExperimentFinished { summary: &'a JobSummary },
+}
+
+impl<'a> Event<'a> {
+    /// A short identifier of the kind of event.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::ExperimentStarted { .. } => "experiment_started",
+            Event::JobFailed { .. } => "job_failed",
+            Event::ExperimentFinished { .. } => "experiment_finished",
+        }
+    }
+
+    /// A one-line summary of the event that happened in `experiment`.
+    pub fn subject(&self, experiment: &str) -> String {
+        match self {
+            Event::ExperimentStarted { .. } => format!("[k2] {}: experiment started", experiment),
+            Event::JobFailed { id, .. } => format!("[k2] {}: job {} failed", experiment, id),
+            Event::ExperimentFinished { .. } => {
+                format!("[k2] {}: experiment finished", experiment)
+            }
+        }
+    }
+
+    /// A detailed description of the event that happened in `experiment`.
+    pub fn message(&self, experiment: &str) -> String {
+        match self {
+            Event::ExperimentStarted { num_jobs } => {
+                format!(
+                    "Experiment {} started: {} jobs to run.",
+                    experiment, num_jobs
+                )
+            }
+            Event::JobFailed { id, key, error } => {
+                format!("Job {} ({}) failed: {}", id, key, error.chain())
+            }
+            Event::ExperimentFinished { summary } => {
+                format!("Experiment {} finished.\n\n{}", experiment, summary)
+            }
+        }
+    }
+}
+
+/// Something that can tell the user about the progress of an experiment.
+pub trait Notifier: Send {
+    /// Notify the user that `event` happened in `experiment`.
+    fn notify(&self, experiment: &str, event: &Event) -> Result<(), K2Error>;
+}
+
+/// Sends email notifications about the progress of an experiment.
+pub(crate) struct Mailer {
+    transport: MailTransport,
+    from: String,
+    to: Vec<String>,
+}
+
+impl Notifier for Mailer {
+    fn notify(&self, experiment: &str, event: &Event) -> Result<(), K2Error> {
+        self.send(&event.subject(experiment), &event.message(experiment))
+    }
+}
+
+impl Mailer {
+    pub fn new(transport: MailTransport, to: Vec<String>) -> Mailer {
+        Mailer {
+            transport,
+            from: format!("k2@{}", util::hostname()),
+            to,
+        }
+    }
+
+    fn send(&self, subject: &str, body: &str) -> Result<(), K2Error> {
+        if self.to.is_empty() {
+            return Ok(());
+        }
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\n\r\n{}\r\n",
+            self.from,
+            self.to.join(", "),
+            subject,
+            body.replace('\n', "\r\n")
+        );
+        match &self.transport {
+            MailTransport::Sendmail(path) => self.sendmail(path, &message),
+            MailTransport::Smtp { host, port } => self.smtp(host, *port, &message),
+        }
+    }
+
+    fn sendmail(&self, path: &PathBuf, message: &str) -> Result<(), K2Error> {
+        let mut child = Command::new(path).arg("-t").stdin(Stdio::piped()).spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(message.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            Ok(())
+        } else {
+            Err(K2Error::Notification(format!("{} failed", path.display())))
+        }
+    }
+
+    fn smtp(&self, host: &str, port: u16, message: &str) -> Result<(), K2Error> {
+        let stream = smtp_connect(host, port)?;
+        stream.set_read_timeout(Some(SMTP_TIMEOUT))?;
+        stream.set_write_timeout(Some(SMTP_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        smtp_reply(&mut reader)?;
+        smtp_cmd(
+            &mut writer,
+            &mut reader,
+            &format!("HELO {}", util::hostname()),
+        )?;
+        smtp_cmd(
+            &mut writer,
+            &mut reader,
+            &format!("MAIL FROM:<{}>", self.from),
+        )?;
+        for to in &self.to {
+            smtp_cmd(&mut writer, &mut reader, &format!("RCPT TO:<{}>", to))?;
+        }
+        smtp_cmd(&mut writer, &mut reader, "DATA")?;
+        // Lines starting with a '.' must be escaped by doubling the '.'.
+        let message = message.replace("\r\n.", "\r\n..");
+        smtp_cmd(&mut writer, &mut reader, &format!("{}\r\n.", message))?;
+        smtp_cmd(&mut writer, &mut reader, "QUIT")
+    }
+}
+
+/// Connect to the SMTP server at `host:port`, trying each of its addresses in turn.
+fn smtp_connect(host: &str, port: u16) -> Result<TcpStream, K2Error> {
+    let mut last_err = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, SMTP_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err
+        .unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", host))
+        })
+        .into())
+}
+
+/// Posts notifications to a Slack (or Slack-compatible, e.g. Mattermost) incoming
+/// webhook.
+pub struct SlackNotifier {
+    webhook_url: String,
+}
+
+impl SlackNotifier {
+    pub fn new(webhook_url: &str) -> SlackNotifier {
+        SlackNotifier {
+            webhook_url: webhook_url.to_string(),
+        }
+    }
+}
+
+impl Notifier for SlackNotifier {
+    fn notify(&self, experiment: &str, event: &Event) -> Result<(), K2Error> {
+        let payload = json!({
+            "text": format!("*{}*\n{}", event.subject(experiment), event.message(experiment)),
+        });
+        http_post(&self.webhook_url, &payload.to_string())
+    }
+}
+
+/// POSTs each notification as a JSON object to an arbitrary URL.
+///
+/// The object has the fields `experiment`, `event` (the kind of event), `subject`
+/// and `message`.
+pub struct HttpNotifier {
+    url: String,
+}
+
+impl HttpNotifier {
+    pub fn new(url: &str) -> HttpNotifier {
+        HttpNotifier {
+            url: url.to_string(),
+        }
+    }
+}
+
+impl Notifier for HttpNotifier {
+    fn notify(&self, experiment: &str, event: &Event) -> Result<(), K2Error> {
+        let payload = json!({
+            "experiment": experiment,
+            "event": event.kind(),
+            "subject": event.subject(experiment),
+            "message": event.message(experiment),
+        });
+        http_post(&self.url, &payload.to_string())
+    }
+}
+
+/// Runs a command for each notification.
+///
+/// The details of the event are passed to the command through the
+/// `K2_EXPERIMENT`, `K2_EVENT`, `K2_SUBJECT` and `K2_MESSAGE` environment
+/// variables.
+pub struct CommandNotifier {
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+impl CommandNotifier {
+    pub fn new(program: &str) -> CommandNotifier {
+        CommandNotifier {
+            program: PathBuf::from(program),
+            args: Default::default(),
+        }
+    }
+
+    pub fn arg(mut self, arg: &str) -> CommandNotifier {
+        self.args.push(arg.to_string());
+        self
+    }
+}
+
+impl Notifier for CommandNotifier {
+    fn notify(&self, experiment: &str, event: &Event) -> Result<(), K2Error> {
+        let status = Command::new(&self.program)
+            .args(&self.args)
+            .env("K2_EXPERIMENT", experiment)
+            .env("K2_EVENT", event.kind())
+            .env("K2_SUBJECT", event.subject(experiment))
+            .env("K2_MESSAGE", event.message(experiment))
+            .status()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(K2Error::Notification(format!(
+                "{} failed",
+                self.program.display()
+            )))
+        }
+    }
+}
+
+/// POST `body` (a JSON document) to `url` using `curl`.
+fn http_post(url: &str, body: &str) -> Result<(), K2Error> {
+    let args = [
+        "--header",
+        "Content-Type: application/json",
+        "--data-binary",
+        "@-",
+    ];
+    if util::http_request("POST", url, &args, body)? {
+        Ok(())
+    } else {
+        Err(K2Error::Notification(format!("POST {} failed", url)))
+    }
+}
+
+/// Send an SMTP command, and check the reply of the server.
+fn smtp_cmd<W: Write, R: BufRead>(
+    writer: &mut W,
+    reader: &mut R,
+    cmd: &str,
+) -> Result<(), K2Error> {
+    write!(writer, "{}\r\n", cmd)?;
+    writer.flush()?;
+    smtp_reply(reader)
+}
+
+/// Read a (possibly multiline) SMTP reply, and check it isn't an error.
+fn smtp_reply<R: BufRead>(reader: &mut R) -> Result<(), K2Error> {
+    loop {
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .map_err(|err| match err.kind() {
+                // The read timed out (see `SMTP_TIMEOUT`).
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => K2Error::Notification(
+                    format!("the SMTP server didn't reply within {:?}", SMTP_TIMEOUT),
+                ),
+                _ => err.into(),
+            })?;
+        // The last line of a reply has a space after the reply code.
+        if line.len() < 4 || line.as_bytes()[3] != b'-' {
+            return match line.chars().next() {
+                Some('2') | Some('3') => Ok(()),
+                _ => Err(K2Error::Notification(format!(
+                    "SMTP error: {}",
+                    line.trim()
+                ))),
+            };
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! NUMA topology and memory binding.
+//!
+//! On a NUMA machine, a benchmark is slower if its memory is allocated on a node
+//! other than the one it runs on, and the kernel may move it between nodes from
+//! one process execution to the next. Binding a benchmark to a node (see
+//! `Config::numa_node`) is the equivalent of running it under
+//! `numactl --membind=<node> --cpunodebind=<node>`: its memory is only allocated on
+//! the node, and it only runs on the CPUs of the node (unless it is pinned to other
+//! CPUs, see `Config::cpu_affinity`).
+
+use crate::{error::K2Error, isolation};
+
+use std::{fs, io, path::Path};
+
+/// The directory where the NUMA nodes are exposed.
+const NODE_DIR: &str = "/sys/devices/system/node";
+/// The memory policy which only allocates memory on the nodes of a node mask (see
+/// `set_mempolicy(2)`).
+const MPOL_BIND: libc::c_int = 2;
+/// The number of bits in each word of a node mask.
+const MASK_WORD_BITS: usize = libc::c_ulong::BITS as usize;
+
+/// A NUMA node of the machine.
+#[derive(Debug, Clone)]
+pub struct NumaNode {
+    pub id: usize,
+    /// The CPUs of the node.
+    pub cpus: Vec<usize>,
+    /// The memory of the node, as reported by its `meminfo` (such as `16384 kB`).
+    pub mem_total: Option<String>,
+}
+
+/// Find the NUMA nodes of the machine. Returns an empty list if the kernel doesn't
+/// support NUMA.
+pub fn nodes() -> Result<Vec<NumaNode>, K2Error> {
+    let dir = Path::new(NODE_DIR);
+    if !dir.exists() {
+        return Ok(Default::default());
+    }
+    let mut nodes = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let id = match path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|id| id.parse().ok())
+        {
+            Some(id) => id,
+            None => continue,
+        };
+        let cpus = isolation::parse_cpu_list(&fs::read_to_string(path.join("cpulist"))?)?;
+        // Each line of the `meminfo` of a node has the form
+        // `Node <id> MemTotal:       16384 kB`.
+        let mem_total = fs::read_to_string(path.join("meminfo"))
+            .ok()
+            .and_then(|meminfo| {
+                meminfo
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(key, _)| key.ends_with("MemTotal"))
+                    .map(|(_, value)| value.trim().to_string())
+            });
+        nodes.push(NumaNode {
+            id,
+            cpus,
+            mem_total,
+        });
+    }
+    nodes.sort_by_key(|node| node.id);
+    Ok(nodes)
+}
+
+/// Describe the NUMA topology of the machine, for the audit: the CPUs and memory
+/// of each node, such as `node0: cpus 0-7, 16384 kB; node1: cpus 8-15, 16384 kB`.
+pub(crate) fn topology() -> String {
+    match nodes() {
+        Ok(nodes) if !nodes.is_empty() => nodes
+            .iter()
+            .map(|node| {
+                format!(
+                    "node{}: cpus {}, {}",
+                    node.id,
+                    isolation::format_cpu_list(&node.cpus),
+                    node.mem_total.as_deref().unwrap_or("unknown")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; "),
+        _ => "unknown".into(),
+    }
+}
+
+/// The NUMA node with identifier `id`.
+///
+/// Returns `K2Error::InvalidConfig` if the machine has no such node.
+pub fn node(id: usize) -> Result<NumaNode, K2Error> {
+    nodes()?
+        .into_iter()
+        .find(|node| node.id == id)
+        .ok_or_else(|| K2Error::InvalidConfig(format!("The machine has no NUMA node {}", id)))
+}
+
+/// Build the node mask of `set_mempolicy(2)` which only contains `node`.
+pub(crate) fn node_mask(node: usize) -> Vec<libc::c_ulong> {
+    let mut mask = vec![0; node / MASK_WORD_BITS + 1];
+    mask[node / MASK_WORD_BITS] |= 1 << (node % MASK_WORD_BITS);
+    mask
+}
+
+/// Only allocate the memory of the calling process (and of the processes it
+/// starts) on the nodes of `mask` (see `node_mask`).
+///
+/// This is called between `fork` and `exec`, so it must not allocate.
+pub(crate) fn bind_memory(mask: &[libc::c_ulong]) -> io::Result<()> {
+    // The kernel ignores the last bit of the mask, so `maxnode` is one more than
+    // the number of nodes the mask describes (as `numactl` does).
+    let maxnode = mask.len() * MASK_WORD_BITS + 1;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_set_mempolicy,
+            MPOL_BIND,
+            mask.as_ptr(),
+            maxnode as libc::c_ulong,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
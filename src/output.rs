@@ -0,0 +1,38 @@
+use crate::{error::K2Error, process::InvocationOutcome};
+
+use flate2::{write::GzEncoder, Compression};
+
+use std::{fs, io::Write, path::Path};
+
+/// The directory (relative to the results directory) where the output of each job
+/// is stored.
+pub const OUTPUT_DIR: &str = "output";
+/// Output larger than this many bytes is gzip-compressed before being stored.
+const COMPRESSION_THRESHOLD: usize = 64 * 1024;
+
+/// Store the stdout and stderr of the job with identifier `id`.
+///
+/// The output is written to `<results_dir>/output/<id>.stdout` and
+/// `<results_dir>/output/<id>.stderr` (with a `.gz` suffix if it is compressed).
+pub(crate) fn save<P: AsRef<Path>>(
+    results_dir: P,
+    id: usize,
+    output: &InvocationOutcome,
+) -> Result<(), K2Error> {
+    let output_dir = results_dir.as_ref().join(OUTPUT_DIR);
+    fs::create_dir_all(&output_dir).map_err(|err| K2Error::file(&output_dir, err))?;
+    save_stream(&output_dir, &format!("{}.stdout", id), &output.stdout)?;
+    save_stream(&output_dir, &format!("{}.stderr", id), &output.stderr)
+}
+
+fn save_stream(output_dir: &Path, file_name: &str, contents: &[u8]) -> Result<(), K2Error> {
+    if contents.len() > COMPRESSION_THRESHOLD {
+        let file = fs::File::create(output_dir.join(format!("{}.gz", file_name)))?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        encoder.write_all(contents)?;
+        encoder.finish()?;
+    } else {
+        fs::write(output_dir.join(file_name), contents)?;
+    }
+    Ok(())
+}
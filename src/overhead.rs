@@ -0,0 +1,113 @@
+//! Measuring the overhead of running a benchmark on each language implementation.
+//!
+//! The wall-clock time of a process execution includes the time it takes to start
+//! the language implementation and load the benchmark, and the wall-clock time of
+//! each in-process iteration includes the time it takes the iterations runner to
+//! read the clock. When the overhead of an experiment is measured (see
+//! `ExperimentBuilder::overhead`), an empty benchmark (one whose iterations do
+//! nothing, see `ExperimentBuilder::empty_benchmark`) is run `OverheadConfig::pexecs`
+//! times on each language implementation which has one, before the first job, with
+//! the same number of in-process iterations as the other benchmarks.
+//!
+//! The overhead of each language implementation is recorded in the `overhead`
+//! table of the results database (see `Results::overheads`), so it is only measured
+//! once, and logged. `Results::adjusted_wallclock_times` subtracts the overhead of
+//! an in-process iteration from the wall-clock times of the benchmarks.
+
+use crate::{error::K2Error, measure::Measurements};
+
+use serde::{Deserialize, Serialize};
+
+use std::time::Duration;
+
+/// How to measure the overhead of the language implementations.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverheadConfig {
+    /// The number of process executions of each empty benchmark.
+    #[serde(default = "default_pexecs")]
+    pub pexecs: usize,
+}
+
+fn default_pexecs() -> usize {
+    10
+}
+
+impl Default for OverheadConfig {
+    fn default() -> OverheadConfig {
+        OverheadConfig::new()
+    }
+}
+
+impl OverheadConfig {
+    /// Run each empty benchmark 10 times.
+    pub fn new() -> OverheadConfig {
+        OverheadConfig {
+            pexecs: default_pexecs(),
+        }
+    }
+
+    /// Run each empty benchmark `pexecs` times, instead of 10.
+    pub fn pexecs(mut self, pexecs: usize) -> Self {
+        self.pexecs = pexecs;
+        self
+    }
+
+    /// Check that the empty benchmarks run at least once.
+    pub(crate) fn check(&self) -> Result<(), K2Error> {
+        if self.pexecs > 0 {
+            Ok(())
+        } else {
+            Err(K2Error::InvalidConfig(
+                "Invalid overhead pexecs: 0 (expected at least 1)".into(),
+            ))
+        }
+    }
+}
+
+/// The overhead of running a benchmark on a language implementation, in seconds,
+/// measured by running an empty benchmark on it.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Overhead {
+    /// The number of process executions of the empty benchmark.
+    pub pexecs: usize,
+    /// The mean time a process execution spent outside its in-process iterations
+    /// (starting the language implementation, loading the benchmark, and exiting).
+    pub startup: f64,
+    /// The mean wall-clock time of an empty in-process iteration, or `None` if the
+    /// empty benchmark didn't report the wall-clock time of its iterations.
+    pub iteration: Option<f64>,
+}
+
+impl Overhead {
+    /// The overhead measured by the process executions of an empty benchmark, each
+    /// of which took the given time and reported the given measurements.
+    pub(crate) fn new(pexecs: &[(Duration, Measurements)]) -> Overhead {
+        let mut startup = 0.0;
+        let (mut iters, mut iters_time) = (0, 0.0);
+        for (elapsed, measurements) in pexecs {
+            let pexec_iters_time = measurements
+                .wallclock
+                .iter()
+                .map(Duration::as_secs_f64)
+                .sum::<f64>();
+            startup += (elapsed.as_secs_f64() - pexec_iters_time).max(0.0);
+            iters += measurements.num_iters();
+            iters_time += pexec_iters_time;
+        }
+        Overhead {
+            pexecs: pexecs.len(),
+            startup: startup / pexecs.len().max(1) as f64,
+            iteration: Some(iters_time / iters as f64).filter(|_| iters > 0),
+        }
+    }
+
+    /// Subtract the overhead of an in-process iteration from the wall-clock time of
+    /// each in-process iteration of `pexecs`. A time is never adjusted below 0.
+    pub(crate) fn adjust(&self, pexecs: &mut [Vec<f64>]) {
+        let iteration = self.iteration.unwrap_or_default();
+        for time in pexecs.iter_mut().flatten() {
+            *time = (*time - iteration).max(0.0);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Overriding the configuration of an experiment without recompiling it.
+//!
+//! The operational settings of an experiment (whether it runs in quick mode or as
+//! a dry run, whether the machine reboots between jobs, and where the results go)
+//! often change from one run to the next, while the benchmarks don't. These
+//! settings can be overridden, in this order, by:
+//!
+//! 1. the `K2_*` environment variables (see `ExperimentBuilder::apply_env`):
+//!    `K2_QUICK`, `K2_DRY_RUN` and `K2_REBOOT`, which are set to
+//!    `1`/`true`/`yes`/`on` or `0`/`false`/`no`/`off`, `K2_RESULTS_DIR`, and
+//!    `K2_EXPERIMENT`, which names the experiment (see `registry`);
+//! 2. the command-line options of `args` (see `ExperimentBuilder::apply_cli`):
+//!    `--quick`, `--dry-run`, `--reboot`/`--no-reboot`, `--results-dir` and
+//!    `--experiment`.
+//!
+//! A setting which isn't overridden keeps the value it was given by the builder (or
+//! the configuration file). The command line is preserved when k2 restarts after a
+//! job, or resumes the experiment after a reboot, but the environment isn't
+//! necessarily preserved across reboots (see `boot`): the settings which must stay
+//! the same for the whole experiment are best passed on the command line.
+
+use crate::{error::K2Error, experiment::ExperimentBuilder};
+
+use clap::{Arg, ArgMatches};
+
+use std::env;
+
+/// The environment variable which sets `ExperimentBuilder::quick`.
+pub const QUICK_VAR: &str = "K2_QUICK";
+/// The environment variable which sets `ExperimentBuilder::dry_run`.
+pub const DRY_RUN_VAR: &str = "K2_DRY_RUN";
+/// The environment variable which sets `ExperimentBuilder::reboot`.
+pub const REBOOT_VAR: &str = "K2_REBOOT";
+/// The environment variable which sets `ExperimentBuilder::results_dir`.
+pub const RESULTS_DIR_VAR: &str = "K2_RESULTS_DIR";
+/// The environment variable which sets `ExperimentBuilder::name`.
+pub const EXPERIMENT_VAR: &str = "K2_EXPERIMENT";
+
+/// The command-line options which override the configuration, to add to the
+/// arguments of a program which runs an experiment.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("quick")
+            .long("quick")
+            .help("Run the benchmarks in quick mode"),
+        Arg::with_name("dry_run")
+            .long("dry-run")
+            .help("Check the experiment without running the benchmarks"),
+        Arg::with_name("reboot")
+            .long("reboot")
+            .conflicts_with("no_reboot")
+            .help("Reboot the machine between jobs"),
+        Arg::with_name("no_reboot")
+            .long("no-reboot")
+            .help("Don't reboot the machine between jobs"),
+        Arg::with_name("results_dir")
+            .long("results-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("The directory to store the results in"),
+        Arg::with_name("experiment")
+            .long("experiment")
+            .takes_value(true)
+            .value_name("NAME")
+            .help("The name of the experiment, to share the results directory with others"),
+    ]
+}
+
+/// Override the configuration of `expb` with the environment variables.
+pub(crate) fn apply_env(mut expb: ExperimentBuilder) -> Result<ExperimentBuilder, K2Error> {
+    if let Some(quick) = env_bool(QUICK_VAR)? {
+        expb = expb.quick(quick);
+    }
+    if let Some(dry_run) = env_bool(DRY_RUN_VAR)? {
+        expb = expb.dry_run(dry_run);
+    }
+    if let Some(reboot) = env_bool(REBOOT_VAR)? {
+        expb = expb.reboot(reboot);
+    }
+    if let Some(results_dir) = env::var_os(RESULTS_DIR_VAR).filter(|dir| !dir.is_empty()) {
+        expb = expb.results_dir(results_dir);
+    }
+    match env::var(EXPERIMENT_VAR) {
+        Ok(name) if !name.is_empty() => expb = expb.name(&name),
+        Ok(_) | Err(env::VarError::NotPresent) => (),
+        Err(env::VarError::NotUnicode(_)) => {
+            return Err(K2Error::InvalidConfig(format!(
+                "{} is not valid UTF-8",
+                EXPERIMENT_VAR
+            )))
+        }
+    }
+    Ok(expb)
+}
+
+/// Override the configuration of `expb` with the options of `args` which are
+/// present in `matches`.
+pub(crate) fn apply_cli(mut expb: ExperimentBuilder, matches: &ArgMatches) -> ExperimentBuilder {
+    if matches.is_present("quick") {
+        expb = expb.quick(true);
+    }
+    if matches.is_present("dry_run") {
+        expb = expb.dry_run(true);
+    }
+    if matches.is_present("reboot") {
+        expb = expb.reboot(true);
+    } else if matches.is_present("no_reboot") {
+        expb = expb.reboot(false);
+    }
+    if let Some(results_dir) = matches.value_of_os("results_dir") {
+        expb = expb.results_dir(results_dir);
+    }
+    if let Some(name) = matches.value_of("experiment") {
+        expb = expb.name(name);
+    }
+    expb
+}
+
+/// The value of the boolean environment variable `var`, or `None` if it isn't set.
+fn env_bool(var: &str) -> Result<Option<bool>, K2Error> {
+    let value = match env::var(var) {
+        Ok(value) => value,
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(_)) => {
+            return Err(K2Error::InvalidConfig(format!(
+                "{} is not valid UTF-8",
+                var
+            )))
+        }
+    };
+    match value.trim().to_lowercase().as_str() {
+        "" => Ok(None),
+        "1" | "true" | "yes" | "on" => Ok(Some(true)),
+        "0" | "false" | "no" | "off" => Ok(Some(false)),
+        _ => Err(K2Error::InvalidConfig(format!(
+            "Invalid {}: {} (expected true or false)",
+            var, value
+        ))),
+    }
+}
@@ -0,0 +1,326 @@
+//! Hardware (and software) performance counters, collected with `perf_event_open`.
+//!
+//! The counters are only available if k2 is built with the `perf` feature.
+
+use crate::{
+    error::K2Error,
+    measure::{MeasurementCollector, Measurements},
+    process::InvocationOutcome,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A performance counter to collect for each process execution.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PerfEvent {
+    /// The number of CPU cycles.
+    Cycles,
+    /// The number of instructions retired.
+    Instructions,
+    /// The number of last-level cache accesses.
+    CacheReferences,
+    /// The number of last-level cache misses.
+    CacheMisses,
+    /// The number of branch instructions retired.
+    BranchInstructions,
+    /// The number of mispredicted branches.
+    BranchMisses,
+    /// The CPU time, in nanoseconds.
+    TaskClock,
+    /// The number of page faults.
+    PageFaults,
+    /// The number of context switches.
+    ContextSwitches,
+    /// The number of times the process migrated to a different CPU.
+    CpuMigrations,
+}
+
+impl PerfEvent {
+    /// The name used to identify the counter in the results.
+    pub fn name(self) -> &'static str {
+        match self {
+            PerfEvent::Cycles => "cycles",
+            PerfEvent::Instructions => "instructions",
+            PerfEvent::CacheReferences => "cache_references",
+            PerfEvent::CacheMisses => "cache_misses",
+            PerfEvent::BranchInstructions => "branch_instructions",
+            PerfEvent::BranchMisses => "branch_misses",
+            PerfEvent::TaskClock => "task_clock",
+            PerfEvent::PageFaults => "page_faults",
+            PerfEvent::ContextSwitches => "context_switches",
+            PerfEvent::CpuMigrations => "cpu_migrations",
+        }
+    }
+}
+
+/// Collects the performance counters in `ProcessOpts::perf_events`, as
+/// `perf:<event>`.
+///
+/// The counters are opened by `process::run`, since they must be attached to the
+/// benchmark process before it starts.
+#[derive(Debug, Default)]
+pub struct PerfCollector;
+
+impl MeasurementCollector for PerfCollector {
+    fn collect(
+        &mut self,
+        outcome: &InvocationOutcome,
+        measurements: &mut Measurements,
+    ) -> Result<(), K2Error> {
+        for (name, value) in &outcome.perf_counters {
+            measurements
+                .per_pexec
+                .insert(format!("perf:{}", name), *value as f64);
+        }
+        Ok(())
+    }
+}
+
+/// Return an error if the performance counters can't be collected, because k2 was
+/// built without the `perf` feature.
+pub(crate) fn check_supported(events: &[PerfEvent]) -> Result<(), K2Error> {
+    if !events.is_empty() && cfg!(not(feature = "perf")) {
+        return Err(K2Error::InvalidConfig(
+            "k2 must be built with the `perf` feature to collect performance counters".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "perf")]
+mod imp {
+    use super::PerfEvent;
+    use crate::error::K2Error;
+
+    use std::{
+        collections::BTreeMap,
+        io, mem,
+        os::unix::io::RawFd,
+        thread::{self, JoinHandle},
+    };
+
+    const PERF_TYPE_HARDWARE: u32 = 0;
+    const PERF_TYPE_SOFTWARE: u32 = 1;
+    const PERF_FORMAT_TOTAL_TIME_ENABLED: u64 = 1;
+    const PERF_FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+    const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+    /// The flags of `perf_event_attr`: `disabled`, `inherit`, `exclude_kernel`,
+    /// `exclude_hv` and `enable_on_exec`. The kernel and hypervisor are excluded, so
+    /// that the counters can be opened by unprivileged users.
+    const ATTR_FLAGS: u64 = 1 | 1 << 1 | 1 << 5 | 1 << 6 | 1 << 12;
+
+    /// The first version of `struct perf_event_attr`, which is all k2 needs.
+    #[repr(C)]
+    #[derive(Default)]
+    struct PerfEventAttr {
+        type_: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+    }
+
+    impl PerfEvent {
+        fn type_and_config(self) -> (u32, u64) {
+            match self {
+                PerfEvent::Cycles => (PERF_TYPE_HARDWARE, 0),
+                PerfEvent::Instructions => (PERF_TYPE_HARDWARE, 1),
+                PerfEvent::CacheReferences => (PERF_TYPE_HARDWARE, 2),
+                PerfEvent::CacheMisses => (PERF_TYPE_HARDWARE, 3),
+                PerfEvent::BranchInstructions => (PERF_TYPE_HARDWARE, 4),
+                PerfEvent::BranchMisses => (PERF_TYPE_HARDWARE, 5),
+                PerfEvent::TaskClock => (PERF_TYPE_SOFTWARE, 1),
+                PerfEvent::PageFaults => (PERF_TYPE_SOFTWARE, 2),
+                PerfEvent::ContextSwitches => (PERF_TYPE_SOFTWARE, 3),
+                PerfEvent::CpuMigrations => (PERF_TYPE_SOFTWARE, 4),
+            }
+        }
+    }
+
+    /// A file descriptor which is closed when dropped.
+    struct Fd(RawFd);
+
+    impl Drop for Fd {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.0) };
+        }
+    }
+
+    fn pipe() -> io::Result<(Fd, Fd)> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((Fd(fds[0]), Fd(fds[1])))
+    }
+
+    /// A set of open counters.
+    struct Counters(Vec<(PerfEvent, Fd)>);
+
+    impl Counters {
+        /// Open a counter for each event in `events`, which starts counting when
+        /// process `pid` calls `exec`.
+        fn open(events: &[PerfEvent], pid: libc::pid_t) -> Result<Counters, K2Error> {
+            let mut counters = Vec::with_capacity(events.len());
+            for event in events {
+                let (type_, config) = event.type_and_config();
+                let attr = PerfEventAttr {
+                    type_,
+                    size: mem::size_of::<PerfEventAttr>() as u32,
+                    config,
+                    read_format: PERF_FORMAT_TOTAL_TIME_ENABLED | PERF_FORMAT_TOTAL_TIME_RUNNING,
+                    flags: ATTR_FLAGS,
+                    ..Default::default()
+                };
+                let fd = unsafe {
+                    libc::syscall(
+                        libc::SYS_perf_event_open,
+                        &attr as *const PerfEventAttr,
+                        pid,
+                        -1,
+                        -1,
+                        PERF_FLAG_FD_CLOEXEC,
+                    )
+                };
+                if fd < 0 {
+                    return Err(K2Error::SensorError(format!(
+                        "Failed to open the {} counter: {}",
+                        event.name(),
+                        io::Error::last_os_error()
+                    )));
+                }
+                counters.push((*event, Fd(fd as RawFd)));
+            }
+            Ok(Counters(counters))
+        }
+
+        /// Read the value of each counter. If the counters had to be multiplexed,
+        /// the values are scaled to estimate the value over the whole run.
+        fn read(&self) -> Result<BTreeMap<String, u64>, K2Error> {
+            let mut values = BTreeMap::new();
+            for (event, fd) in &self.0 {
+                // The value, the time enabled and the time running.
+                let mut buf = [0u64; 3];
+                let size = mem::size_of_val(&buf);
+                let n = unsafe { libc::read(fd.0, buf.as_mut_ptr() as *mut libc::c_void, size) };
+                if n != size as isize {
+                    return Err(K2Error::SensorError(format!(
+                        "Failed to read the {} counter",
+                        event.name()
+                    )));
+                }
+                let [value, enabled, running] = buf;
+                let value = if running == 0 {
+                    0
+                } else {
+                    (value as f64 * enabled as f64 / running as f64) as u64
+                };
+                values.insert(event.name().to_string(), value);
+            }
+            Ok(values)
+        }
+    }
+
+    /// The counters of a benchmark process.
+    ///
+    /// The counters must be opened before the process calls `exec`, but
+    /// `Command::spawn` doesn't return until then. The child process therefore
+    /// sends its pid to a helper thread (from its `pre_exec` hook), and waits for
+    /// the thread to open the counters before calling `exec`.
+    pub(crate) struct PerfSession {
+        /// The write end of the pipe the child sends its pid through, and the read
+        /// end of the pipe the helper thread tells the child to continue through.
+        child_fds: Option<(Fd, Fd)>,
+        /// The helper thread.
+        handle: JoinHandle<Result<Counters, K2Error>>,
+    }
+
+    impl PerfSession {
+        /// Start the helper thread which opens the counters of the child.
+        pub fn start(events: &[PerfEvent]) -> Result<PerfSession, K2Error> {
+            let (pid_read, pid_write) = pipe()?;
+            let (go_read, go_write) = pipe()?;
+            let events = events.to_vec();
+            let handle = thread::spawn(move || {
+                let mut pid: libc::pid_t = 0;
+                let size = mem::size_of_val(&pid);
+                let n = unsafe {
+                    libc::read(
+                        pid_read.0,
+                        &mut pid as *mut libc::pid_t as *mut libc::c_void,
+                        size,
+                    )
+                };
+                if n != size as isize {
+                    return Err(K2Error::SensorError(
+                        "The benchmark process exited before its counters were opened".into(),
+                    ));
+                }
+                let counters = Counters::open(&events, pid);
+                // Let the child continue, even if the counters couldn't be opened.
+                unsafe { libc::write(go_write.0, [0u8].as_ptr() as *const libc::c_void, 1) };
+                counters
+            });
+            Ok(PerfSession {
+                child_fds: Some((pid_write, go_read)),
+                handle,
+            })
+        }
+
+        /// The file descriptors to pass to `child_hook`.
+        pub fn child_fds(&self) -> (RawFd, RawFd) {
+            match &self.child_fds {
+                Some((pid_write, go_read)) => (pid_write.0, go_read.0),
+                None => (-1, -1),
+            }
+        }
+
+        /// Close the file descriptors used by the child. This must be called once
+        /// the child has been spawned, so that the helper thread gives up if the
+        /// child exits before sending its pid.
+        pub fn close_child_fds(&mut self) {
+            self.child_fds = None;
+        }
+
+        /// Read the counters. This must be called after the process has exited.
+        pub fn finish(mut self) -> Result<BTreeMap<String, u64>, K2Error> {
+            self.close_child_fds();
+            self.handle
+                .join()
+                .map_err(|_| K2Error::SensorError("The perf thread panicked".into()))??
+                .read()
+        }
+    }
+
+    /// Called in the child process (from a `pre_exec` hook) with the file
+    /// descriptors returned by `PerfSession::child_fds`. This only uses
+    /// async-signal-safe functions.
+    pub(crate) fn child_hook((pid_write, go_read): (RawFd, RawFd)) -> io::Result<()> {
+        let pid = unsafe { libc::getpid() };
+        let size = mem::size_of_val(&pid);
+        let n = unsafe {
+            libc::write(
+                pid_write,
+                &pid as *const libc::pid_t as *const libc::c_void,
+                size,
+            )
+        };
+        if n != size as isize {
+            return Err(io::Error::last_os_error());
+        }
+        let mut go = 0u8;
+        if unsafe { libc::read(go_read, &mut go as *mut u8 as *mut libc::c_void, 1) } != 1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "perf")]
+pub(crate) use self::imp::{child_hook, PerfSession};
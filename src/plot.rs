@@ -0,0 +1,200 @@
+//! Plots of the wall-clock times of the benchmarks, drawn as SVG images.
+//!
+//! Each function returns the SVG document as a string, which can be written to a
+//! file or embedded in an HTML page (see `report`). The plots are only available if
+//! k2 is built with the `plot` feature.
+
+use crate::{error::K2Error, measure::Measurements};
+
+use plotters::prelude::*;
+
+use std::ops::Range;
+
+/// The size of each plot, in pixels.
+const PLOT_SIZE: (u32, u32) = (800, 400);
+/// The size of the font of the caption of each plot.
+const CAPTION_SIZE: u32 = 16;
+/// The radius of the points of a lag plot, and of the outliers of a run-sequence
+/// plot.
+const POINT_SIZE: u32 = 2;
+
+/// Draw the wall-clock time of each in-process iteration of a process execution,
+/// in iteration order. The outliers (see `analysis::outliers`) are circled in red.
+pub fn run_sequence(measurements: &Measurements, title: &str) -> Result<String, K2Error> {
+    let times = wallclock_secs(measurements);
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, PLOT_SIZE).into_drawing_area();
+        root.fill(&WHITE).map_err(plot_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", CAPTION_SIZE))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(70)
+            .build_cartesian_2d(0..times.len().max(1), value_range(times.iter().copied()))
+            .map_err(plot_error)?;
+        chart
+            .configure_mesh()
+            .x_desc("In-process iteration")
+            .y_desc("Wall-clock time (s)")
+            .draw()
+            .map_err(plot_error)?;
+        chart
+            .draw_series(LineSeries::new(times.iter().copied().enumerate(), &BLUE))
+            .map_err(plot_error)?;
+        let outliers = measurements
+            .outliers
+            .iter()
+            .filter_map(|&iter| Some((iter, *times.get(iter)?)))
+            .map(|point| Circle::new(point, POINT_SIZE + 2, RED.stroke_width(1)));
+        chart.draw_series(outliers).map_err(plot_error)?;
+        root.present().map_err(plot_error)?;
+    }
+    Ok(svg)
+}
+
+/// Draw the wall-clock time of each in-process iteration of a process execution
+/// against the wall-clock time of the iteration `lag` iterations before it. Points
+/// away from the diagonal show that the time of an iteration depends on the ones
+/// before it (for example, because of a periodic garbage collection).
+pub fn lag(measurements: &Measurements, lag: usize, title: &str) -> Result<String, K2Error> {
+    if lag == 0 {
+        return Err(K2Error::InvalidConfig(
+            "The lag of a lag plot must be at least 1".into(),
+        ));
+    }
+    let times = wallclock_secs(measurements);
+    let range = value_range(times.iter().copied());
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, PLOT_SIZE).into_drawing_area();
+        root.fill(&WHITE).map_err(plot_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", CAPTION_SIZE))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(70)
+            .build_cartesian_2d(range.clone(), range)
+            .map_err(plot_error)?;
+        chart
+            .configure_mesh()
+            .x_desc(format!("Wall-clock time of iteration i - {} (s)", lag))
+            .y_desc("Wall-clock time of iteration i (s)")
+            .draw()
+            .map_err(plot_error)?;
+        let points = times
+            .iter()
+            .zip(times.iter().skip(lag))
+            .map(|(&before, &after)| Circle::new((before, after), POINT_SIZE, BLUE.filled()));
+        chart.draw_series(points).map_err(plot_error)?;
+        root.present().map_err(plot_error)?;
+    }
+    Ok(svg)
+}
+
+/// Draw the wall-clock time of each in-process iteration of several process
+/// executions of a benchmark, in iteration order: one line (of a different colour)
+/// per process execution. `pexecs` contains the wall-clock times of each process
+/// execution, in seconds (see `Results::wallclock_times`).
+pub fn run_sequences(pexecs: &[Vec<f64>], title: &str) -> Result<String, K2Error> {
+    let num_iters = pexecs.iter().map(Vec::len).max().unwrap_or(0);
+    let y_range = value_range(pexecs.iter().flatten().copied());
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, PLOT_SIZE).into_drawing_area();
+        root.fill(&WHITE).map_err(plot_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", CAPTION_SIZE))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(70)
+            .build_cartesian_2d(0..num_iters.max(1), y_range)
+            .map_err(plot_error)?;
+        chart
+            .configure_mesh()
+            .x_desc("In-process iteration")
+            .y_desc("Wall-clock time (s)")
+            .draw()
+            .map_err(plot_error)?;
+        for (pexec, iters) in pexecs.iter().enumerate() {
+            let color = Palette99::pick(pexec);
+            chart
+                .draw_series(LineSeries::new(iters.iter().copied().enumerate(), &color))
+                .map_err(plot_error)?;
+        }
+        root.present().map_err(plot_error)?;
+    }
+    Ok(svg)
+}
+
+/// Draw the distribution of the wall-clock times of a benchmark on several language
+/// implementations, as box plots. `samples` contains the name of each language
+/// implementation, and the wall-clock times (in seconds) of all its in-process
+/// iterations.
+pub fn box_plot(samples: &[(String, Vec<f64>)], title: &str) -> Result<String, K2Error> {
+    // The quartiles of a box plot are single-precision.
+    let y_range = value_range(samples.iter().flat_map(|(_, times)| times).copied());
+    let y_range = y_range.start as f32..y_range.end as f32;
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, PLOT_SIZE).into_drawing_area();
+        root.fill(&WHITE).map_err(plot_error)?;
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", CAPTION_SIZE))
+            .margin(10)
+            .x_label_area_size(40)
+            .y_label_area_size(70)
+            .build_cartesian_2d((0..samples.len()).into_segmented(), y_range)
+            .map_err(plot_error)?;
+        chart
+            .configure_mesh()
+            .disable_x_mesh()
+            .x_labels(samples.len())
+            .x_label_formatter(&|value| match value {
+                SegmentValue::CenterOf(idx) => samples
+                    .get(*idx)
+                    .map(|(name, _)| name.clone())
+                    .unwrap_or_default(),
+                _ => String::new(),
+            })
+            .y_desc("Wall-clock time (s)")
+            .draw()
+            .map_err(plot_error)?;
+        let boxes = samples
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, times))| !times.is_empty())
+            .map(|(idx, (_, times))| {
+                Boxplot::new_vertical(SegmentValue::CenterOf(idx), &Quartiles::new(times))
+            });
+        chart.draw_series(boxes).map_err(plot_error)?;
+        root.present().map_err(plot_error)?;
+    }
+    Ok(svg)
+}
+
+/// The wall-clock time of each in-process iteration of `measurements`, in seconds.
+fn wallclock_secs(measurements: &Measurements) -> Vec<f64> {
+    measurements
+        .wallclock
+        .iter()
+        .map(|wallclock| wallclock.as_secs_f64())
+        .collect()
+}
+
+/// The range of an axis which shows `values`, with a margin on either side of them.
+fn value_range<I: Iterator<Item = f64>>(values: I) -> Range<f64> {
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+        (min.min(value), max.max(value))
+    });
+    if min > max {
+        return 0.0..1.0;
+    }
+    let margin = ((max - min) * 0.05).max(max.abs() * 0.01).max(1e-9);
+    (min - margin).max(0.0)..max + margin
+}
+
+/// Describe an error of the plotting library.
+fn plot_error<E: std::error::Error + Send + Sync>(err: DrawingAreaErrorKind<E>) -> K2Error {
+    K2Error::Plot(err.to_string())
+}
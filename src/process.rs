@@ -0,0 +1,676 @@
+#[cfg(feature = "perf")]
+use crate::perf::{self, PerfSession};
+use crate::{
+    clock::{Clock, CLOCK_ENV},
+    error::K2Error,
+    limit::{self, Cgroup, ResourceLimits},
+    measure::{CPU_TIME_ENV, IN_PROC_ITERS_ENV},
+    network, numa,
+    perf::PerfEvent,
+    profile::Profile,
+    shutdown, util,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::debug;
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read},
+    mem,
+    os::unix::{
+        io::{AsRawFd, FromRawFd, OwnedFd},
+        process::{CommandExt, ExitStatusExt},
+    },
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How often to check whether a child process has exceeded its timeout (or, if
+/// the kernel doesn't support `pidfd_open`, whether it has exited), whether k2 was
+/// asked to stop while it runs, and how often to sample the memory usage of a
+/// process (see `ProcessOpts::sample_memory`).
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What to do with a benchmark process that exceeds its timeout.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case")]
+pub enum KillBehaviour {
+    /// Send `SIGKILL` straight away.
+    Kill,
+    /// Send `SIGTERM`, and then `SIGKILL` if the process is still running after
+    /// the specified grace period (serialized in seconds).
+    Terminate(#[serde(with = "crate::util::secs")] Duration),
+}
+
+impl<'de> Deserialize<'de> for KillBehaviour {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        util::deserialize_variant(deserializer)
+    }
+}
+
+impl<'de> util::Variants<'de> for KillBehaviour {
+    fn deserialize_derived<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        KillBehaviour::deserialize(deserializer)
+    }
+}
+
+impl Serialize for KillBehaviour {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            KillBehaviour::Kill => serializer.serialize_str("kill"),
+            KillBehaviour::Terminate(grace) => {
+                util::serialize_variant(serializer, "terminate", &grace.as_secs_f64())
+            }
+        }
+    }
+}
+
+/// The scheduling policy of a benchmark process.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(remote = "Self", rename_all = "snake_case")]
+pub enum SchedPolicy {
+    /// Use the default time-sharing scheduler (`SCHED_OTHER`) at the given niceness,
+    /// from -20 (the highest priority) to 19. A negative niceness requires
+    /// `CAP_SYS_NICE`.
+    Nice(i32),
+    /// Use the real-time `SCHED_FIFO` scheduler at the given priority, from 1 to 99.
+    /// The process isn't preempted by any time-sharing process, but it can still be
+    /// throttled by the kernel (see `/proc/sys/kernel/sched_rt_runtime_us`).
+    /// Requires `CAP_SYS_NICE`.
+    Fifo(i32),
+}
+
+impl<'de> Deserialize<'de> for SchedPolicy {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        util::deserialize_variant(deserializer)
+    }
+}
+
+impl<'de> util::Variants<'de> for SchedPolicy {
+    fn deserialize_derived<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        SchedPolicy::deserialize(deserializer)
+    }
+}
+
+impl Serialize for SchedPolicy {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            SchedPolicy::Nice(niceness) => util::serialize_variant(serializer, "nice", &niceness),
+            SchedPolicy::Fifo(priority) => util::serialize_variant(serializer, "fifo", &priority),
+        }
+    }
+}
+
+impl SchedPolicy {
+    /// The name of the policy, as recorded in the audit (e.g. `fifo(50)`).
+    pub fn name(self) -> String {
+        match self {
+            SchedPolicy::Nice(niceness) => format!("nice({})", niceness),
+            SchedPolicy::Fifo(priority) => format!("fifo({})", priority),
+        }
+    }
+
+    /// Check that the niceness or priority is in range.
+    pub(crate) fn check(self) -> Result<(), K2Error> {
+        let in_range = match self {
+            SchedPolicy::Nice(niceness) => (-20..=19).contains(&niceness),
+            SchedPolicy::Fifo(priority) => (1..=99).contains(&priority),
+        };
+        if in_range {
+            Ok(())
+        } else {
+            Err(K2Error::InvalidConfig(format!(
+                "Invalid scheduling policy: {}",
+                self.name()
+            )))
+        }
+    }
+
+    /// Apply the policy to the current process. This only uses async-signal-safe
+    /// functions.
+    fn apply(self) -> io::Result<()> {
+        let ret = match self {
+            SchedPolicy::Nice(niceness) => unsafe {
+                libc::setpriority(libc::PRIO_PROCESS, 0, niceness)
+            },
+            SchedPolicy::Fifo(priority) => {
+                let param = libc::sched_param {
+                    sched_priority: priority,
+                };
+                unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) }
+            }
+        };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+/// The options that control how a benchmark process is run.
+#[derive(Debug, Clone)]
+pub struct ProcessOpts {
+    /// The number of in-process iterations the benchmark should run.
+    pub in_proc_iters: usize,
+    /// The maximum amount of time the process is allowed to run for.
+    pub timeout: Option<Duration>,
+    /// What to do if the process exceeds its timeout.
+    pub kill_behaviour: KillBehaviour,
+    /// How long to let the process run once k2 is asked to stop (see `shutdown`),
+    /// before killing it according to `kill_behaviour`. If `None`, the process is
+    /// allowed to finish.
+    pub interrupt_grace: Option<Duration>,
+    /// The resource limits of the process.
+    pub limits: ResourceLimits,
+    /// The CPUs the process is allowed to run on. If `None`, the process can run on
+    /// any CPU.
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// The NUMA node the process is bound to (see `numa`). Unless `cpu_affinity` is
+    /// set, the process is also pinned to the CPUs of the node. If `None`, the
+    /// process can allocate memory on any node.
+    pub numa_node: Option<usize>,
+    /// Whether to run the process in its own network namespace (see `network`).
+    pub isolate_network: bool,
+    /// The scheduling policy of the process. If `None`, the process inherits the
+    /// policy of k2.
+    pub sched_policy: Option<SchedPolicy>,
+    /// The performance counters to collect. Requires the `perf` feature.
+    pub perf_events: Vec<PerfEvent>,
+    /// The file to feed to the standard input of the process. If `None`, the
+    /// standard input is `/dev/null`.
+    pub stdin: Option<PathBuf>,
+    /// How to profile the process. If `None`, the process isn't profiled.
+    pub profile: Option<Profile>,
+    /// Whether to sample the memory usage of the process while it runs (see
+    /// `InvocationOutcome::memory`).
+    pub sample_memory: bool,
+    /// The clock the benchmark should measure its in-process iterations with.
+    pub clock: Clock,
+    /// Whether the benchmark should report the CPU time of each in-process
+    /// iteration.
+    pub cpu_time: bool,
+}
+
+impl ProcessOpts {
+    /// The environment variables which tell the benchmark how to run and measure
+    /// its in-process iterations (see `measure`).
+    pub(crate) fn measure_env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            (IN_PROC_ITERS_ENV, self.in_proc_iters.to_string()),
+            (CLOCK_ENV, self.clock.name().to_string()),
+        ];
+        if self.cpu_time {
+            env.push((CPU_TIME_ENV, "1".to_string()));
+        }
+        env
+    }
+}
+
+/// The resources used by a benchmark process (and its descendants), as reported
+/// by `wait4`.
+#[derive(Debug, Default, Clone)]
+pub struct ResourceUsage {
+    /// The maximum resident set size, in KiB.
+    pub max_rss_kib: u64,
+    /// The time spent executing in user mode.
+    pub user_time: Duration,
+    /// The time spent executing in kernel mode.
+    pub sys_time: Duration,
+    /// The number of page faults serviced without any I/O.
+    pub minor_faults: u64,
+    /// The number of page faults which required I/O.
+    pub major_faults: u64,
+}
+
+impl ResourceUsage {
+    fn from_rusage(rusage: &libc::rusage) -> ResourceUsage {
+        let duration = |tv: libc::timeval| {
+            Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+        };
+        ResourceUsage {
+            max_rss_kib: rusage.ru_maxrss as u64,
+            user_time: duration(rusage.ru_utime),
+            sys_time: duration(rusage.ru_stime),
+            minor_faults: rusage.ru_minflt as u64,
+            major_faults: rusage.ru_majflt as u64,
+        }
+    }
+}
+
+/// The outcome of running a benchmark process.
+#[derive(Debug)]
+pub struct InvocationOutcome {
+    /// The exit code of the process, or `None` if it was terminated by a signal.
+    pub exit_code: Option<i32>,
+    /// The signal that terminated the process, if any.
+    pub signal: Option<i32>,
+    /// The standard output of the process.
+    pub stdout: Vec<u8>,
+    /// The standard error of the process.
+    pub stderr: Vec<u8>,
+    /// The value of each performance counter in `ProcessOpts::perf_events`,
+    /// indexed by name.
+    pub perf_counters: BTreeMap<String, u64>,
+    /// The resources used by the process.
+    pub rusage: ResourceUsage,
+    /// The peak memory usage of the process and its descendants, in bytes, if it
+    /// was run in a cgroup (see `limit::CgroupLimits`) which reports it.
+    pub memory_peak: Option<u64>,
+    /// The memory usage of the process itself, if `ProcessOpts::sample_memory` was
+    /// set.
+    pub memory: Option<MemoryUsage>,
+}
+
+impl InvocationOutcome {
+    fn new(
+        (status, rusage): (ExitStatus, ResourceUsage),
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    ) -> InvocationOutcome {
+        InvocationOutcome {
+            exit_code: status.code(),
+            signal: status.signal(),
+            stdout,
+            stderr,
+            perf_counters: Default::default(),
+            rusage,
+            memory_peak: None,
+            memory: None,
+        }
+    }
+
+    /// Whether the process exited with a zero exit code.
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+/// Run `cmd` to completion, and collect its output.
+///
+/// If the process doesn't finish within `opts.timeout`, it is killed according to
+/// `opts.kill_behaviour`, and `K2Error::TimedOut` is returned. Similarly, if k2 is
+/// asked to stop (see `shutdown`) and the process doesn't finish within
+/// `opts.interrupt_grace`, it is killed, and `K2Error::Interrupted` is returned.
+pub fn run(cmd: &mut Command, opts: &ProcessOpts) -> Result<InvocationOutcome, K2Error> {
+    debug!("running {:?}", cmd);
+    let limits = opts.limits.clone();
+    let stdin = match &opts.stdin {
+        Some(path) => Stdio::from(File::open(path)?),
+        None => Stdio::null(),
+    };
+    // The cgroup is removed when it's dropped, once the process has exited (or
+    // been killed).
+    let cgroup = match &opts.limits.cgroup {
+        Some(cgroup_limits) => Some(Cgroup::create(cgroup_limits)?),
+        None => None,
+    };
+    let cgroup_fd = cgroup.as_ref().map(Cgroup::procs_fd);
+    // The CPU set and the node mask are built here rather than in the child, which
+    // must not allocate.
+    let cpu_set = match (&opts.cpu_affinity, opts.numa_node) {
+        (Some(cpus), _) => Some(cpu_set(cpus)),
+        (None, Some(node)) => Some(cpu_set(&numa::node(node)?.cpus)),
+        (None, None) => None,
+    };
+    let node_mask = opts.numa_node.map(numa::node_mask);
+    let isolate_network = opts.isolate_network;
+    let sched_policy = opts.sched_policy;
+    let own_group = shutdown::installed();
+    #[cfg(feature = "perf")]
+    let mut perf = if opts.perf_events.is_empty() {
+        None
+    } else {
+        Some(PerfSession::start(&opts.perf_events)?)
+    };
+    #[cfg(feature = "perf")]
+    let perf_fds = perf.as_ref().map(|perf| perf.child_fds());
+    unsafe {
+        cmd.pre_exec(move || {
+            // Keep the signals the terminal sends to k2 from reaching the process.
+            if own_group && libc::setpgid(0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(cgroup_fd) = cgroup_fd {
+                limit::join_cgroup(cgroup_fd)?;
+            }
+            limits.apply()?;
+            if let Some(cpu_set) = &cpu_set {
+                set_affinity(cpu_set)?;
+            }
+            if let Some(node_mask) = &node_mask {
+                numa::bind_memory(node_mask)?;
+            }
+            if isolate_network {
+                network::isolate()?;
+            }
+            if let Some(sched_policy) = sched_policy {
+                sched_policy.apply()?;
+            }
+            // This must be the last hook: the counters are opened while it runs.
+            #[cfg(feature = "perf")]
+            {
+                if let Some(perf_fds) = perf_fds {
+                    perf::child_hook(perf_fds)?;
+                }
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd
+        .envs(opts.measure_env())
+        .stdin(stdin)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    #[cfg(feature = "perf")]
+    {
+        if let Some(perf) = &mut perf {
+            perf.close_child_fds();
+        }
+    }
+    // The output is read on separate threads, so that the child doesn't block on a
+    // full pipe while we're waiting for it to exit.
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+    let sampler = if opts.sample_memory {
+        Some(MemorySampler::start(child.id()))
+    } else {
+        None
+    };
+    let status = wait(&mut child, opts.timeout, opts.interrupt_grace);
+    let memory = sampler.map(MemorySampler::stop);
+    let status = match status? {
+        Wait::Exited(status) => status,
+        Wait::TimedOut => {
+            kill(&mut child, opts.kill_behaviour)?;
+            // Don't wait for the reader threads: the pipes might still be held
+            // open by the descendants of the child.
+            return Err(K2Error::TimedOut(opts.timeout.unwrap_or_default()));
+        }
+        Wait::Interrupted(signal) => {
+            kill(&mut child, opts.kill_behaviour)?;
+            return Err(K2Error::Interrupted(signal));
+        }
+    };
+    // The descendants of the child are killed with it, rather than left to hold its
+    // output pipes open.
+    if let Some(cgroup) = &cgroup {
+        cgroup.kill();
+    }
+    let mut outcome = InvocationOutcome::new(status, join_reader(stdout), join_reader(stderr));
+    outcome.memory_peak = cgroup.as_ref().and_then(Cgroup::memory_peak);
+    outcome.memory = memory;
+    #[cfg(feature = "perf")]
+    {
+        if let Some(perf) = perf {
+            outcome.perf_counters = perf.finish()?;
+        }
+    }
+    Ok(outcome)
+}
+
+/// The memory usage of a process, as reported by `/proc/<pid>/status`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemoryUsage {
+    /// The peak size of the address space (`VmPeak`), in bytes.
+    pub peak_vm: u64,
+    /// The peak resident set size (`VmHWM`), in bytes.
+    pub peak_rss: u64,
+    /// The size of the stack of the main thread (`VmStk`), in bytes.
+    pub stack: u64,
+}
+
+impl MemoryUsage {
+    /// Parse the contents of `/proc/<pid>/status`. Returns `None` if the process
+    /// has no address space (i.e. it's a zombie).
+    fn parse(status: &str) -> Option<MemoryUsage> {
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.trim().strip_suffix("kB"))
+                .and_then(|kib| kib.trim().parse::<u64>().ok())
+                .map(|kib| kib * 1024)
+        };
+        Some(MemoryUsage {
+            peak_vm: field("VmPeak")?,
+            peak_rss: field("VmHWM")?,
+            stack: field("VmStk")?,
+        })
+    }
+
+    /// The largest of each value of `self` and `other`.
+    fn max(self, other: MemoryUsage) -> MemoryUsage {
+        MemoryUsage {
+            peak_vm: self.peak_vm.max(other.peak_vm),
+            peak_rss: self.peak_rss.max(other.peak_rss),
+            stack: self.stack.max(other.stack),
+        }
+    }
+}
+
+/// A thread which samples the memory usage of a process every `POLL_INTERVAL`,
+/// until it's stopped.
+///
+/// The usage reported by the kernel only covers the process itself (not its
+/// descendants), and a peak which falls between two samples is missed.
+struct MemorySampler {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<MemoryUsage>,
+}
+
+impl MemorySampler {
+    /// Start sampling the memory usage of the process `pid`.
+    fn start(pid: u32) -> MemorySampler {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || {
+                let path = format!("/proc/{}/status", pid);
+                let mut usage = MemoryUsage::default();
+                while !stop.load(Ordering::SeqCst) {
+                    let sample = fs::read_to_string(&path).ok();
+                    if let Some(sample) = sample.as_deref().and_then(MemoryUsage::parse) {
+                        usage = usage.max(sample);
+                    }
+                    thread::park_timeout(POLL_INTERVAL);
+                }
+                usage
+            })
+        };
+        MemorySampler { stop, handle }
+    }
+
+    /// Stop sampling, and return the largest usage sampled.
+    fn stop(self) -> MemoryUsage {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.thread().unpark();
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+/// Build a CPU set containing `cpus`.
+pub(crate) fn cpu_set(cpus: &[usize]) -> libc::cpu_set_t {
+    unsafe {
+        let mut cpu_set = mem::zeroed::<libc::cpu_set_t>();
+        libc::CPU_ZERO(&mut cpu_set);
+        for cpu in cpus {
+            libc::CPU_SET(*cpu, &mut cpu_set);
+        }
+        cpu_set
+    }
+}
+
+/// Pin the current process to the CPUs in `cpu_set`.
+fn set_affinity(cpu_set: &libc::cpu_set_t) -> io::Result<()> {
+    let size = mem::size_of::<libc::cpu_set_t>();
+    if unsafe { libc::sched_setaffinity(0, size, cpu_set) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Read everything from `reader` on a new thread.
+fn read_to_end<R: Read + Send + 'static>(mut reader: R) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        // A read error simply truncates the output.
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+fn join_reader(handle: Option<JoinHandle<Vec<u8>>>) -> Vec<u8> {
+    handle
+        .and_then(|handle| handle.join().ok())
+        .unwrap_or_default()
+}
+
+/// Reap `child` with `wait4`, and return its exit status and resource usage. If
+/// `nohang` is set, return `None` straight away if the child is still running.
+///
+/// `Child::wait` can't be used, because it doesn't report the resource usage of
+/// the child. Once the child is reaped, its `Child` must no longer be waited on.
+fn wait4(child: &Child, nohang: bool) -> io::Result<Option<(ExitStatus, ResourceUsage)>> {
+    let pid = child.id() as libc::pid_t;
+    let options = if nohang { libc::WNOHANG } else { 0 };
+    let mut status = 0;
+    let mut rusage = unsafe { mem::zeroed::<libc::rusage>() };
+    loop {
+        match unsafe { libc::wait4(pid, &mut status, options, &mut rusage) } {
+            0 => return Ok(None),
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            _ => {
+                return Ok(Some((
+                    ExitStatus::from_raw(status),
+                    ResourceUsage::from_rusage(&rusage),
+                )))
+            }
+        }
+    }
+}
+
+/// Why `wait` stopped waiting for a child process.
+enum Wait {
+    /// The child exited.
+    Exited((ExitStatus, ResourceUsage)),
+    /// The child exceeded its timeout.
+    TimedOut,
+    /// k2 was asked to stop by this signal, and the child didn't exit in time.
+    Interrupted(i32),
+}
+
+/// Wait for `child` to exit, for at most `timeout` (if set). If k2 is asked to stop
+/// (see `shutdown`), the child is given at most `grace` (if set) to exit, or no
+/// time at all if k2 is asked more than once.
+fn wait(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    grace: Option<Duration>,
+) -> Result<Wait, K2Error> {
+    if timeout.is_none() && !shutdown::installed() {
+        let status = wait4(child, false)?.expect("wait4 should block until the child exits");
+        return Ok(Wait::Exited(status));
+    }
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let pidfd = pidfd_open(child);
+    let mut interrupted_at = None;
+    loop {
+        if let Some(status) = wait4(child, true)? {
+            return Ok(Wait::Exited(status));
+        }
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            return Ok(Wait::TimedOut);
+        }
+        if let Some(signal) = shutdown::requested() {
+            let interrupted_at = *interrupted_at.get_or_insert_with(Instant::now);
+            if shutdown::forced() || grace.is_some_and(|grace| interrupted_at.elapsed() >= grace) {
+                return Ok(Wait::Interrupted(signal));
+            }
+        }
+        let pause = deadline.map_or(POLL_INTERVAL, |deadline| {
+            POLL_INTERVAL.min(deadline.saturating_duration_since(now))
+        });
+        wait_exit(pidfd.as_ref(), pause);
+    }
+}
+
+/// A file descriptor which becomes readable once `child` exits (see
+/// `pidfd_open(2)`), or `None` if the kernel doesn't support it.
+fn pidfd_open(child: &Child) -> Option<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, child.id() as libc::pid_t, 0) };
+    if fd < 0 {
+        debug!("pidfd_open failed: {}", io::Error::last_os_error());
+        return None;
+    }
+    Some(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+/// Block for at most `timeout`, returning as soon as the process of `pidfd` exits,
+/// so that the time it exits at isn't rounded up to the next poll. Without a
+/// `pidfd`, this sleeps for `timeout`.
+fn wait_exit(pidfd: Option<&OwnedFd>, timeout: Duration) {
+    let pidfd = match pidfd {
+        Some(pidfd) => pidfd,
+        None => return thread::sleep(timeout),
+    };
+    let mut pollfd = libc::pollfd {
+        fd: pidfd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // The timeout is rounded up, so that `poll` doesn't return straight away when
+    // less than a millisecond is left. An interrupted `poll` returns early, which
+    // is harmless: the caller checks the child again.
+    let timeout_ms = timeout.as_micros().div_ceil(1000) as libc::c_int;
+    unsafe { libc::poll(&mut pollfd, 1, timeout_ms) };
+}
+
+/// Wait for `child` to exit for at most `timeout`. Returns `None` if the child is
+/// still running after `timeout`.
+fn wait_timeout(
+    child: &mut Child,
+    timeout: Duration,
+) -> Result<Option<(ExitStatus, ResourceUsage)>, K2Error> {
+    let deadline = Instant::now() + timeout;
+    let pidfd = pidfd_open(child);
+    loop {
+        if let Some(status) = wait4(child, true)? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        wait_exit(pidfd.as_ref(), deadline - now);
+    }
+}
+
+/// Kill `child` according to `kill_behaviour`, and reap it.
+fn kill(child: &mut Child, kill_behaviour: KillBehaviour) -> Result<(), K2Error> {
+    if let KillBehaviour::Terminate(grace) = kill_behaviour {
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+        if wait_timeout(child, grace)?.is_some() {
+            return Ok(());
+        }
+    }
+    child.kill()?;
+    wait4(child, false)?;
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! Profiling selected process executions.
+//!
+//! The process executions selected by a `ProfileConfig` are run under
+//! `perf record`, unless their language implementation has a profiler of its own
+//! (such as `Jvm::profiler_flag`). Each profiled job writes its profile to its own
+//! directory, `<results_dir>/profiles/<job id>`, which is recorded in the `profile`
+//! table of the results database (see `Results::profiles`).
+//!
+//! Profiling slows the benchmarks down, so the measurements of the profiled
+//! process executions shouldn't be compared with the others.
+
+use crate::error::K2Error;
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    fs,
+    path::{self, Path, PathBuf},
+    process::Command,
+};
+
+/// The directory (relative to the results directory) where the profile of each
+/// profiled job is stored.
+pub const PROFILE_DIR: &str = "profiles";
+/// The placeholder for the profile directory in the arguments of a profiler.
+pub const PROFILE_DIR_PLACEHOLDER: &str = "{profile_dir}";
+/// The file `perf record` writes the profile to, in the profile directory.
+const PERF_DATA: &str = "perf.data";
+
+/// Which process executions to profile, and how.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// The indices (starting at 0) of the process executions of each benchmark to
+    /// profile.
+    pub pexecs: Vec<usize>,
+    /// The arguments to pass to `perf record`, in addition to the output file.
+    #[serde(default = "default_perf_args")]
+    pub perf_args: Vec<String>,
+}
+
+impl ProfileConfig {
+    /// Profile the process executions with indices `pexecs`, recording their call
+    /// graphs with `perf record -g`.
+    pub fn new(pexecs: &[usize]) -> ProfileConfig {
+        ProfileConfig {
+            pexecs: pexecs.to_vec(),
+            perf_args: default_perf_args(),
+        }
+    }
+
+    /// Pass `args` to `perf record` instead of `-g`.
+    pub fn perf_args(mut self, args: &[&str]) -> Self {
+        self.perf_args = args.iter().map(|arg| arg.to_string()).collect();
+        self
+    }
+
+    /// How to profile process execution `pexec` of the job with identifier `job`,
+    /// or `None` if it isn't profiled. The profile directory is created if needed.
+    pub(crate) fn profile(
+        &self,
+        results_dir: &Path,
+        job: usize,
+        pexec: Option<usize>,
+    ) -> Result<Option<Profile>, K2Error> {
+        match pexec {
+            Some(pexec) if self.pexecs.contains(&pexec) => {
+                // The benchmark runs in its own working directory.
+                let dir = path::absolute(results_dir.join(profile_dir(job)))?;
+                fs::create_dir_all(&dir)?;
+                Ok(Some(Profile {
+                    dir,
+                    perf_args: self.perf_args.clone(),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn default_perf_args() -> Vec<String> {
+    vec!["-g".into()]
+}
+
+/// How to profile a process execution.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// The directory to write the profile to.
+    pub dir: PathBuf,
+    /// The arguments to pass to `perf record`, in addition to the output file.
+    pub perf_args: Vec<String>,
+}
+
+impl Profile {
+    /// Create a `perf record` command, which runs the program passed to it as its
+    /// remaining arguments.
+    pub(crate) fn perf_record(&self) -> Command {
+        let mut cmd = Command::new("perf");
+        cmd.arg("record")
+            .arg("-o")
+            .arg(self.dir.join(PERF_DATA))
+            .args(&self.perf_args)
+            .arg("--");
+        cmd
+    }
+
+    /// Replace the profile directory placeholder in the arguments of a language
+    /// implementation's profiler (see `PROFILE_DIR_PLACEHOLDER`).
+    pub(crate) fn profiler_args(&self, args: &[String]) -> Vec<String> {
+        let dir = self.dir.to_string_lossy();
+        args.iter()
+            .map(|arg| arg.replace(PROFILE_DIR_PLACEHOLDER, &dir))
+            .collect()
+    }
+
+    /// Whether the profiler wrote anything to the profile directory.
+    pub(crate) fn is_empty(&self) -> bool {
+        fs::read_dir(&self.dir).map_or(true, |mut entries| entries.next().is_none())
+    }
+}
+
+/// The profile directory of the job with identifier `job`, relative to the results
+/// directory.
+pub(crate) fn profile_dir(job: usize) -> PathBuf {
+    Path::new(PROFILE_DIR).join(job.to_string())
+}
@@ -0,0 +1,114 @@
+//! Sharing a results directory between several named experiments.
+//!
+//! By default, an experiment has a results directory of its own. Experiments which
+//! are given a name (see `ExperimentBuilder::name`) can share one instead: each of
+//! them has its own namespace in the shared directory,
+//! `<results_dir>/experiments/<name>`, which holds its manifest, its results
+//! database and the output of its jobs, and which is a results directory in its
+//! own right (it can be passed to `Results::open`, `status`, etc., like any
+//! other). The named experiments are recorded in the `experiment` table of the
+//! results database of the shared directory, along with a UUID which identifies
+//! them across machines (see `experiments`).
+//!
+//! The name of an experiment is part of its path, so it may only contain ASCII
+//! letters, digits, `-`, `_` and `.`, and must not start with a `.`.
+
+use crate::{db::K2Store, error::K2Error, migrations};
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// The directory (relative to the shared results directory) which holds the
+/// namespace of each named experiment.
+pub const EXPERIMENTS_DIR: &str = "experiments";
+
+/// A named experiment in a shared results directory.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExperimentRecord {
+    /// The name of the experiment.
+    pub name: String,
+    /// The UUID of the experiment, which is generated when it starts.
+    pub uuid: String,
+    /// When the experiment started.
+    #[serde(with = "crate::util::timestamp")]
+    pub created_at: SystemTime,
+    /// The namespace of the experiment (see `experiment_dir`).
+    pub dir: PathBuf,
+}
+
+/// The namespace of the experiment called `name` in the shared `results_dir`.
+pub fn experiment_dir<P: AsRef<Path>>(results_dir: P, name: &str) -> PathBuf {
+    results_dir.as_ref().join(EXPERIMENTS_DIR).join(name)
+}
+
+/// The named experiments which share `results_dir`, in the order they started.
+pub fn experiments<P: AsRef<Path>>(results_dir: P) -> Result<Vec<ExperimentRecord>, K2Error> {
+    let results_dir = results_dir.as_ref();
+    if !results_dir.join(K2Store::K2_DB).exists() {
+        return Ok(Default::default());
+    }
+    migrations::migrate(results_dir)?;
+    let mut store = K2Store::new(results_dir);
+    if !store.has_table("experiment")? {
+        return Ok(Default::default());
+    }
+    Ok(store
+        .experiments()?
+        .into_iter()
+        .map(|(name, uuid, created_at)| ExperimentRecord {
+            dir: experiment_dir(results_dir, &name),
+            name,
+            uuid,
+            created_at,
+        })
+        .collect())
+}
+
+/// Check that `name` can be used as the name of an experiment.
+pub(crate) fn check_name(name: &str) -> Result<(), K2Error> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(K2Error::InvalidConfig(format!(
+            "Invalid experiment name: {:?} (expected ASCII letters, digits, '-', '_' and '.')",
+            name
+        )))
+    }
+}
+
+/// Record the experiment called `name` in the shared `results_dir`, unless it's
+/// already recorded, and create the directory which holds the namespaces.
+pub(crate) fn register(results_dir: &Path, name: &str) -> Result<(), K2Error> {
+    let dir = results_dir.join(EXPERIMENTS_DIR);
+    fs::create_dir_all(&dir).map_err(|err| K2Error::file(&dir, err))?;
+    // The shared directory might have been used by an older version of k2.
+    migrations::migrate(results_dir)?;
+    let mut store = K2Store::new(results_dir);
+    store.create_experiment_table()?;
+    store.insert_experiment(name, &new_uuid(), SystemTime::now())
+}
+
+/// A random (version 4) UUID.
+fn new_uuid() -> String {
+    let bits = rand::random::<u128>();
+    // Set the version (4) and the variant (RFC 4122).
+    let bits = (bits & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        bits >> 96,
+        (bits >> 80) & 0xffff,
+        (bits >> 64) & 0xffff,
+        (bits >> 48) & 0xffff,
+        bits & 0xffff_ffff_ffff
+    )
+}
@@ -0,0 +1,134 @@
+//! HTML reports of the results of an experiment.
+//!
+//! A report is a single, self-contained HTML page, with the plots embedded as SVG:
+//! the summary statistics of each benchmark on each language implementation, a box
+//! plot comparing the language implementations each benchmark ran on, the
+//! run-sequence plot of each benchmark on each language implementation (one line
+//! per process execution), and the audit of the experiment.
+//!
+//! Reports are only available if k2 is built with the `report` feature.
+
+use crate::error::K2Error;
+
+use std::path::Path;
+
+/// Render the report of the experiment in `results_dir`, and write it to the HTML
+/// file at `out_path`. The experiment doesn't have to be finished: only the jobs
+/// which completed successfully are included.
+pub fn write_report<P: AsRef<Path>, Q: AsRef<Path>>(
+    results_dir: P,
+    out_path: Q,
+) -> Result<(), K2Error> {
+    #[cfg(feature = "report")]
+    {
+        let html = imp::render(results_dir.as_ref())?;
+        std::fs::write(out_path, html)?;
+        Ok(())
+    }
+    #[cfg(not(feature = "report"))]
+    {
+        let _ = (results_dir, out_path);
+        Err(K2Error::InvalidConfig(
+            "k2 must be built with the `report` feature to render reports".into(),
+        ))
+    }
+}
+
+#[cfg(feature = "report")]
+mod imp {
+    use crate::{
+        analysis::{BootstrapOpts, Estimate},
+        error::K2Error,
+        plot,
+        results::Results,
+    };
+
+    use std::{fmt::Write, path::Path};
+
+    /// The style sheet of the report.
+    const STYLE: &str = "
+        body { font-family: sans-serif; margin: 2em; }
+        table { border-collapse: collapse; }
+        th, td { border: 1px solid #ccc; padding: 0.2em 0.5em; text-align: left; }
+        td.num { text-align: right; font-family: monospace; }";
+
+    /// Render the report of the experiment in `results_dir`.
+    pub(super) fn render(results_dir: &Path) -> Result<String, K2Error> {
+        let mut results = Results::open(results_dir)?;
+        let summary = results.job_summary()?;
+        let statistics = results.statistics(&BootstrapOpts::default())?;
+        let audit = results.audit()?;
+        let benchmarks = results.wallclock_times_by_benchmark()?;
+        let mut html = String::new();
+        let title = format!("k2 report: {}", results_dir.display());
+        // Writing to a `String` can't fail.
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+            escape(&title),
+            STYLE,
+            escape(&title)
+        );
+        let _ = write!(
+            html,
+            "<h2>Jobs</h2>\n<pre>{}</pre>\n",
+            escape(&summary.to_string())
+        );
+        html.push_str("<h2>Summary</h2>\n<table>\n<tr><th>Key</th><th>Process executions</th>");
+        html.push_str("<th>Iterations</th><th>Mean (s)</th><th>Median (s)</th></tr>\n");
+        for (key, stats) in &statistics {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td>\
+                 <td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+                escape(key),
+                stats.pexecs,
+                stats.iters,
+                describe(&stats.mean),
+                describe(&stats.median)
+            );
+        }
+        html.push_str("</table>\n<h2>Benchmarks</h2>\n");
+        for (bench, times) in &benchmarks {
+            let _ = writeln!(html, "<h3>{}</h3>", escape(bench));
+            let samples = times
+                .iter()
+                .map(|(lang_impl, pexecs)| (lang_impl.clone(), pexecs.concat()))
+                .collect::<Vec<_>>();
+            html.push_str(&plot::box_plot(&samples, bench)?);
+            for (lang_impl, pexecs) in times {
+                let _ = writeln!(html, "<h4>{}</h4>", escape(lang_impl));
+                let title = format!("{} on {}", bench, lang_impl);
+                html.push_str(&plot::run_sequences(pexecs, &title)?);
+            }
+        }
+        html.push_str("<h2>Audit</h2>\n<table>\n");
+        for (key, value) in &audit {
+            let _ = writeln!(
+                html,
+                "<tr><th>{}</th><td><pre>{}</pre></td></tr>",
+                escape(key),
+                escape(value)
+            );
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        Ok(html)
+    }
+
+    /// Describe an estimate and its confidence interval.
+    fn describe(estimate: &Estimate) -> String {
+        format!(
+            "{:.6} ({:.6} - {:.6})",
+            estimate.value, estimate.lower, estimate.upper
+        )
+    }
+
+    /// Escape the characters of `text` which have a special meaning in HTML.
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+}
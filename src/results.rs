@@ -0,0 +1,521 @@
+use crate::{
+    analysis::{self, BootstrapOpts, Statistics},
+    audit::Audit,
+    benchmark::TagStore,
+    calibrate::Calibration,
+    db::{JobRecord, JobSummary, K2Store},
+    error::K2Error,
+    hooks::HookRun,
+    load::LoadSample,
+    manifest::{JobStatus, ManifestManager},
+    measure::Measurements,
+    migrations,
+    overhead::Overhead,
+    services::StoppedService,
+};
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The progress of an experiment. The durations are serialized in seconds.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ExperimentStatus {
+    /// The number of jobs with each status.
+    pub jobs: JobSummary,
+    /// The number of times the experiment has rebooted so far.
+    pub num_reboots: usize,
+    /// The mean duration of the jobs which have run so far, if any.
+    #[serde(default, with = "crate::util::opt_secs")]
+    pub mean_job_duration: Option<Duration>,
+    /// The mean time it took to run each job so far, including the time spent
+    /// rebooting between jobs, if known.
+    #[serde(default, with = "crate::util::opt_secs")]
+    pub mean_job_interval: Option<Duration>,
+}
+
+impl ExperimentStatus {
+    /// Read the status of an experiment from its `manifest` and `store`.
+    pub(crate) fn read(
+        manifest: &ManifestManager,
+        store: &mut K2Store,
+    ) -> Result<ExperimentStatus, K2Error> {
+        // The job table is only created after the first job has run.
+        if !store.has_table("job")? {
+            return Ok(ExperimentStatus {
+                jobs: JobSummary {
+                    outstanding: manifest.num_remaining(),
+                    ..Default::default()
+                },
+                num_reboots: manifest.num_reboots(),
+                mean_job_duration: None,
+                mean_job_interval: None,
+            });
+        }
+        Ok(ExperimentStatus {
+            jobs: store.job_summary()?,
+            num_reboots: manifest.num_reboots(),
+            mean_job_duration: store.mean_job_duration()?,
+            mean_job_interval: store.mean_job_interval()?,
+        })
+    }
+
+    /// The number of jobs which have run, successfully or not.
+    pub fn completed(&self) -> usize {
+        self.jobs.done + self.failed()
+    }
+
+    /// The number of jobs which haven't run yet.
+    pub fn remaining(&self) -> usize {
+        self.jobs.outstanding
+    }
+
+    /// The number of jobs which failed, with an error, a timeout or a wrong answer,
+    /// or which k2 gave up on.
+    pub fn failed(&self) -> usize {
+        self.jobs.error + self.jobs.timed_out + self.jobs.wrong_answer + self.jobs.too_many_attempts
+    }
+
+    /// Estimate how long it will take to run the remaining jobs, based on the
+    /// time it took to run the jobs run so far. If the start and end times of the
+    /// jobs weren't recorded (by older versions of k2), this is based on the
+    /// duration of the jobs, so it doesn't account for the time spent rebooting
+    /// between jobs.
+    pub fn eta(&self) -> Option<Duration> {
+        self.mean_job_interval
+            .or(self.mean_job_duration)
+            .map(|mean| mean * self.remaining() as u32)
+    }
+}
+
+impl fmt::Display for ExperimentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.jobs)?;
+        writeln!(f, "Reboots: {}", self.num_reboots)?;
+        match self.eta() {
+            Some(eta) => write!(f, "Estimated time remaining: {}s", eta.as_secs()),
+            None => write!(f, "Estimated time remaining: unknown"),
+        }
+    }
+}
+
+/// Summary statistics of the wall-clock times of a benchmark. Each process
+/// execution contributes a single sample: the mean wall-clock time (in seconds) of
+/// its in-process iterations.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Summary {
+    /// The number of process executions.
+    pub pexecs: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// The sample standard deviation.
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Summary {
+    /// Compute the summary statistics of `samples`, or return `None` if there are
+    /// no samples.
+    fn new(mut samples: Vec<f64>) -> Option<Summary> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let n = samples.len();
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        let median = if n.is_multiple_of(2) {
+            (samples[n / 2 - 1] + samples[n / 2]) / 2.0
+        } else {
+            samples[n / 2]
+        };
+        let stddev = if n > 1 {
+            let sq_diffs = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>();
+            (sq_diffs / (n - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+        Some(Summary {
+            pexecs: n,
+            mean,
+            median,
+            stddev,
+            min: samples[0],
+            max: samples[n - 1],
+        })
+    }
+
+    /// Compute the summary statistics of the wall-clock times (in seconds) of the
+    /// in-process iterations of each process execution in `pexecs` (see
+    /// `Results::wallclock_times`), or return `None` if there are none.
+    pub fn from_wallclock_times(pexecs: &[Vec<f64>]) -> Option<Summary> {
+        Summary::new(
+            pexecs
+                .iter()
+                .filter(|iters| !iters.is_empty())
+                .map(|iters| iters.iter().sum::<f64>() / iters.len() as f64)
+                .collect(),
+        )
+    }
+}
+
+/// The wall-clock times (in seconds) of the in-process iterations of each process
+/// execution of some benchmarks, indexed by results key.
+pub type WallclockTimes = BTreeMap<String, Vec<Vec<f64>>>;
+
+/// The results of an experiment, which can be read while the experiment is still
+/// running.
+pub struct Results {
+    store: K2Store,
+}
+
+impl Results {
+    /// Open the results of the experiment in `results_dir`, upgrading them if they
+    /// were created by an older version of k2.
+    pub fn open<P: AsRef<Path>>(results_dir: P) -> Result<Results, K2Error> {
+        Ok(Results {
+            store: open_store(results_dir.as_ref())?,
+        })
+    }
+
+    /// All the jobs of the experiment, in job order.
+    pub fn jobs(&mut self) -> Result<Vec<JobRecord>, K2Error> {
+        // The job table doesn't exist if the experiment was started by an older
+        // version of k2, and its first job hasn't run yet.
+        if !self.store.has_table("job")? {
+            return Ok(Default::default());
+        }
+        self.store.jobs()
+    }
+
+    /// Count the jobs with each status.
+    pub fn job_summary(&mut self) -> Result<JobSummary, K2Error> {
+        self.store.job_summary()
+    }
+
+    /// The measurements of the job with identifier `id`.
+    pub fn job_measurements(&mut self, id: usize) -> Result<Measurements, K2Error> {
+        self.store.measurements(id)
+    }
+
+    /// The measurements of each process execution of the benchmark at `path` on the
+    /// language implementation with results key `lang_impl`, in job order. Only the
+    /// jobs which completed successfully are included.
+    pub fn measurements(
+        &mut self,
+        path: &str,
+        lang_impl: &str,
+    ) -> Result<Vec<Measurements>, K2Error> {
+        self.jobs()?
+            .iter()
+            .filter(|job| {
+                job.status == JobStatus::Done
+                    && job.path.as_deref() == Some(path)
+                    && job.lang_impl.as_deref() == Some(lang_impl)
+            })
+            .map(|job| self.store.measurements(job.id))
+            .collect()
+    }
+
+    /// The environment the experiment was started in, which is empty if the
+    /// experiment hasn't started yet.
+    pub fn audit(&mut self) -> Result<Audit, K2Error> {
+        if !self.store.has_table("audit")? {
+            return Ok(Default::default());
+        }
+        self.store.audit()
+    }
+
+    /// The profile directory (relative to the results directory) of each profiled
+    /// job, indexed by job identifier (see `profile`).
+    pub fn profiles(&mut self) -> Result<BTreeMap<usize, PathBuf>, K2Error> {
+        if !self.store.has_table("profile")? {
+            return Ok(Default::default());
+        }
+        self.store.profiles()
+    }
+
+    /// Why each of the benchmarks which aren't run on some language implementation is
+    /// skipped, indexed by the results key it would have had (see
+    /// `ExperimentBuilder::skip`).
+    pub fn skipped(&mut self) -> Result<BTreeMap<String, String>, K2Error> {
+        if !self.store.has_table("skipped")? {
+            return Ok(Default::default());
+        }
+        self.store.skipped()
+    }
+
+    /// The memory usage of each benchmark in its calibration run, and the limits
+    /// suggested for it, indexed by results key (see `calibrate`). Only the
+    /// benchmarks which have been calibrated are included.
+    pub fn calibrations(&mut self) -> Result<BTreeMap<String, Calibration>, K2Error> {
+        if !self.store.has_table("calibration")? {
+            return Ok(Default::default());
+        }
+        self.store.calibrations()
+    }
+
+    /// The overhead of each language implementation, measured by running an empty
+    /// benchmark on it, indexed by results key (see `overhead`). Only the language
+    /// implementations whose overhead was measured are included.
+    pub fn overheads(&mut self) -> Result<BTreeMap<String, Overhead>, K2Error> {
+        if !self.store.has_table("overhead")? {
+            return Ok(Default::default());
+        }
+        self.store.overheads()
+    }
+
+    /// The messages the kernel logged while each job ran, indexed by job identifier
+    /// (see `dmesg`). Only the jobs during which the kernel logged messages are
+    /// included.
+    pub fn kernel_messages(&mut self) -> Result<BTreeMap<usize, Vec<String>>, K2Error> {
+        if !self.store.has_table("dmesg")? {
+            return Ok(Default::default());
+        }
+        self.store.kernel_messages()
+    }
+
+    /// The load of the machine while each job ran, sampled once a second, indexed by
+    /// job identifier (see `load`).
+    pub fn background_load(&mut self) -> Result<BTreeMap<usize, Vec<LoadSample>>, K2Error> {
+        if !self.store.has_table("load_sample")? {
+            return Ok(Default::default());
+        }
+        self.store.load_samples()
+    }
+
+    /// The services k2 stopped while the experiment ran, and whether it started them
+    /// again (see `services`).
+    pub fn stopped_services(&mut self) -> Result<Vec<StoppedService>, K2Error> {
+        if !self.store.has_table("stopped_service")? {
+            return Ok(Default::default());
+        }
+        self.store.stopped_services()
+    }
+
+    /// Each run of the hooks of the experiment, in the order they ran (see `hooks`).
+    pub fn hook_runs(&mut self) -> Result<Vec<HookRun>, K2Error> {
+        if !self.store.has_table("hook_run")? {
+            return Ok(Default::default());
+        }
+        self.store.hook_runs()
+    }
+
+    /// The tags of the benchmark of the job with identifier `id`.
+    pub fn tags(&mut self, id: usize) -> Result<TagStore, K2Error> {
+        self.store.job_tags(id)
+    }
+
+    /// Summary statistics of the wall-clock times of the benchmark at `path` on the
+    /// language implementation with results key `lang_impl`, or `None` if none of its
+    /// jobs completed successfully.
+    pub fn summary(&mut self, path: &str, lang_impl: &str) -> Result<Option<Summary>, K2Error> {
+        let samples = self
+            .measurements(path, lang_impl)?
+            .iter()
+            .filter(|measurements| !measurements.wallclock.is_empty())
+            .map(|measurements| {
+                let total = measurements
+                    .wallclock
+                    .iter()
+                    .map(|wallclock| wallclock.as_secs_f64())
+                    .sum::<f64>();
+                total / measurements.wallclock.len() as f64
+            })
+            .collect();
+        Ok(Summary::new(samples))
+    }
+
+    /// The wall-clock times (in seconds) of the jobs which completed successfully,
+    /// indexed by results key. Each key maps to one list of in-process iteration
+    /// times per process execution, in job order.
+    pub fn wallclock_times(&mut self) -> Result<WallclockTimes, K2Error> {
+        if !self.store.has_table("job")? {
+            return Ok(Default::default());
+        }
+        self.store.wallclock_times()
+    }
+
+    /// The wall-clock times of the jobs which completed successfully (see
+    /// `wallclock_times`), indexed by benchmark and then by the results key of the
+    /// language implementation. The benchmark of a job is its results key without
+    /// the language implementation.
+    pub fn wallclock_times_by_benchmark(
+        &mut self,
+    ) -> Result<BTreeMap<String, WallclockTimes>, K2Error> {
+        let lang_impls = self
+            .jobs()?
+            .into_iter()
+            .filter_map(|job| Some((job.key, job.lang_impl?)))
+            .collect::<BTreeMap<_, _>>();
+        let mut benchmarks = BTreeMap::<_, BTreeMap<_, _>>::new();
+        for (key, pexecs) in self.wallclock_times()? {
+            let lang_impl = lang_impls.get(&key).cloned().unwrap_or_default();
+            let bench = key
+                .strip_prefix(&format!("{}:", lang_impl))
+                .unwrap_or(&key)
+                .to_string();
+            benchmarks
+                .entry(bench)
+                .or_default()
+                .insert(lang_impl, pexecs);
+        }
+        Ok(benchmarks)
+    }
+
+    /// The wall-clock times of the jobs which completed successfully (see
+    /// `wallclock_times`), minus the overhead of an in-process iteration of their
+    /// language implementation (see `overheads`). Only the benchmarks whose language
+    /// implementation's overhead was measured are included.
+    pub fn adjusted_wallclock_times(&mut self) -> Result<WallclockTimes, K2Error> {
+        let overheads = self.overheads()?;
+        if overheads.is_empty() {
+            return Ok(Default::default());
+        }
+        let lang_impls = self
+            .jobs()?
+            .into_iter()
+            .filter_map(|job| Some((job.key, job.lang_impl?)))
+            .collect::<BTreeMap<_, _>>();
+        let mut times = self.wallclock_times()?;
+        times.retain(|key, pexecs| {
+            match lang_impls
+                .get(key)
+                .and_then(|lang_impl| overheads.get(lang_impl))
+            {
+                Some(overhead) => {
+                    overhead.adjust(pexecs);
+                    true
+                }
+                None => false,
+            }
+        });
+        Ok(times)
+    }
+
+    /// Summary statistics, with bootstrapped confidence intervals, of the wall-clock
+    /// times of each benchmark on each language implementation, indexed by results
+    /// key (see `analysis::statistics`). Only the jobs which completed successfully
+    /// are included.
+    pub fn statistics(
+        &mut self,
+        opts: &BootstrapOpts,
+    ) -> Result<BTreeMap<String, Statistics>, K2Error> {
+        Ok(statistics(self.wallclock_times()?, opts))
+    }
+
+    /// Summary statistics, as computed by `statistics`, of the wall-clock times
+    /// adjusted for the overhead of the language implementations (see
+    /// `adjusted_wallclock_times`).
+    pub fn adjusted_statistics(
+        &mut self,
+        opts: &BootstrapOpts,
+    ) -> Result<BTreeMap<String, Statistics>, K2Error> {
+        Ok(statistics(self.adjusted_wallclock_times()?, opts))
+    }
+}
+
+/// Summary statistics of the wall-clock `times` of each benchmark, indexed by
+/// results key.
+fn statistics(times: WallclockTimes, opts: &BootstrapOpts) -> BTreeMap<String, Statistics> {
+    times
+        .into_iter()
+        .filter_map(|(key, pexecs)| {
+            analysis::statistics(&pexecs, opts).map(|statistics| (key, statistics))
+        })
+        .collect()
+}
+
+/// The tag which records the host which ran each job of a merged results directory
+/// (see `merge`).
+pub const TAG_HOST: &str = "host";
+/// The tag which records the results directory each job of a merged results
+/// directory comes from (see `merge`).
+pub const TAG_SOURCE: &str = "source";
+
+/// Combine the jobs of the experiments in `results_dirs` (e.g. the same experiment,
+/// run on different machines) into a new results directory, `out`, so that they can
+/// be analysed together with `Results::open`. Returns the number of jobs merged.
+///
+/// Each results directory is upgraded to the current schema first, so that all
+/// their jobs fit the same tables. The jobs are renumbered, in the order of
+/// `results_dirs`, and tagged with the host which ran them (`TAG_HOST`, which is
+/// the name of the directory if the experiment predates the recording of the host)
+/// and the directory they come from (`TAG_SOURCE`). Only the jobs, their tags and
+/// their measurements are merged: `out` has no manifest, so it can't be resumed.
+///
+/// `out` must not exist.
+pub fn merge<P: AsRef<Path>, Q: AsRef<Path>>(results_dirs: &[P], out: Q) -> Result<usize, K2Error> {
+    let out = out.as_ref();
+    fs::create_dir(out).map_err(|err| K2Error::file(out, err))?;
+    let mut merged = K2Store::new(out);
+    merged.create_merged_tables()?;
+    let mut num_jobs = 0;
+    for results_dir in results_dirs {
+        let results_dir = results_dir.as_ref();
+        let mut store = open_store(results_dir)?;
+        if !store.has_table("job")? {
+            continue;
+        }
+        let audit = if store.has_table("audit")? {
+            store.audit()?
+        } else {
+            Default::default()
+        };
+        let host = audit.get("hostname").cloned().unwrap_or_else(|| {
+            results_dir
+                .canonicalize()
+                .ok()
+                .and_then(|dir| {
+                    dir.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                })
+                .unwrap_or_else(|| results_dir.display().to_string())
+        });
+        let mut tags = TagStore::new();
+        tags.insert(TAG_HOST.into(), host);
+        tags.insert(TAG_SOURCE.into(), results_dir.display().to_string());
+        num_jobs += merged.merge_from(&results_dir.join(K2Store::K2_DB), &tags)?;
+    }
+    Ok(num_jobs)
+}
+
+/// Open the results database in `results_dir`.
+pub(crate) fn open_store(results_dir: &Path) -> Result<K2Store, K2Error> {
+    let db_path: PathBuf = results_dir.join(K2Store::K2_DB);
+    if !db_path.exists() {
+        return Err(K2Error::not_found(db_path));
+    }
+    migrations::migrate(results_dir)?;
+    Ok(K2Store::new(results_dir))
+}
+
+/// Read the status of the experiment in `results_dir`.
+pub fn status<P: AsRef<Path>>(results_dir: P) -> Result<ExperimentStatus, K2Error> {
+    let results_dir = results_dir.as_ref();
+    migrations::migrate(results_dir)?;
+    let manifest = ManifestManager::open(results_dir)?;
+    ExperimentStatus::read(&manifest, &mut K2Store::new(results_dir))
+}
+
+/// Count the jobs of the experiment in `results_dir` with each status.
+pub fn job_summary<P: AsRef<Path>>(results_dir: P) -> Result<JobSummary, K2Error> {
+    open_store(results_dir.as_ref())?.job_summary()
+}
+
+/// Re-queue the jobs of the experiment in `results_dir` which failed (with an
+/// error or a timeout) fewer than `max_retries` times. Returns the number of jobs
+/// re-queued.
+///
+/// The jobs are run the next time the experiment is resumed.
+pub fn reset_errored<P: AsRef<Path>>(results_dir: P, max_retries: usize) -> Result<usize, K2Error> {
+    let results_dir = results_dir.as_ref();
+    let mut store = open_store(results_dir)?;
+    ManifestManager::open(results_dir)?.reset_errored(&mut store, max_retries)
+}
@@ -0,0 +1,136 @@
+//! Checking that the services which could disturb the benchmarks are stopped.
+//!
+//! Services such as `cron`, `ntpd` or `unattended-upgrades` wake up at arbitrary
+//! times, and slow down whichever benchmark happens to be running. Before each job,
+//! k2 checks (using `systemctl`) that none of the services of a `ServicesConfig` is
+//! running. A running service is either stopped by k2, or handled according to
+//! `Config::check_policy`.
+//!
+//! The services k2 stops are recorded in the `stopped_service` table of the results
+//! database, and started again once the experiment is over (see
+//! `Results::stopped_services`).
+
+use crate::{checks::CheckPolicy, config::Config, db::K2Store, error::K2Error, util};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use std::{
+    process::{Command, Stdio},
+    time::SystemTime,
+};
+
+/// The services which must be stopped while the benchmarks run.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServicesConfig {
+    /// The names of the systemd units of the services, such as `cron`.
+    pub names: Vec<String>,
+    /// Whether k2 stops the running services itself (this usually requires root),
+    /// and starts them again once the experiment is over.
+    #[serde(default)]
+    pub stop: bool,
+}
+
+impl ServicesConfig {
+    /// Check that the services `names` are stopped before each job.
+    pub fn new(names: &[&str]) -> ServicesConfig {
+        ServicesConfig {
+            names: names.iter().map(|name| name.to_string()).collect(),
+            stop: false,
+        }
+    }
+
+    /// Set whether k2 stops the running services itself.
+    pub fn stop(mut self, stop: bool) -> Self {
+        self.stop = stop;
+        self
+    }
+}
+
+/// A service k2 stopped.
+#[derive(Debug, Clone)]
+pub struct StoppedService {
+    pub name: String,
+    /// When k2 first stopped the service.
+    pub stopped_at: SystemTime,
+    /// When k2 started the service again, if it did.
+    pub restored_at: Option<SystemTime>,
+}
+
+/// Check that `systemctl` is available, if the experiment checks any services.
+pub(crate) fn check_supported(config: &Config) -> Result<(), K2Error> {
+    if config.services.is_some() && !config.dry_run {
+        util::find_executable("systemctl")?;
+    }
+    Ok(())
+}
+
+/// Check that none of the services of `config.services` is running, stopping them
+/// if `ServicesConfig::stop` is set, or handling them according to
+/// `config.check_policy` otherwise. The services k2 stops are recorded in `store`.
+pub(crate) fn run(config: &Config, store: &mut K2Store) -> Result<(), K2Error> {
+    let services = match (&config.services, config.dry_run) {
+        (Some(services), false) => services,
+        _ => return Ok(()),
+    };
+    for name in &services.names {
+        if !is_active(name)? {
+            continue;
+        }
+        if services.stop {
+            if !systemctl("stop", name)? {
+                return Err(K2Error::CheckFailed(format!(
+                    "service {} is running, and couldn't be stopped",
+                    name
+                )));
+            }
+            info!("stopped service {}", name);
+            store.insert_stopped_service(name, SystemTime::now())?;
+            continue;
+        }
+        let description = format!("service {} is running", name);
+        match config.check_policy {
+            CheckPolicy::Ignore => (),
+            CheckPolicy::Warn => warn!("{}", description),
+            // Use `ServicesConfig::stop` to stop the services.
+            CheckPolicy::Abort | CheckPolicy::Fix => return Err(K2Error::CheckFailed(description)),
+        }
+    }
+    Ok(())
+}
+
+/// Start the services k2 stopped again. A service which can't be started is
+/// reported, but doesn't stop the others from being started.
+pub(crate) fn restore(store: &mut K2Store) -> Result<(), K2Error> {
+    for service in store.stopped_services()? {
+        if service.restored_at.is_some() {
+            continue;
+        }
+        if systemctl("start", &service.name)? {
+            info!("started service {} again", service.name);
+            store.update_service_restored(&service.name, SystemTime::now())?;
+        } else {
+            warn!("failed to start service {} again", service.name);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the service `name` is running.
+fn is_active(name: &str) -> Result<bool, K2Error> {
+    systemctl("is-active", name)
+}
+
+/// Run `systemctl <command> <name>`, and return whether it succeeded.
+fn systemctl(command: &str, name: &str) -> Result<bool, K2Error> {
+    let status = Command::new("systemctl")
+        .arg(command)
+        .arg("--quiet")
+        .arg(name)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    Ok(status.success())
+}
@@ -0,0 +1,103 @@
+//! Stopping an experiment cleanly when k2 is interrupted.
+//!
+//! Once `install` is called, `SIGINT` (such as Ctrl-C) and `SIGTERM` (such as the
+//! machine shutting down) no longer kill k2. Instead, the process execution which
+//! is running is allowed to finish (for at most `Config::interrupt_grace`, after
+//! which it is killed), the results of its job are stored, and `Experiment::run`
+//! returns `K2Error::Interrupted` rather than starting the next job. A second
+//! signal kills the process execution straight away. The job of a process
+//! execution which was killed is still outstanding, so it is run again when the
+//! experiment is resumed.
+//!
+//! The benchmark processes are started in their own process group, so that the
+//! `SIGINT` a terminal sends to its foreground process group when Ctrl-C is pressed
+//! only reaches k2.
+
+use crate::error::K2Error;
+
+use tracing::debug;
+
+use std::{
+    io, mem,
+    sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The signals which ask k2 to stop.
+const SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+/// How often `sleep` checks whether k2 was asked to stop.
+const SLEEP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether the signal handlers are installed.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+/// The number of signals k2 received.
+static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+/// The first signal k2 received, or 0 if it hasn't received any.
+static FIRST_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Handle the signals which ask k2 to stop, instead of letting them kill k2.
+pub(crate) fn install() -> Result<(), K2Error> {
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    for signal in &SIGNALS {
+        let mut action = unsafe { mem::zeroed::<libc::sigaction>() };
+        action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // The handler only records the signal, so the interrupted system calls can
+        // simply be restarted.
+        action.sa_flags = libc::SA_RESTART;
+        if unsafe { libc::sigaction(*signal, &action, std::ptr::null_mut()) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// Record that k2 received `signal`. This only uses atomics, which are
+/// async-signal-safe.
+extern "C" fn handle(signal: libc::c_int) {
+    let _ = FIRST_SIGNAL.compare_exchange(0, signal, Ordering::SeqCst, Ordering::SeqCst);
+    RECEIVED.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Whether the signal handlers are installed (see `install`).
+pub(crate) fn installed() -> bool {
+    INSTALLED.load(Ordering::SeqCst)
+}
+
+/// The signal which asked k2 to stop, or `None` if it hasn't been asked to.
+pub(crate) fn requested() -> Option<i32> {
+    Some(FIRST_SIGNAL.load(Ordering::SeqCst)).filter(|signal| *signal != 0)
+}
+
+/// Whether k2 was asked to stop more than once, in which case the running process
+/// executions are killed straight away.
+pub(crate) fn forced() -> bool {
+    RECEIVED.load(Ordering::SeqCst) > 1
+}
+
+/// Returns `K2Error::Interrupted` if k2 was asked to stop.
+pub(crate) fn check() -> Result<(), K2Error> {
+    match requested() {
+        Some(signal) => {
+            debug!("stopping, since k2 received signal {}", signal);
+            Err(K2Error::Interrupted(signal))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Sleep for `duration`, or return `K2Error::Interrupted` as soon as k2 is asked
+/// to stop.
+pub(crate) fn sleep(duration: Duration) -> Result<(), K2Error> {
+    let deadline = Instant::now() + duration;
+    loop {
+        check()?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(());
+        }
+        thread::sleep((deadline - now).min(SLEEP_POLL_INTERVAL));
+    }
+}
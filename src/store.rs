@@ -0,0 +1,281 @@
+//! Stores the results of each job are copied to, in addition to the results
+//! database of the experiment.
+//!
+//! The results database in the results directory is always used to schedule the
+//! jobs of an experiment, and to resume it after a reboot. The other stores only
+//! receive a copy of the results: for example, a JSON lines file (`JsonlStore`)
+//! which can be copied off the machine while the experiment is running, or a
+//! Postgres database (`PgStore`) shared by several benchmarking machines. The
+//! Postgres store is only available if k2 is built with the `postgres` feature.
+
+use crate::{
+    db::{timestamp, JobRecord},
+    error::K2Error,
+    measure::Measurements,
+};
+
+use serde_json::json;
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+/// A store the results of each job are copied to.
+pub trait ResultStore: Send {
+    /// Record the `measurements` of `job`, which has just run. If the job was run
+    /// before (because it failed and was retried), these results supersede its
+    /// previous ones.
+    fn store_job(&mut self, job: &JobRecord, measurements: &Measurements) -> Result<(), K2Error>;
+}
+
+/// Appends the results of each job to a JSON lines file: one JSON object per line,
+/// and one line each time a job runs.
+///
+/// Each line is written to disk before the machine reboots, so the file can be
+/// copied and inspected at any point of the experiment. A job which is retried
+/// has one line per run: the last one is the most recent.
+pub struct JsonlStore {
+    file: File,
+}
+
+impl JsonlStore {
+    /// Append the results to the file at `path`, creating it if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<JsonlStore, K2Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonlStore { file })
+    }
+}
+
+impl ResultStore for JsonlStore {
+    fn store_job(&mut self, job: &JobRecord, measurements: &Measurements) -> Result<(), K2Error> {
+        let line = json!({
+            "job_id": job.id,
+            "key": job.key,
+            "status": job.status.name(),
+            "retries": job.retries,
+            "pexec": job.pexec,
+            "path": job.path,
+            "lang_impl": job.lang_impl,
+            "cpu_affinity": job.cpu_affinity,
+            "numa_node": job.numa_node,
+            "reruns": job.reruns,
+            "attempts": job.attempts,
+            "path_sha256": job.path_sha256,
+            "lang_impl_sha256": job.lang_impl_sha256,
+            "in_proc_iters": job.in_proc_iters,
+            "interrupted": job.interrupted,
+            "duration": job.duration.map(|duration| duration.as_secs_f64()),
+            "started_at": job.started_at.map(timestamp),
+            "finished_at": job.finished_at.map(timestamp),
+            "wallclock": measurements
+                .wallclock
+                .iter()
+                .map(|wallclock| wallclock.as_secs_f64())
+                .collect::<Vec<_>>(),
+            "cputime": measurements
+                .cputime
+                .iter()
+                .map(|cputime| cputime.as_secs_f64())
+                .collect::<Vec<_>>(),
+            "outliers": measurements.outliers,
+            "measurements": measurements.per_pexec,
+        });
+        writeln!(self.file, "{}", line)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use super::ResultStore;
+    use crate::{db::JobRecord, error::K2Error, measure::Measurements, util};
+
+    use pg::{types::ToSql, Client, NoTls};
+
+    /// The tables of a Postgres result store. The jobs of each experiment are
+    /// identified by the name of the experiment and the hostname of the machine
+    /// which ran them.
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS k2_job(
+            experiment TEXT NOT NULL,
+            hostname TEXT NOT NULL,
+            job_id BIGINT NOT NULL,
+            key TEXT NOT NULL,
+            status BIGINT NOT NULL,
+            retries BIGINT NOT NULL,
+            cpu_affinity TEXT,
+            duration DOUBLE PRECISION,
+            started_at TIMESTAMPTZ,
+            finished_at TIMESTAMPTZ,
+            pexec BIGINT,
+            path TEXT,
+            lang_impl TEXT,
+            numa_node BIGINT,
+            reruns BIGINT NOT NULL DEFAULT 0,
+            attempts BIGINT NOT NULL DEFAULT 0,
+            path_sha256 TEXT,
+            lang_impl_sha256 TEXT,
+            in_proc_iters BIGINT,
+            interrupted BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY(experiment, hostname, job_id));
+        ALTER TABLE k2_job ADD COLUMN IF NOT EXISTS numa_node BIGINT;
+        ALTER TABLE k2_job ADD COLUMN IF NOT EXISTS reruns BIGINT NOT NULL DEFAULT 0;
+        ALTER TABLE k2_job ADD COLUMN IF NOT EXISTS attempts BIGINT NOT NULL DEFAULT 0;
+        ALTER TABLE k2_job ADD COLUMN IF NOT EXISTS path_sha256 TEXT;
+        ALTER TABLE k2_job ADD COLUMN IF NOT EXISTS lang_impl_sha256 TEXT;
+        ALTER TABLE k2_job ADD COLUMN IF NOT EXISTS in_proc_iters BIGINT;
+        ALTER TABLE k2_job ADD COLUMN IF NOT EXISTS interrupted BIGINT NOT NULL DEFAULT 0;
+        CREATE TABLE IF NOT EXISTS k2_measurement(
+            experiment TEXT NOT NULL,
+            hostname TEXT NOT NULL,
+            job_id BIGINT NOT NULL,
+            iter BIGINT NOT NULL,
+            wallclock DOUBLE PRECISION NOT NULL,
+            outlier BOOLEAN NOT NULL DEFAULT FALSE,
+            cputime DOUBLE PRECISION,
+            PRIMARY KEY(experiment, hostname, job_id, iter));
+        ALTER TABLE k2_measurement
+            ADD COLUMN IF NOT EXISTS outlier BOOLEAN NOT NULL DEFAULT FALSE;
+        ALTER TABLE k2_measurement ADD COLUMN IF NOT EXISTS cputime DOUBLE PRECISION;
+        CREATE TABLE IF NOT EXISTS k2_pexec_measurement(
+            experiment TEXT NOT NULL,
+            hostname TEXT NOT NULL,
+            job_id BIGINT NOT NULL,
+            name TEXT NOT NULL,
+            value DOUBLE PRECISION NOT NULL,
+            PRIMARY KEY(experiment, hostname, job_id, name));";
+
+    /// A Postgres database several machines can copy their results to.
+    pub struct PgStore {
+        client: Client,
+        /// The name of the experiment, which identifies it across machines.
+        experiment: String,
+        /// The hostname of this machine, which is recorded for each job.
+        hostname: String,
+    }
+
+    impl PgStore {
+        /// Connect to the Postgres database at `url` (such as
+        /// `postgresql://k2@db.example.com/results`), and create the k2 tables if
+        /// they don't exist. The results are recorded as the results of
+        /// `experiment` on this machine.
+        pub fn connect(url: &str, experiment: &str) -> Result<PgStore, K2Error> {
+            let mut client = Client::connect(url, NoTls)?;
+            client.batch_execute(SCHEMA)?;
+            Ok(PgStore {
+                client,
+                experiment: experiment.to_string(),
+                hostname: util::hostname(),
+            })
+        }
+    }
+
+    impl ResultStore for PgStore {
+        fn store_job(
+            &mut self,
+            job: &JobRecord,
+            measurements: &Measurements,
+        ) -> Result<(), K2Error> {
+            let (experiment, hostname) = (&self.experiment, &self.hostname);
+            let id = job.id as i64;
+            let cpu_affinity = job.cpu_affinity.as_ref().map(|cpus| {
+                cpus.iter()
+                    .map(|cpu| cpu.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            });
+            let params: &[&(dyn ToSql + Sync)] = &[
+                experiment,
+                hostname,
+                &id,
+                &job.key,
+                &(job.status as i64),
+                &(job.retries as i64),
+                &cpu_affinity,
+                &job.duration.map(|duration| duration.as_secs_f64()),
+                &job.started_at,
+                &job.finished_at,
+                &job.pexec.map(|pexec| pexec as i64),
+                &job.path,
+                &job.lang_impl,
+                &job.numa_node.map(|node| node as i64),
+                &(job.reruns as i64),
+                &(job.attempts as i64),
+                &job.path_sha256,
+                &job.lang_impl_sha256,
+                &job.in_proc_iters.map(|iters| iters as i64),
+                &(job.interrupted as i64),
+            ];
+            let query = format!(
+                "INSERT INTO k2_job VALUES ({})
+                 ON CONFLICT (experiment, hostname, job_id) DO UPDATE SET
+                    status = EXCLUDED.status,
+                    retries = EXCLUDED.retries,
+                    cpu_affinity = EXCLUDED.cpu_affinity,
+                    numa_node = EXCLUDED.numa_node,
+                    reruns = EXCLUDED.reruns,
+                    attempts = EXCLUDED.attempts,
+                    path_sha256 = EXCLUDED.path_sha256,
+                    lang_impl_sha256 = EXCLUDED.lang_impl_sha256,
+                    in_proc_iters = EXCLUDED.in_proc_iters,
+                    interrupted = EXCLUDED.interrupted,
+                    duration = EXCLUDED.duration,
+                    started_at = EXCLUDED.started_at,
+                    finished_at = EXCLUDED.finished_at;",
+                placeholders(params.len())
+            );
+            let mut tx = self.client.transaction()?;
+            tx.execute(query.as_str(), params)?;
+            // A retried job might have fewer measurements than its previous run.
+            for table in &["k2_measurement", "k2_pexec_measurement"] {
+                tx.execute(
+                    format!(
+                        "DELETE FROM {} WHERE experiment = $1 AND hostname = $2 AND job_id = $3;",
+                        table
+                    )
+                    .as_str(),
+                    &[experiment, hostname, &id],
+                )?;
+            }
+            for (iter, wallclock) in measurements.wallclock.iter().enumerate() {
+                tx.execute(
+                    "INSERT INTO k2_measurement VALUES ($1, $2, $3, $4, $5, $6, $7);",
+                    &[
+                        experiment,
+                        hostname,
+                        &id,
+                        &(iter as i64),
+                        &wallclock.as_secs_f64(),
+                        &measurements.outliers.binary_search(&iter).is_ok(),
+                        &measurements
+                            .cputime
+                            .get(iter)
+                            .map(|cputime| cputime.as_secs_f64()),
+                    ],
+                )?;
+            }
+            for (name, value) in &measurements.per_pexec {
+                tx.execute(
+                    "INSERT INTO k2_pexec_measurement VALUES ($1, $2, $3, $4, $5);",
+                    &[experiment, hostname, &id, name, value],
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        }
+    }
+
+    /// The placeholders of the `n` parameters of a query (`$1, $2, ..., $n`).
+    fn placeholders(n: usize) -> String {
+        (1..=n)
+            .map(|i| format!("${}", i))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use self::postgres::PgStore;
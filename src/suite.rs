@@ -0,0 +1,456 @@
+//! Suites of benchmarks, either discovered in a directory tree (see
+//! `BenchmarkSuite`) or declared in a TOML manifest (see `SuiteManifest`).
+//!
+//! A suite manifest (conventionally called `k2.toml`) declares the language
+//! implementations (the VMs) of the suite, the benchmarks, and the pairs of
+//! benchmarks and language implementations not to run:
+//!
+//! ```toml
+//! include = ["../base/k2.toml"]
+//! skip = ["richards:pypy:nojit", "nbody:cpython"]
+//!
+//! [lang_impls.cpython]
+//! kind = "scripting"
+//! path = "/usr/bin/python3"
+//!
+//! [lang_impls.pypy]
+//! inherit = "cpython"
+//! path = "/opt/pypy/bin/pypy3"
+//!
+//! [lang_impls.pypy.variants.jit]
+//! [lang_impls.pypy.variants.nojit]
+//! args = ["--jit", "off"]
+//!
+//! [benchmarks.nbody]
+//! path = "benchmarks/nbody/nbody.py"
+//! args = ["1000"]
+//!
+//! [benchmarks.richards]
+//! path = "benchmarks/richards/richards.py"
+//! lang_impls = ["pypy"]
+//! ```
+//!
+//! The language implementations are declared like those of an experiment
+//! configuration file (see `ExperimentBuilder::from_config_file`), and so are the
+//! benchmarks, except that a benchmark runs on all the language implementations of
+//! the suite unless its `lang_impls` are listed.
+//!
+//! Each of the `variants` of a language implementation is a configuration of it
+//! (e.g. with its JIT compiler turned on or off), whose settings are merged over
+//! those of the language implementation, and whose name identifies it in the
+//! results keys (see `GenericScriptingVm::variant`). Each benchmark runs on every
+//! variant of its language implementations, and is tagged with its name
+//! (`benchmark_name`), the name of the language implementation (`lang_impl_name`)
+//! and the name of the variant (`variant`).
+//!
+//! Each of the `skip` patterns, `<benchmark>:<lang_impl>[:<variant>]`, prevents a
+//! benchmark from running on a language implementation (or only on one of its
+//! variants), as in Krun. Any part of a pattern may be `*`, which matches
+//! anything; a language implementation without variants only matches the variant
+//! `*`. The skipped benchmarks are recorded in the results, along with the pattern
+//! which matched them (see `Results::skipped`).
+//!
+//! A manifest can `include` others (relative to the directory which contains it),
+//! such as a base suite shared by a lab, and override them: the included manifests
+//! are merged in order, then the manifest itself is merged over them. Tables (such
+//! as a language implementation, or its `env`) are merged key by key, the `skip`
+//! patterns are combined, and any other setting replaces the included one. In the
+//! same way, a language implementation or a benchmark which `inherit`s another one
+//! is merged over it. The paths of the benchmarks are relative to the directory
+//! the experiment runs in, as in an experiment configuration file.
+
+use crate::{
+    benchmark::Benchmark,
+    config_file::{BenchmarkEntry, LangImplEntry},
+    error::K2Error,
+    lang_impl::LangImpl,
+};
+
+use serde::Deserialize;
+use toml::{value::Table, Value};
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// The key of the tag which records the name of a benchmark discovered in a suite.
+pub const TAG_BENCHMARK_NAME: &str = "benchmark_name";
+/// The key of the tag which records the name of the language implementation of a
+/// benchmark declared in a suite manifest.
+pub const TAG_LANG_IMPL_NAME: &str = "lang_impl_name";
+/// The key of the tag which records the variant of the language implementation of
+/// a benchmark declared in a suite manifest.
+pub const TAG_VARIANT: &str = "variant";
+
+/// A benchmark file found in a suite.
+#[derive(Debug, Clone)]
+pub struct SuiteEntry {
+    /// The name of the benchmark (the name of its directory).
+    pub name: String,
+    /// The path of the benchmark file.
+    pub path: PathBuf,
+    /// The extension of the benchmark file, which determines the language
+    /// implementations it runs on.
+    pub extension: String,
+}
+
+/// A suite of benchmarks laid out as `<dir>/<name>/<name>.<ext>`.
+///
+/// Each benchmark file is run on every language implementation registered for its
+/// extension. For example, if `.py` files are registered with both CPython and
+/// PyPy, `<dir>/nbody/nbody.py` produces one benchmark for each of them.
+pub struct BenchmarkSuite {
+    /// The benchmark files of the suite, sorted by name and extension.
+    entries: Vec<SuiteEntry>,
+    /// The language implementations, with the extension of the benchmarks they
+    /// run.
+    lang_impls: Vec<(String, Arc<dyn LangImpl>)>,
+}
+
+impl BenchmarkSuite {
+    /// Find all the benchmark files in `dir`. Any other files or directories are
+    /// ignored.
+    pub fn discover<P: AsRef<Path>>(dir: P) -> Result<BenchmarkSuite, K2Error> {
+        let mut entries = Vec::new();
+        for bench_dir in fs::read_dir(&dir)? {
+            let bench_dir = bench_dir?.path();
+            if !bench_dir.is_dir() {
+                continue;
+            }
+            let name = match bench_dir.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            for file in fs::read_dir(&bench_dir)? {
+                let path = file?.path();
+                let stem = path.file_stem().and_then(|stem| stem.to_str());
+                let extension = path.extension().and_then(|ext| ext.to_str());
+                if let (Some(stem), Some(extension)) = (stem, extension) {
+                    if stem == name && path.is_file() {
+                        entries.push(SuiteEntry {
+                            name: name.clone(),
+                            extension: extension.to_string(),
+                            path,
+                        });
+                    }
+                }
+            }
+        }
+        // `read_dir` doesn't return the entries in any particular order.
+        entries.sort_by(|a, b| (&a.name, &a.extension).cmp(&(&b.name, &b.extension)));
+        Ok(BenchmarkSuite {
+            entries,
+            lang_impls: Default::default(),
+        })
+    }
+
+    /// Run the benchmark files with extension `ext` (e.g. `py`) on `lang_impl`.
+    pub fn lang_impl(mut self, ext: &str, lang_impl: Arc<dyn LangImpl>) -> Self {
+        self.lang_impls
+            .push((ext.trim_start_matches('.').to_string(), lang_impl));
+        self
+    }
+
+    /// The benchmark files found in the suite.
+    pub fn entries(&self) -> &[SuiteEntry] {
+        &self.entries
+    }
+
+    /// Create a benchmark for each benchmark file and language implementation
+    /// registered for its extension. Each benchmark is tagged with its name
+    /// (`benchmark_name`).
+    ///
+    /// Benchmark files with an extension no language implementation is registered
+    /// for are skipped.
+    pub fn benchmarks(&self) -> Result<Vec<Benchmark>, K2Error> {
+        let mut benchmarks = Vec::new();
+        for entry in &self.entries {
+            let path = entry.path.to_str().ok_or_else(|| {
+                K2Error::InvalidConfig(format!(
+                    "The path of {} should be valid unicode",
+                    entry.path.display()
+                ))
+            })?;
+            for (ext, lang_impl) in &self.lang_impls {
+                if *ext == entry.extension {
+                    benchmarks.push(
+                        Benchmark::new(path, Arc::clone(lang_impl))
+                            .tag(TAG_BENCHMARK_NAME, &entry.name),
+                    );
+                }
+            }
+        }
+        Ok(benchmarks)
+    }
+}
+
+/// A suite of benchmarks declared in a TOML manifest (see the module
+/// documentation).
+pub struct SuiteManifest {
+    /// The benchmarks of the suite, in order of benchmark name, language
+    /// implementation name and variant name.
+    benchmarks: Vec<Benchmark>,
+    /// The benchmarks matched by a `skip` pattern, with the reason they're skipped.
+    skipped: Vec<(Benchmark, String)>,
+}
+
+/// The contents of a suite manifest, once the included manifests are merged.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    #[serde(default)]
+    skip: Vec<String>,
+    #[serde(default)]
+    lang_impls: Table,
+    #[serde(default)]
+    benchmarks: Table,
+}
+
+impl SuiteManifest {
+    /// Read the suite manifest at `path`, along with the manifests it includes.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SuiteManifest, K2Error> {
+        let path = path.as_ref();
+        let invalid =
+            |err: &dyn fmt::Display| K2Error::InvalidConfig(format!("{}: {}", path.display(), err));
+        let manifest = Value::Table(load_manifest(path, &mut Vec::new())?)
+            .try_into::<ManifestFile>()
+            .map_err(|err| invalid(&err))?;
+        let skip = manifest
+            .skip
+            .iter()
+            .map(|pattern| SkipPattern::parse(pattern).map_err(|err| invalid(&err)))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Each variant of each language implementation.
+        let mut lang_impls = BTreeMap::new();
+        for name in manifest.lang_impls.keys() {
+            let mut entry = inherited(&manifest.lang_impls, "lang_impls", name, &mut Vec::new())
+                .map_err(|err| invalid(&err))?;
+            let variants = match entry.remove("variants") {
+                Some(Value::Table(variants)) => variants
+                    .into_iter()
+                    .map(|(variant, settings)| match settings {
+                        Value::Table(settings) => {
+                            let mut entry = entry.clone();
+                            merge(&mut entry, settings);
+                            entry.insert("variant".into(), Value::String(variant.clone()));
+                            Ok((Some(variant), entry))
+                        }
+                        _ => Err(invalid(&format!(
+                            "lang_impls.{}.variants.{} should be a table",
+                            name, variant
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                Some(_) => {
+                    return Err(invalid(&format!(
+                        "lang_impls.{}.variants should be a table",
+                        name
+                    )))
+                }
+                None => vec![(None, entry)],
+            };
+            let variants = variants
+                .into_iter()
+                .map(|(variant, entry)| {
+                    let entry = Value::Table(entry)
+                        .try_into::<LangImplEntry>()
+                        .map_err(|err| invalid(&format!("lang_impls.{}: {}", name, err)))?;
+                    Ok((variant, entry.build()?))
+                })
+                .collect::<Result<Vec<_>, K2Error>>()?;
+            lang_impls.insert(name.as_str(), variants);
+        }
+        let mut benchmarks = Vec::new();
+        let mut skipped = Vec::new();
+        for name in manifest.benchmarks.keys() {
+            let mut entry = inherited(&manifest.benchmarks, "benchmarks", name, &mut Vec::new())
+                .map_err(|err| invalid(&err))?;
+            entry.entry("lang_impls").or_insert_with(|| {
+                Value::Array(
+                    lang_impls
+                        .keys()
+                        .map(|lang_impl| Value::String(lang_impl.to_string()))
+                        .collect(),
+                )
+            });
+            let entry = Value::Table(entry)
+                .try_into::<BenchmarkEntry>()
+                .map_err(|err| invalid(&format!("benchmarks.{}: {}", name, err)))?;
+            for lang_impl_name in &entry.lang_impls {
+                let variants = lang_impls.get(lang_impl_name.as_str()).ok_or_else(|| {
+                    invalid(&format!(
+                        "Unknown language implementation {}",
+                        lang_impl_name
+                    ))
+                })?;
+                for (variant, lang_impl) in variants {
+                    let variant = variant.as_deref();
+                    let pattern = skip
+                        .iter()
+                        .position(|pattern| pattern.matches(name, lang_impl_name, variant));
+                    let mut bench = entry
+                        .build(Arc::clone(lang_impl))?
+                        .tag(TAG_BENCHMARK_NAME, name)
+                        .tag(TAG_LANG_IMPL_NAME, lang_impl_name);
+                    if let Some(variant) = variant {
+                        bench = bench.tag(TAG_VARIANT, variant);
+                    }
+                    match pattern {
+                        Some(pattern) => skipped.push((
+                            bench,
+                            format!("Skipped by the suite manifest: {}", manifest.skip[pattern]),
+                        )),
+                        None => benchmarks.push(bench),
+                    }
+                }
+            }
+        }
+        Ok(SuiteManifest {
+            benchmarks,
+            skipped,
+        })
+    }
+
+    /// The benchmarks of the suite which aren't skipped, in order of benchmark
+    /// name, language implementation name and variant name.
+    pub fn benchmarks(&self) -> &[Benchmark] {
+        &self.benchmarks
+    }
+
+    /// The benchmarks matched by a `skip` pattern, with the reason they're skipped
+    /// (which names the pattern), in the same order as `benchmarks`.
+    pub fn skipped(&self) -> &[(Benchmark, String)] {
+        &self.skipped
+    }
+
+    /// The default results directory of the experiment which runs the suite
+    /// declared at `path`: `<name>_results`, next to the manifest (e.g.
+    /// `k2_results` for `k2.toml`).
+    pub fn results_dir(path: &Path) -> PathBuf {
+        let stem = path
+            .file_stem()
+            .map_or_else(|| "k2".into(), |stem| stem.to_string_lossy());
+        path.with_file_name(format!("{}_results", stem))
+    }
+}
+
+/// Read the manifest at `path`, merged over the manifests it includes. `including`
+/// holds the manifests which include it, so that a circular include is reported.
+fn load_manifest(path: &Path, including: &mut Vec<PathBuf>) -> Result<Table, K2Error> {
+    let invalid =
+        |err: &dyn fmt::Display| K2Error::InvalidConfig(format!("{}: {}", path.display(), err));
+    let canonical = path
+        .canonicalize()
+        .map_err(|err| K2Error::file(path, err))?;
+    if including.contains(&canonical) {
+        return Err(invalid(&"The manifest includes itself"));
+    }
+    let contents = fs::read_to_string(path).map_err(|err| K2Error::file(path, err))?;
+    let mut manifest = toml::from_str::<Table>(&contents).map_err(|err| invalid(&err))?;
+    let includes = match manifest.remove("include") {
+        Some(includes) => includes
+            .try_into::<Vec<PathBuf>>()
+            .map_err(|err| invalid(&err))?,
+        None => Vec::new(),
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut merged = Table::new();
+    including.push(canonical);
+    for include in includes {
+        merge(&mut merged, load_manifest(&dir.join(include), including)?);
+    }
+    including.pop();
+    merge(&mut merged, manifest);
+    Ok(merged)
+}
+
+/// Merge `overrides` into `base`: tables are merged key by key, `skip` patterns
+/// are combined, and any other value is replaced.
+fn merge(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        let value = match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(value)) => {
+                merge(base, value);
+                continue;
+            }
+            (Some(Value::Array(base)), Value::Array(value)) if key == "skip" => {
+                base.extend(value);
+                continue;
+            }
+            (_, value) => value,
+        };
+        base.insert(key, value);
+    }
+}
+
+/// The entry called `name` of the `section` table `entries`, merged over the entry
+/// it inherits (if any). `inheriting` holds the entries which inherit it, so that
+/// circular inheritance is reported.
+fn inherited(
+    entries: &Table,
+    section: &str,
+    name: &str,
+    inheriting: &mut Vec<String>,
+) -> Result<Table, String> {
+    let mut entry = match entries.get(name) {
+        Some(Value::Table(entry)) => entry.clone(),
+        Some(_) => return Err(format!("{}.{} should be a table", section, name)),
+        None => return Err(format!("{}.{} doesn't exist", section, name)),
+    };
+    let parent = match entry.remove("inherit") {
+        Some(Value::String(parent)) => parent,
+        Some(_) => return Err(format!("{}.{}.inherit should be a string", section, name)),
+        None => return Ok(entry),
+    };
+    inheriting.push(name.to_string());
+    if inheriting.contains(&parent) {
+        return Err(format!("{}.{} inherits itself", section, parent));
+    }
+    let mut merged = inherited(entries, section, &parent, inheriting)?;
+    inheriting.pop();
+    merge(&mut merged, entry);
+    Ok(merged)
+}
+
+/// A `skip` pattern: `<benchmark>:<lang_impl>[:<variant>]`, where `None` matches
+/// anything.
+struct SkipPattern {
+    benchmark: Option<String>,
+    lang_impl: Option<String>,
+    variant: Option<String>,
+}
+
+impl SkipPattern {
+    fn parse(pattern: &str) -> Result<SkipPattern, String> {
+        let part = |part: &str| Some(part.to_string()).filter(|part| part != "*");
+        match pattern.split(':').collect::<Vec<_>>().as_slice() {
+            [benchmark, lang_impl] => Ok(SkipPattern {
+                benchmark: part(benchmark),
+                lang_impl: part(lang_impl),
+                variant: None,
+            }),
+            [benchmark, lang_impl, variant] => Ok(SkipPattern {
+                benchmark: part(benchmark),
+                lang_impl: part(lang_impl),
+                variant: part(variant),
+            }),
+            _ => Err(format!(
+                "Invalid skip pattern: {} (expected <benchmark>:<lang_impl>[:<variant>])",
+                pattern
+            )),
+        }
+    }
+
+    /// Whether the benchmark called `benchmark` is skipped on the `variant` of the
+    /// language implementation called `lang_impl`.
+    fn matches(&self, benchmark: &str, lang_impl: &str, variant: Option<&str>) -> bool {
+        let matches =
+            |part: &Option<String>, value: Option<&str>| part.is_none() || part.as_deref() == value;
+        matches(&self.benchmark, Some(benchmark))
+            && matches(&self.lang_impl, Some(lang_impl))
+            && matches(&self.variant, variant)
+    }
+}
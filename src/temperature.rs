@@ -0,0 +1,190 @@
+use crate::{
+    error::K2Error,
+    measure::{MeasurementCollector, Measurements},
+    process::InvocationOutcome,
+};
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The directory where the thermal zones are exposed.
+const THERMAL_DIR: &str = "/sys/class/thermal";
+/// The directory where the hardware monitoring sensors are exposed.
+const HWMON_DIR: &str = "/sys/class/hwmon";
+/// How often to poll the sensors while waiting for the machine to cool down.
+const COOL_DOWN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A set of temperature readings (in degrees Celsius), indexed by sensor name.
+pub type Temperatures = BTreeMap<String, f64>;
+
+/// When a set of temperature readings was taken.
+#[derive(Debug, Copy, Clone)]
+pub enum TempPhase {
+    /// Right before running a job.
+    Before,
+    /// Right after running a job.
+    After,
+}
+
+impl TempPhase {
+    /// The name used to identify the phase in the results.
+    pub fn name(self) -> &'static str {
+        match self {
+            TempPhase::Before => "before",
+            TempPhase::After => "after",
+        }
+    }
+}
+
+/// A temperature sensor.
+#[derive(Debug)]
+pub struct Sensor {
+    /// The name used to identify the sensor in the results.
+    pub name: String,
+    /// The file which contains the current temperature, in millidegrees Celsius.
+    path: PathBuf,
+}
+
+impl Sensor {
+    /// Read the current temperature (in degrees Celsius).
+    pub fn read(&self) -> Result<f64, K2Error> {
+        let millidegrees = fs::read_to_string(&self.path)?;
+        let millidegrees = millidegrees.trim().parse::<f64>().map_err(|_| {
+            K2Error::SensorError(format!("Invalid reading from {}", self.path.display()))
+        })?;
+        Ok(millidegrees / 1000.0)
+    }
+}
+
+/// Find all the temperature sensors exposed by the thermal and hwmon subsystems.
+pub fn sensors() -> Result<Vec<Sensor>, K2Error> {
+    let mut sensors = Vec::new();
+    for zone in list_dir(THERMAL_DIR)? {
+        let name = file_name(&zone);
+        if name.starts_with("thermal_zone") && zone.join("temp").exists() {
+            sensors.push(Sensor {
+                name,
+                path: zone.join("temp"),
+            });
+        }
+    }
+    for hwmon in list_dir(HWMON_DIR)? {
+        let hwmon_name = file_name(&hwmon);
+        for input in list_dir(&hwmon)? {
+            let input_name = file_name(&input);
+            if input_name.starts_with("temp") && input_name.ends_with("_input") {
+                sensors.push(Sensor {
+                    name: format!("{}:{}", hwmon_name, input_name),
+                    path: input,
+                });
+            }
+        }
+    }
+    Ok(sensors)
+}
+
+/// Read the current temperature of each sensor in `sensors`.
+pub fn read_all(sensors: &[Sensor]) -> Result<Temperatures, K2Error> {
+    sensors
+        .iter()
+        .map(|sensor| Ok((sensor.name.clone(), sensor.read()?)))
+        .collect()
+}
+
+/// Block until the temperature of each sensor is within `threshold` degrees of its
+/// `baseline` reading.
+///
+/// Returns `K2Error::TooHot` if the sensors don't cool down within `timeout`.
+pub fn wait_until_cool(
+    sensors: &[Sensor],
+    baseline: &Temperatures,
+    threshold: f64,
+    timeout: Duration,
+) -> Result<(), K2Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let temps = read_all(sensors)?;
+        let too_hot = temps.iter().any(|(name, temp)| match baseline.get(name) {
+            Some(base) => *temp > base + threshold,
+            None => false,
+        });
+        if !too_hot {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(K2Error::TooHot);
+        }
+        thread::sleep(COOL_DOWN_POLL_INTERVAL);
+    }
+}
+
+/// Collects the temperature of each sensor before and after each process
+/// execution, as `temperature:<phase>:<sensor>` (in degrees Celsius).
+#[derive(Debug)]
+pub struct TemperatureCollector {
+    sensors: Vec<Sensor>,
+    before: Temperatures,
+    after: Temperatures,
+}
+
+impl TemperatureCollector {
+    pub fn new() -> Result<TemperatureCollector, K2Error> {
+        Ok(TemperatureCollector {
+            sensors: sensors()?,
+            before: Default::default(),
+            after: Default::default(),
+        })
+    }
+}
+
+impl MeasurementCollector for TemperatureCollector {
+    fn start(&mut self) -> Result<(), K2Error> {
+        self.before = read_all(&self.sensors)?;
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), K2Error> {
+        self.after = read_all(&self.sensors)?;
+        Ok(())
+    }
+
+    fn collect(
+        &mut self,
+        _outcome: &InvocationOutcome,
+        measurements: &mut Measurements,
+    ) -> Result<(), K2Error> {
+        for (phase, temps) in &[
+            (TempPhase::Before, &self.before),
+            (TempPhase::After, &self.after),
+        ] {
+            for (sensor, celsius) in temps.iter() {
+                let name = format!("temperature:{}:{}", phase.name(), sensor);
+                measurements.per_pexec.insert(name, *celsius);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Return the entries of `dir`, or an empty list if `dir` doesn't exist.
+fn list_dir<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, K2Error> {
+    if !dir.as_ref().exists() {
+        return Ok(Default::default());
+    }
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,271 @@
+//! Uploading the results of an experiment to continuous benchmarking dashboards.
+//!
+//! Once all the jobs of an experiment have run, its results are uploaded to each
+//! of its uploaders (see `ExperimentBuilder::uploader`): a Codespeed instance
+//! (`CodespeedUploader`), or a ReBenchDB instance (`ReBenchDbUploader`). Both
+//! dashboards identify the results by project, commit, environment (the machine
+//! which ran the benchmarks), executable (the language implementation) and
+//! benchmark: `UploadMapping` maps the results of k2 to these names.
+//!
+//! Only the jobs which completed successfully are uploaded.
+
+use crate::{
+    error::K2Error,
+    results::{Results, Summary},
+    util,
+};
+
+use serde_json::{json, Value};
+
+use std::{collections::BTreeMap, time::SystemTime};
+
+/// The name of the dashboards' criterion for the wall-clock times.
+const WALLCLOCK_CRITERION: &str = "total";
+
+/// How the results of an experiment are identified on a dashboard.
+#[derive(Debug, Clone)]
+pub struct UploadMapping {
+    project: String,
+    commit: String,
+    branch: String,
+    environment: String,
+    /// The name of each language implementation, indexed by results key.
+    executables: BTreeMap<String, String>,
+    /// The name of each benchmark, indexed by results key (without the language
+    /// implementation).
+    benchmarks: BTreeMap<String, String>,
+}
+
+impl UploadMapping {
+    /// Upload the results as the results of `commit` (a revision of the benchmarked
+    /// software) of `project`. By default, the branch is `master`, the environment
+    /// is the hostname of the machine, and the language implementations and
+    /// benchmarks are named after their results keys.
+    pub fn new(project: &str, commit: &str) -> UploadMapping {
+        UploadMapping {
+            project: project.to_string(),
+            commit: commit.to_string(),
+            branch: "master".into(),
+            environment: util::hostname(),
+            executables: Default::default(),
+            benchmarks: Default::default(),
+        }
+    }
+
+    /// Set the branch `commit` is on.
+    pub fn branch(mut self, branch: &str) -> Self {
+        self.branch = branch.to_string();
+        self
+    }
+
+    /// Set the name of the machine which runs the benchmarks.
+    pub fn environment(mut self, environment: &str) -> Self {
+        self.environment = environment.to_string();
+        self
+    }
+
+    /// Name the language implementation with results key `lang_impl` `name`.
+    pub fn executable(mut self, lang_impl: &str, name: &str) -> Self {
+        self.executables
+            .insert(lang_impl.to_string(), name.to_string());
+        self
+    }
+
+    /// Name the benchmark with results key `bench` (the key of its jobs, without the
+    /// language implementation) `name`.
+    pub fn benchmark(mut self, bench: &str, name: &str) -> Self {
+        self.benchmarks.insert(bench.to_string(), name.to_string());
+        self
+    }
+
+    /// The name of the language implementation with results key `lang_impl`.
+    fn executable_name<'a>(&'a self, lang_impl: &'a str) -> &'a str {
+        self.executables
+            .get(lang_impl)
+            .map_or(lang_impl, String::as_str)
+    }
+
+    /// The name of the benchmark with results key `bench`.
+    fn benchmark_name<'a>(&'a self, bench: &'a str) -> &'a str {
+        self.benchmarks.get(bench).map_or(bench, String::as_str)
+    }
+}
+
+/// A dashboard the results of an experiment are uploaded to once all its jobs
+/// have run.
+pub trait Uploader: Send {
+    /// Upload the `results` of `experiment`.
+    fn upload(&self, experiment: &str, results: &mut Results) -> Result<(), K2Error>;
+}
+
+/// Uploads the results to a Codespeed instance.
+///
+/// Codespeed only records one result per benchmark: the mean of the mean
+/// wall-clock times of its process executions (in seconds), with their standard
+/// deviation, minimum and maximum (see `Summary`).
+pub struct CodespeedUploader {
+    url: String,
+    mapping: UploadMapping,
+}
+
+impl CodespeedUploader {
+    /// Upload the results to the Codespeed instance at `url` (such as
+    /// `http://codespeed.example.com`).
+    pub fn new(url: &str, mapping: UploadMapping) -> CodespeedUploader {
+        CodespeedUploader {
+            url: url.trim_end_matches('/').to_string(),
+            mapping,
+        }
+    }
+}
+
+impl Uploader for CodespeedUploader {
+    fn upload(&self, _experiment: &str, results: &mut Results) -> Result<(), K2Error> {
+        let mapping = &self.mapping;
+        let mut payload = Vec::new();
+        for (bench, lang_impls) in results.wallclock_times_by_benchmark()? {
+            for (lang_impl, pexecs) in lang_impls {
+                let summary = match Summary::from_wallclock_times(&pexecs) {
+                    Some(summary) => summary,
+                    None => continue,
+                };
+                payload.push(json!({
+                    "commitid": mapping.commit,
+                    "branch": mapping.branch,
+                    "project": mapping.project,
+                    "executable": mapping.executable_name(&lang_impl),
+                    "benchmark": mapping.benchmark_name(&bench),
+                    "environment": mapping.environment,
+                    "result_value": summary.mean,
+                    "std_dev": summary.stddev,
+                    "min": summary.min,
+                    "max": summary.max,
+                }));
+            }
+        }
+        if payload.is_empty() {
+            return Ok(());
+        }
+        // Codespeed expects the results as a form field, rather than as the body.
+        let url = format!("{}/result/add/json/", self.url);
+        let body = Value::Array(payload).to_string();
+        if util::http_request("POST", &url, &["--data-urlencode", "json@-"], &body)? {
+            Ok(())
+        } else {
+            Err(K2Error::Upload(format!("POST {} failed", url)))
+        }
+    }
+}
+
+/// Uploads the results to a ReBenchDB instance.
+///
+/// Unlike Codespeed, ReBenchDB records the wall-clock time of each in-process
+/// iteration of each process execution, in milliseconds. The experiment is named
+/// after the experiment k2 ran.
+pub struct ReBenchDbUploader {
+    url: String,
+    mapping: UploadMapping,
+}
+
+impl ReBenchDbUploader {
+    /// Upload the results to the ReBenchDB instance at `url` (such as
+    /// `https://rebench.example.com`).
+    pub fn new(url: &str, mapping: UploadMapping) -> ReBenchDbUploader {
+        ReBenchDbUploader {
+            url: url.trim_end_matches('/').to_string(),
+            mapping,
+        }
+    }
+}
+
+impl Uploader for ReBenchDbUploader {
+    fn upload(&self, experiment: &str, results: &mut Results) -> Result<(), K2Error> {
+        let mapping = &self.mapping;
+        let mut runs = Vec::new();
+        for (bench, lang_impls) in results.wallclock_times_by_benchmark()? {
+            for (lang_impl, pexecs) in lang_impls {
+                let data_points = pexecs
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(pexec, iters)| {
+                        iters.iter().enumerate().map(move |(iter, secs)| {
+                            json!({
+                                "in": pexec + 1,
+                                "it": iter + 1,
+                                "m": [{ "c": 0, "v": secs * 1000.0 }],
+                            })
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                runs.push(json!({
+                    "runId": {
+                        "benchmark": {
+                            "name": mapping.benchmark_name(&bench),
+                            "suite": {
+                                "name": mapping.project,
+                                "executor": { "name": mapping.executable_name(&lang_impl) },
+                            },
+                            "runDetails": {
+                                "invocations": pexecs.len(),
+                                "iterations": pexecs.iter().map(Vec::len).max().unwrap_or(0),
+                                "warmup": null,
+                            },
+                        },
+                        "cmdline": format!("{}:{}", lang_impl, bench),
+                        "location": "",
+                    },
+                    "d": data_points,
+                }));
+            }
+        }
+        if runs.is_empty() {
+            return Ok(());
+        }
+        let audit = results.audit()?;
+        let audit_value = |key: &str| audit.get(key).cloned().unwrap_or_default();
+        // The total memory is recorded in kB, but ReBenchDB expects bytes.
+        let memory = audit_value("mem_total")
+            .trim_end_matches("kB")
+            .trim()
+            .parse::<u64>()
+            .map_or(0, |kb| kb * 1024);
+        let start_time = results
+            .jobs()?
+            .iter()
+            .filter_map(|job| job.started_at)
+            .min()
+            .unwrap_or_else(SystemTime::now);
+        let payload = json!({
+            "data": runs,
+            "criteria": [{ "i": 0, "c": WALLCLOCK_CRITERION, "u": "ms" }],
+            "env": {
+                "hostName": mapping.environment,
+                "cpu": audit_value("cpu_model"),
+                "osType": audit_value("uname"),
+                "memory": memory,
+                "software": [],
+                "manualRun": false,
+            },
+            "startTime": util::iso8601(start_time),
+            "endTime": util::iso8601(SystemTime::now()),
+            "source": {
+                "commitId": mapping.commit,
+                "branchOrTag": mapping.branch,
+            },
+            "experimentName": experiment,
+            "projectName": mapping.project,
+        });
+        let url = format!("{}/rebenchdb/results", self.url);
+        let args = [
+            "--header",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+        ];
+        if util::http_request("PUT", &url, &args, &payload.to_string())? {
+            Ok(())
+        } else {
+            Err(K2Error::Upload(format!("PUT {} failed", url)))
+        }
+    }
+}
@@ -1,22 +1,100 @@
-use crate::error::K2Error;
+use crate::{error::K2Error, shutdown};
 
 use libc::c_char;
-use std::{ffi, ptr};
+use serde::{
+    de::{self, value::MapAccessDeserializer, IntoDeserializer, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserializer, Serialize, Serializer,
+};
+use tracing::info;
+
+use std::{
+    ffi, fmt, fs,
+    io::{self, Write},
+    marker::PhantomData,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    ptr, thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The directory where the CPUs are exposed.
+pub(crate) const CPU_DIR: &str = "/sys/devices/system/cpu";
+
+/// How long to wait for an HTTP server to accept a connection (see `http_request`).
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long an HTTP request may take altogether. The notifications are sent between
+/// jobs, so a server which hangs mustn't stall the experiment.
+const HTTP_MAX_TIME: Duration = Duration::from_secs(300);
 
 /// Return the absolute path of `bin_name` by searching ${PATH}.
-pub fn find_executable(bin_name: &str) -> String {
-    which::which(bin_name)
-        .unwrap_or_else(|_| panic!("Could not find {}.", bin_name))
-        .to_str()
-        .expect("Path must be a utf-8 string.")
-        .into()
+pub fn find_executable(bin_name: &str) -> Result<String, K2Error> {
+    let path =
+        which::which(bin_name).map_err(|_| K2Error::ExecutableNotFound(bin_name.to_string()))?;
+    path.to_str()
+        .map(String::from)
+        .ok_or_else(|| K2Error::ExecutableNotFound(bin_name.to_string()))
+}
+
+/// The path of `program`: a program without a directory is looked up in the
+/// `PATH` (and left as it is if it isn't found).
+pub(crate) fn program_path(program: PathBuf) -> PathBuf {
+    if program.components().count() <= 1 {
+        find_executable(&program.to_string_lossy())
+            .map(PathBuf::from)
+            .unwrap_or(program)
+    } else {
+        program
+    }
+}
+
+/// Check that the directory `dir` can be written to or, if it doesn't exist yet,
+/// that it can be created.
+pub(crate) fn check_writable(dir: &Path) -> Result<(), K2Error> {
+    let dir = if dir.exists() {
+        dir
+    } else {
+        dir.parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+    };
+    let path = ffi::CString::new(dir.as_os_str().as_bytes())
+        .map_err(|err| K2Error::file(dir, err.into()))?;
+    // This also fails if `dir` is on a read-only filesystem.
+    if unsafe { libc::access(path.as_ptr(), libc::W_OK) } != 0 {
+        return Err(K2Error::file(dir, io::Error::last_os_error()));
+    }
+    Ok(())
 }
 
 /// Reboot, if `hardware_reboot` is `true`. Otherwise, replace the current process
 /// with a fresh copy of itself.
+///
+/// This only returns if the machine couldn't be rebooted (or the process couldn't
+/// be replaced), or if k2 is asked to stop (see `shutdown`) while the machine shuts
+/// down.
 pub fn reboot(hardware_reboot: bool) -> K2Error {
     if hardware_reboot {
-        unimplemented!("reboot")
+        info!("rebooting");
+        match Command::new("shutdown").args(["-r", "now"]).status() {
+            Ok(status) if status.success() => (),
+            Ok(status) => {
+                return K2Error::Io(io::Error::other(format!(
+                    "shutdown -r now exited with {}",
+                    status
+                )))
+            }
+            Err(err) => return err.into(),
+        }
+        // Don't run anything else while the machine shuts down. The `SIGTERM` sent to
+        // the processes which are still running stops k2 cleanly.
+        loop {
+            if let Err(err) = shutdown::check() {
+                return err;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
     } else {
         let args = std::env::args();
         let mut cstrs = Vec::with_capacity(args.len());
@@ -26,7 +104,7 @@ pub fn reboot(hardware_reboot: bool) -> K2Error {
         let mut argv: Vec<*const c_char> = cstrs.iter().map(|arg| arg.as_ptr()).collect();
         argv.push(ptr::null());
         unsafe { libc::execv(argv[0], argv.as_ptr()) };
-        K2Error::Unknown
+        io::Error::last_os_error().into()
     }
 }
 
@@ -38,3 +116,261 @@ pub fn num_digits(value: usize) -> usize {
         (value as f64).log10().floor() as usize + 1
     }
 }
+
+/// Return the hostname of the machine, or `"localhost"` if it can't be determined.
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut c_char, buf.len()) } != 0 {
+        return "localhost".into();
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Send `body` to `url` in an HTTP `method` (such as `POST`) request, using `curl`.
+/// `args` are passed to `curl` before the URL: they set the headers, and how the
+/// body (which `curl` reads from its standard input, `@-`) is encoded. Returns
+/// whether the server accepted the request (in time, see `HTTP_MAX_TIME`).
+pub(crate) fn http_request(
+    method: &str,
+    url: &str,
+    args: &[&str],
+    body: &str,
+) -> Result<bool, K2Error> {
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--request", method])
+        .arg("--connect-timeout")
+        .arg(HTTP_CONNECT_TIMEOUT.as_secs().to_string())
+        .arg("--max-time")
+        .arg(HTTP_MAX_TIME.as_secs().to_string())
+        .args(args)
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    let written = match child.stdin.take() {
+        Some(mut stdin) => stdin.write_all(body.as_bytes()),
+        None => Ok(()),
+    };
+    // `curl` might have exited without reading the body (e.g. if it couldn't
+    // connect), but it must still be waited for, so that it isn't left a zombie.
+    let status = child.wait()?;
+    written?;
+    Ok(status.success())
+}
+
+/// Format `time` as an ISO 8601 UTC timestamp (e.g. `2019-10-03T14:05:09Z`).
+pub(crate) fn iso8601(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Convert the number of days since the epoch to a date in the proleptic
+    // Gregorian calendar, which repeats every 400 years (146097 days). The years
+    // are counted from March, so that the leap day is the last day of the year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Return the paths of the `scaling_governor` files of the CPUs which have one,
+/// sorted by CPU number.
+pub(crate) fn scaling_governors() -> Vec<PathBuf> {
+    let mut cpus = match fs::read_dir(CPU_DIR) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.join("cpufreq/scaling_governor").exists())
+            .collect::<Vec<_>>(),
+        Err(_) => return Default::default(),
+    };
+    // Sort numerically, so that cpu10 comes after cpu9.
+    cpus.sort_by_key(|path| cpu_number(path));
+    cpus.iter()
+        .map(|cpu| cpu.join("cpufreq/scaling_governor"))
+        .collect()
+}
+
+/// The number of the CPU exposed at `path` (e.g. `/sys/devices/system/cpu/cpu3`).
+fn cpu_number(path: &Path) -> Option<usize> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.trim_start_matches("cpu").parse::<usize>().ok())
+}
+
+/// Serialize the variant `variant` of an enum, with data `value`, as a map with a
+/// single entry, which is how serde represents it by default. Unlike the default,
+/// this is also supported by `toml`, which can't serialize enum variants with data.
+pub(crate) fn serialize_variant<S: Serializer, T: Serialize + ?Sized>(
+    serializer: S,
+    variant: &str,
+    value: &T,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry(variant, value)?;
+    map.end()
+}
+
+/// An enum which is serialized with `serialize_variant`.
+pub(crate) trait Variants<'de>: Sized {
+    /// Deserialize the enum with the implementation derived by serde (using
+    /// `#[serde(remote = "Self")]`).
+    fn deserialize_derived<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+}
+
+/// Deserialize an enum serialized with `serialize_variant` (or a unit variant,
+/// serialized as its name). `toml` only deserializes enum variants with data from
+/// inline tables, so the map is handed to the derived implementation as is.
+pub(crate) fn deserialize_variant<'de, D: Deserializer<'de>, T: Variants<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    struct VariantVisitor<T>(PhantomData<T>);
+
+    impl<'de, T: Variants<'de>> Visitor<'de> for VariantVisitor<T> {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a variant name, or a map with a single entry")
+        }
+
+        fn visit_str<E: de::Error>(self, variant: &str) -> Result<T, E> {
+            T::deserialize_derived(variant.into_deserializer())
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<T, A::Error> {
+            T::deserialize_derived(MapAccessDeserializer::new(map))
+        }
+    }
+
+    deserializer.deserialize_any(VariantVisitor(PhantomData))
+}
+
+/// Convert a number of seconds read from a serialized value to a `Duration`.
+fn secs_to_duration<E: serde::de::Error>(secs: f64) -> Result<Duration, E> {
+    if secs.is_finite() && secs >= 0.0 {
+        Ok(Duration::from_secs_f64(secs))
+    } else {
+        Err(E::custom(format!("invalid duration: {}", secs)))
+    }
+}
+
+/// (De)serialize a `Duration` as a number of seconds, for use with
+/// `#[serde(with = "util::secs")]`.
+pub(crate) mod secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        super::secs_to_duration(f64::deserialize(deserializer)?)
+    }
+}
+
+/// (De)serialize an `Option<Duration>` as an optional number of seconds.
+pub(crate) mod opt_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_some(&duration.as_secs_f64()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<f64>::deserialize(deserializer)?
+            .map(super::secs_to_duration)
+            .transpose()
+    }
+}
+
+/// (De)serialize a `Vec<Duration>` as a list of numbers of seconds.
+pub(crate) mod vec_secs {
+    use serde::{ser::SerializeSeq, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        durations: &[Duration],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(durations.len()))?;
+        for duration in durations {
+            seq.serialize_element(&duration.as_secs_f64())?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Duration>, D::Error> {
+        Vec::<f64>::deserialize(deserializer)?
+            .into_iter()
+            .map(super::secs_to_duration)
+            .collect()
+    }
+}
+
+/// (De)serialize a `SystemTime` as a number of seconds since the Unix epoch.
+pub(crate) mod timestamp {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(crate::db::timestamp(*time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        super::secs_to_duration(f64::deserialize(deserializer)?).map(|since| UNIX_EPOCH + since)
+    }
+}
+
+/// (De)serialize an `Option<SystemTime>` as an optional number of seconds since the
+/// Unix epoch.
+pub(crate) mod opt_timestamp {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    pub fn serialize<S: Serializer>(
+        time: &Option<SystemTime>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => serializer.serialize_some(&crate::db::timestamp(*time)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<SystemTime>, D::Error> {
+        Option::<f64>::deserialize(deserializer)?
+            .map(|secs| super::secs_to_duration(secs).map(|since| UNIX_EPOCH + since))
+            .transpose()
+    }
+}